@@ -0,0 +1,95 @@
+// < begin copyright >
+// Copyright Ryan Marcus 2020
+//
+// See root directory of this project for license terms.
+//
+// < end copyright >
+
+//! The whole pipeline on a synthetic dataset: train a two-layer RMI with a
+//! radix top layer and `LearnedFIB` leaves, check and time its lookups in
+//! Rust, and emit the same RMI as C++.
+//!
+//! Run with `cargo run --release --example roundtrip`.
+
+use rmi_lib::{train, CodeGen, KeyType, RMITrainingData, TrainedRMI};
+use std::time::Instant;
+
+const NUM_KEYS: u64 = 1_000_000;
+const NUM_QUERIES: u64 = 1_000_000;
+
+// pseudo-random, so every run sees the same keys
+fn scramble(i: u64) -> u64 {
+    return i.wrapping_mul(0x9E37_79B9_7F4A_7C15).rotate_left(29);
+}
+
+// clustered keys: a random cluster start plus a quadratic offset within it
+fn make_keys() -> Vec<u64> {
+    let mut keys: Vec<u64> = (0..NUM_KEYS)
+        .map(|i| (scramble(i / 1000) >> 8) + (i % 1000) * (i % 1000) * 1009)
+        .collect();
+    keys.sort_unstable();
+    keys.dedup();
+    return keys;
+}
+
+// the lower bound of `key` found by searching the RMI's window
+fn lookup(rmi: &TrainedRMI, keys: &[u64], key: u64) -> (usize, u64) {
+    let (pos, err) = rmi.predict(&key.into());
+    let lo = usize::min(pos.saturating_sub(err) as usize, keys.len());
+    let hi = usize::min(pos.saturating_add(err).saturating_add(1) as usize, keys.len());
+    return (lo + keys[lo..hi].partition_point(|k| *k < key), hi as u64 - lo as u64);
+}
+
+fn main() {
+    env_logger::init();
+
+    let keys = make_keys();
+    println!("{} keys from {} to {}", keys.len(), keys[0], keys[keys.len() - 1]);
+    let data = RMITrainingData::from_keys(keys.clone());
+
+    let rmi = train(&data, "radix,learned_fib", 4096);
+    println!("trained radix,learned_fib in {:.2}s: average error {:.2}, max error {}, \
+              {} cache lines per lookup",
+             rmi.build_time as f64 / 1e9, rmi.model_avg_error, rmi.model_max_error,
+             rmi.lookup_cache_lines());
+
+    match rmi.verify_against_baseline(&keys) {
+        Ok(()) => println!("every key, and its neighbours, is found within the error bound"),
+        Err(mismatch) => {
+            println!("lookup of {} found {} instead of {}",
+                     mismatch.key, mismatch.found, mismatch.expected);
+            std::process::exit(1);
+        }
+    }
+
+    // random queries, most of which are not keys
+    let (lo, hi) = (keys[0], keys[keys.len() - 1]);
+    let queries: Vec<u64> = (0..NUM_QUERIES).map(|i| lo + scramble(i) % (hi - lo)).collect();
+    let start = Instant::now();
+    let mut total_window = 0;
+    let mut checksum = 0;
+    for query in queries.iter() {
+        let (pos, window) = lookup(&rmi, &keys, *query);
+        total_window += window;
+        checksum ^= pos;
+    }
+    let elapsed = start.elapsed();
+    println!("{} queries in {:.2}s ({:.0} ns each), average window of {:.1} keys \
+              (checksum {})",
+             queries.len(), elapsed.as_secs_f64(),
+             elapsed.as_nanos() as f64 / queries.len() as f64,
+             total_window as f64 / queries.len() as f64, checksum);
+    for query in queries.iter().step_by(1009) {
+        assert_eq!(lookup(&rmi, &keys, *query).0, keys.partition_point(|k| k < query));
+    }
+
+    let dir = std::env::temp_dir().join("rmi_roundtrip");
+    std::fs::create_dir_all(dir.join("rmi_data")).unwrap();
+    let codegen = CodeGen::new("roundtrip", KeyType::U64)
+        .output_dir(&dir)
+        .data_dir(dir.join("rmi_data"));
+    let report = codegen.dry_run(&rmi);
+    codegen.write(rmi).unwrap();
+    println!("wrote roundtrip.cpp, roundtrip.h and {} bytes of parameters to {}",
+             report.total_data_bytes, dir.display());
+}
@@ -0,0 +1,78 @@
+// < begin copyright >
+// Copyright Ryan Marcus 2020
+//
+// See root directory of this project for license terms.
+//
+// < end copyright >
+
+//! Builds `RadixModel`, `RadixTable` and `LearnedFIB` from arbitrary sorted
+//! keys and checks their predictions. The first three bytes of the input
+//! pick the table bits, the network prefix and the error threshold; every
+//! following 8 bytes are a key.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rmi_lib::{LearnedFIB, Model, RMITrainingData, RadixModel, RadixTable};
+use std::convert::TryInto;
+
+// enough keys to fill several buckets, but few enough to keep runs quick
+const MAX_KEYS: usize = 4096;
+
+// `pred` is within the model's error bound of every trained key's position
+fn check_bound(model: &dyn Model, keys: &[u64], positions: &[usize]) {
+    let err = model.error_bound().unwrap();
+    for (key, pos) in keys.iter().zip(positions.iter()) {
+        let pred = model.predict_to_int(&(*key).into());
+        let dist = u64::max(pred, *pos as u64) - u64::min(pred, *pos as u64);
+        assert!(dist <= err, "{} predicted {} for key {} at {}, error bound {}",
+                model.function_name(), pred, key, pos, err);
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 3 {
+        return;
+    }
+    let bits = data[0] % 17;
+    let prefix = (data[1] % 9) as u64;
+    let threshold = 1 + (data[2] % 64) as u64;
+
+    let mut keys: Vec<u64> = data[3..]
+        .chunks_exact(8)
+        .take(MAX_KEYS)
+        .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()))
+        .collect();
+    keys.sort_unstable();
+    let md = RMITrainingData::from_keys(keys.clone());
+    // copies of a key share the position of the first
+    let positions: Vec<usize> = md.iter().map(|(_, pos)| pos).collect();
+    let n = keys.len() as u64;
+
+    // the keys, their neighbours and both ends of the key space
+    let queries: Vec<u64> = keys.iter()
+        .flat_map(|k| [k.wrapping_sub(1), *k, k.wrapping_add(1)])
+        .chain([0, u64::MAX])
+        .collect();
+
+    let radix = RadixModel::new(&md);
+    for query in queries.iter() {
+        radix.predict_to_int(&(*query).into());
+    }
+    check_bound(&radix, &keys, &positions);
+
+    let table = RadixTable::new(&md, bits);
+    for query in queries.iter() {
+        let pred = table.predict_to_int(&(*query).into());
+        assert!(pred >= table.min_value() && pred <= n, "table predicted {} of {}", pred, n);
+        let (lo, hi) = table.predict_window(&(*query).into());
+        assert!(lo <= hi && hi <= n, "table window {}..{} of {}", lo, hi, n);
+    }
+    check_bound(&table, &keys, &positions);
+
+    let fib = LearnedFIB::new(&md, threshold, prefix);
+    for query in queries.iter() {
+        fib.predict_to_int(&(*query).into());
+    }
+    check_bound(&fib, &keys, &positions);
+});
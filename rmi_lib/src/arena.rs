@@ -0,0 +1,440 @@
+// < begin copyright >
+// Copyright Ryan Marcus 2020
+//
+// See root directory of this project for license terms.
+//
+// < end copyright >
+
+//! A shared, fixed-size buffer that models keep their parameters in.
+//!
+//! A model moved into a `ParamArena` (with `Model::move_into`, or built by
+//! `RadixTable::new_in` or `train_in`) appends its `Model::state` to the
+//! arena and then reads its parameter arrays from there, dropping its own
+//! copies. Arenas are handles: clones share one buffer, and every model
+//! living in it holds one. Allocation only bumps an offset, and the buffer
+//! never moves or grows, so once full, models simply keep their own
+//! arrays. Each model's parameters are laid out as by `flatten_params`,
+//! except that every array starts on a 64-byte line of its own.
+//!
+//! The whole arena serializes with one copy of its buffer plus a small
+//! index:
+//!
+//! ```text
+//! magic "RMIA" | count: u64 | count entries | buffer len: u64 | buffer
+//! ```
+//!
+//! where an entry is its kind tag, start offset, and parameter layout (all
+//! integers little endian).
+
+use crate::models::{model_from_state, ModelParam, ParamField, ParamKind};
+use crate::models::{Model, ModelKind, RadixTable, RelativeRadixTable};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use crate::error::Error;
+use std::alloc::{self, Layout};
+use std::io::{Cursor, Read};
+use std::ptr::NonNull;
+use std::slice;
+use std::sync::{Arc, Mutex};
+
+const MAGIC: &[u8; 4] = b"RMIA";
+
+const PARAM_KINDS: [ParamKind; 6] = [
+    ParamKind::Int, ParamKind::Float, ParamKind::ShortArray,
+    ParamKind::IntArray, ParamKind::Int32Array, ParamKind::FloatArray,
+];
+
+// what every entry and parameter array is aligned to
+const LINE_BYTES: usize = 64;
+
+fn corrupt(reason: String) -> Error {
+    return Error::Corrupt { reason };
+}
+
+fn is_array(kind: ParamKind) -> bool {
+    return !matches!(kind, ParamKind::Int | ParamKind::Float);
+}
+
+// Where each of `params` goes, from the start of an entry, and the size of
+// the entry.
+fn aligned_layout(params: &[ModelParam]) -> (Vec<ParamField>, usize) {
+    let mut offset: usize = 0;
+    let fields = params.iter()
+        .map(|p| {
+            if is_array(p.kind()) {
+                offset = offset.next_multiple_of(LINE_BYTES);
+            }
+            let field = ParamField { kind: p.kind(), offset, len: p.len() };
+            offset += p.size();
+            field
+        })
+        .collect();
+    return (fields, offset);
+}
+
+struct ArenaEntry {
+    kind: ModelKind,
+    start: usize,
+    fields: Vec<ParamField>,
+}
+
+struct Index {
+    used: usize,
+    entries: Vec<ArenaEntry>,
+}
+
+// The buffer, zeroed when allocated. Bytes below `used` are written once,
+// while the index is locked, and only read after that; writes only go to
+// the bytes past it.
+struct Storage {
+    buf: NonNull<u8>,
+    capacity: usize,
+    index: Mutex<Index>,
+}
+
+// the buffer is only reached through the rules above
+unsafe impl Send for Storage {}
+unsafe impl Sync for Storage {}
+
+impl Storage {
+    fn layout(capacity: usize) -> Layout {
+        return Layout::from_size_align(usize::max(capacity, LINE_BYTES), LINE_BYTES).unwrap();
+    }
+}
+
+impl Drop for Storage {
+    fn drop(&mut self) {
+        unsafe { alloc::dealloc(self.buf.as_ptr(), Storage::layout(self.capacity)) };
+    }
+}
+
+/// One contiguous allocation holding the parameters of many models.
+#[derive(Clone)]
+pub struct ParamArena {
+    storage: Arc<Storage>,
+}
+
+impl ParamArena {
+    /// An arena that holds up to `bytes` bytes of parameters.
+    pub fn with_capacity(bytes: usize) -> ParamArena {
+        let capacity = bytes.next_multiple_of(LINE_BYTES);
+        let layout = Storage::layout(capacity);
+        let buf = NonNull::new(unsafe { alloc::alloc_zeroed(layout) })
+            .unwrap_or_else(|| alloc::handle_alloc_error(layout));
+        let index = Mutex::new(Index { used: 0, entries: Vec::new() });
+        return ParamArena { storage: Arc::new(Storage { buf, capacity, index }) };
+    }
+
+    /// Appends a model's `state` as a new entry and returns its index, or
+    /// `None` if it does not fit. Models only use the stored arrays as they
+    /// are, which takes a little-endian machine; elsewhere this is always
+    /// `None`.
+    pub(crate) fn push(&self, kind: ModelKind, params: &[ModelParam]) -> Option<usize> {
+        if cfg!(target_endian = "big") {
+            return None;
+        }
+        let (fields, size) = aligned_layout(params);
+        let mut index = self.storage.index.lock().unwrap();
+        let start = index.used;
+        if size > self.storage.capacity - start {
+            return None;
+        }
+
+        // past `used`, so no one else reads or writes these bytes
+        let region = unsafe {
+            slice::from_raw_parts_mut(self.storage.buf.as_ptr().add(start), size)
+        };
+        for (param, field) in params.iter().zip(fields.iter()) {
+            // writes into a slice of the param's own size cannot fail
+            param.write_to(&mut &mut region[field.offset..]).unwrap();
+        }
+        // the capacity is whole lines, so this stays inside it
+        index.used = (start + size).next_multiple_of(LINE_BYTES);
+        index.entries.push(ArenaEntry { kind, start, fields });
+        return Some(index.entries.len() - 1);
+    }
+
+    /// Where the `param`th parameter of entry `idx` starts in the buffer,
+    /// and how many elements it has.
+    pub(crate) fn param_location(&self, idx: usize, param: usize) -> (usize, usize) {
+        let index = self.storage.index.lock().unwrap();
+        let entry = &index.entries[idx];
+        let field = &entry.fields[param];
+        return (entry.start + field.offset, field.len);
+    }
+
+    /// A pointer to byte `offset` of the buffer. What it points to never
+    /// changes below `used_bytes`. Lookups go through here, so it takes no
+    /// lock; tables check their bounds once, when they are attached.
+    pub(crate) fn ptr(&self, offset: usize) -> *const u8 {
+        assert!(offset <= self.storage.capacity);
+        return unsafe { self.storage.buf.as_ptr().add(offset) };
+    }
+
+    pub fn len(&self) -> usize {
+        return self.storage.index.lock().unwrap().entries.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.len() == 0;
+    }
+
+    /// How many bytes the arena can hold.
+    pub fn capacity(&self) -> usize {
+        return self.storage.capacity;
+    }
+
+    /// How many bytes the models allocated so far take, with padding.
+    pub fn used_bytes(&self) -> usize {
+        return self.storage.index.lock().unwrap().used;
+    }
+
+    /// The part of the buffer every model allocated so far lives in.
+    pub fn as_bytes(&self) -> &[u8] {
+        let used = self.used_bytes();
+        // bytes below `used` are never written again
+        return unsafe { slice::from_raw_parts(self.storage.buf.as_ptr(), used) };
+    }
+
+    pub fn kind(&self, idx: usize) -> ModelKind {
+        return self.storage.index.lock().unwrap().entries[idx].kind;
+    }
+
+    /// The parameters of the `idx`th model, read out of the shared buffer.
+    pub fn params(&self, idx: usize) -> Vec<ModelParam> {
+        let (start, fields) = {
+            let index = self.storage.index.lock().unwrap();
+            let entry = &index.entries[idx];
+            (entry.start, entry.fields.clone())
+        };
+        let buf = &self.as_bytes()[start..];
+        return fields.iter().map(|f| f.read(buf)).collect();
+    }
+
+    /// Rebuilds the `idx`th model from its parameters. Radix tables read
+    /// their arrays from the arena, like the models it was built from;
+    /// other kinds get their own copy.
+    pub fn model(&self, idx: usize) -> Option<Box<dyn Model>> {
+        let (kind, params) = (self.kind(idx), self.params(idx));
+        return match kind {
+            ModelKind::RadixTable => {
+                let mut table = RadixTable::from_state(&params)?;
+                table.attach(self, idx);
+                Some(Box::new(table))
+            }
+            ModelKind::RelativeRadixTable => {
+                let mut table = RelativeRadixTable::from_state(&params)?;
+                table.attach(self, idx);
+                Some(Box::new(table))
+            }
+            _ => model_from_state(kind, &params),
+        };
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let buf = self.as_bytes();
+        let index = self.storage.index.lock().unwrap();
+        let mut out = Vec::with_capacity(buf.len() + 64 * index.entries.len() + 16);
+        out.extend_from_slice(MAGIC);
+        // writes into a Vec<u8> cannot fail
+        out.write_u64::<LittleEndian>(index.entries.len() as u64).unwrap();
+        for entry in index.entries.iter() {
+            let tag = entry.kind.tag();
+            out.write_u64::<LittleEndian>(tag.len() as u64).unwrap();
+            out.extend_from_slice(tag.as_bytes());
+            out.write_u64::<LittleEndian>(entry.start as u64).unwrap();
+            out.write_u64::<LittleEndian>(entry.fields.len() as u64).unwrap();
+            for field in entry.fields.iter() {
+                let kind = PARAM_KINDS.iter().position(|k| *k == field.kind).unwrap();
+                out.push(kind as u8);
+                out.write_u64::<LittleEndian>(field.offset as u64).unwrap();
+                out.write_u64::<LittleEndian>(field.len as u64).unwrap();
+            }
+        }
+        out.write_u64::<LittleEndian>(buf.len() as u64).unwrap();
+        out.extend_from_slice(buf);
+        return out;
+    }
+
+    /// Loads an arena written by `to_bytes` into one just big enough for
+    /// it, checking that every parameter lies inside the buffer, with its
+    /// arrays on lines of their own.
+    pub fn from_bytes(bytes: &[u8]) -> Result<ParamArena, Error> {
+        if bytes.len() < MAGIC.len() || &bytes[0..4] != MAGIC {
+            return Err(corrupt(String::from("not a serialized parameter arena")));
+        }
+
+        let mut cur = Cursor::new(&bytes[4..]);
+        let count = cur.read_u64::<LittleEndian>()?;
+        let mut entries = Vec::new();
+        for _ in 0..count {
+            let tag_len = cur.read_u64::<LittleEndian>()?;
+            let mut tag = Vec::new();
+            (&mut cur).take(tag_len).read_to_end(&mut tag)?;
+            let tag = String::from_utf8(tag).map_err(|e| corrupt(e.to_string()))?;
+            let kind = ModelKind::from_tag(&tag)
+                .ok_or_else(|| corrupt(format!("unknown model kind {}", tag)))?;
+            let start = cur.read_u64::<LittleEndian>()? as usize;
+
+            let num_fields = cur.read_u64::<LittleEndian>()?;
+            let mut fields = Vec::new();
+            for _ in 0..num_fields {
+                let kind = *PARAM_KINDS.get(cur.read_u8()? as usize)
+                    .ok_or_else(|| corrupt(String::from("unknown parameter kind")))?;
+                let offset = cur.read_u64::<LittleEndian>()? as usize;
+                let len = cur.read_u64::<LittleEndian>()? as usize;
+                fields.push(ParamField { kind, offset, len });
+            }
+            entries.push(ArenaEntry { kind, start, fields });
+        }
+
+        let buf_len = cur.read_u64::<LittleEndian>()?;
+        let mut buf = Vec::new();
+        (&mut cur).take(buf_len).read_to_end(&mut buf)?;
+        if buf.len() as u64 != buf_len {
+            return Err(corrupt(String::from("parameter buffer is truncated")));
+        }
+
+        for entry in entries.iter() {
+            for field in entry.fields.iter() {
+                let elem_size = match field.kind {
+                    ParamKind::ShortArray => 2,
+                    ParamKind::Int32Array => 4,
+                    _ => 8,
+                };
+                let begin = entry.start.checked_add(field.offset);
+                let end = field.len.checked_mul(elem_size)
+                    .and_then(|size| size.checked_add(begin?));
+                if end.map(|end| end > buf.len()).unwrap_or(true) {
+                    return Err(corrupt(format!("a {} parameter runs past the buffer",
+                                               entry.kind.tag())));
+                }
+                if is_array(field.kind) && !begin.unwrap().is_multiple_of(LINE_BYTES) {
+                    return Err(corrupt(format!("a {} array is not on a line of its own",
+                                               entry.kind.tag())));
+                }
+            }
+        }
+
+        let arena = ParamArena::with_capacity(buf.len());
+        // the new buffer is at least `buf.len()` bytes, and no one else has it
+        unsafe {
+            std::ptr::copy_nonoverlapping(buf.as_ptr(), arena.storage.buf.as_ptr(), buf.len());
+        }
+        *arena.storage.index.lock().unwrap() = Index { used: buf.len(), entries };
+        return Ok(arena);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{LinearModel, RMITrainingData};
+    use crate::train::train_in;
+
+    // where the arrays of a radix table are read from
+    fn array_ptrs(model: &dyn Model) -> Vec<*const u8> {
+        if let Some(table) = model.as_any().downcast_ref::<RadixTable>() {
+            return table.array_ptrs();
+        }
+        return model.as_any().downcast_ref::<RelativeRadixTable>().unwrap().array_ptrs();
+    }
+
+    #[test]
+    fn test_arena_round_trip() {
+        let data: Vec<(u64, usize)> = (0..1000u64).map(|i| (i * i, i as usize)).collect();
+        let data = RMITrainingData::new(Box::new(data));
+        let arena = ParamArena::with_capacity(1 << 16);
+        let mut models: Vec<Box<dyn Model>> = vec![
+            Box::new(RadixTable::new_in(&data, 8, &arena)),
+            Box::new(RadixTable::new_leaf(&data, 10)),
+            Box::new(RelativeRadixTable::new(&data, 9, 4)),
+            Box::new(LinearModel::new(&data)),
+        ];
+        assert_eq!(models[1].move_into(&arena), Some(1));
+        assert_eq!(models[2].move_into(&arena), Some(2));
+        // without arrays there is nothing to move
+        assert_eq!(models[3].move_into(&arena), None);
+        assert_eq!(arena.len(), 3);
+
+        // every table's arrays now live in the arena's one buffer
+        let buf = arena.as_bytes().as_ptr_range();
+        for model in models[..3].iter() {
+            assert!(array_ptrs(model.as_ref()).iter().all(|ptr| buf.contains(ptr)),
+                    "{} keeps its own arrays", model.function_name());
+        }
+
+        // the whole arena at once, then each model from its entry
+        let loaded = ParamArena::from_bytes(&arena.to_bytes()).unwrap();
+        assert_eq!(loaded.len(), 3);
+        assert_eq!(loaded.as_bytes(), arena.as_bytes());
+        let loaded_buf = loaded.as_bytes().as_ptr_range();
+        for (idx, model) in models[..3].iter().enumerate() {
+            let rebuilt = loaded.model(idx).unwrap();
+            assert!(array_ptrs(rebuilt.as_ref()).iter().all(|ptr| loaded_buf.contains(ptr)));
+            for (key, _) in data.iter_model_input() {
+                assert_eq!(rebuilt.predict_to_int(&key), model.predict_to_int(&key));
+            }
+        }
+
+        let bytes = arena.to_bytes();
+        assert!(ParamArena::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(matches!(ParamArena::from_bytes(b"RMIB"), Err(Error::Corrupt { .. })));
+    }
+
+    #[test]
+    fn test_full_arena() {
+        let data = RMITrainingData::from_keys((0..1000u64).map(|i| i * 3));
+        // a line of scalars, then 256 hints of 4 bytes, fits once
+        let arena = ParamArena::with_capacity(64 + 1024);
+
+        let fits = RadixTable::new_in(&data, 8, &arena);
+        assert_eq!(arena.len(), 1);
+        let mut full = RadixTable::new(&data, 8);
+        assert_eq!(full.move_into(&arena), None);
+        assert_eq!(arena.len(), 1);
+        for (key, _) in data.iter_model_input() {
+            assert_eq!(full.predict_to_int(&key), fits.predict_to_int(&key));
+        }
+    }
+
+    #[test]
+    fn test_retrain_moves_out() {
+        let keys: Vec<u64> = (0..1000u64).map(|i| i * 3).collect();
+        let arena = ParamArena::with_capacity(1 << 12);
+        let mut table = RadixTable::new_in(&RMITrainingData::from_keys(keys.clone()), 8, &arena);
+        let before = arena.as_bytes().to_vec();
+
+        // retraining writes the hints, so they are copied out first
+        let mut more = keys.clone();
+        more.push(3000);
+        let more = RMITrainingData::from_keys(more);
+        table.retrain(&more, 1000..1001).unwrap();
+        assert_eq!(arena.as_bytes(), &before[..]);
+        let buf = arena.as_bytes().as_ptr_range();
+        assert!(!array_ptrs(&table).iter().any(|ptr| buf.contains(ptr)));
+
+        let expected = RadixTable::new(&more, 8);
+        for (key, _) in more.iter_model_input() {
+            assert_eq!(table.predict_to_int(&key), expected.predict_to_int(&key));
+        }
+    }
+
+    #[test]
+    fn test_train_in() {
+        let keys: Vec<u64> = (0..10_000u64).map(|i| i * i).collect();
+        let data = RMITrainingData::from_keys(keys.clone());
+        let arena = ParamArena::with_capacity(1 << 20);
+        let expected = crate::train(&data, "radix,radix_leaf8", 64);
+        let rmi = train_in(&data, "radix,radix_leaf8", 64, &arena);
+
+        // the top model has no arrays, and each leaf is in the arena
+        assert_eq!(arena.len(), 64);
+        let buf = arena.as_bytes().as_ptr_range();
+        assert!(rmi.rmi[1].iter()
+                .all(|leaf| array_ptrs(leaf.as_ref()).iter().all(|ptr| buf.contains(ptr))));
+        for key in keys.iter() {
+            let key = (*key).into();
+            assert_eq!(rmi.predict(&key), expected.predict(&key));
+        }
+    }
+}
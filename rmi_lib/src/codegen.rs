@@ -17,14 +17,79 @@ use std::str;
 use crate::train::TrainedRMI;
 use std::fs::File;
 use std::io::BufWriter;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::fmt;
 
 
 enum LayerParams {
     Constant(usize, Vec<ModelParam>),
     Array(usize, usize, Vec<ModelParam>),
-    MixedArray(usize, usize, Vec<ModelParam>)
+    MixedArray(usize, usize, Vec<ModelParam>),
+
+    // models whose parameters differ in size from model to model, such as
+    // LearnedFIB leaves with networks of different sizes. The data starts
+    // with a table of the byte offset of every parameter of every model,
+    // `params_per_model` entries per model, followed by each model's block
+    // of parameters, each starting on 8 bytes.
+    VariableArray(usize, usize, Vec<ModelParam>)
+}
+
+// The offset table of a `VariableArray` of `params`, and its total size in
+// bytes.
+fn variable_offsets(params_per_model: usize, params: &[ModelParam]) -> (Vec<u64>, usize) {
+    assert!(params.len().is_multiple_of(params_per_model));
+    let mut offsets = Vec::with_capacity(params.len());
+    let mut end = 8 * params.len();
+    for param in params {
+        let offset = end.next_multiple_of(8);
+        offsets.push(offset as u64);
+        end = offset + param.size();
+    }
+    return (offsets, end);
+}
+
+// CRC-32 (the IEEE polynomial, as used by zlib and `crc32(1)`) of an
+// emitted data file's parameters. It is stored after them as 4 little-endian
+// bytes; `CRC32_CODE` computes the same checksum in the generated loader.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc: u32 = !0;
+    for b in bytes {
+        crc ^= u32::from(*b);
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB8_8320 & (!(crc & 1)).wrapping_add(1));
+        }
+    }
+    return !crc;
+}
+
+const CRC32_CODE: &str = "
+static uint32_t crc32(const char* data, size_t len) {
+  uint32_t crc = ~0u;
+  for (size_t i = 0; i < len; i++) {
+    crc ^= (unsigned char) data[i];
+    for (int b = 0; b < 8; b++) crc = (crc >> 1) ^ (0xEDB88320u & (0u - (crc & 1)));
+  }
+  return ~crc;
+}";
+
+/// Reads a data file written alongside a generated RMI, returning its
+/// parameters after checking them against the CRC-32 stored in the last
+/// 4 bytes of the file.
+pub fn read_data_file(path: &Path) -> Result<Vec<u8>, std::io::Error> {
+    let mut bytes = std::fs::read(path)?;
+    if bytes.len() < 4 {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData,
+                                       "data file is too short for its checksum"));
+    }
+
+    let stored = bytes.split_off(bytes.len() - 4);
+    let stored = u32::from_le_bytes([stored[0], stored[1], stored[2], stored[3]]);
+    if crc32(&bytes) != stored {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("checksum mismatch in data file {}", path.display())));
+    }
+    return Ok(bytes);
 }
 
 macro_rules! constant_name {
@@ -50,6 +115,16 @@ impl LayerParams {
         let first_param = params.first().unwrap();
         let mixed = !params.iter().all(|p| first_param.is_same_type(p));
 
+        // a parameter whose size differs between models cannot be found at
+        // a fixed stride
+        let (first_model, rest) = params.split_at(params_per_model);
+        let variable = rest.chunks(params_per_model).any(|model| {
+            model.iter().zip(first_model).any(|(a, b)| a.size() != b.size())
+        });
+        if variable {
+            return LayerParams::VariableArray(idx, params_per_model, params);
+        }
+
         if mixed {
             return LayerParams::MixedArray(idx, params_per_model, params);
         }
@@ -68,7 +143,8 @@ impl LayerParams {
                 for (p_idx, param) in params.iter().enumerate() {
                     writeln!(
                         target,
-                        "const {} {}{} = {};",
+                        "{}const {} {}{} = {};",
+                        if param.is_array() { "alignas(64) " } else { "" },
                         param.c_type(),
                         constant_name!(idx, p_idx),
                         param.c_type_mod(),
@@ -80,7 +156,7 @@ impl LayerParams {
             LayerParams::Array(idx, _, params) => {
                 write!(
                     target,
-                    "const {} {}[] = {{",
+                    "alignas(64) const {} {}[] = {{",
                     params[0].c_type(),
                     array_name!(idx)
                 )?;
@@ -93,7 +169,7 @@ impl LayerParams {
                 writeln!(target, "}};")?;
             },
 
-            LayerParams::MixedArray(_, _, _) => {
+            LayerParams::MixedArray(_, _, _) | LayerParams::VariableArray(_, _, _) => {
                 panic!("Cannot hardcode mixed array.");
             }
         };
@@ -107,7 +183,7 @@ impl LayerParams {
                 let array_size: usize = params.iter().map(|p| p.size()).sum();
                 return array_size >= 4 * 1024;
             },
-            LayerParams::MixedArray(_, _, _) => true,
+            LayerParams::MixedArray(_, _, _) | LayerParams::VariableArray(_, _, _) => true,
             LayerParams::Constant(_, _) => false,
         }; 
     }
@@ -116,7 +192,7 @@ impl LayerParams {
         assert!(self.requires_malloc());
         return match self {
             LayerParams::Array(_, _, params) => params[0].c_type(),
-            LayerParams::MixedArray(_, _, _) => "char",
+            LayerParams::MixedArray(_, _, _) | LayerParams::VariableArray(_, _, _) => "char",
             LayerParams::Constant(_, _) => panic!("No pointer type for constant params")
         };
     }
@@ -132,7 +208,7 @@ impl LayerParams {
                     let num_items: usize = params.iter().map(|p| p.len()).sum();
                     writeln!(
                         target,
-                        "{} {}[{}];",
+                        "alignas(64) {} {}[{}];",
                         params[0].c_type(),
                         array_name!(idx),
                         num_items
@@ -147,7 +223,7 @@ impl LayerParams {
                 }
             },
 
-            LayerParams::MixedArray(idx, _, _) => {
+            LayerParams::MixedArray(idx, _, _) | LayerParams::VariableArray(idx, _, _) => {
                 assert!(self.requires_malloc());
                 writeln!(
                     target,
@@ -176,6 +252,19 @@ impl LayerParams {
                 }
                 return Ok(());
             },
+            LayerParams::VariableArray(_idx, ppm, params) => {
+                let (offsets, _) = variable_offsets(*ppm, params);
+                for offset in offsets.iter() {
+                    target.write_all(&offset.to_le_bytes())?;
+                }
+                let mut written = 8 * offsets.len();
+                for (param, offset) in params.iter().zip(offsets.iter()) {
+                    target.write_all(&vec![0; *offset as usize - written])?;
+                    param.write_to(target)?;
+                    written = *offset as usize + param.size();
+                }
+                return Ok(());
+            },
             LayerParams::Constant(_, _) =>
                 panic!("Cannot write constant parameters to binary file.")
         };
@@ -184,7 +273,8 @@ impl LayerParams {
     fn params(&self) -> &[ModelParam] {
         return match self {
             LayerParams::Array(_, _, params) |
-            LayerParams::MixedArray(_, _, params)
+            LayerParams::MixedArray(_, _, params) |
+            LayerParams::VariableArray(_, _, params)
                 => params,
             LayerParams::Constant(_, params) => params
         };
@@ -193,7 +283,8 @@ impl LayerParams {
     fn index(&self) -> usize {
         return match self {
             LayerParams::Array(idx, _, _) |
-            LayerParams::MixedArray(idx, _, _)
+            LayerParams::MixedArray(idx, _, _) |
+            LayerParams::VariableArray(idx, _, _)
                 => *idx,
             LayerParams::Constant(idx, _) => *idx
         };
@@ -202,13 +293,17 @@ impl LayerParams {
     fn params_per_model(&self) -> usize {
         return match self {
             LayerParams::Array(_idx, ppm, _params) |
-            LayerParams::MixedArray(_idx, ppm, _params)
+            LayerParams::MixedArray(_idx, ppm, _params) |
+            LayerParams::VariableArray(_idx, ppm, _params)
                 => *ppm,
             LayerParams::Constant(_, params) => params.len()
         };
     }
 
     fn size(&self) -> usize {
+        if let LayerParams::VariableArray(_, ppm, params) = self {
+            return variable_offsets(*ppm, params).1;
+        }
         return self.params().iter().map(|p| p.size()).sum();
     }
 
@@ -232,7 +327,10 @@ impl LayerParams {
         parameter_index: usize
     ) -> Result<(), std::io::Error> {
 
-        if self.params()[0].is_array() {
+        // a mixed layer finds each array by its byte offset below
+        let mixed = matches!(self, LayerParams::MixedArray(_, _, _)
+                             | LayerParams::VariableArray(_, _, _));
+        if !mixed && self.params()[0].is_array() {
             assert_eq!(self.params().len(), 1,
                        "Layer params with array had more than one member.");
             write!(target, "{}", array_name!(self.index()))?;
@@ -276,10 +374,29 @@ impl LayerParams {
                                        model_index, bytes_per_model,
                                        offset);
                                        
-                write!(target, "*(({new_type}*) ({ptr_expr}))",
-                       new_type=c_type, ptr_expr=ptr_expr)?;
+                // arrays are passed as a pointer to their first element
+                if params[parameter_index].is_array() {
+                    write!(target, "(({new_type}*) ({ptr_expr}))",
+                           new_type=c_type, ptr_expr=ptr_expr)?;
+                } else {
+                    write!(target, "*(({new_type}*) ({ptr_expr}))",
+                           new_type=c_type, ptr_expr=ptr_expr)?;
+                }
                 
             }
+
+            LayerParams::VariableArray(idx, params_per_model, params) => {
+                // the parameter's byte offset comes from the table
+                let c_type = params[parameter_index].c_type();
+                let ptr_expr = format!("{arr} + ((uint64_t*) {arr})[{} * {} + {}]",
+                                       model_index, params_per_model, parameter_index,
+                                       arr=array_name!(idx));
+                if params[parameter_index].is_array() {
+                    write!(target, "(({}*) ({}))", c_type, ptr_expr)?;
+                } else {
+                    write!(target, "*(({}*) ({}))", c_type, ptr_expr)?;
+                }
+            }
         };
 
         return Result::Ok(());
@@ -326,6 +443,9 @@ impl fmt::Display for LayerParams {
                        idx, ppm, params.len(), self.requires_malloc()),
             LayerParams::MixedArray(idx, ppm, params) =>
                 write!(f, "MixedArray(idx: {}, ppm: {}, len: {}, malloc: {})",
+                       idx, ppm, params.len(), self.requires_malloc()),
+            LayerParams::VariableArray(idx, ppm, params) =>
+                write!(f, "VariableArray(idx: {}, ppm: {}, len: {}, malloc: {})",
                        idx, ppm, params.len(), self.requires_malloc())
                 
         }
@@ -343,6 +463,39 @@ fn params_for_layer(layer_idx: usize,
                             params);
 }
 
+// The parameters of every layer as they are emitted: the last layer's
+// errors zipped into its models' parameters when there is more than one,
+// followed by the cache fix table, if any.
+fn layer_params_for(rmi: &TrainedRMI, lle: &[u64]) -> Vec<LayerParams> {
+    let mut layer_params: Vec<LayerParams> = rmi.rmi
+        .iter()
+        .enumerate()
+        .map(|(layer_idx, models)| params_for_layer(layer_idx, models))
+        .collect();
+
+    if lle.len() > 1 {
+        let old_last = layer_params.pop().unwrap();
+        layer_params.push(old_last.with_zipped_errors(lle));
+    }
+
+    if rmi.cache_fix.is_some() {
+        let cfv: Vec<ModelParam> = rmi.cache_fix.as_ref().unwrap().1.iter()
+            .flat_map(|(mi, offset)| vec![(*mi).into(), (*offset).into()])
+            .collect();
+        let cache_fix_params = LayerParams::new(
+            layer_params.len(), true, 2, cfv
+        );
+
+        layer_params.push(cache_fix_params);
+    }
+
+    return layer_params;
+}
+
+fn data_file_name(namespace: &str, layer_idx: usize) -> String {
+    return format!("{}_{}", namespace, array_name!(layer_idx));
+}
+
 macro_rules! model_index_from_output {
     ($from: expr, $bound: expr, $needs_check: expr) => {
         match $from {
@@ -376,10 +529,10 @@ pub fn rmi_size(rmi: &TrainedRMI) -> u64 {
     // compute the RMI size (used in the header, compute here before consuming)
     let mut num_total_bytes = 0;
     for layer in rmi.rmi.iter() {
-        let model_on_this_layer_size: usize = layer[0].params().iter().map(|p| p.size()).sum();
-        
-        // assume all models on this layer have the same size
-        num_total_bytes += model_on_this_layer_size * layer.len();
+        // models on one layer may differ in size, as LearnedFIB leaves do
+        num_total_bytes += layer.iter()
+            .map(|m| m.params().iter().map(|p| p.size()).sum::<usize>())
+            .sum::<usize>();
     }
 
     if !rmi.last_layer_max_l1s.is_empty() {
@@ -396,7 +549,8 @@ pub fn rmi_size(rmi: &TrainedRMI) -> u64 {
 fn generate_cache_fix_code<T: Write>(
     target: &mut T,
     rmi: &TrainedRMI,
-    array_name: String) -> Result<(), std::io::Error> {
+    array_name: String,
+    position_type: &str) -> Result<(), std::io::Error> {
 
     let num_splines = rmi.cache_fix.as_ref().unwrap().1.len();
     let line_size = rmi.cache_fix.as_ref().unwrap().0;
@@ -409,7 +563,7 @@ struct __attribute__((packed)) SplinePoint {{
   uint64_t value;
 }};
 
-uint64_t lookup(uint64_t key, size_t* err) {{
+{pos} lookup(uint64_t key, size_t* err) {{
   const uint64_t num_spline_pts = {};
   const uint64_t total_keys = {};
   size_t error_on_spline_search;
@@ -441,12 +595,59 @@ uint64_t lookup(uint64_t key, size_t* err) {{
   auto v1 = (double)pt2.value;
   auto t = ((double)(key - pt1.key)) / (double)(pt2.key - pt1.key);
   return (((uint64_t) std::fma(1.0 - t, v0, t * v1)) / {3}) * {3};
-}}", num_splines, total_keys, array_name, line_size)?;
+}}", num_splines, total_keys, array_name, line_size, pos = position_type)?;
     
 
     return Ok(());
 }
 
+// The C++ types `lookup` takes its key as and returns its position as.
+#[derive(Clone, Copy)]
+struct LookupTypes {
+    key: KeyType,
+    narrow_positions: bool,
+}
+
+impl LookupTypes {
+    fn position(&self) -> &'static str {
+        return if self.narrow_positions { "uint32_t" } else { "uint64_t" };
+    }
+}
+
+// `lookup_batch`, which looks up `n` keys in one call. Its pointers are
+// `__restrict` so the compiler may vectorize the loop, so `keys`, `out` and
+// `errs` must not overlap.
+fn generate_batch_code<T: Write>(
+    code_output: &mut T,
+    header_output: &mut T,
+    namespace: &str,
+    types: LookupTypes,
+    with_errors: bool) -> Result<(), std::io::Error> {
+
+    let (err_param, err_arg) = if with_errors {
+        (", size_t* __restrict errs", ", &errs[i]")
+    } else {
+        ("", "")
+    };
+    let batch_sig = format!(
+        "void lookup_batch(const {}* __restrict keys, size_t n, {}* __restrict out{})",
+        types.key.c_type(), types.position(), err_param);
+
+    writeln!(code_output, "
+namespace {} {{
+{} {{
+  for (size_t i = 0; i < n; i++)
+    out[i] = lookup(keys[i]{});
+}}
+}} // namespace", namespace, batch_sig, err_arg)?;
+
+    writeln!(header_output, "namespace {} {{", namespace)?;
+    writeln!(header_output, "// keys, out and errs must not overlap")?;
+    writeln!(header_output, "{};", batch_sig)?;
+    writeln!(header_output, "}}")?;
+    return Ok(());
+}
+
 fn generate_code<T: Write>(
     code_output: &mut T,
     data_output: &mut T,
@@ -454,14 +655,11 @@ fn generate_code<T: Write>(
     namespace: &str,
     rmi: TrainedRMI,
     data_dir: &str,
-    key_type: KeyType
+    types: LookupTypes
 ) -> Result<(), std::io::Error> {
+    let key_type = types.key;
     // construct the code for the model parameters.
-    let mut layer_params: Vec<LayerParams> = rmi.rmi
-        .iter()
-        .enumerate()
-        .map(|(layer_idx, models)| params_for_layer(layer_idx, models))
-        .collect();
+    let layer_params = layer_params_for(&rmi, &rmi.last_layer_max_l1s);
     
     let report_last_layer_errors = !rmi.last_layer_max_l1s.is_empty();
 
@@ -469,32 +667,16 @@ fn generate_code<T: Write>(
     if report_last_layer_errors {
         let lle = &rmi.last_layer_max_l1s;
         if lle.len() > 1 {
-            let old_last = layer_params.pop().unwrap();
-            let new_last = old_last.with_zipped_errors(lle);
-            
+            let last = &layer_params[rmi.rmi.len() - 1];
             write!(report_lle, "  *err = ")?;
-            new_last.access_by_ref(&mut report_lle, "modelIndex",
-                                   new_last.params_per_model() - 1)?;
+            last.access_by_ref(&mut report_lle, "modelIndex",
+                               last.params_per_model() - 1)?;
             writeln!(report_lle, ";")?;
-            
-            layer_params.push(new_last);
-            
         } else {
             write!(report_lle, "  *err = {};", lle[0])?;
         }
     }
 
-    if rmi.cache_fix.is_some() {
-        let cfv: Vec<ModelParam> = rmi.cache_fix.as_ref().unwrap().1.iter()
-            .flat_map(|(mi, offset)| vec![(*mi).into(), (*offset).into()])
-            .collect();
-        let cache_fix_params = LayerParams::new(
-            layer_params.len(), true, 2, cfv
-        );
-
-        layer_params.push(cache_fix_params);
-    }
-
     trace!("Layer parameters:");
     for lps in layer_params.iter() {
         trace!("{}", lps);
@@ -511,14 +693,19 @@ fn generate_code<T: Write>(
             LayerParams::Constant(_idx, _) => lp.to_code(data_output)?,
             
             LayerParams::Array(idx, _, _) |
-            LayerParams::MixedArray(idx, _, _) => {
+            LayerParams::MixedArray(idx, _, _) |
+            LayerParams::VariableArray(idx, _, _) => {
                 let data_path = Path::new(&data_dir)
-                    .join(format!("{}_{}", namespace, array_name!(idx)));
+                    .join(data_file_name(namespace, *idx));
                 let f = File::create(data_path)
                     .expect("Could not write data file to RMI directory");
                 let mut bw = BufWriter::new(f);
-                
-                lp.write_to(&mut bw)?; // write to data file
+
+                // write to data file, followed by its checksum
+                let mut bytes = Vec::with_capacity(lp.size());
+                lp.write_to(&mut bytes)?;
+                bw.write_all(&bytes)?;
+                bw.write_all(&crc32(&bytes).to_le_bytes())?;
                 lp.to_decl(data_output)?; // write to source code
                 
                 read_code.push("  {".to_string());
@@ -533,7 +720,11 @@ fn generate_code<T: Write>(
                 }
                 read_code.push(format!("    infile.read((char*){fn}, {size});",
                                        fn=array_name!(idx), size=lp.size()));
+                read_code.push("    uint32_t stored_crc;".to_string());
+                read_code.push("    infile.read((char*)&stored_crc, sizeof(stored_crc));".to_string());
                 read_code.push("    if (!infile.good()) return false;".to_string());
+                read_code.push(format!("    if (crc32((const char*){fn}, {size}) != stored_crc) return false;",
+                                       fn=array_name!(idx), size=lp.size()));
                 read_code.push("  }".to_string());
             }
         }
@@ -548,7 +739,8 @@ fn generate_code<T: Write>(
     // generate free code
     for lp in layer_params.iter() {
         if !lp.requires_malloc() { continue; }
-        if let LayerParams::Array(idx, _, _) | LayerParams::MixedArray(idx, _, _) = lp {
+        if let LayerParams::Array(idx, _, _) | LayerParams::MixedArray(idx, _, _)
+            | LayerParams::VariableArray(idx, _, _) = lp {
             free_code.push(format!("    free({});", array_name!(idx)));
             continue;
         }
@@ -572,16 +764,24 @@ fn generate_code<T: Write>(
 
     writeln!(code_output, "#include \"{}.h\"", namespace)?;
     writeln!(code_output, "#include \"{}_data.h\"", namespace)?;
-    writeln!(code_output, "#include <math.h>")?;
-    writeln!(code_output, "#include <cmath>")?;
-    writeln!(code_output, "#include <fstream>")?;
-    writeln!(code_output, "#include <filesystem>")?;
-    writeln!(code_output, "#include <iostream>")?;
+    let mut includes = vec!["<math.h>", "<cmath>", "<fstream>", "<filesystem>", "<iostream>"];
     if rmi.cache_fix.is_some() {
-        writeln!(code_output, "#include <algorithm>")?;
+        includes.push("<algorithm>");
+    }
+    // plus whatever the model code needs
+    for layer in rmi.rmi.iter() {
+        for include in layer[0].required_includes() {
+            if !includes.contains(&include) {
+                includes.push(include);
+            }
+        }
+    }
+    for include in includes {
+        writeln!(code_output, "#include {}", include)?;
     }
 
     writeln!(code_output, "namespace {} {{", namespace)?;
+    writeln!(code_output, "{}", CRC32_CODE)?;
 
     for ln in read_code {
         writeln!(code_output, "{}", ln)?;
@@ -625,9 +825,10 @@ inline size_t FCLAMP(double inp, double bound) {{
     };
     
     let lookup_sig = if report_last_layer_errors {
-        format!("uint64_t {}({} key, size_t* err)", rmi_lookup_name, key_type.c_type())
+        format!("{} {}({} key, size_t* err)", types.position(), rmi_lookup_name,
+                key_type.c_type())
     } else {
-        format!("uint64_t {}({} key)", rmi_lookup_name, key_type.c_type())
+        format!("{} {}({} key)", types.position(), rmi_lookup_name, key_type.c_type())
     };
     writeln!(code_output, "{} {{", lookup_sig)?;
 
@@ -710,15 +911,18 @@ inline size_t FCLAMP(double inp, double bound) {{
 
     writeln!(code_output, "{}", str::from_utf8(&report_lle).unwrap())?;
 
-    writeln!(
-        code_output,
-        "  return {};",
-        model_index_from_output!(last_model_output, rmi.num_rmi_rows, true)
-    )?; // always bounds check the last level
+    // always bounds check the last level
+    let position = model_index_from_output!(last_model_output, rmi.num_rmi_rows, true);
+    if types.narrow_positions {
+        writeln!(code_output, "  return (uint32_t) ({});", position)?;
+    } else {
+        writeln!(code_output, "  return {};", position)?;
+    }
     writeln!(code_output, "}}")?;
 
     if rmi.cache_fix.is_some() {
-        generate_cache_fix_code(code_output, &rmi, array_name!(layer_params.len()-1))?;
+        generate_cache_fix_code(code_output, &rmi, array_name!(layer_params.len()-1),
+                                types.position())?;
     }
     
     writeln!(code_output, "}} // namespace")?;
@@ -746,7 +950,7 @@ inline size_t FCLAMP(double inp, double bound) {{
     if rmi.cache_fix.is_none() {
         writeln!(header_output, "{};", lookup_sig)?;
     } else {
-        writeln!(header_output, "uint64_t lookup(uint64_t key, size_t* err);")?;
+        writeln!(header_output, "{} lookup(uint64_t key, size_t* err);", types.position())?;
     }
     writeln!(header_output, "}}")?;
 
@@ -754,35 +958,603 @@ inline size_t FCLAMP(double inp, double bound) {{
 }
 
 
+/// What `CodeGen::write` would emit for a trained RMI, as computed by
+/// `CodeGen::dry_run`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodeGenReport {
+    /// The model functions defined in the source file, in layer order.
+    pub functions: Vec<String>,
+
+    /// The parameter size in bytes of each model, by layer.
+    pub size_bytes: Vec<Vec<usize>>,
+
+    /// The name and size in bytes (checksum included) of each data file.
+    pub data_files: Vec<(String, u64)>,
+
+    /// The combined size of `data_files`.
+    pub total_data_bytes: u64,
+
+    /// The size of the RMI, as reported by the generated `RMI_SIZE`.
+    pub rmi_size: u64,
+
+    /// Whether `lookup` clamps the model index of any inner layer. The
+    /// final position is always clamped.
+    pub needs_bounds_check: bool,
+}
+
+/// Assembles a trained RMI into a complete translation unit: the source
+/// file (includes, standard and model functions, `load`/`cleanup` for the
+/// externally stored parameters, and the top-level `lookup` implementing the
+/// layer dispatch and error reporting), the data header, and the public
+/// header.
+pub struct CodeGen {
+    namespace: String,
+    output_dir: PathBuf,
+    data_dir: PathBuf,
+    key_type: KeyType,
+    include_errors: bool,
+    batch: bool,
+    narrow_positions: bool,
+}
+
+impl CodeGen {
+    pub fn new(namespace: &str, key_type: KeyType) -> CodeGen {
+        return CodeGen {
+            namespace: String::from(namespace),
+            output_dir: PathBuf::from("."),
+            data_dir: PathBuf::from("rmi_data"),
+            key_type,
+            include_errors: true,
+            batch: false,
+            narrow_positions: false,
+        };
+    }
+
+    /// Directory the `.cpp` and `.h` files are written to (default: the
+    /// current directory).
+    pub fn output_dir<P: AsRef<Path>>(mut self, dir: P) -> CodeGen {
+        self.output_dir = dir.as_ref().to_path_buf();
+        return self;
+    }
+
+    /// Directory the parameter data files are written to (default: `rmi_data`).
+    /// The generated `load` function must be given this directory at runtime.
+    pub fn data_dir<P: AsRef<Path>>(mut self, dir: P) -> CodeGen {
+        self.data_dir = dir.as_ref().to_path_buf();
+        return self;
+    }
+
+    /// When false, last-level errors are dropped and `lookup` no longer
+    /// takes an `err` out-parameter.
+    pub fn include_errors(mut self, include_errors: bool) -> CodeGen {
+        self.include_errors = include_errors;
+        return self;
+    }
+
+    /// When true, also emits `lookup_batch(keys, n, out)` (plus `errs` when
+    /// `lookup` reports errors), which writes the lookup of each of the `n`
+    /// keys to `out`. Its pointers are declared `__restrict`, so callers
+    /// must not pass overlapping arrays.
+    pub fn batch(mut self, batch: bool) -> CodeGen {
+        self.batch = batch;
+        return self;
+    }
+
+    /// When true, `lookup` (and `lookup_batch`) return positions as
+    /// `uint32_t` rather than `uint64_t`, matching
+    /// `TrainedRMI::predict_to_u32`. Generating fails for an RMI with more
+    /// than `u32::MAX` positions.
+    pub fn narrow_positions(mut self, narrow_positions: bool) -> CodeGen {
+        self.narrow_positions = narrow_positions;
+        return self;
+    }
+
+    pub fn generate<T: Write>(&self,
+                              mut trained_model: TrainedRMI,
+                              code_output: &mut T,
+                              data_output: &mut T,
+                              header_output: &mut T) -> Result<(), std::io::Error> {
+        if !self.include_errors {
+            trained_model.last_layer_max_l1s.clear();
+        }
+
+        let rust_only = trained_model.rmi.iter()
+            .find(|layer| matches!(layer[0].restriction(), ModelRestriction::RustOnlyBottom));
+        if let Some(layer) = rust_only {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("{} only predicts from Rust and has no generated code",
+                        layer[0].function_name())));
+        }
+
+        if self.narrow_positions && !trained_model.positions_fit_u32() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("cannot narrow the positions of an RMI over {} rows to 32 bits",
+                        trained_model.num_rmi_rows)));
+        }
+        let types = LookupTypes { key: self.key_type, narrow_positions: self.narrow_positions };
+
+        // the cache fix's `lookup` always reports its error
+        let with_errors = !trained_model.last_layer_max_l1s.is_empty()
+            || trained_model.cache_fix.is_some();
+
+        generate_code(
+            code_output,
+            data_output,
+            header_output,
+            &self.namespace,
+            trained_model,
+            self.data_dir.to_str().expect("RMI data directory must be valid UTF-8"),
+            types
+        )?;
+
+        if self.batch {
+            generate_batch_code(code_output, header_output, &self.namespace,
+                                types, with_errors)?;
+        }
+        return Ok(());
+    }
+
+    /// Reports the functions, sizes and data files `write` would produce for
+    /// `trained_model`, without touching the filesystem.
+    pub fn dry_run(&self, trained_model: &TrainedRMI) -> CodeGenReport {
+        let mut functions: Vec<String> = Vec::new();
+        for layer in trained_model.rmi.iter() {
+            let name = layer[0].function_name();
+            if !functions.contains(&name) {
+                functions.push(name);
+            }
+        }
+
+        let size_bytes = trained_model.rmi.iter()
+            .map(|layer| layer.iter()
+                 .map(|m| m.params().iter().map(|p| p.size()).sum())
+                 .collect())
+            .collect();
+
+        let lle: &[u64] = if self.include_errors {
+            &trained_model.last_layer_max_l1s
+        } else {
+            &[]
+        };
+        let layer_params = layer_params_for(trained_model, lle);
+
+        let data_files: Vec<(String, u64)> = layer_params.iter()
+            .filter(|lp| !matches!(lp, LayerParams::Constant(_, _)))
+            .map(|lp| (data_file_name(&self.namespace, lp.index()),
+                       lp.size() as u64 + 4))
+            .collect();
+        let total_data_bytes = data_files.iter().map(|(_, size)| size).sum();
+
+        // mirrors the index computation in `generate_code`
+        let mut needs_bounds_check = false;
+        let mut previous_needs_check = true;
+        for layer in trained_model.rmi.iter() {
+            needs_bounds_check |= layer.len() > 1 && previous_needs_check;
+            previous_needs_check = layer[0].needs_bounds_check();
+        }
+
+        // `generate` drops the errors before sizing the RMI
+        let mut size = rmi_size(trained_model);
+        if !self.include_errors && !trained_model.last_layer_max_l1s.is_empty() {
+            size -= trained_model.rmi.last().unwrap().len() as u64 * 8;
+        }
+
+        return CodeGenReport {
+            functions,
+            size_bytes,
+            data_files,
+            total_data_bytes,
+            rmi_size: size,
+            needs_bounds_check,
+        };
+    }
+
+    pub fn write(&self, trained_model: TrainedRMI) -> Result<(), std::io::Error> {
+        let out = |suffix: &str| self.output_dir.join(format!("{}{}", self.namespace, suffix));
+
+        let f1 = File::create(out(".cpp")).expect("Could not write RMI CPP file");
+        let mut bw1 = BufWriter::new(f1);
+
+        let f2 = File::create(out("_data.h")).expect("Could not write RMI data file");
+        let mut bw2 = BufWriter::new(f2);
+
+        let f3 = File::create(out(".h")).expect("Could not write RMI header file");
+        let mut bw3 = BufWriter::new(f3);
+
+        return self.generate(trained_model, &mut bw1, &mut bw2, &mut bw3);
+    }
+}
+
 pub fn output_rmi(namespace: &str,
-                  mut trained_model: TrainedRMI,
+                  trained_model: TrainedRMI,
                   data_dir: &str,
                   key_type: KeyType,
                   include_errors: bool) -> Result<(), std::io::Error> {
-    
-    let f1 = File::create(format!("{}.cpp", namespace)).expect("Could not write RMI CPP file");
-    let mut bw1 = BufWriter::new(f1);
-    
-    let f2 =
-        File::create(format!("{}_data.h", namespace)).expect("Could not write RMI data file");
-    let mut bw2 = BufWriter::new(f2);
-    
-    let f3 = File::create(format!("{}.h", namespace)).expect("Could not write RMI header file");
-    let mut bw3 = BufWriter::new(f3);
-
-    if !include_errors {
-        trained_model.last_layer_max_l1s.clear();
-    }
-
-    return generate_code(
-        &mut bw1,
-        &mut bw2,
-        &mut bw3,
-        namespace,
-        trained_model,
-        data_dir,
-        key_type
-    );
-        
-    
+    return CodeGen::new(namespace, key_type)
+        .data_dir(data_dir)
+        .include_errors(include_errors)
+        .write(trained_model);
+}
+
+#[cfg(test)]
+#[path = "cpp_test.rs"]
+pub(crate) mod cpp_test;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::process::Command;
+    use std::convert::TryInto;
+
+    fn test_keys() -> Vec<u64> {
+        return (0..20_000u64).map(|i| i * i + 7 * i + 1).collect();
+    }
+
+    #[test]
+    fn test_generated_rmi_compiles_and_answers() {
+        let keys = test_keys();
+        let data: Vec<(u64, usize)> = keys.iter()
+            .enumerate()
+            .map(|(idx, k)| (*k, idx))
+            .collect();
+        let rmi = crate::train(&RMITrainingData::new(Box::new(data)), "linear,linear", 128);
+
+        let dir = std::env::temp_dir()
+            .join(format!("rmi_codegen_test_{}", std::process::id()));
+        fs::create_dir_all(dir.join("rmi_data")).unwrap();
+
+        CodeGen::new("cgtest", KeyType::U64)
+            .output_dir(&dir)
+            .data_dir(dir.join("rmi_data"))
+            .write(rmi)
+            .unwrap();
+
+        fs::write(dir.join("main.cpp"), "
+#include <algorithm>
+#include <vector>
+#include \"cgtest.h\"
+
+int main() {
+  std::vector<uint64_t> keys;
+  for (uint64_t i = 0; i < 20000; i++) keys.push_back(i * i + 7 * i + 1);
+  if (!cgtest::load(\"rmi_data\")) return 2;
+
+  for (uint64_t key : keys) {
+    size_t err;
+    uint64_t guess = cgtest::lookup(key, &err);
+    uint64_t correct = std::lower_bound(keys.begin(), keys.end(), key) - keys.begin();
+    uint64_t diff = guess > correct ? guess - correct : correct - guess;
+    if (diff > err) return 1;
+  }
+
+  cgtest::cleanup();
+  return 0;
+}
+").unwrap();
+
+        let sources = [dir.join("main.cpp"), dir.join("cgtest.cpp")];
+        let ran = match cpp_test::compile_and_run(&sources, &["-std=c++17", "-O1"]) {
+            Some(status) => status,
+            None => {
+                fs::remove_dir_all(&dir).unwrap();
+                return;
+            }
+        };
+        assert!(ran.success(), "generated RMI gave a wrong lookup ({:?})", ran.code());
+
+        // a flipped byte in a data file makes `load` fail
+        let data_file = fs::read_dir(dir.join("rmi_data")).unwrap()
+            .next().unwrap().unwrap().path();
+        let mut bytes = fs::read(&data_file).unwrap();
+        bytes[3] ^= 0x10;
+        fs::write(&data_file, bytes).unwrap();
+        let ran = Command::new(dir.join("main"))
+            .current_dir(&dir)
+            .status()
+            .unwrap();
+        assert_eq!(ran.code(), Some(2), "corrupt data file was loaded");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_batch_lookup() {
+        let keys = test_keys();
+        let rmi = crate::train(&RMITrainingData::from_keys(keys), "linear,linear", 128);
+
+        let dir = std::env::temp_dir()
+            .join(format!("rmi_batch_test_{}", std::process::id()));
+        fs::create_dir_all(dir.join("rmi_data")).unwrap();
+
+        CodeGen::new("batchtest", KeyType::U64)
+            .output_dir(&dir)
+            .data_dir(dir.join("rmi_data"))
+            .batch(true)
+            .write(rmi)
+            .unwrap();
+        let header = fs::read_to_string(dir.join("batchtest.h")).unwrap();
+        assert!(header.contains("void lookup_batch(const uint64_t* __restrict keys, size_t n, \
+                                 uint64_t* __restrict out, size_t* __restrict errs);"));
+
+        // the batch must match one lookup at a time
+        fs::write(dir.join("main.cpp"), "
+#include <vector>
+#include \"batchtest.h\"
+
+int main() {
+  std::vector<uint64_t> keys;
+  for (uint64_t i = 0; i < 20000; i++) keys.push_back(i * i + 7 * i + 1);
+  if (!batchtest::load(\"rmi_data\")) return 2;
+
+  std::vector<uint64_t> out(keys.size());
+  std::vector<size_t> errs(keys.size());
+  batchtest::lookup_batch(keys.data(), keys.size(), out.data(), errs.data());
+
+  for (size_t i = 0; i < keys.size(); i++) {
+    size_t err;
+    if (batchtest::lookup(keys[i], &err) != out[i] || err != errs[i]) return 1;
+  }
+
+  batchtest::cleanup();
+  return 0;
+}
+").unwrap();
+
+        let sources = [dir.join("main.cpp"), dir.join("batchtest.cpp")];
+        let ran = match cpp_test::compile_and_run(&sources, &["-std=c++17", "-O3"]) {
+            Some(status) => status,
+            None => {
+                fs::remove_dir_all(&dir).unwrap();
+                return;
+            }
+        };
+        assert!(ran.success(), "batch lookup disagreed with lookup ({:?})", ran.code());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_narrow_positions() {
+        let keys = test_keys();
+        let train = || {
+            crate::train(&RMITrainingData::from_keys(keys.clone()), "linear,linear", 128)
+        };
+
+        let dir = std::env::temp_dir()
+            .join(format!("rmi_narrow_test_{}", std::process::id()));
+        fs::create_dir_all(dir.join("rmi_data")).unwrap();
+        for (namespace, narrow) in [("wide", false), ("narrow", true)] {
+            CodeGen::new(namespace, KeyType::U64)
+                .output_dir(&dir)
+                .data_dir(dir.join("rmi_data"))
+                .narrow_positions(narrow)
+                .batch(true)
+                .write(train())
+                .unwrap();
+        }
+        let header = fs::read_to_string(dir.join("narrow.h")).unwrap();
+        assert!(header.contains("uint32_t lookup(uint64_t key, size_t* err);"));
+
+        fs::write(dir.join("main.cpp"), "
+#include <vector>
+#include \"wide.h\"
+#include \"narrow.h\"
+
+int main() {
+  std::vector<uint64_t> keys;
+  for (uint64_t i = 0; i < 20000; i++) keys.push_back(i * i + 7 * i + 1);
+  if (!wide::load(\"rmi_data\") || !narrow::load(\"rmi_data\")) return 2;
+
+  std::vector<uint32_t> out(keys.size());
+  std::vector<size_t> errs(keys.size());
+  narrow::lookup_batch(keys.data(), keys.size(), out.data(), errs.data());
+  for (size_t i = 0; i < keys.size(); i++) {
+    size_t wide_err, narrow_err;
+    for (uint64_t key : {keys[i], keys[i] + 1}) {
+      uint64_t expected = wide::lookup(key, &wide_err);
+      uint32_t found = narrow::lookup(key, &narrow_err);
+      if (found != expected || narrow_err != wide_err) return 1;
+    }
+    if (out[i] != wide::lookup(keys[i], &wide_err) || errs[i] != wide_err) return 3;
+  }
+
+  wide::cleanup();
+  narrow::cleanup();
+  return 0;
+}
+").unwrap();
+
+        let sources = [dir.join("main.cpp"), dir.join("wide.cpp"), dir.join("narrow.cpp")];
+        let ran = match cpp_test::compile_and_run(&sources, &["-std=c++17", "-O1"]) {
+            Some(status) => status,
+            None => {
+                fs::remove_dir_all(&dir).unwrap();
+                return;
+            }
+        };
+        assert!(ran.success(), "32-bit positions differ from 64-bit ones ({:?})", ran.code());
+        fs::remove_dir_all(&dir).unwrap();
+
+        // too many rows for 32 bits
+        let mut rmi = train();
+        rmi.num_rmi_rows = u32::MAX as usize + 2;
+        let (mut code, mut data, mut header) = (Vec::new(), Vec::new(), Vec::new());
+        let err = CodeGen::new("toobig", KeyType::U64)
+            .narrow_positions(true)
+            .generate(rmi, &mut code, &mut data, &mut header)
+            .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_short_array_params() {
+        let keys = test_keys();
+        let rmi = crate::train(&RMITrainingData::from_keys(keys), "radix_rel18,linear", 128);
+        // a top layer routing into 128 leaves has offsets that fit 16 bits
+        assert!(matches!(rmi.rmi[0][0].params()[1], ModelParam::ShortArray(_)));
+
+        let dir = std::env::temp_dir()
+            .join(format!("rmi_short_test_{}", std::process::id()));
+        fs::create_dir_all(dir.join("rmi_data")).unwrap();
+        CodeGen::new("shorttest", KeyType::U64)
+            .output_dir(&dir)
+            .data_dir(dir.join("rmi_data"))
+            .write(rmi)
+            .unwrap();
+        let code = fs::read_to_string(dir.join("shorttest.cpp")).unwrap();
+        assert!(code.contains("const uint16_t* offsets"));
+
+        fs::write(dir.join("main.cpp"), "
+#include <algorithm>
+#include <vector>
+#include \"shorttest.h\"
+
+int main() {
+  std::vector<uint64_t> keys;
+  for (uint64_t i = 0; i < 20000; i++) keys.push_back(i * i + 7 * i + 1);
+  if (!shorttest::load(\"rmi_data\")) return 2;
+
+  for (uint64_t key : keys) {
+    size_t err;
+    uint64_t guess = shorttest::lookup(key, &err);
+    uint64_t correct = std::lower_bound(keys.begin(), keys.end(), key) - keys.begin();
+    uint64_t diff = guess > correct ? guess - correct : correct - guess;
+    if (diff > err) return 1;
+  }
+
+  shorttest::cleanup();
+  return 0;
+}
+").unwrap();
+
+        let sources = [dir.join("main.cpp"), dir.join("shorttest.cpp")];
+        let ran = match cpp_test::compile_and_run(&sources, &["-std=c++17", "-O1"]) {
+            Some(status) => status,
+            None => {
+                fs::remove_dir_all(&dir).unwrap();
+                return;
+            }
+        };
+        assert!(ran.success(), "RMI with 16-bit offsets gave a wrong lookup ({:?})", ran.code());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_variable_array_params() {
+        // two models whose second parameter differs in size
+        let params: Vec<ModelParam> = vec![
+            3u64.into(), vec![1.0, 2.0].into(),
+            4u64.into(), vec![3.0, 4.0, 5.0].into(),
+        ];
+        let layer = LayerParams::new(1, true, 2, params.clone());
+        assert!(matches!(layer, LayerParams::VariableArray(1, 2, _)));
+
+        let mut bytes = Vec::new();
+        layer.write_to(&mut bytes).unwrap();
+        assert_eq!(bytes.len(), layer.size());
+        for (idx, param) in params.iter().enumerate() {
+            let offset = u64::from_le_bytes(bytes[8 * idx..8 * idx + 8].try_into().unwrap());
+            assert!(offset.is_multiple_of(8));
+            let mut expected = Vec::new();
+            param.write_to(&mut expected).unwrap();
+            let offset = offset as usize;
+            assert_eq!(&bytes[offset..offset + param.size()], &expected[..]);
+        }
+
+        let mut access = Vec::new();
+        layer.access_by_ref(&mut access, "modelIndex", 1).unwrap();
+        assert_eq!(str::from_utf8(&access).unwrap(),
+                   "((double*) (L1_PARAMETERS + ((uint64_t*) L1_PARAMETERS)[modelIndex * 2 + 1]))");
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn test_data_file_checksum() {
+        let data: Vec<(u64, usize)> = test_keys().into_iter().zip(0..).collect();
+        let rmi = crate::train(&RMITrainingData::new(Box::new(data)), "linear,linear", 64);
+
+        let dir = std::env::temp_dir()
+            .join(format!("rmi_checksum_test_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        CodeGen::new("cstest", KeyType::U64)
+            .output_dir(&dir)
+            .data_dir(&dir)
+            .write(rmi)
+            .unwrap();
+
+        let data_file = dir.join("cstest_L1_PARAMETERS");
+        let params = read_data_file(&data_file).unwrap();
+        assert_eq!(params.len() + 4, fs::metadata(&data_file).unwrap().len() as usize);
+
+        for flip in [0, params.len() / 2, params.len() + 1] {
+            let mut bytes = fs::read(&data_file).unwrap();
+            bytes[flip] ^= 1;
+            let corrupt = dir.join("corrupt");
+            fs::write(&corrupt, bytes).unwrap();
+            let err = read_data_file(&corrupt).err().unwrap();
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_dry_run_matches_output() {
+        let data: Vec<(u64, usize)> = test_keys().into_iter().zip(0..).collect();
+        let data = RMITrainingData::new(Box::new(data));
+
+        for (models, include_errors) in [("cubic,linear", true), ("linear,linear", false)] {
+            let dir = std::env::temp_dir()
+                .join(format!("rmi_dry_run_test_{}_{}", include_errors, std::process::id()));
+            fs::create_dir_all(&dir).unwrap();
+            let codegen = CodeGen::new("drtest", KeyType::U64)
+                .output_dir(&dir)
+                .data_dir(dir.join("rmi_data"))
+                .include_errors(include_errors);
+
+            let rmi = crate::train(&data, models, 256);
+            let report = codegen.dry_run(&rmi);
+            assert!(!dir.join("rmi_data").exists(), "dry run touched the filesystem");
+
+            assert_eq!(report.size_bytes.len(), 2);
+            assert_eq!(report.size_bytes[1], vec![16; 256]);
+
+            fs::create_dir_all(dir.join("rmi_data")).unwrap();
+            codegen.write(rmi).unwrap();
+
+            let mut written: Vec<(String, u64)> = fs::read_dir(dir.join("rmi_data")).unwrap()
+                .map(|entry| {
+                    let entry = entry.unwrap();
+                    (entry.file_name().into_string().unwrap(),
+                     entry.metadata().unwrap().len())
+                })
+                .collect();
+            written.sort();
+            let mut reported = report.data_files.clone();
+            reported.sort();
+            assert_eq!(reported, written);
+            assert_eq!(report.total_data_bytes, written.iter().map(|(_, len)| len).sum::<u64>());
+
+            let code = fs::read_to_string(dir.join("drtest.cpp")).unwrap();
+            for function in report.functions.iter() {
+                assert!(code.contains(&format!(" {}(", function)), "{} not emitted", function);
+            }
+            assert_eq!(report.needs_bounds_check, code.contains("modelIndex = FCLAMP"));
+            // cubic clamps its own output, linear does not
+            assert_eq!(report.needs_bounds_check, models == "linear,linear");
+
+            let header = fs::read_to_string(dir.join("drtest.h")).unwrap();
+            assert!(header.contains(&format!("RMI_SIZE = {};", report.rmi_size)));
+
+            fs::remove_dir_all(&dir).unwrap();
+        }
+    }
 }
@@ -0,0 +1,184 @@
+// < begin copyright >
+// Copyright Ryan Marcus 2020
+//
+// See root directory of this project for license terms.
+//
+// < end copyright >
+
+//! Training data read from an Arrow `UInt64Array` or a Parquet column of
+//! sorted `u64` keys.
+//!
+//! An Arrow array is used as it is: the training data shares its value
+//! buffer rather than copying it. A Parquet column is decoded batch by
+//! batch into one buffer, which is then used the same way.
+
+use crate::error::Error;
+use crate::models::{BorrowedRecords, KeyType, RMITrainingData, RMITrainingDataIteratorProvider};
+use arrow::array::{Array, UInt64Array};
+use arrow::buffer::ScalarBuffer;
+use arrow::datatypes::DataType;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use parquet::arrow::ProjectionMask;
+use std::fs::File;
+use std::path::Path;
+
+// Sorted keys in an Arrow buffer, each at its index as its position.
+struct ArrowKeys(ScalarBuffer<u64>);
+
+impl RMITrainingDataIteratorProvider for ArrowKeys {
+    type InpType = u64;
+    fn len(&self) -> usize {
+        return self.0.len();
+    }
+
+    fn cdf_iter(&self) -> Box<dyn Iterator<Item = (u64, usize)> + '_> {
+        return Box::new(self.0.iter().cloned().zip(0..));
+    }
+
+    fn key_type(&self) -> KeyType {
+        return KeyType::U64;
+    }
+
+    fn get(&self, idx: usize) -> Option<(u64, usize)> {
+        return self.0.get(idx).map(|key| (*key, idx));
+    }
+
+    fn cdf_iter_borrowed(&self, from: usize) -> Option<BorrowedRecords<'_, u64>> {
+        return Some(Box::new(self.0[from..].iter().zip(from..)));
+    }
+}
+
+fn invalid(reason: String) -> Error {
+    return Error::InvalidArgument { reason };
+}
+
+fn corrupt(e: impl ToString) -> Error {
+    return Error::Corrupt { reason: e.to_string() };
+}
+
+impl RMITrainingData<u64> {
+    /// Training data for the sorted keys of `array`, each at its index as
+    /// its position, like `from_keys`. The data shares the array's buffer
+    /// instead of copying it. Returns `Error::InvalidArgument` if the array
+    /// has nulls, and `Error::Unsorted` with the index of the first key
+    /// smaller than the one before it.
+    pub fn from_arrow(array: &UInt64Array) -> Result<RMITrainingData<u64>, Error> {
+        if array.null_count() > 0 {
+            return Err(invalid(format!("key array has {} nulls", array.null_count())));
+        }
+        let keys = array.values().clone();
+        if let Some(idx) = keys.windows(2).position(|pair| pair[1] < pair[0]) {
+            return Err(Error::Unsorted { index: idx + 1 });
+        }
+        return Ok(RMITrainingData::new(Box::new(ArrowKeys(keys))));
+    }
+
+    /// Training data for the sorted keys in `column` of the Parquet file at
+    /// `path`, which must be a `UInt64` column. Only that column is read,
+    /// a batch at a time, into one buffer used as by `from_arrow`. A column
+    /// that is missing or of another type is `Error::InvalidArgument`, and
+    /// a file that cannot be decoded is `Error::Corrupt`.
+    pub fn from_parquet<P: AsRef<Path>>(path: P, column: &str)
+                                        -> Result<RMITrainingData<u64>, Error> {
+        let builder = ParquetRecordBatchReaderBuilder::try_new(File::open(path)?)
+            .map_err(corrupt)?;
+        let field = builder.schema().field_with_name(column)
+            .map_err(|_| invalid(format!("no column {} in the Parquet file", column)))?;
+        if *field.data_type() != DataType::UInt64 {
+            return Err(invalid(format!("column {} holds {}, not UInt64 keys",
+                                       column, field.data_type())));
+        }
+
+        let num_rows = builder.metadata().file_metadata().num_rows() as usize;
+        let mask = ProjectionMask::columns(builder.parquet_schema(), [column]);
+        let reader = builder.with_projection(mask).build().map_err(corrupt)?;
+
+        let mut keys = Vec::with_capacity(num_rows);
+        for batch in reader {
+            let batch = batch.map_err(corrupt)?;
+            // the projection leaves only the key column, checked above
+            let array = batch.column(0).as_any().downcast_ref::<UInt64Array>().unwrap();
+            if array.null_count() > 0 {
+                return Err(invalid(format!("column {} has nulls", column)));
+            }
+            keys.extend_from_slice(array.values());
+        }
+        return RMITrainingData::from_arrow(&UInt64Array::from(keys));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::ModelInput;
+    use crate::train::train;
+    use arrow::array::{ArrayRef, Int64Array};
+    use arrow::record_batch::RecordBatch;
+    use parquet::arrow::ArrowWriter;
+    use std::sync::Arc;
+
+    // writes `columns` to a Parquet file in small row groups, so reading
+    // it back takes several batches
+    fn write_parquet(name: &str, columns: Vec<(&str, ArrayRef)>) -> std::path::PathBuf {
+        let path = std::env::temp_dir()
+            .join(format!("rmi_{}_{}.parquet", name, std::process::id()));
+        let batch = RecordBatch::try_from_iter(columns).unwrap();
+        let props = parquet::file::properties::WriterProperties::builder()
+            .set_max_row_group_size(1000)
+            .build();
+        let mut writer = ArrowWriter::try_new(File::create(&path).unwrap(), batch.schema(),
+                                              Some(props)).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+        return path;
+    }
+
+    #[test]
+    fn test_parquet_matches_vec() {
+        let keys: Vec<u64> = (0..10_000u64).map(|i| 3 * i * i + i % 7).collect();
+        let path = write_parquet("keys", vec![
+            ("id", Arc::new(Int64Array::from_iter_values(0..10_000)) as ArrayRef),
+            ("key", Arc::new(UInt64Array::from(keys.clone())) as ArrayRef),
+        ]);
+
+        let from_parquet = RMITrainingData::from_parquet(&path, "key").unwrap();
+        let from_vec = RMITrainingData::from_keys(keys.clone());
+        assert_eq!(from_parquet.len(), keys.len());
+        assert!(from_parquet.iter().eq(from_vec.iter()));
+
+        let expected = train(&from_vec, "radix,linear", 256);
+        let rmi = train(&from_parquet, "radix,linear", 256);
+        for key in keys.iter() {
+            let key = ModelInput::from(*key);
+            assert_eq!(rmi.predict(&key), expected.predict(&key));
+        }
+
+        assert!(matches!(RMITrainingData::from_parquet(&path, "missing"),
+                         Err(Error::InvalidArgument { .. })));
+        assert!(matches!(RMITrainingData::from_parquet(&path, "id"),
+                         Err(Error::InvalidArgument { .. })));
+        std::fs::remove_file(&path).unwrap();
+
+        let err = RMITrainingData::from_parquet(std::env::temp_dir().join("rmi_no_such.parquet"),
+                                                "key");
+        assert!(matches!(err, Err(Error::Io(_))));
+    }
+
+    #[test]
+    fn test_arrow_checks_keys() {
+        let array = UInt64Array::from(vec![1, 5, 5, 9]);
+        let data = RMITrainingData::from_arrow(&array).unwrap();
+        // duplicates are reported at the position of their first copy
+        assert_eq!(data.iter().collect::<Vec<_>>(), vec![(1, 0), (5, 1), (5, 1), (9, 3)]);
+        // the data shares the array's buffer
+        assert_eq!(data.iter_borrowed().unwrap().next().unwrap().0 as *const u64,
+                   array.values().as_ptr());
+
+        let unsorted = UInt64Array::from(vec![1, 5, 4, 9]);
+        assert!(matches!(RMITrainingData::from_arrow(&unsorted),
+                         Err(Error::Unsorted { index: 2 })));
+        let nulls = UInt64Array::from(vec![Some(1), None, Some(9)]);
+        assert!(matches!(RMITrainingData::from_arrow(&nulls),
+                         Err(Error::InvalidArgument { .. })));
+    }
+}
@@ -0,0 +1,44 @@
+// < begin copyright >
+// Copyright Ryan Marcus 2020
+//
+// See root directory of this project for license terms.
+//
+// < end copyright >
+
+// The one way tests build C++: unit tests reach it as
+// `codegen::cpp_test`, and integration tests include this file as a
+// module of their own.
+
+use std::path::PathBuf;
+use std::process::{Command, ExitStatus};
+
+// Compiles `sources` with `flags` in the directory of the first of them,
+// then runs the program from there, so it finds data files next to it.
+// Returns the program's exit status, or `None` if there is no C++
+// compiler, in which case the test should skip. With `-fsyntax-only`
+// there is no program, and the status is the compiler's. Panics if the
+// sources do not compile.
+pub fn compile_and_run(sources: &[PathBuf], flags: &[&str]) -> Option<ExitStatus> {
+    let dir = sources[0].parent().unwrap();
+    let binary = dir.join(sources[0].file_stem().unwrap());
+    let syntax_only = flags.contains(&"-fsyntax-only");
+
+    let mut compile = Command::new("c++");
+    compile.current_dir(dir).args(flags).args(sources);
+    if !syntax_only {
+        compile.arg("-o").arg(&binary);
+    }
+    let compiled = match compile.status() {
+        Ok(status) => status,
+        Err(e) => {
+            eprintln!("Skipping C++ test, no C++ compiler: {}", e);
+            return None;
+        }
+    };
+    assert!(compiled.success(), "{:?} failed to compile", sources);
+    if syntax_only {
+        return Some(compiled);
+    }
+
+    return Some(Command::new(&binary).current_dir(dir).status().unwrap());
+}
@@ -0,0 +1,163 @@
+// < begin copyright >
+// Copyright Ryan Marcus 2020
+//
+// See root directory of this project for license terms.
+//
+// < end copyright >
+
+//! The error of the crate's fallible constructors and of reading back
+//! serialized models.
+//!
+//! Each variant says what went wrong rather than how, so callers can match
+//! on it. An `Error` converts to and from `std::io::Error`: `?` works on
+//! either side, and `kind` gives the `io::ErrorKind` these functions
+//! reported before they had their own error.
+
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum Error {
+    /// There are no records to build from.
+    EmptyData,
+
+    /// The record at `index` comes before the one ahead of it, by key or by
+    /// position.
+    Unsorted { index: usize },
+
+    /// A value is too large for the integer or float meant to hold it.
+    ParamOverflow { reason: String },
+
+    /// Serialized bytes that cannot be read back as a model.
+    Corrupt { reason: String },
+
+    /// Parameters or tables that do not have the layout their model needs.
+    ShapeMismatch { reason: String },
+
+    /// A setting outside the range its constructor accepts.
+    InvalidArgument { reason: String },
+
+    /// Reading or writing failed.
+    Io(io::Error),
+}
+
+impl Error {
+    /// The `io::ErrorKind` that matches this error: `InvalidData` for bytes
+    /// that do not describe a model, `InvalidInput` for anything wrong with
+    /// the arguments, and the underlying error's own kind for `Io`.
+    pub fn kind(&self) -> io::ErrorKind {
+        return match self {
+            Error::Corrupt { .. } | Error::ShapeMismatch { .. } => io::ErrorKind::InvalidData,
+            Error::EmptyData | Error::Unsorted { .. } | Error::ParamOverflow { .. }
+                | Error::InvalidArgument { .. } => io::ErrorKind::InvalidInput,
+            Error::Io(e) => e.kind(),
+        };
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return match self {
+            Error::EmptyData => write!(f, "no records to build from"),
+            Error::Unsorted { index } => write!(f, "record {} is out of order", index),
+            Error::ParamOverflow { reason } | Error::Corrupt { reason }
+                | Error::ShapeMismatch { reason } | Error::InvalidArgument { reason } => {
+                write!(f, "{}", reason)
+            }
+            Error::Io(e) => write!(f, "{}", e),
+        };
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        return match self {
+            Error::Io(e) => Some(e),
+            _ => None,
+        };
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        return Error::Io(e);
+    }
+}
+
+// the error itself rides along, so `get_ref` and `into_inner` on the
+// `io::Error` recover the variant
+impl From<Error> for io::Error {
+    fn from(e: Error) -> io::Error {
+        return match e {
+            Error::Io(e) => e,
+            e => io::Error::new(e.kind(), e),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_io_round_trip() {
+        let err: io::Error = Error::Unsorted { index: 3 }.into();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(err.to_string(), "record 3 is out of order");
+        let inner = err.into_inner().unwrap().downcast::<Error>().unwrap();
+        assert!(matches!(*inner, Error::Unsorted { index: 3 }));
+
+        // an io error passes through untouched
+        let eof = Error::from(io::Error::from(io::ErrorKind::UnexpectedEof));
+        assert_eq!(eof.kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(io::Error::from(eof).kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_kind_per_variant() {
+        let reason = || String::from("reason");
+        let kinds = [
+            (Error::EmptyData, io::ErrorKind::InvalidInput),
+            (Error::Unsorted { index: 0 }, io::ErrorKind::InvalidInput),
+            (Error::ParamOverflow { reason: reason() }, io::ErrorKind::InvalidInput),
+            (Error::Corrupt { reason: reason() }, io::ErrorKind::InvalidData),
+            (Error::ShapeMismatch { reason: reason() }, io::ErrorKind::InvalidData),
+            (Error::InvalidArgument { reason: reason() }, io::ErrorKind::InvalidInput),
+            (Error::Io(io::Error::from(io::ErrorKind::NotFound)), io::ErrorKind::NotFound),
+        ];
+        for (err, kind) in kinds {
+            assert_eq!(err.kind(), kind, "{:?}", err);
+            assert_eq!(io::Error::from(err).kind(), kind);
+        }
+    }
+
+    #[test]
+    fn test_lookup_and_retrain_errors() {
+        use crate::models::*;
+        use crate::train::train;
+
+        // keys no model can place
+        for bad in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            let err = ModelInput::finite_float(bad).unwrap_err();
+            assert!(matches!(err, Error::InvalidArgument { .. }));
+        }
+
+        let keys: Vec<u64> = (0..1_000u64).map(|i| i * 7).collect();
+        let md = RMITrainingData::from_keys(keys);
+        let mut rmi = train(&md, "linear,linear", 16);
+        let err = rmi.try_predict(&ModelInput::Float(f64::NAN)).unwrap_err();
+        assert!(matches!(err, Error::InvalidArgument { .. }));
+
+        // more rows than a u32 position can hold
+        rmi.num_rmi_rows = u32::MAX as usize + 2;
+        let err = rmi.predict_to_u32(&ModelInput::Int(7)).unwrap_err();
+        assert!(matches!(err, Error::ParamOverflow { .. }));
+
+        // a model that cannot be trained from the data alone
+        let boundaries = vec![Vec::new(); 2];
+        let mut fib = LearnedFIB::from_boundaries(&RMITrainingData::<u64>::empty(), 1,
+                                                  &boundaries).unwrap();
+        let err = fib.retrain(&md, 0..md.len()).unwrap_err();
+        assert!(matches!(err, Error::InvalidArgument { .. }));
+    }
+}
@@ -0,0 +1,105 @@
+// < begin copyright >
+// Copyright Ryan Marcus 2020
+//
+// See root directory of this project for license terms.
+//
+// < end copyright >
+
+//! C entry points for evaluating a trained model in-process.
+
+use crate::models::{Model, ModelInput};
+use std::slice;
+
+/// Predicts the position of an integer key given as `len` raw
+/// little-endian bytes (at most 8, zero extended, so a `uint32_t` key
+/// may be passed directly on a little-endian machine).
+///
+/// # Safety
+///
+/// `model_ptr` must point to a live `Box<dyn Model>`, for example one
+/// taken from `TrainedRMI::rmi`. `key_bytes` must be valid for reads of
+/// `len` bytes; it needs no alignment and may be null only when `len`
+/// is 0. Panics (aborting the caller) if `len` is greater than 8.
+#[no_mangle]
+pub unsafe extern "C" fn rmi_predict(model_ptr: *const Box<dyn Model>,
+                                     key_bytes: *const u8,
+                                     len: usize) -> u64 {
+    let model: &dyn Model = &**model_ptr;
+    let bytes: &[u8] = if len == 0 { &[] } else { slice::from_raw_parts(key_bytes, len) };
+    return model.predict_to_int(&ModelInput::from_le_bytes(bytes));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::*;
+
+    #[test]
+    fn test_from_le_bytes() {
+        assert_eq!(ModelInput::from_le_bytes(&[]), ModelInput::Int(0));
+        assert_eq!(ModelInput::from_le_bytes(&[0x34, 0x12]), ModelInput::Int(0x1234));
+        assert_eq!(ModelInput::from_le_bytes(&u64::MAX.to_le_bytes()),
+                   ModelInput::Int(u64::MAX));
+        assert_eq!(ModelInput::from_f64_bits(2.5f64.to_bits()), ModelInput::Float(2.5));
+    }
+
+    #[test]
+    #[should_panic(expected = "at most 8 bytes")]
+    fn test_from_le_bytes_too_long() {
+        ModelInput::from_le_bytes(&[0; 9]);
+    }
+
+    #[test]
+    fn test_predict_bytes() {
+        let data: Vec<(u64, usize)> = (1..5000u64).map(|i| (i * 977) << 40).zip(0..).collect();
+        let md = RMITrainingData::new(Box::new(data.clone()));
+        let table = RadixTable::new(&md, 12);
+        let linear = LinearModel::new(&md);
+
+        for (key, _) in data.iter() {
+            let native = table.predict_to_int(&(*key).into());
+            assert_eq!(table.predict_bytes(&key.to_le_bytes()), native);
+
+            // a 16-byte key is placed by its high 64 bits
+            let wide = (*key as u128) << 64 | 0xdead_beef;
+            assert_eq!(table.predict_bytes(&wide.to_le_bytes()), native);
+
+            // float models read the bit pattern of a double
+            let float = *key as f64;
+            assert_eq!(linear.predict_bytes(&float.to_bits().to_le_bytes()),
+                       linear.predict_to_int(&float.into()));
+        }
+
+        let kind = |bytes: &[u8], ty| ModelInput::from_key_bytes(bytes, ty).err().map(|e| e.kind());
+        assert_eq!(kind(&[0; 17], ModelDataType::Int), Some(std::io::ErrorKind::InvalidInput));
+        assert_eq!(kind(&[0; 4], ModelDataType::Float), Some(std::io::ErrorKind::InvalidInput));
+        assert_eq!(ModelInput::from_key_bytes(&[1, 0, 0, 0, 0, 0, 0, 0, 2],
+                                              ModelDataType::Int128).unwrap(),
+                   ModelInput::Int(2));
+    }
+
+    #[test]
+    fn test_rmi_predict() {
+        let data: Vec<(u64, usize)> = (1..5000u64).map(|i| i * 977).zip(0..).collect();
+        let md = RMITrainingData::new(Box::new(data.clone()));
+        let model: Box<dyn Model> = Box::new(RadixTable::new(&md, 12));
+
+        // call through a C function pointer, as a foreign caller would
+        let entry: unsafe extern "C" fn(*const Box<dyn Model>, *const u8, usize) -> u64 =
+            rmi_predict;
+
+        for (key, _) in data.iter() {
+            let native = model.predict_to_int(&(*key).into());
+
+            let bytes = key.to_le_bytes();
+            let via_ffi = unsafe { entry(&model, bytes.as_ptr(), bytes.len()) };
+            assert_eq!(via_ffi, native);
+
+            // keys below 2^32 can be passed as 4 bytes, unaligned
+            let mut unaligned = [0u8; 5];
+            unaligned[1..].copy_from_slice(&(*key as u32).to_le_bytes());
+            let via_ffi = unsafe { entry(&model, unaligned[1..].as_ptr(), 4) };
+            assert_eq!(via_ffi, native);
+        }
+    }
+}
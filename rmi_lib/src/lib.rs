@@ -2,11 +2,37 @@ mod codegen;
 mod models;
 mod train;
 mod cache_fix;
+mod serialize;
+mod search;
+mod error;
+#[cfg(feature = "arena")]
+mod arena;
+#[cfg(feature = "arrow")]
+mod columnar;
 
+pub mod ffi;
 pub mod optimizer;
-pub use models::{RMITrainingData, RMITrainingDataIteratorProvider, ModelInput};
+pub use models::{RMITrainingData, RMITrainingDataView, RMITrainingDataIteratorProvider, ModelInput};
+pub use models::BorrowedRecords;
 pub use models::KeyType;
-pub use optimizer::find_pareto_efficient_configs;
-pub use train::{train, train_for_size, train_bounded};
+pub use models::{Model, RadixModel, FixedRadixModel, RadixTable, RelativeRadixTable, OnOverflow, HintPolicy, LearnedFIB};
+pub use models::{KeyTransform, PlainRadix, Multiplicative};
+pub use models::{LearnedFIBBuilder, LazyLearnedFIB, ErrorPercentiles, Threshold};
+pub use models::{ModelKind, ModelParam, ParamField, ParamKind, CACHE_LINE_BYTES};
+pub use models::{ModelDataType, ModelRestriction, StdFunctions, TrainingKey};
+pub use models::{verify, merge_errors};
+pub use optimizer::{find_pareto_efficient_configs, find_pareto_efficient_configs_with, Objective};
+pub use train::{train, train_for_size, train_bounded, TrainedRMI, Mismatch};
+pub use train::{train_with_report, BuildReport, LayerReport};
+pub use train::{register_model, CustomModelTrainer};
 pub use codegen::rmi_size;
+#[cfg(feature = "arena")]
+pub use arena::ParamArena;
+#[cfg(feature = "arena")]
+pub use train::train_in;
 pub use codegen::output_rmi;
+pub use codegen::read_data_file;
+pub use codegen::{CodeGen, CodeGenReport};
+pub use search::{counted_search, Explanation, SearchStrategy, SearchStats};
+pub use serialize::load_model;
+pub use error::Error;
@@ -6,7 +6,7 @@
 // < end copyright > 
  
 
-use crate::models::utils::{common_prefix_size, num_bits};
+use crate::models::utils::{common_prefix_size, fit_radix, num_bits};
 use crate::models::*;
 use log::*;
 use std::f64;
@@ -44,7 +44,8 @@ fn bradix<T: TrainingKey>(data: &RMITrainingData<T>, max_output: u64) -> Balance
     let mut best_result_score = f64::INFINITY;
     let mut best_result = None;
     for test_bits in bits..u8::min(bits + 2, 64) {
-        let bits_max = (1 << (test_bits + 1)) - 1;
+        let (common_prefix, test_bits) = fit_radix(common_prefix, test_bits);
+        let bits_max = u64::MAX >> (63 - test_bits);
 
         let high = BalancedRadixModel {
             params: (common_prefix, test_bits, max_output - 1),
@@ -97,6 +98,18 @@ impl BalancedRadixModel {
 
         return bradix(data, largest_value as u64);
     }
+
+    pub(crate) fn from_state(params: &[ModelParam]) -> Option<BalancedRadixModel> {
+        return match params {
+            [ModelParam::Int(prefix), ModelParam::Int(bits),
+             ModelParam::Int(clamp), ModelParam::Int(high)] =>
+                Some(BalancedRadixModel {
+                    params: (*prefix as u8, *bits as u8, *clamp),
+                    high: *high != 0
+                }),
+            _ => None,
+        };
+    }
 }
 
 impl Model for BalancedRadixModel {
@@ -113,6 +126,10 @@ impl Model for BalancedRadixModel {
         }
     }
 
+    fn integer_only(&self) -> bool {
+        return true;
+    }
+
     fn input_type(&self) -> ModelDataType {
         return ModelDataType::Int;
     }
@@ -153,6 +170,10 @@ inline uint64_t bradix_clamp_low(uint64_t prefix_length,
         }
     }
 
+    fn required_includes(&self) -> Vec<&'static str> {
+        return vec!["<cstdint>"];
+    }
+
     fn function_name(&self) -> String {
         return if self.high {
             String::from("bradix_clamp_high")
@@ -167,6 +188,29 @@ inline uint64_t bradix_clamp_low(uint64_t prefix_length,
     fn restriction(&self) -> ModelRestriction {
         return ModelRestriction::MustBeTop;
     }
+
+    fn retrain(&mut self, data: &RMITrainingData<u64>,
+               _changed: Range<usize>) -> Result<(), Error> {
+        *self = BalancedRadixModel::new(data);
+        return Ok(());
+    }
+
+    fn kind(&self) -> ModelKind {
+        return ModelKind::BalancedRadix;
+    }
+
+    fn state(&self) -> Option<(ModelKind, Vec<ModelParam>)> {
+        return Some((self.kind(), vec![
+            self.params.0.into(),
+            self.params.1.into(),
+            self.params.2.into(),
+            (self.high as u64).into()
+        ]));
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        return self;
+    }
 }
 
 #[cfg(test)]
@@ -175,7 +219,7 @@ mod tests {
 
     #[test]
     fn test_empty() {
-        BalancedRadixModel::new(&ModelData::empty());
+        BalancedRadixModel::new(&RMITrainingData::<u64>::empty());
     }
 
 }
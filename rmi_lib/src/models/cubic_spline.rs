@@ -134,6 +134,14 @@ impl CubicSplineModel {
 
         return cubic;
     }
+
+    pub(crate) fn from_state(params: &[ModelParam]) -> Option<CubicSplineModel> {
+        return match params {
+            [ModelParam::Float(a), ModelParam::Float(b), ModelParam::Float(c), ModelParam::Float(d)] =>
+                Some(CubicSplineModel { params: (*a, *b, *c, *d) }),
+            _ => None,
+        };
+    }
 }
 
 impl Model for CubicSplineModel {
@@ -178,6 +186,10 @@ inline double cubic(double a, double b, double c, double d, double x) {
         );
     }
 
+    fn required_includes(&self) -> Vec<&'static str> {
+        return vec!["<cmath>"];
+    }
+
     fn function_name(&self) -> String {
         return String::from("cubic");
     }
@@ -189,6 +201,24 @@ inline double cubic(double a, double b, double c, double d, double x) {
         self.params = (0.0, 0.0, 0.0, constant as f64);
         return true;
     }    
+
+    fn retrain(&mut self, data: &RMITrainingData<u64>,
+               _changed: Range<usize>) -> Result<(), Error> {
+        *self = CubicSplineModel::new(data);
+        return Ok(());
+    }
+
+    fn kind(&self) -> ModelKind {
+        return ModelKind::Cubic;
+    }
+
+    fn state(&self) -> Option<(ModelKind, Vec<ModelParam>)> {
+        return Some((self.kind(), self.params()));
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        return self;
+    }
 }
 
 #[cfg(test)]
@@ -198,55 +228,55 @@ mod tests {
 
     #[test]
     fn test_cubic() {
-        let md = ModelData::IntKeyToIntPos(vec![(1, 2), (2, 3), (3, 8), (4, 20)]);
+        let md = RMITrainingData::<u64>::new(Box::new(vec![(1, 2), (2, 3), (3, 8), (4, 20)]));
 
         let cubic_mod = CubicSplineModel::new(&md);
 
-        assert_abs_diff_eq!(cubic_mod.predict_to_float(1.into()), 2.0, epsilon = 0.5);
-        assert_abs_diff_eq!(cubic_mod.predict_to_float(4.into()), 20.0, epsilon = 0.5);
+        assert_abs_diff_eq!(cubic_mod.predict_to_float(&1.into()), 2.0, epsilon = 0.5);
+        assert_abs_diff_eq!(cubic_mod.predict_to_float(&4.into()), 20.0, epsilon = 0.5);
     }
 
     #[test]
     fn test_cubic2() {
-        let md = ModelData::IntKeyToIntPos(vec![(1, 2), (2, 3), (3, 8), (4, 20), (5, 80)]);
+        let md = RMITrainingData::<u64>::new(Box::new(vec![(1, 2), (2, 3), (3, 8), (4, 20), (5, 80)]));
 
         let cubic_mod = CubicSplineModel::new(&md);
 
-        assert_abs_diff_eq!(cubic_mod.predict_to_float(1.into()), 2.0, epsilon = 0.5);
-        assert_abs_diff_eq!(cubic_mod.predict_to_float(5.into()), 80.0, epsilon = 0.5);
+        assert_abs_diff_eq!(cubic_mod.predict_to_float(&1.into()), 2.0, epsilon = 0.5);
+        assert_abs_diff_eq!(cubic_mod.predict_to_float(&5.into()), 80.0, epsilon = 0.5);
     }
 
     #[test]
     fn test_cubic_dup() {
-        let md = ModelData::IntKeyToIntPos(vec![(1, 2), (1, 2), (3, 8), (4, 20), (5, 80)]);
+        let md = RMITrainingData::<u64>::new(Box::new(vec![(1, 2), (1, 2), (3, 8), (4, 20), (5, 80)]));
 
         let cubic_mod = CubicSplineModel::new(&md);
 
-        assert_abs_diff_eq!(cubic_mod.predict_to_float(1.into()), 2.0, epsilon = 0.5);
-        assert_abs_diff_eq!(cubic_mod.predict_to_float(5.into()), 80.0, epsilon = 0.5);
+        assert_abs_diff_eq!(cubic_mod.predict_to_float(&1.into()), 2.0, epsilon = 0.5);
+        assert_abs_diff_eq!(cubic_mod.predict_to_float(&5.into()), 80.0, epsilon = 0.5);
     }
 
     #[test]
     fn test_cubic_all_dup() {
-        let md = ModelData::IntKeyToIntPos(vec![(1, 2), (1, 2), (1, 2)]);
+        let md = RMITrainingData::<u64>::new(Box::new(vec![(1, 2), (1, 2), (1, 2)]));
 
         let cubic_mod = CubicSplineModel::new(&md);
 
-        assert_abs_diff_eq!(cubic_mod.predict_to_float(1.into()), 2.0, epsilon = 0.5);
+        assert_abs_diff_eq!(cubic_mod.predict_to_float(&1.into()), 2.0, epsilon = 0.5);
     }
 
     #[test]
     fn test_linear_spline_single() {
-        let md = ModelData::IntKeyToIntPos(vec![(1, 2)]);
+        let md = RMITrainingData::<u64>::new(Box::new(vec![(1, 2)]));
 
         let cubic_mod = CubicSplineModel::new(&md);
 
-        assert_eq!(cubic_mod.predict_to_int(1.into()), 2);
+        assert_eq!(cubic_mod.predict_to_int(&1.into()), 2);
     }
 
     #[test]
     fn test_empty() {
-        CubicSplineModel::new(&ModelData::empty());
+        CubicSplineModel::new(&RMITrainingData::<u64>::empty());
     }
 
 }
@@ -50,6 +50,14 @@ impl EquidepthHistogramModel {
             params, radix
         };
     }
+
+    pub(crate) fn from_state(params: &[ModelParam]) -> Option<EquidepthHistogramModel> {
+        return match params {
+            [ModelParam::IntArray(params), ModelParam::IntArray(radix)] =>
+                Some(EquidepthHistogramModel { params: params.clone(), radix: radix.clone() }),
+            _ => None,
+        };
+    }
 }
 
 impl Model for EquidepthHistogramModel {
@@ -67,6 +75,7 @@ impl Model for EquidepthHistogramModel {
         return self.params.len() as u64 - 1;*/
     }
 
+    fn integer_only(&self) -> bool { return true; }
     fn input_type(&self) -> ModelDataType { return ModelDataType::Int; }
     fn output_type(&self) -> ModelDataType { return ModelDataType::Int; }
 
@@ -99,8 +108,26 @@ inline uint64_t ed_histogram(const uint64_t length,
     }
 
     fn function_name(&self) -> String { return String::from("ed_histogram"); }
+    fn required_includes(&self) -> Vec<&'static str> { return vec!["<cstdint>"]; }
     fn restriction(&self) -> ModelRestriction { return ModelRestriction::MustBeTop; }
     fn needs_bounds_check(&self) -> bool { return false; }
+    fn retrain(&mut self, data: &RMITrainingData<u64>,
+               _changed: Range<usize>) -> Result<(), Error> {
+        *self = EquidepthHistogramModel::new(data);
+        return Ok(());
+    }
+
+    fn kind(&self) -> ModelKind {
+        return ModelKind::Histogram;
+    }
+
+    fn state(&self) -> Option<(ModelKind, Vec<ModelParam>)> {
+        return Some((self.kind(), vec![self.params.clone().into(), self.radix.clone().into()]));
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        return self;
+    }
 }
 
 #[cfg(test)]
@@ -109,26 +136,26 @@ mod tests {
     
     #[test]
     fn test_ed_hist1() {
-        let mut test_data: Vec<(u64, u64)> = Vec::new();
+        let mut test_data: Vec<(u64, usize)> = Vec::new();
 
-        for i in 0..1000 {
-            test_data.push((i*3, i/3));
+        for i in 0..1000u64 {
+            test_data.push((i*3, (i/3) as usize));
         }
         
-        let md = ModelData::IntKeyToIntPos(test_data);
+        let md = RMITrainingData::<u64>::new(Box::new(test_data));
 
         let ed_mod = EquidepthHistogramModel::new(&md);
 
-        assert_eq!(ed_mod.predict_to_int((0).into()), 0);
-        assert_eq!(ed_mod.predict_to_int((1*3).into()), 0);
-        assert_eq!(ed_mod.predict_to_int((4*3).into()), 1);
-        assert_eq!(ed_mod.predict_to_int((500*3).into()), 166);
-        assert_eq!(ed_mod.predict_to_int((5000*3).into()), 333);
+        assert_eq!(ed_mod.predict_to_int(&0.into()), 0);
+        assert_eq!(ed_mod.predict_to_int(&(1*3).into()), 0);
+        assert_eq!(ed_mod.predict_to_int(&(4*3).into()), 1);
+        assert_eq!(ed_mod.predict_to_int(&(500*3).into()), 166);
+        assert_eq!(ed_mod.predict_to_int(&(5000*3).into()), 332);
     }
 
     #[test]
     fn test_empty() {
-        EquidepthHistogramModel::new(&ModelData::empty());
+        EquidepthHistogramModel::new(&RMITrainingData::<u64>::empty());
     }
 
 }
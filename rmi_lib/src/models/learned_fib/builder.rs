@@ -0,0 +1,216 @@
+// < begin copyright >
+// Copyright Ryan Marcus 2020
+//
+// See root directory of this project for license terms.
+//
+// < end copyright >
+
+//! Named settings for training a `LearnedFIB`, in place of the positional
+//! arguments of its constructors.
+
+use super::{neural_network, BucketTraining, LearnedFIB, Threshold};
+use crate::error::Error;
+use crate::models::*;
+use std::collections::HashMap;
+
+// the largest prefix `build` accepts, which already means a table of four
+// billion buckets
+const MAX_PREFIX: u64 = 32;
+
+fn invalid(reason: String) -> Error {
+    return Error::InvalidArgument { reason };
+}
+
+/// Settings for a `LearnedFIB`, checked when it is built. The defaults are
+/// a single bucket, an absolute threshold of 16 positions, no limit on the
+/// segments, no context keys and no offset table, trained on the calling
+/// thread.
+#[derive(Debug, Clone, Copy)]
+pub struct LearnedFIBBuilder {
+    prefix: u64,
+    threshold: Threshold,
+    max_segments: usize,
+    context: usize,
+    offset_table: bool,
+    parallel: bool,
+    second_layer: bool,
+}
+
+impl Default for LearnedFIBBuilder {
+    fn default() -> LearnedFIBBuilder {
+        return LearnedFIBBuilder {
+            prefix: 0,
+            threshold: Threshold::Absolute(16),
+            max_segments: usize::MAX,
+            context: 0,
+            offset_table: false,
+            parallel: false,
+            second_layer: false,
+        };
+    }
+}
+
+impl LearnedFIBBuilder {
+    pub fn new() -> LearnedFIBBuilder {
+        return LearnedFIBBuilder::default();
+    }
+
+    /// How many leading key bits pick a key's bucket, giving `1 << prefix`
+    /// buckets, up to 32.
+    pub fn prefix(mut self, prefix: u64) -> LearnedFIBBuilder {
+        self.prefix = prefix;
+        return self;
+    }
+
+    /// The error threshold of the networks' segments. Fractions must be
+    /// between 0 and 1.
+    pub fn threshold(mut self, threshold: impl Into<Threshold>) -> LearnedFIBBuilder {
+        self.threshold = threshold.into();
+        return self;
+    }
+
+    /// The most segments a bucket's network may have, at least 1, as for
+    /// `LearnedFIB::new_with_max_segments`.
+    pub fn max_segments(mut self, max_segments: usize) -> LearnedFIBBuilder {
+        self.max_segments = max_segments;
+        return self;
+    }
+
+    /// How many keys on either side of a bucket its network also sees, as
+    /// for `LearnedFIB::new_with_context`.
+    pub fn context(mut self, context: usize) -> LearnedFIBBuilder {
+        self.context = context;
+        return self;
+    }
+
+    /// When true, bucket offsets are kept in a table, as for
+    /// `LearnedFIB::new_with_offset_table`.
+    pub fn offset_table(mut self, offset_table: bool) -> LearnedFIBBuilder {
+        self.offset_table = offset_table;
+        return self;
+    }
+
+    /// When true, the buckets' networks are trained on rayon's thread pool.
+    /// The model is the same as when trained on one thread.
+    pub fn parallel(mut self, parallel: bool) -> LearnedFIBBuilder {
+        self.parallel = parallel;
+        return self;
+    }
+
+    /// When true, a bucket that needs more than `max_segments` segments
+    /// also tries a network of two layers, the first squeezing out the
+    /// widest gaps between its keys, and keeps it if it is closer. This
+    /// suits step-like CDFs, with keys in clusters, and can give such a
+    /// bucket up to twice `max_segments` segments.
+    pub fn second_layer(mut self, second_layer: bool) -> LearnedFIBBuilder {
+        self.second_layer = second_layer;
+        return self;
+    }
+
+    /// Trains the model on `data`. Returns `Error::InvalidArgument` if a
+    /// setting is out of range, and `Error::ParamOverflow` if `data` has
+    /// positions past 2^53, which the networks cannot place exactly.
+    pub fn build<T: TrainingKey>(&self, data: &RMITrainingData<T>) -> Result<LearnedFIB, Error> {
+        if self.prefix > MAX_PREFIX {
+            return Err(invalid(format!("LearnedFIB prefix {} is larger than the {} supported",
+                                       self.prefix, MAX_PREFIX)));
+        }
+        if let Threshold::Fraction(f) = self.threshold {
+            if !(0.0..=1.0).contains(&f) {
+                return Err(invalid(format!("LearnedFIB threshold fraction {} is not \
+                                            between 0 and 1", f)));
+            }
+        }
+        if self.max_segments == 0 {
+            return Err(invalid(String::from("a network needs at least one segment")));
+        }
+        neural_network::check_positions(data)?;
+
+        let training = BucketTraining {
+            max_segments: self.max_segments,
+            context: self.context,
+            second_layer: self.second_layer,
+            ..BucketTraining::new(self.threshold)
+        };
+        let fib = if self.parallel {
+            LearnedFIB::train_parallel(data, self.prefix, &training)
+        } else {
+            let mut no_progress = Progress::new(None, 0, 0);
+            LearnedFIB::train_cached(data, self.prefix, &training,
+                                     &mut HashMap::new(), &mut no_progress, None)
+        };
+
+        if self.offset_table {
+            return Ok(fib.into_offset_table());
+        }
+        return Ok(fib);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::ErrorKind;
+
+    fn test_data() -> RMITrainingData<u64> {
+        return RMITrainingData::from_keys((0..5_000u64).map(|i| i * i * 31 + (i % 7) * 1_000));
+    }
+
+    fn assert_same(a: &LearnedFIB, b: &LearnedFIB, data: &RMITrainingData<u64>) {
+        assert_eq!(a.error_bound(), b.error_bound());
+        assert_eq!(a.export_segments(), b.export_segments());
+        for (key, _) in data.iter().step_by(37) {
+            assert_eq!(a.predict_to_int(&key.into()), b.predict_to_int(&key.into()));
+        }
+    }
+
+    #[test]
+    fn test_defaults() {
+        let md = test_data();
+        let fib = LearnedFIBBuilder::new().build(&md).unwrap();
+        assert_same(&fib, &LearnedFIB::new(&md, 16, 0), &md);
+    }
+
+    #[test]
+    fn test_overrides() {
+        let md = test_data();
+        let builder = LearnedFIBBuilder::new().prefix(6).threshold(4);
+        assert_same(&builder.build(&md).unwrap(), &LearnedFIB::new(&md, 4, 6), &md);
+        assert_same(&builder.max_segments(2).build(&md).unwrap(),
+                    &LearnedFIB::new_with_max_segments(&md, 4, 6, 2), &md);
+        assert_same(&builder.context(50).build(&md).unwrap(),
+                    &LearnedFIB::new_with_context(&md, 4, 6, 50), &md);
+
+        let offsets = builder.offset_table(true).build(&md).unwrap();
+        assert_eq!(offsets.lookup_cache_lines(),
+                   LearnedFIB::new_with_offset_table(&md, 4, 6).lookup_cache_lines());
+        assert_same(&offsets, &LearnedFIB::new_with_offset_table(&md, 4, 6), &md);
+
+        let fraction = builder.threshold(Threshold::Fraction(0.0008));
+        assert_same(&fraction.build(&md).unwrap(), &LearnedFIB::new(&md, 4, 6), &md);
+        assert_same(&builder.parallel(true).build(&md).unwrap(),
+                    &builder.build(&md).unwrap(), &md);
+
+        // within its segments, no bucket needs the second layer
+        assert_same(&builder.second_layer(true).build(&md).unwrap(),
+                    &LearnedFIB::new(&md, 4, 6), &md);
+        let capped = builder.max_segments(2);
+        let stacked = capped.second_layer(true).build(&md).unwrap();
+        assert!(stacked.error_bound() <= capped.build(&md).unwrap().error_bound());
+    }
+
+    #[test]
+    fn test_rejects_out_of_range() {
+        let md = test_data();
+        let kind = |builder: LearnedFIBBuilder| builder.build(&md).err().map(|e| e.kind());
+        assert_eq!(kind(LearnedFIBBuilder::new().prefix(33)), Some(ErrorKind::InvalidInput));
+        assert_eq!(kind(LearnedFIBBuilder::new().threshold(Threshold::Fraction(1.5))),
+                   Some(ErrorKind::InvalidInput));
+        assert_eq!(kind(LearnedFIBBuilder::new().threshold(Threshold::Fraction(f64::NAN))),
+                   Some(ErrorKind::InvalidInput));
+        assert_eq!(kind(LearnedFIBBuilder::new().max_segments(0)),
+                   Some(ErrorKind::InvalidInput));
+        assert!(matches!(LearnedFIBBuilder::new().prefix(33).build(&md),
+                         Err(Error::InvalidArgument { .. })));
+    }
+}
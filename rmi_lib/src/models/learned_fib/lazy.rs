@@ -0,0 +1,338 @@
+// < begin copyright >
+// Copyright Ryan Marcus 2020
+//
+// See root directory of this project for license terms.
+//
+// < end copyright >
+
+//! A single-file format for a `LearnedFIB`, and a model that reads each
+//! bucket's network from it only when a key first falls into that bucket.
+//!
+//! Layout (all integers little endian):
+//!
+//! ```text
+//! magic "LFIB" | version: u32 | prefix: u64 | max_error: u64
+//! | has_offsets: u8 | offsets: [u64; 1 << prefix] if has_offsets
+//! | key_bases: [u64; 1 << prefix] | index: [u64; (1 << prefix) + 1] | networks
+//! ```
+//!
+//! `index[i]..index[i + 1]` is the byte range of bucket `i`'s network,
+//! relative to the start of `networks`. Each network is its flat layout
+//! of f64s: weights1, weights2, biases1, then bias2, and takes its inputs
+//! relative to its key base. Version 1 files have no key bases, and their
+//! networks take keys as they are.
+
+use super::neural_network::NN;
+use super::{clip, LearnedFIB};
+use crate::models::utils::f64_to_pos;
+use crate::models::*;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::fs::File;
+use crate::error::Error;
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+const MAGIC: &[u8; 4] = b"LFIB";
+const VERSION: u32 = 2;
+
+fn corrupt(reason: String) -> Error {
+    return Error::Corrupt { reason };
+}
+
+pub(super) fn write(fib: &LearnedFIB, path: &Path) -> Result<(), io::Error> {
+    let mut out = BufWriter::new(File::create(path)?);
+    out.write_all(MAGIC)?;
+    out.write_u32::<LittleEndian>(VERSION)?;
+    out.write_u64::<LittleEndian>(fib.prefix)?;
+    out.write_u64::<LittleEndian>(fib.max_error)?;
+
+    match &fib.offsets {
+        Some(offsets) => {
+            out.write_u8(1)?;
+            for offset in offsets.iter() {
+                out.write_u64::<LittleEndian>(*offset)?;
+            }
+        }
+        None => out.write_u8(0)?,
+    }
+    for base in fib.key_bases() {
+        out.write_u64::<LittleEndian>(base)?;
+    }
+
+    let flats: Vec<Vec<f64>> = fib.neural_networks.iter().map(|nn| nn.to_flat()).collect();
+    let mut start = 0u64;
+    out.write_u64::<LittleEndian>(start)?;
+    for flat in flats.iter() {
+        start += 8 * flat.len() as u64;
+        out.write_u64::<LittleEndian>(start)?;
+    }
+    for flat in flats.iter() {
+        for v in flat.iter() {
+            out.write_f64::<LittleEndian>(*v)?;
+        }
+    }
+
+    return out.flush();
+}
+
+/// A `LearnedFIB` read from a file written by `LearnedFIB::save`, which
+/// loads each bucket's network the first time a key falls into the bucket
+/// and keeps it from then on. Opening the file reads only its header, so
+/// queries that touch few buckets never read the rest.
+pub struct LazyLearnedFIB {
+    prefix: u64,
+    max_error: u64,
+    offsets: Option<Vec<u64>>,
+    key_bases: Vec<u64>,
+    index: Vec<u64>,
+    networks_start: u64,
+
+    file: Mutex<File>,
+    networks: Vec<OnceLock<NN>>,
+    loads: AtomicUsize,
+}
+
+impl LazyLearnedFIB {
+    pub fn open(path: &Path) -> Result<LazyLearnedFIB, Error> {
+        let mut file = File::open(path)?;
+
+        let mut magic = [0u8; 4];
+        file.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(corrupt(String::from("not a LearnedFIB file")));
+        }
+        let version = file.read_u32::<LittleEndian>()?;
+        if version != 1 && version != VERSION {
+            return Err(corrupt(format!("unsupported LearnedFIB file version {}", version)));
+        }
+
+        let prefix = file.read_u64::<LittleEndian>()?;
+        if prefix >= 64 {
+            return Err(corrupt(format!("invalid LearnedFIB prefix {}", prefix)));
+        }
+        let max_error = file.read_u64::<LittleEndian>()?;
+        let buckets = 1usize << prefix;
+
+        let offsets = match file.read_u8()? {
+            0 => None,
+            1 => Some((0..buckets)
+                      .map(|_| file.read_u64::<LittleEndian>())
+                      .collect::<Result<Vec<u64>, io::Error>>()?),
+            flag => return Err(corrupt(format!("invalid offset table flag {}", flag))),
+        };
+        let key_bases = if version == 1 {
+            vec![0; buckets]
+        } else {
+            (0..buckets)
+                .map(|_| file.read_u64::<LittleEndian>())
+                .collect::<Result<Vec<u64>, io::Error>>()?
+        };
+
+        let index = (0..=buckets)
+            .map(|_| file.read_u64::<LittleEndian>())
+            .collect::<Result<Vec<u64>, io::Error>>()?;
+        if index.windows(2).any(|w| w[0] > w[1] || (w[1] - w[0]) % 8 != 0) {
+            return Err(corrupt(String::from("corrupt LearnedFIB network index")));
+        }
+        let networks_start = file.stream_position()?;
+
+        return Ok(LazyLearnedFIB {
+            prefix,
+            max_error,
+            offsets,
+            key_bases,
+            index,
+            networks_start,
+            file: Mutex::new(file),
+            networks: (0..buckets).map(|_| OnceLock::new()).collect(),
+            loads: AtomicUsize::new(0),
+        });
+    }
+
+    /// How many buckets' networks have been read from the file so far.
+    pub fn loads(&self) -> usize {
+        return self.loads.load(Ordering::Relaxed);
+    }
+
+    fn network(&self, bucket: usize) -> &NN {
+        return self.networks[bucket].get_or_init(|| {
+            let (from, to) = (self.index[bucket], self.index[bucket + 1]);
+            let mut flat = vec![0.0; ((to - from) / 8) as usize];
+
+            let mut file = self.file.lock().unwrap();
+            file.seek(SeekFrom::Start(self.networks_start + from))
+                .and_then(|_| file.read_f64_into::<LittleEndian>(&mut flat))
+                .unwrap_or_else(|e| panic!("could not read network of bucket {}: {}", bucket, e));
+            self.loads.fetch_add(1, Ordering::Relaxed);
+
+            return NN::from_flat(&flat)
+                .unwrap_or_else(|| panic!("corrupt network for bucket {}", bucket))
+                .with_key_base(self.key_bases[bucket]);
+        });
+    }
+}
+
+impl Model for LazyLearnedFIB {
+    fn predict_to_float(&self, inp: &ModelInput) -> f64 {
+        let bucket = clip(inp.as_int(), self.prefix);
+        let local = self.network(bucket).predict(inp);
+        return match &self.offsets {
+            Some(offsets) => local + offsets[bucket] as f64,
+            None => local,
+        };
+    }
+
+    fn predict_to_int(&self, inp: &ModelInput) -> u64 {
+        return f64_to_pos(self.predict_to_float(inp), u64::MAX);
+    }
+
+    // as for `LearnedFIB`, which loads the bucket's network if it is not
+    // cached yet
+    fn estimated_flops(&self, inp: &ModelInput) -> u64 {
+        let bucket = clip(inp.as_int(), self.prefix);
+        return 1 + self.network(bucket).inference_flops() + 2 * self.offsets.is_some() as u64;
+    }
+
+    fn input_type(&self) -> ModelDataType {
+        return ModelDataType::Int;
+    }
+
+    fn output_type(&self) -> ModelDataType {
+        return ModelDataType::Int;
+    }
+
+    fn params(&self) -> Vec<ModelParam> {
+        return Vec::new();
+    }
+
+    // never asked for, as the code generator rejects `RustOnlyBottom`
+    // models
+    fn code(&self) -> String {
+        return String::new();
+    }
+
+    fn function_name(&self) -> String {
+        return String::from("LazyLearnedFIB");
+    }
+
+    fn restriction(&self) -> ModelRestriction {
+        return ModelRestriction::RustOnlyBottom;
+    }
+
+    fn error_bound(&self) -> Option<u64> {
+        return Some(self.max_error);
+    }
+
+    fn kind(&self) -> ModelKind {
+        return ModelKind::LearnedFIB;
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        return self;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::ErrorKind;
+    use std::path::PathBuf;
+
+    fn temp_path(name: &str) -> PathBuf {
+        return std::env::temp_dir()
+            .join(format!("rmi_{}_{}.lfib", name, std::process::id()));
+    }
+
+    // one key in the middle of every bucket's key range, plus the data
+    fn keys_per_bucket(prefix: u64) -> (Vec<u64>, RMITrainingData<u64>) {
+        let probes: Vec<u64> = (0..1u64 << prefix)
+            .map(|b| (b << (64 - prefix)) | (1 << (63 - prefix)))
+            .collect();
+        let mut data: Vec<u64> = (0..20_000u64)
+            .map(|i| i.wrapping_mul(0x9E37_79B9_7F4A_7C15))
+            .collect();
+        data.sort_unstable();
+        return (probes, RMITrainingData::from_keys(data));
+    }
+
+    #[test]
+    fn test_lazy_loads_only_queried_bucket() {
+        let (probes, md) = keys_per_bucket(4);
+        let fib = LearnedFIB::new(&md, 16, 4);
+        let path = temp_path("lazy");
+        fib.save(&path).unwrap();
+
+        let lazy = LazyLearnedFIB::open(&path).unwrap();
+        assert_eq!(lazy.loads(), 0);
+        assert_eq!(lazy.error_bound(), fib.error_bound());
+
+        let in_bucket_3 = probes[3];
+        assert_eq!(lazy.predict_to_int(&in_bucket_3.into()),
+                   fib.predict_to_int(&in_bucket_3.into()));
+        assert_eq!(lazy.loads(), 1);
+        assert!(lazy.networks[3].get().is_some());
+        assert_eq!(lazy.networks.iter().filter(|n| n.get().is_some()).count(), 1);
+
+        // cached from then on
+        lazy.predict_to_int(&(in_bucket_3 + 1).into());
+        assert_eq!(lazy.loads(), 1);
+
+        for probe in probes.iter() {
+            assert_eq!(lazy.predict_to_int(&(*probe).into()),
+                       fib.predict_to_int(&(*probe).into()));
+        }
+        assert_eq!(lazy.loads(), 16);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_lazy_offset_table() {
+        let (probes, md) = keys_per_bucket(3);
+        let fib = LearnedFIB::new_with_offset_table(&md, 16, 3);
+        let path = temp_path("lazy_offsets");
+        fib.save(&path).unwrap();
+
+        let lazy = LazyLearnedFIB::open(&path).unwrap();
+        for (key, _) in md.iter().step_by(97) {
+            assert_eq!(lazy.predict_to_int(&key.into()), fib.predict_to_int(&key.into()));
+        }
+        for probe in probes {
+            assert_eq!(lazy.predict_to_int(&probe.into()), fib.predict_to_int(&probe.into()));
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_lazy_has_no_generated_code() {
+        let (_, md) = keys_per_bucket(2);
+        let path = temp_path("lazy_codegen");
+        LearnedFIB::new(&md, 16, 2).save(&path).unwrap();
+
+        // an RMI whose leaves are swapped for lazy ones after training
+        let mut rmi = crate::train(&md, "radix,linear", 4);
+        for leaf in rmi.rmi[1].iter_mut() {
+            *leaf = Box::new(LazyLearnedFIB::open(&path).unwrap());
+        }
+        let (mut code, mut data, mut header) = (Vec::new(), Vec::new(), Vec::new());
+        let err = crate::CodeGen::new("lazy", KeyType::U64)
+            .generate(rmi, &mut code, &mut data, &mut header)
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_lazy_rejects_other_files() {
+        let path = temp_path("not_lfib");
+        std::fs::write(&path, b"RMIB\x01\x00\x00\x00").unwrap();
+        let err = LazyLearnedFIB::open(&path).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(matches!(err, Error::Corrupt { .. }));
+        std::fs::remove_file(&path).unwrap();
+
+        let err = LazyLearnedFIB::open(&path).err().unwrap();
+        assert!(matches!(err, Error::Io(ref e) if e.kind() == ErrorKind::NotFound));
+    }
+}
@@ -6,108 +6,953 @@
 // < end copyright >
 
 use crate::models::*;
-use std::fs;
+use crate::models::utils::{f64_to_pos, num_bits, pos_to_f64};
+use log::*;
+use rayon::prelude::*;
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
 
-use std::convert::{TryFrom, TryInto};
+use std::convert::TryInto;
 
+mod builder;
+mod lazy;
 mod neural_network;
 
+pub use builder::LearnedFIBBuilder;
+pub use lazy::LazyLearnedFIB;
+
 fn clip(inp: u64, prefix: u64) -> usize {
-    let mask: u64 = (1 << prefix) - 1;
-    let val: u64 = (inp & mask) >> prefix;
+    // the bucket is given by the top `prefix` bits of the key
+    if prefix == 0 {
+        return 0;
+    }
+    let val: u64 = inp >> (64 - prefix);
     return u64::try_into(val).unwrap();
 }
 
+// [from, to) index ranges of the keys falling into each bucket.
+fn bucket_ranges<T: TrainingKey>(data: &RMITrainingData<T>, prefix: u64) -> Vec<(usize, usize)> {
+    return match data.iter_borrowed() {
+        Some(records) => bucket_ranges_of::<T, _>(records, prefix),
+        None => bucket_ranges_of::<T, _>(data.iter(), prefix),
+    };
+}
+
+fn bucket_ranges_of<T: TrainingKey, K: Borrow<T>>(
+    records: impl Iterator<Item = (K, usize)>,
+    prefix: u64,
+) -> Vec<(usize, usize)> {
+    let mut ranges: Vec<(usize, usize)> = vec![(0, 0); 1 << prefix];
+    let mut prev_prefix: usize = 0;
+    let mut from: usize = 0;
+    let mut to: usize = 0;
+    for (key, _) in records {
+        let cur_prefix: usize = clip(key.borrow().as_uint(), prefix);
+        if prev_prefix != cur_prefix {
+            ranges[prev_prefix] = (from, to);
+            prev_prefix = cur_prefix;
+            from = to;
+        }
+        to += 1;
+    }
+    // flush the last bucket, which is the only one if every key shares
+    // the same prefix
+    ranges[prev_prefix] = (from, to);
+    return ranges;
+}
+
+/// A piecewise linear network per bucket of keys sharing their top
+/// `prefix` bits. Its parameters, and so its generated data file, list the
+/// buckets in index order, so identical training data always gives
+/// byte-identical output.
+///
+/// The networks compute positions in f64, so training panics (or, from
+/// `LearnedFIBBuilder`, fails) on data with positions past 2^53, which an
+/// f64 cannot tell apart from their neighbours.
 pub struct LearnedFIB {
     prefix: u64,
+
+    // one network per bucket, in bucket order, which `state` relies on.
+    // Storage that is not indexed by bucket, such as a map of only the
+    // non-empty buckets, must be sorted by bucket before it is written.
     neural_networks: Vec<neural_network::NN>,
     max_error: u64,
+
+    // per-bucket error bounds and the key ranges they were measured over,
+    // so a bucket can be rescanned on its own once it is marked dirty.
+    // `ranges` is empty for models rebuilt from their state or set to a
+    // constant.
+    bucket_errors: Vec<u64>,
+    ranges: Vec<(usize, usize)>,
+    dirty: Vec<bool>,
+
+    // in offset table mode, the global position each bucket starts at. The
+    // networks then carry no bias and predict positions within the bucket.
+    offsets: Option<Vec<u64>>,
+
+    // the smallest and largest trained key, unknown after `from_state`
+    domain: Option<(u64, u64)>,
+
+    // integer versions of the networks, predicting in their place once
+    // `use_fixed_point` is called
+    fixed: Option<FixedPoint>,
+
+    // how the buckets were trained, so `retrain` can train them again;
+    // unknown for models built from boundaries or their state
+    training: Option<BucketTraining>,
+}
+
+struct FixedPoint {
+    scale: u32,
+    networks: Vec<neural_network::FixedNN>,
+}
+
+/// Approximate percentiles of the per-key errors, from a uniform sample of
+/// the keys seen by the error pass.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ErrorPercentiles {
+    pub p50: u64,
+    pub p95: u64,
+    pub p99: u64,
+}
+
+// How many errors the reservoir keeps, and how many keys pass between the
+// running percentiles being logged.
+const RESERVOIR_SIZE: usize = 4096;
+const LOG_STATS_EVERY: usize = 1 << 20;
+
+// A reservoir sample (Algorithm R) of the errors seen so far.
+struct ErrorReservoir {
+    samples: Vec<u64>,
+    seen: usize,
+    state: u64,
+}
+
+impl ErrorReservoir {
+    fn new() -> ErrorReservoir {
+        return ErrorReservoir {
+            samples: Vec::with_capacity(RESERVOIR_SIZE),
+            seen: 0,
+            state: 0x9E37_79B9_7F4A_7C15,
+        };
+    }
+
+    fn add(&mut self, err: u64) {
+        self.seen += 1;
+        if self.samples.len() < RESERVOIR_SIZE {
+            self.samples.push(err);
+        } else {
+            // xorshift64, which is plenty for picking a slot
+            self.state ^= self.state << 13;
+            self.state ^= self.state >> 7;
+            self.state ^= self.state << 17;
+            let slot = (self.state % self.seen as u64) as usize;
+            if slot < RESERVOIR_SIZE {
+                self.samples[slot] = err;
+            }
+        }
+
+        if self.seen.is_multiple_of(LOG_STATS_EVERY) {
+            let p = self.percentiles();
+            info!("LearnedFIB errors after {} keys: p50 ~{}, p95 ~{}, p99 ~{}",
+                  self.seen, p.p50, p.p95, p.p99);
+        }
+    }
+
+    fn percentiles(&self) -> ErrorPercentiles {
+        let mut sorted = self.samples.clone();
+        sorted.sort_unstable();
+        return ErrorPercentiles {
+            p50: nearest_rank(&sorted, 0.50),
+            p95: nearest_rank(&sorted, 0.95),
+            p99: nearest_rank(&sorted, 0.99),
+        };
+    }
+}
+
+// the `q`th quantile of `sorted`, or 0 if it is empty
+fn nearest_rank(sorted: &[u64], q: f64) -> u64 {
+    if sorted.is_empty() {
+        return 0;
+    }
+    let rank = (q * sorted.len() as f64).ceil() as usize;
+    return sorted[rank.clamp(1, sorted.len()) - 1];
+}
+
+// Largest error of `predict` over the keys of one bucket, measured against
+// the floored position `predict_to_int` returns. Every key's error is also
+// handed to `stats`, if given.
+fn bucket_error<T: TrainingKey>(
+    predict: impl Fn(&T) -> u64,
+    bucket: &RMITrainingDataView<T>,
+    offset: usize,
+    progress: &mut Progress,
+    total: usize,
+    stats: Option<&mut ErrorReservoir>,
+) -> u64 {
+    // borrowing also starts at the bucket, rather than skipping every
+    // record before it
+    return match bucket.iter_borrowed() {
+        Some(records) => records_error::<T, _>(predict, records, offset, progress, total, stats),
+        None => records_error::<T, _>(predict, bucket.iter(), offset, progress, total, stats),
+    };
+}
+
+fn records_error<T: TrainingKey, K: Borrow<T>>(
+    predict: impl Fn(&T) -> u64,
+    records: impl Iterator<Item = (K, usize)>,
+    offset: usize,
+    progress: &mut Progress,
+    total: usize,
+    mut stats: Option<&mut ErrorReservoir>,
+) -> u64 {
+    let mut max_error = 0;
+    for (idx, (key, value)) in records.enumerate() {
+        progress.update(1, total, offset + idx);
+        let answer = value as u64;
+        let predicted = predict(key.borrow());
+        let err: u64 = u64::max(predicted, answer) - u64::min(predicted, answer);
+        if let Some(stats) = stats.as_mut() {
+            stats.add(err);
+        }
+        if err > max_error {
+            max_error = err;
+        }
+    }
+    return max_error;
+}
+
+// Largest error of `nn` over the records of `bucket`, for comparing two
+// networks trained on it.
+fn view_error<T: TrainingKey>(bucket: &RMITrainingDataView<T>, nn: &neural_network::NN) -> u64 {
+    let mut no_progress = Progress::new(None, 0, 0);
+    let predict = |x: &T| f64_to_pos(nn.predict(&x.to_model_input()), u64::MAX);
+    return bucket_error(predict, bucket, 0, &mut no_progress, 0, None);
+}
+
+/// The error threshold of the networks' linear segments: how far, in
+/// positions, a key may be from its segment before a new one starts.
+/// Plain integers convert to `Absolute`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Threshold {
+    /// A number of positions.
+    Absolute(u64),
+
+    /// A fraction of the number of keys trained on, so the same setting
+    /// suits datasets of any size. `Fraction(0.001)` over a million keys
+    /// is `Absolute(1000)`.
+    Fraction(f64),
+}
+
+impl Threshold {
+    /// The threshold in positions for data of `n` keys, rounded to the
+    /// nearest position.
+    pub fn positions(self, n: usize) -> u64 {
+        return match self {
+            Threshold::Absolute(t) => t,
+            Threshold::Fraction(f) => (f * n as f64).round() as u64,
+        };
+    }
+}
+
+impl From<u64> for Threshold {
+    fn from(t: u64) -> Threshold {
+        return Threshold::Absolute(t);
+    }
+}
+
+// How each bucket's network is trained: the error threshold of its
+// segments, the most segments it may have, how many keys of the
+// neighbouring buckets it also sees, and whether buckets that need more
+// segments than that try a second layer. Fractional thresholds are
+// relative to the whole data, not to a bucket.
+#[derive(Clone, Copy)]
+struct BucketTraining {
+    threshold: Threshold,
+    max_segments: usize,
+    context: usize,
+    second_layer: bool,
+}
+
+impl BucketTraining {
+    fn new(threshold: impl Into<Threshold>) -> BucketTraining {
+        let threshold = threshold.into();
+        return BucketTraining {
+            threshold,
+            max_segments: usize::MAX,
+            context: 0,
+            second_layer: false,
+        };
+    }
 }
 
+// Largest prefix `auto_prefix` will try, and how many prefixes in a row
+// may fail to improve the error before it gives up. The error tends to sit
+// at the threshold until buckets become smaller than a single segment, so
+// the patience has to span that plateau.
+const MAX_AUTO_PREFIX: u64 = 24;
+const AUTO_PREFIX_PATIENCE: u64 = 8;
+
 impl LearnedFIB {
     pub fn new<T: TrainingKey>(
         data: &RMITrainingData<T>,
-        threshold: u64,
+        threshold: impl Into<Threshold>,
         prefix: u64,
     ) -> LearnedFIB {
-        let mut neural_networks: Vec<neural_network::NN> = Vec::new();
-        for _ in 1..(1 << prefix) {
-            neural_networks.push(neural_network::NN::new());
+        return LearnedFIB::new_with_progress(data, threshold, prefix, None);
+    }
+
+    /// Like `new`, but reports the fraction of training done to `progress`
+    /// as buckets are trained and the error bound is computed.
+    pub fn new_with_progress<T: TrainingKey>(
+        data: &RMITrainingData<T>,
+        threshold: impl Into<Threshold>,
+        prefix: u64,
+        progress: Option<&dyn Fn(f64)>,
+    ) -> LearnedFIB {
+        let mut progress = Progress::new(progress, 2, data.len());
+        let fib = LearnedFIB::train_cached(data, prefix, &BucketTraining::new(threshold),
+                                           &mut HashMap::new(), &mut progress, None);
+        progress.finish();
+        return fib;
+    }
+
+    /// Like `new`, but also samples the per-key errors during the error
+    /// pass, logging approximate percentiles as it goes and returning them
+    /// at the end. The error bound is still the exact maximum.
+    pub fn new_with_error_stats<T: TrainingKey>(
+        data: &RMITrainingData<T>,
+        threshold: impl Into<Threshold>,
+        prefix: u64,
+    ) -> (LearnedFIB, ErrorPercentiles) {
+        let mut no_progress = Progress::new(None, 0, 0);
+        let mut stats = ErrorReservoir::new();
+        let fib = LearnedFIB::train_cached(data, prefix, &BucketTraining::new(threshold),
+                                           &mut HashMap::new(), &mut no_progress,
+                                           Some(&mut stats));
+        return (fib, stats.percentiles());
+    }
+
+    /// Like `new`, but no bucket's network gets more than `max_segments`
+    /// linear segments. Buckets that would need more are retrained with a
+    /// coarser threshold, doubled until they fit, which shows up as a
+    /// larger error bound.
+    pub fn new_with_max_segments<T: TrainingKey>(
+        data: &RMITrainingData<T>,
+        threshold: impl Into<Threshold>,
+        prefix: u64,
+        max_segments: usize,
+    ) -> LearnedFIB {
+        assert!(max_segments >= 1, "a network needs at least one segment");
+        let mut no_progress = Progress::new(None, 0, 0);
+        let training = BucketTraining { max_segments, ..BucketTraining::new(threshold) };
+        return LearnedFIB::train_cached(data, prefix, &training,
+                                        &mut HashMap::new(), &mut no_progress, None);
+    }
+
+    /// Like `new`, but each bucket's network is also trained on up to
+    /// `context` keys on either side of its bucket, clamped to the data.
+    /// The networks then extrapolate towards their neighbours' positions,
+    /// which smooths predictions for keys between two buckets' keys. The
+    /// error bound is still measured over each bucket's own keys.
+    pub fn new_with_context<T: TrainingKey>(
+        data: &RMITrainingData<T>,
+        threshold: impl Into<Threshold>,
+        prefix: u64,
+        context: usize,
+    ) -> LearnedFIB {
+        let mut no_progress = Progress::new(None, 0, 0);
+        let training = BucketTraining { context, ..BucketTraining::new(threshold) };
+        return LearnedFIB::train_cached(data, prefix, &training,
+                                        &mut HashMap::new(), &mut no_progress, None);
+    }
+
+    /// Trains with increasing `prefix` values until the maximum error is at
+    /// most `target_error`, or until several larger prefixes in a row fail
+    /// to improve on the best error seen.
+    /// Returns the chosen prefix and its model. Networks are cached by the
+    /// range of keys they cover, so buckets that are not split by the next
+    /// prefix are not retrained.
+    pub fn auto_prefix<T: TrainingKey>(
+        data: &RMITrainingData<T>,
+        threshold: impl Into<Threshold>,
+        target_error: u64,
+    ) -> (u64, LearnedFIB) {
+        let max_prefix = if data.len() < 2 {
+            0
+        } else {
+            u64::min(MAX_AUTO_PREFIX, num_bits(data.len() as u64 - 1) as u64)
+        };
+
+        let mut cache = HashMap::new();
+        let mut no_progress = Progress::new(None, 0, 0);
+        let training = BucketTraining::new(threshold);
+        let mut best = LearnedFIB::train_cached(data, 0, &training,
+                                                &mut cache, &mut no_progress, None);
+        let mut since_improvement = 0;
+        for prefix in 1..=max_prefix {
+            if best.max_error <= target_error || since_improvement >= AUTO_PREFIX_PATIENCE {
+                break;
+            }
+
+            let candidate = LearnedFIB::train_cached(data, prefix, &training,
+                                                     &mut cache, &mut no_progress, None);
+            trace!("LearnedFIB prefix {} has max error {}", prefix, candidate.max_error);
+            if candidate.max_error < best.max_error {
+                best = candidate;
+                since_improvement = 0;
+            } else {
+                since_improvement += 1;
+            }
         }
 
+        return (best.prefix, best);
+    }
+
+    fn train_cached<T: TrainingKey>(
+        data: &RMITrainingData<T>,
+        prefix: u64,
+        training: &BucketTraining,
+        cache: &mut HashMap<(usize, usize), neural_network::NN>,
+        progress: &mut Progress,
+        stats: Option<&mut ErrorReservoir>,
+    ) -> LearnedFIB {
+        neural_network::check_positions(data).unwrap_or_else(|e| panic!("{}", e));
+
         // train
-        let mut prev_prefix: usize = 0;
-        let mut from: usize = 0;
-        let mut to: usize = 0;
-        for datum in data.iter() {
-            let (key, _) = datum;
-            let cur_prefix: usize = clip(key.as_uint(), prefix);
-
-            if prev_prefix != cur_prefix {
-                LearnedFIB::train_subset(
-                    data,
-                    from,
-                    to,
-                    &mut neural_networks[cur_prefix],
-                    threshold as f64,
-                );
-                prev_prefix = cur_prefix;
-                from = to + 1;
-            }
-            to = to + 1;
+        let ranges = bucket_ranges(data, prefix);
+        let mut neural_networks: Vec<neural_network::NN> = Vec::new();
+        for &(from, to) in ranges.iter() {
+            progress.update(0, data.len(), from);
+            if from == to {
+                neural_networks.push(neural_network::NN::new());
+                continue;
+            }
+
+            // networks are cached by the keys they are trained on
+            let lo = from.saturating_sub(training.context);
+            let hi = usize::min(data.len(), to.saturating_add(training.context));
+            let nn = cache.entry((lo, hi)).or_insert_with(|| {
+                let mut nn = neural_network::NN::new();
+                LearnedFIB::train_subset(&data.slice(lo..hi), &mut nn,
+                                         training.threshold.positions(data.len()) as f64,
+                                         training.max_segments, training.second_layer);
+                nn
+            });
+            neural_networks.push(nn.clone());
         }
 
-        // check_error
-        let mut max_error = 0;
-        for datum in data.iter() {
-            let (key, value) = datum;
-            let answer = u64::try_from(value).unwrap() as f64;
-            let nn_idx: usize = clip(key.as_uint(), prefix);
-            let predicted: f64 = neural_networks[nn_idx].inference(key.as_float());
-            let err: u64 = if predicted > answer {
-                (predicted - answer) as u64
-            } else {
-                (answer - predicted) as u64
-            };
-            if err > max_error {
-                max_error = err;
+        let mut fib = LearnedFIB::from_networks(data, prefix, ranges, neural_networks,
+                                                progress, stats);
+        fib.training = Some(*training);
+        return fib;
+    }
+
+    // like `train_cached`, but training the buckets' networks on rayon's
+    // thread pool, without a cache
+    fn train_parallel<T: TrainingKey>(
+        data: &RMITrainingData<T>,
+        prefix: u64,
+        training: &BucketTraining,
+    ) -> LearnedFIB {
+        neural_network::check_positions(data).unwrap_or_else(|e| panic!("{}", e));
+        let ranges = bucket_ranges(data, prefix);
+        let threshold = training.threshold.positions(data.len()) as f64;
+        let neural_networks: Vec<neural_network::NN> = ranges.par_iter()
+            .map(|&(from, to)| {
+                let mut nn = neural_network::NN::new();
+                if from < to {
+                    let lo = from.saturating_sub(training.context);
+                    let hi = usize::min(data.len(), to.saturating_add(training.context));
+                    LearnedFIB::train_subset(&data.slice(lo..hi), &mut nn, threshold,
+                                             training.max_segments, training.second_layer);
+                }
+                nn
+            })
+            .collect();
+
+        let mut no_progress = Progress::new(None, 0, 0);
+        let mut fib = LearnedFIB::from_networks(data, prefix, ranges, neural_networks,
+                                                &mut no_progress, None);
+        fib.training = Some(*training);
+        return fib;
+    }
+
+    /// Builds a model from boundaries found by some other tool, skipping
+    /// the piecewise linear pass: `boundaries[b]` are the points bucket
+    /// `b`'s network interpolates between, as `train_subset` would derive
+    /// them. There must be one list per bucket, empty exactly when the
+    /// bucket has no keys, with strictly increasing keys inside the range
+    /// of the bucket's keys in `data`, and positions no larger than 2^53.
+    /// The error bound is measured over `data`. Returns the first bucket
+    /// whose boundaries are invalid.
+    pub fn from_boundaries<T: TrainingKey>(
+        data: &RMITrainingData<T>,
+        prefix: u64,
+        boundaries: &[Vec<(T, usize)>],
+    ) -> Result<LearnedFIB, usize> {
+        let ranges = bucket_ranges(data, prefix);
+        if boundaries.len() != ranges.len() {
+            return Err(usize::min(boundaries.len(), ranges.len()));
+        }
+
+        let mut neural_networks: Vec<neural_network::NN> = Vec::new();
+        for (bucket, (&(from, to), points)) in ranges.iter().zip(boundaries.iter()).enumerate() {
+            if from == to || points.is_empty() {
+                if from != to || !points.is_empty() {
+                    return Err(bucket);
+                }
+                neural_networks.push(neural_network::NN::new());
+                continue;
             }
+
+            // keys are compared as they are bucketed, by `as_uint`
+            let (lo, hi) = (data.get_key(from).as_uint(), data.get_key(to - 1).as_uint());
+            let sorted = points.windows(2).all(|w| w[0].0.as_uint() < w[1].0.as_uint());
+            let in_range = points.iter().all(|(key, _)| key.as_uint() >= lo && key.as_uint() <= hi);
+            if !sorted || !in_range {
+                return Err(bucket);
+            }
+
+            // the points are not empty, so only their positions can fail
+            let mut nn = neural_network::NN::new();
+            if nn.train(&RMITrainingData::new(Box::new(points.clone()))).is_err() {
+                return Err(bucket);
+            }
+            neural_networks.push(nn);
         }
 
+        let mut no_progress = Progress::new(None, 0, 0);
+        return Ok(LearnedFIB::from_networks(data, prefix, ranges, neural_networks,
+                                            &mut no_progress, None));
+    }
+
+    // measure the error of each bucket's trained network over its keys
+    fn from_networks<T: TrainingKey>(
+        data: &RMITrainingData<T>,
+        prefix: u64,
+        ranges: Vec<(usize, usize)>,
+        neural_networks: Vec<neural_network::NN>,
+        progress: &mut Progress,
+        mut stats: Option<&mut ErrorReservoir>,
+    ) -> LearnedFIB {
+        // check_error
+        let bucket_errors: Vec<u64> = ranges.iter()
+            .zip(neural_networks.iter())
+            .map(|(&(from, to), nn)| {
+                let predict = |x: &T| f64_to_pos(nn.predict(&x.to_model_input()), u64::MAX);
+                bucket_error(predict, &data.slice(from..to), from, progress, data.len(),
+                             stats.as_deref_mut())
+            })
+            .collect();
+        let max_error = bucket_errors.iter().cloned().max().unwrap_or(0);
+
         // return
         return LearnedFIB {
             prefix: prefix,
+            dirty: vec![false; neural_networks.len()],
             neural_networks: neural_networks,
             max_error: max_error,
+            bucket_errors,
+            ranges,
+            offsets: None,
+            domain: data.key_domain(),
+            fixed: None,
+            training: None,
         };
     }
 
-    // same as derive_boundaries() and train nerual network
-    fn train_subset<T: TrainingKey>(
+    /// Like `new`, but stores the global position each bucket starts at
+    /// once, in a table of `1 << prefix` offsets, instead of as the bias of
+    /// every bucket's network. The networks predict positions within their
+    /// bucket, and the offset is added back at inference, so predictions
+    /// and the error bound are the same as `new`'s for trained keys. Keys
+    /// falling into empty buckets predict the position they would be
+    /// inserted at, rather than 0.
+    pub fn new_with_offset_table<T: TrainingKey>(
+        data: &RMITrainingData<T>,
+        threshold: impl Into<Threshold>,
+        prefix: u64,
+    ) -> LearnedFIB {
+        return LearnedFIB::new(data, threshold, prefix).into_offset_table();
+    }
+
+    // moves the networks' biases into an offset table, as for
+    // `new_with_offset_table`
+    fn into_offset_table(mut self) -> LearnedFIB {
+        // a network's bias is the position of its bucket's first key, so
+        // moving it into the table is exact
+        let mut offsets = Vec::with_capacity(self.neural_networks.len());
+        let mut next_start = 0;
+        for (nn, &(from, to)) in self.neural_networks.iter_mut().zip(self.ranges.iter()) {
+            let bias = nn.take_bias();
+            if from == to {
+                offsets.push(next_start);
+            } else {
+                offsets.push(bias as u64);
+                next_start = to as u64;
+            }
+        }
+        self.offsets = Some(offsets);
+
+        return self;
+    }
+
+    // the position `bucket`'s network predicts for `inp`, in global terms
+    fn bucket_inference(&self, bucket: usize, inp: &ModelInput) -> f64 {
+        let local = self.neural_networks[bucket].predict(inp);
+        return match &self.offsets {
+            Some(offsets) => local + offsets[bucket] as f64,
+            None => local,
+        };
+    }
+
+    // the position `bucket` predicts for `inp`, by whichever networks
+    // are in use
+    fn bucket_prediction(&self, bucket: usize, inp: &ModelInput) -> u64 {
+        return match &self.fixed {
+            Some(fixed) => fixed.networks[bucket].inference_cumulative(inp.as_int()),
+            None => f64_to_pos(self.bucket_inference(bucket, inp), u64::MAX),
+        };
+    }
+
+    /// Reports `err` as the error bound, and widens `predict_range_bounds`
+    /// by it, without retraining, so that search code can be tested with
+    /// windows of a known size. `refresh_error_bound` puts back the
+    /// measured bound.
+    pub fn with_error_bound(mut self, err: u64) -> LearnedFIB {
+        self.max_error = err;
+        return self;
+    }
+
+    /// `predict_to_int` of `inp`, along with the index of the bucket whose
+    /// network made the prediction: the key's top `prefix` bits. For
+    /// tracking down which network a wrong lookup went through.
+    pub fn predict_with_network(&self, inp: &ModelInput) -> (u64, usize) {
+        let nn_idx: usize = clip(inp.as_int(), self.prefix);
+        return (self.bucket_prediction(nn_idx, inp), nn_idx);
+    }
+
+    /// Writes `predict_to_int` of each key to `out`, which must be as long
+    /// as `sorted_keys`. The bucket's network and offset are only looked up
+    /// again when the key's prefix changes, which in a sorted batch is
+    /// once per bucket. Keys in any order give the same answers.
+    pub fn predict_batch_sorted(&self, sorted_keys: &[u64], out: &mut [u64]) {
+        assert_eq!(sorted_keys.len(), out.len(), "one output per key");
+        if let Some(fixed) = &self.fixed {
+            let mut bucket = usize::MAX;
+            let mut nn = &fixed.networks[0];
+            for (key, pred) in sorted_keys.iter().zip(out.iter_mut()) {
+                if clip(*key, self.prefix) != bucket {
+                    bucket = clip(*key, self.prefix);
+                    nn = &fixed.networks[bucket];
+                }
+                *pred = nn.inference_cumulative(*key);
+            }
+            return;
+        }
+
+        let mut bucket = usize::MAX;
+        let mut nn = &self.neural_networks[0];
+        let mut offset = 0.0;
+        for (key, pred) in sorted_keys.iter().zip(out.iter_mut()) {
+            if clip(*key, self.prefix) != bucket {
+                bucket = clip(*key, self.prefix);
+                nn = &self.neural_networks[bucket];
+                offset = self.offsets.as_ref().map(|o| o[bucket] as f64).unwrap_or(0.0);
+            }
+            let local = nn.predict(&(*key).into());
+            *pred = f64_to_pos(if self.offsets.is_some() { local + offset } else { local },
+                               u64::MAX);
+        }
+    }
+
+    fn fixed_networks(&self, scale: u32) -> Vec<neural_network::FixedNN> {
+        return self.neural_networks.iter()
+            .enumerate()
+            .map(|(bucket, nn)| {
+                let offset = self.offsets.as_ref().map(|o| o[bucket]).unwrap_or(0);
+                nn.to_fixed(scale, offset)
+            })
+            .collect();
+    }
+
+    /// Predicts with fixed point versions of the networks from now on,
+    /// and returns the error bound rescanned over `data`, which must be
+    /// the data the model was trained on. Floating point predictions can
+    /// differ between Rust and C++ depending on whether `fma` is fused,
+    /// while the fixed point ones are bit for bit the same everywhere.
+    /// The scale is the largest every network's slopes fit at.
+    pub fn use_fixed_point<T: TrainingKey>(&mut self, data: &RMITrainingData<T>) -> u64 {
+        let scale = self.neural_networks.iter()
+            .map(|nn| nn.max_fixed_scale())
+            .min()
+            .unwrap_or(neural_network::MAX_FIXED_SCALE);
+        self.fixed = Some(FixedPoint { scale, networks: self.fixed_networks(scale) });
+        self.dirty.iter_mut().for_each(|d| *d = true);
+        return self.refresh_error_bound(data);
+    }
+
+    /// The number of fractional bits of the fixed point slopes, if the
+    /// model predicts in fixed point.
+    pub fn fixed_point_scale(&self) -> Option<u32> {
+        return self.fixed.as_ref().map(|f| f.scale);
+    }
+
+    /// Marks `bucket`'s error bound as stale, so the next call to
+    /// `refresh_error_bound` rescans its keys. Buckets that are not marked
+    /// keep their cached error.
+    pub fn mark_bucket_dirty(&mut self, bucket: usize) {
+        self.dirty[bucket] = true;
+    }
+
+    /// Rescans the keys of every dirty bucket and returns the new global
+    /// error bound, which `error_bound` reports from then on. `data` must
+    /// be the data the model was trained on, plus whatever changes made
+    /// the buckets dirty.
+    pub fn refresh_error_bound<T: TrainingKey>(&mut self, data: &RMITrainingData<T>) -> u64 {
+        if self.ranges.is_empty() {
+            self.ranges = bucket_ranges(data, self.prefix);
+        }
+
+        let mut no_progress = Progress::new(None, 0, 0);
+        for bucket in 0..self.dirty.len() {
+            if !self.dirty[bucket] {
+                continue;
+            }
+            let (from, to) = self.ranges[bucket];
+            let err = bucket_error(|x: &T| self.bucket_prediction(bucket, &x.to_model_input()),
+                                   &data.slice(from..to), from,
+                                   &mut no_progress, data.len(), None);
+            self.bucket_errors[bucket] = err;
+            self.dirty[bucket] = false;
+        }
+
+        self.max_error = self.bucket_errors.iter().cloned().max().unwrap_or(0);
+        return self.max_error;
+    }
+
+    // Trains again the networks of the buckets whose keys moved, or whose
+    // training keys overlap `changed`, leaving the others as they are, and
+    // rebuilds the offsets and fixed point networks from the result.
+    fn retrain_buckets<T: TrainingKey>(
+        &mut self,
         data: &RMITrainingData<T>,
-        from: usize,
-        to: usize,
+        changed: Range<usize>,
+        training: &BucketTraining,
+    ) -> u64 {
+        let ranges = bucket_ranges(data, self.prefix);
+        for (bucket, &(from, to)) in ranges.iter().enumerate() {
+            let lo = from.saturating_sub(training.context);
+            let hi = usize::min(data.len(), to.saturating_add(training.context));
+            let overlaps = from < to && lo < changed.end && hi > changed.start;
+            if self.ranges.get(bucket) == Some(&ranges[bucket]) && !overlaps {
+                continue;
+            }
+
+            let mut nn = neural_network::NN::new();
+            if from < to {
+                LearnedFIB::train_subset(&data.slice(lo..hi), &mut nn,
+                                         training.threshold.positions(data.len()) as f64,
+                                         training.max_segments, training.second_layer);
+            }
+            if let Some(offsets) = self.offsets.as_mut() {
+                offsets[bucket] = nn.take_bias() as u64;
+            }
+            self.neural_networks[bucket] = nn;
+            self.dirty[bucket] = true;
+        }
+
+        // as in `new_with_offset_table`, empty buckets start where the
+        // previous keys end
+        if let Some(offsets) = self.offsets.as_mut() {
+            let mut next_start = 0;
+            for (offset, &(from, to)) in offsets.iter_mut().zip(ranges.iter()) {
+                if from == to {
+                    *offset = next_start;
+                } else {
+                    next_start = to as u64;
+                }
+            }
+        }
+        self.ranges = ranges;
+        self.domain = data.key_domain();
+
+        if self.fixed.is_some() {
+            return self.use_fixed_point(data);
+        }
+        return self.refresh_error_bound(data);
+    }
+
+    // derive the bucket's boundaries and train its network on them, with
+    // at most `max_segments` segments between the boundaries. With
+    // `second_layer`, a bucket that needed more segments than that also
+    // tries `stack_second_layer`, and keeps whichever network is closer.
+    fn train_subset<T: TrainingKey>(
+        bucket: &RMITrainingDataView<T>,
         nn: &mut neural_network::NN,
         threshold: f64,
+        max_segments: usize,
+        second_layer: bool,
     ) {
-        let mut boundary: Vec<(T, usize)> = Vec::new();
-        let mut l: usize = from;
-        for r in (from + 2)..to {
+        // an empty bucket keeps the untrained network, which predicts 0
+        if bucket.is_empty() {
+            return;
+        }
+
+        let (boundary, coarse) = LearnedFIB::capped_boundaries(bucket, threshold, max_segments);
+        if coarse != threshold {
+            warn!("LearnedFIB bucket of {} keys needed more than {} segments; \
+                   raised its threshold from {} to {}",
+                  bucket.len(), max_segments, threshold, coarse);
+        }
+
+        // a non-empty bucket has at least its first key as a boundary, and
+        // the data's positions were checked before training
+        nn.train(&RMITrainingData::new(Box::new(boundary))).unwrap();
+
+        if second_layer && coarse != threshold {
+            let stacked = LearnedFIB::stack_second_layer(bucket, threshold, max_segments);
+            let (single, double) = (view_error(bucket, nn), view_error(bucket, &stacked));
+            if double < single {
+                debug!("LearnedFIB bucket of {} keys took a second layer, lowering its \
+                        error from {} to {}", bucket.len(), single, double);
+                *nn = stacked;
+            }
+        }
+    }
+
+    // the bucket's boundaries, with the threshold doubled until there are
+    // at most `max_segments` segments between them, and that threshold. No
+    // point strays from a line through its segment's ends by more than the
+    // span of the bucket's positions, so once the threshold reaches that
+    // span there is a single segment.
+    fn capped_boundaries<T: TrainingKey>(
+        bucket: &RMITrainingDataView<T>,
+        threshold: f64,
+        max_segments: usize,
+    ) -> (Vec<(T, usize)>, f64) {
+        let mut coarse = threshold;
+        let mut boundary = LearnedFIB::derive_boundaries(bucket, coarse);
+        while boundary.len() - 1 > max_segments {
+            coarse = f64::max(1.0, 2.0 * coarse);
+            boundary = LearnedFIB::derive_boundaries(bucket, coarse);
+        }
+        return (boundary, coarse);
+    }
+
+    // A network of two layers, for buckets whose CDF is step-like: keys in
+    // clusters, with more gaps between them than `max_segments` segments
+    // can follow. The first layer squeezes the widest gaps between
+    // neighbouring keys down to a single unit each, as many as fit in
+    // `max_segments` segments at two bends per gap, and the second is
+    // trained as usual on the squeezed keys, which no longer step there.
+    // Both layers are monotone and piecewise linear, so their composition
+    // is too: it is returned as a single network bending at the first
+    // layer's knots and at the keys the first layer maps onto the
+    // second's, so it is stored, lazily loaded and put in fixed point like
+    // any other, with up to twice `max_segments` segments.
+    fn stack_second_layer<T: TrainingKey>(
+        bucket: &RMITrainingDataView<T>,
+        threshold: f64,
+        max_segments: usize,
+    ) -> neural_network::NN {
+        let base = bucket.get_key(0).as_uint();
+        let xs: Vec<f64> = (0..bucket.len())
+            .map(|idx| neural_network::relative_key(&bucket.get_key(idx).to_model_input(), base))
+            .collect();
+
+        // the widest gaps, by the index of the key before them
+        let mut gaps: Vec<usize> = (0..xs.len() - 1).filter(|i| xs[i + 1] > xs[*i]).collect();
+        gaps.sort_by(|a, b| (xs[b + 1] - xs[*b]).partial_cmp(&(xs[a + 1] - xs[*a])).unwrap());
+        gaps.truncate(max_segments.saturating_sub(1) / 2);
+        gaps.sort_unstable();
+
+        let end = xs[xs.len() - 1];
+        let mut corners: Vec<(f64, f64)> = vec![(0.0, 0.0)];
+        let mut squeezed = 0.0;
+        for gap in gaps {
+            let (lo, hi) = (xs[gap], xs[gap + 1]);
+            if lo > corners.last().unwrap().0 {
+                corners.push((lo, lo - squeezed));
+            }
+            squeezed += hi - lo - 1.0;
+            corners.push((hi, hi - squeezed));
+        }
+        if end > corners.last().unwrap().0 {
+            corners.push((end, end - squeezed));
+        }
+        let first = neural_network::NN::through_points(0, &corners);
+
+        let outputs: Vec<(f64, usize)> = (0..bucket.len())
+            .map(|idx| (first.inference(xs[idx]), bucket.get(idx).1))
+            .collect();
+        let outputs = RMITrainingData::new(Box::new(outputs));
+        let (boundary, _) = LearnedFIB::capped_boundaries(&outputs.slice(0..outputs.len()),
+                                                          threshold, max_segments);
+        let mut second = neural_network::NN::new();
+        second.train(&RMITrainingData::new(Box::new(boundary))).unwrap();
+
+        // the first layer's corners, and where it crosses each of the
+        // second's knots
+        let second_base = second.key_base() as f64;
+        let mut bends: Vec<f64> = corners.iter().map(|(x, _)| *x).collect();
+        for knot in second.knots().into_iter().map(|u| u + second_base) {
+            let crossing = corners.windows(2)
+                .find(|w| w[0].1 < knot && knot <= w[1].1)
+                .map(|w| w[0].0 + (knot - w[0].1) / (w[1].1 - w[0].1) * (w[1].0 - w[0].0));
+            bends.extend(crossing);
+        }
+        bends.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        bends.dedup();
+
+        let points: Vec<(f64, f64)> = bends.into_iter()
+            .map(|x| (x, second.predict(&ModelInput::Float(first.inference(x)))))
+            .collect();
+        return neural_network::NN::through_points(base, &points);
+    }
+
+    // the points where the bucket's piecewise linear fit changes slope, so
+    // that every key is within `threshold` of the line through its segment
+    fn derive_boundaries<T: TrainingKey>(
+        bucket: &RMITrainingDataView<T>,
+        threshold: f64,
+    ) -> Vec<(T, usize)> {
+        // up to two keys, every key is its own boundary (a duplicate
+        // second key adds nothing)
+        match (bucket.try_get(0), bucket.try_get(1), bucket.try_get(2)) {
+            (None, _, _) => return Vec::new(),
+            (Some(first), None, _) => return vec![first],
+            (Some(first), Some(second), None) if first.0 == second.0 => return vec![first],
+            (Some(first), Some(second), None) => return vec![first, second],
+            _ => {}
+        }
+
+        // keys are compared as the network will see them, relative to the
+        // bucket's first key
+        let base = bucket.get_key(0).as_uint();
+        let x = |key: &T| neural_network::relative_key(&key.to_model_input(), base);
+
+        let mut boundary: Vec<(T, usize)> = vec![bucket.get(0)];
+        let mut l: usize = 0;
+        for r in 2..bucket.len() {
             // handling duplicate keys
-            if data.get(l).0 == data.get(r).0 {
+            if bucket.get(l).0 == bucket.get(r).0 {
                 continue;
             }
-            if data.get(l).0 == data.get(r - 1).0 {
+            if bucket.get(l).0 == bucket.get(r - 1).0 {
                 continue;
             }
 
             // return type of RMITrainingData.get() -> (T: TrainingKey, usize)
-            let (key_l, val_l) = data.get(l);
-            let (key_r, val_r) = data.get(r);
-            let x_l: f64 = key_l.as_float();
-            let y_l: f64 = u64::try_from(val_l).unwrap() as f64;
-            let x_r: f64 = key_r.as_float();
-            let y_r: f64 = u64::try_from(val_r).unwrap() as f64;
+            let (key_l, val_l) = bucket.get(l);
+            let (key_r, val_r) = bucket.get(r);
+            let x_l: f64 = x(&key_l);
+            let y_l: f64 = pos_to_f64(val_l);
+            let x_r: f64 = x(&key_r);
+            let y_r: f64 = pos_to_f64(val_r);
 
             // Derive a line's (slope, bias) passing through (x_l, l) and (x_r, r)
             let a: f64 = (y_r - y_l) / (x_r - x_l);
@@ -115,51 +960,229 @@ impl LearnedFIB {
 
             // Examine the error between x_(l+1) and x_(r-1)
             for i in (l + 1)..(r - 1) {
-                let (key_i, val_i) = data.get(i);
-                let x_i = key_i.as_float();
-                let y_i = u64::try_from(val_i).unwrap() as f64;
+                let (key_i, val_i) = bucket.get(i);
+                let x_i = x(&key_i);
+                let y_i = pos_to_f64(val_i);
 
                 // compute the y-value on the line for the x-value of x_i
                 let p: f64 = a * x_i + b;
 
                 let err: f64 = if p > y_i { p - y_i } else { y_i - p };
                 if err > threshold {
-                    boundary.push(data.get(r - 1));
+                    boundary.push(bucket.get(r - 1));
                     l = r - 1;
                     break;
                 }
             }
         }
 
-        // insert last point if not inserted
-        let last_data = data.get_key(data.len() - 1);
-        let last_boundary = boundary.last().unwrap().0;
-        if last_data != last_boundary {
-            boundary.push(data.get(data.len() - 1));
+        // insert the bucket's last point if not inserted. The keys are
+        // compared as the network sees them, since trailing keys that
+        // differ but round to the same f64 would give it a vertical segment
+        let last_data = bucket.len().checked_sub(1).and_then(|idx| bucket.try_get(idx));
+        if let (Some(last_data), Some(last_boundary)) = (last_data, boundary.last()) {
+            if x(&last_data.0) != x(&last_boundary.0) {
+                boundary.push(last_data);
+            }
+        }
+
+        return boundary;
+    }
+
+    /// Writes the model to a single file, with an index of where each
+    /// bucket's network starts so `LazyLearnedFIB::open` can read the
+    /// networks one at a time. Fixed point models cannot be saved, as the
+    /// file has no room for their scale.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if self.fixed.is_some() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      "cannot save a fixed point LearnedFIB"));
         }
+        return lazy::write(self, path);
+    }
+
+    /// The piecewise linear function each bucket's network computes, as
+    /// `(key, position)` points in key order from the bucket's smallest
+    /// possible key to its largest. Interpolating between consecutive
+    /// points reproduces the network's predictions (up to flooring of the
+    /// positions), so the segments can be searched as a standalone PLR
+    /// index.
+    pub fn export_segments(&self) -> Vec<Vec<(f64, u64)>> {
+        let width = 64 - self.prefix;
+        return self.neural_networks.iter()
+            .enumerate()
+            .map(|(idx, nn)| {
+                let (low, high) = if self.prefix == 0 {
+                    (0, u64::MAX)
+                } else {
+                    let low = (idx as u64) << width;
+                    (low, low | (u64::MAX >> self.prefix))
+                };
+                let (low, high) = (low as f64, high as f64);
+
+                let base = nn.key_base() as f64;
+                let mut xs = vec![low];
+                xs.extend(nn.knots().into_iter()
+                    .map(|x| x + base)
+                    .filter(|x| *x > low && *x < high));
+                xs.push(high);
+
+                return xs.into_iter()
+                    .map(|x| (x, f64_to_pos(self.bucket_inference(idx, &x.into()), u64::MAX)))
+                    .collect();
+            })
+            .collect();
+    }
 
-        nn.train(&RMITrainingData::new(Box::new(boundary)));
+    /// The key each bucket's network takes its inputs relative to: the
+    /// first key it was trained on, or 0 for empty buckets. Networks see
+    /// a key's distance above the base rather than the key itself, which
+    /// an f64 holds exactly however large the key, as long as the bucket's
+    /// keys span less than 2^53. A bucket covers 2^(64 - prefix) keys, so
+    /// with a prefix below 11 its keys may span more, and keys further than
+    /// 2^53 above the base lose their low bits.
+    pub fn key_bases(&self) -> Vec<u64> {
+        return self.neural_networks.iter().map(|nn| nn.key_base()).collect();
     }
 
-    // save NN
-    fn save(&self, path: String) -> std::io::Result<()> {
-        for i in 0..(1 << self.prefix) {
-            let file_name: String = String::from("nn_") + &i.to_string();
-            fs::File::create(&file_name)?;
-            self.neural_networks[i].save(&file_name)?;
+    // the offset table, as 32-bit offsets when every one of them fits
+    fn offset_table_param(&self) -> Option<ModelParam> {
+        let offsets = self.offsets.as_ref()?;
+        if offsets.iter().all(|o| *o <= u32::MAX as u64) {
+            return Some(offsets.iter().map(|o| *o as u32).collect::<Vec<u32>>().into());
         }
-        Ok(())
+        return Some(offsets.clone().into());
+    }
+
+    pub(crate) fn from_state(params: &[ModelParam]) -> Option<LearnedFIB> {
+        let (prefix, max_error, rest) = match params {
+            [ModelParam::Int(prefix), ModelParam::Int(max_error), rest @ ..] =>
+                (*prefix, *max_error, rest),
+            _ => return None,
+        };
+        // fixed point models store their scale before the offsets
+        let (scale, rest) = match rest {
+            [ModelParam::Int(scale), rest @ ..] => (Some(*scale), rest),
+            rest => (None, rest),
+        };
+        let (offsets, networks) = match rest {
+            [ModelParam::IntArray(offsets), networks @ ..] => (Some(offsets.clone()), networks),
+            [ModelParam::Int32Array(offsets), networks @ ..] =>
+                (Some(offsets.iter().map(|o| *o as u64).collect()), networks),
+            networks => (None, networks),
+        };
+        // the key bases come last. States written before networks took
+        // relative keys have none, and take every key as it is.
+        let (networks, key_bases) = match networks {
+            [networks @ .., ModelParam::IntArray(bases)] => (networks, Some(bases)),
+            networks => (networks, None),
+        };
+
+        // networks in offset table mode are stored without their bias
+        let neural_networks = networks.iter()
+            .map(|p| match p {
+                ModelParam::FloatArray(flat) if offsets.is_some() => {
+                    let mut flat = flat.clone();
+                    flat.push(0.0);
+                    neural_network::NN::from_flat(&flat)
+                }
+                ModelParam::FloatArray(flat) => neural_network::NN::from_flat(flat),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        if prefix >= 64 || neural_networks.len() != 1 << prefix {
+            return None;
+        }
+        if key_bases.is_some_and(|b| b.len() != neural_networks.len()) {
+            return None;
+        }
+        let neural_networks = match key_bases {
+            Some(bases) => neural_networks.into_iter()
+                .zip(bases.iter())
+                .map(|(nn, base)| nn.with_key_base(*base))
+                .collect(),
+            None => neural_networks,
+        };
+        if offsets.as_ref().is_some_and(|o| o.len() != neural_networks.len()) {
+            return None;
+        }
+        if scale.is_some_and(|s| s > neural_network::MAX_FIXED_SCALE as u64) {
+            return None;
+        }
+
+        // only the global bound is stored, so every bucket starts out with
+        // it and is rescanned on the first refresh
+        let buckets = neural_networks.len();
+        let mut fib = LearnedFIB {
+            prefix, neural_networks, max_error,
+            bucket_errors: vec![max_error; buckets],
+            ranges: Vec::new(),
+            dirty: vec![true; buckets],
+            offsets,
+            domain: None,
+            fixed: None,
+            training: None,
+        };
+        if let Some(scale) = scale {
+            let scale = scale as u32;
+            fib.fixed = Some(FixedPoint { scale, networks: fib.fixed_networks(scale) });
+        }
+        return Some(fib);
     }
 }
 
 impl Model for LearnedFIB {
     fn predict_to_float(&self, inp: &ModelInput) -> f64 {
         let nn_idx: usize = clip(inp.as_int(), self.prefix);
-        return self.neural_networks[nn_idx].inference(inp.as_float());
+        if self.fixed.is_some() {
+            return self.bucket_prediction(nn_idx, inp) as f64;
+        }
+        return self.bucket_inference(nn_idx, inp);
     }
 
     fn predict_to_int(&self, inp: &ModelInput) -> u64 {
-        return f64::max(0.0, self.predict_to_float(inp).floor()) as u64;
+        return self.predict_with_network(inp).0;
+    }
+
+    // the key's bucket network, all of which a prediction runs, in the
+    // worst case of the largest network, plus its offset if there is one
+    fn lookup_cache_lines(&self) -> usize {
+        let network = self.neural_networks.iter()
+            .map(|nn| nn.size_bytes().div_ceil(CACHE_LINE_BYTES))
+            .max()
+            .unwrap_or(0);
+        return network + self.offsets.is_some() as usize;
+    }
+
+    // the shift to the key's bucket, its network's inference, and loading
+    // and adding the bucket's offset if there is one
+    fn estimated_flops(&self, inp: &ModelInput) -> u64 {
+        let bucket = clip(inp.as_int(), self.prefix);
+        let network = match &self.fixed {
+            Some(fixed) => fixed.networks[bucket].inference_flops(),
+            None => self.neural_networks[bucket].inference_flops(),
+        };
+        return 1 + network + 2 * self.offsets.is_some() as u64;
+    }
+
+    fn reference_predict(&self, inp: &ModelInput) -> u64 {
+        let key = inp.as_int();
+        let bucket = (key as u128 / (1u128 << (64 - self.prefix))) as usize;
+        if let Some(fixed) = &self.fixed {
+            return fixed.networks[bucket].inference(key);
+        }
+
+        let mut pos = self.neural_networks[bucket].predict(&ModelInput::Int(key));
+        if let Some(offsets) = &self.offsets {
+            pos += offsets[bucket] as f64;
+        }
+        return f64_to_pos(pos, u64::MAX);
+    }
+
+    // only the fixed point networks; the float ones compute in f64
+    fn integer_only(&self) -> bool {
+        return self.fixed.is_some();
     }
 
     fn input_type(&self) -> ModelDataType {
@@ -170,23 +1193,130 @@ impl Model for LearnedFIB {
         return ModelDataType::Int;
     }
 
-    // TODO
+    // The prefix, then a table of every bucket's entry followed by the
+    // networks the entries point into, so the table has as many entries
+    // and the model as many parameters whatever the networks' sizes. In
+    // floating point, the entry is the key base, the index of the
+    // network's first weight, its number of units and the bucket's offset
+    // (0 without an offset table), and the network is laid out as by
+    // `NN::to_flat`. In fixed point, the scale comes after the prefix and
+    // the entry is the index of the network's first knot, its number of
+    // units and its bias, and each network's knots are followed by its
+    // slopes.
     fn params(&self) -> Vec<ModelParam> {
-        return Vec::new();
+        let buckets = self.neural_networks.len();
+        if let Some(fixed) = &self.fixed {
+            let mut entries: Vec<u64> = Vec::with_capacity(3 * buckets);
+            let mut units: Vec<u64> = Vec::new();
+            for nn in fixed.networks.iter() {
+                entries.extend([(3 * buckets + units.len()) as u64,
+                                nn.knots().len() as u64, nn.bias()]);
+                units.extend_from_slice(nn.knots());
+                units.extend(nn.slopes().iter().map(|s| *s as u64));
+            }
+            entries.extend(units);
+            return vec![self.prefix.into(), (fixed.scale as u64).into(), entries.into()];
+        }
+
+        let mut entries: Vec<u64> = Vec::with_capacity(4 * buckets);
+        let mut weights: Vec<f64> = Vec::new();
+        for (bucket, nn) in self.neural_networks.iter().enumerate() {
+            let offset = self.offsets.as_ref().map(|o| o[bucket]).unwrap_or(0);
+            entries.extend([nn.key_base(), weights.len() as u64, nn.segments() as u64, offset]);
+            weights.extend(nn.to_flat());
+        }
+        return vec![self.prefix.into(), entries.into(), weights.into()];
+    }
+
+    // The flat buffer follows the serialized state rather than the
+    // emitted parameters: the prefix, the error bound, then each bucket's
+    // network, in bucket order, as weights1, weights2, biases1, bias2,
+    // and last the networks' key bases.
+    fn params_bytes(&self) -> Vec<u8> {
+        return flatten_params(&self.state().unwrap().1);
+    }
+
+    fn params_layout(&self) -> Vec<ParamField> {
+        return params_layout_of(&self.state().unwrap().1);
     }
 
-    // TODO
+    // The inference of the key's bucket, matching `bucket_prediction` bit
+    // for bit. In fixed point, `learned_fib_fixed` and
+    // `learned_fib_fixed_cumulative` match `FixedNN::inference` and
+    // `FixedNN::inference_cumulative` for a single network, and the leaf
+    // runs the former, as the cumulative lines need 128-bit parameters.
     fn code(&self) -> String {
+        if self.fixed.is_some() {
+            return String::from(
+                "
+inline uint64_t learned_fib_fixed(const uint64_t* knots, const int64_t* slopes,
+                                  size_t units, uint64_t bias, uint32_t scale,
+                                  uint64_t inp) {
+    unsigned __int128 acc = (unsigned __int128) bias << scale;
+    for (size_t i = 0; i < units; i++) {
+        if (inp > knots[i])
+            acc += (unsigned __int128) (__int128) slopes[i]
+                * (unsigned __int128) (inp - knots[i]);
+    }
+    if (acc >> 127) return 0;
+    acc >>= scale;
+    return acc > UINT64_MAX ? UINT64_MAX : (uint64_t) acc;
+}
+
+inline uint64_t learned_fib_fixed_cumulative(const uint64_t* knots,
+                                             const unsigned __int128* cum_slopes,
+                                             const unsigned __int128* cum_intercepts,
+                                             size_t units, uint32_t scale, uint64_t inp) {
+    size_t segment = 0;
+    for (size_t i = 0; i < units; i++)
+        segment += inp > knots[i];
+    unsigned __int128 acc = cum_intercepts[segment]
+        + cum_slopes[segment] * (unsigned __int128) inp;
+    if (acc >> 127) return 0;
+    acc >>= scale;
+    return acc > UINT64_MAX ? UINT64_MAX : (uint64_t) acc;
+}
+
+inline uint64_t learned_fib_fixed_leaf(uint64_t prefix, uint64_t scale,
+                                       const uint64_t* table, uint64_t inp) {
+    const uint64_t* entry = table + 3 * (prefix == 0 ? 0 : inp >> (64 - prefix));
+    const uint64_t* knots = table + entry[0];
+    return learned_fib_fixed(knots, (const int64_t*) (knots + entry[1]), entry[1],
+                             entry[2], (uint32_t) scale, inp);
+}",
+            );
+        }
         return String::from(
             "
-inline uint64 learned_fib(char *mod_path, double inp) {
-    return std::fma(beta, inp, alpha);
+inline uint64_t learned_fib(uint64_t prefix, const uint64_t* table,
+                            const double* weights, uint64_t inp) {
+    const uint64_t* entry = table + 4 * (prefix == 0 ? 0 : inp >> (64 - prefix));
+    const double* nn = weights + entry[1];
+    const uint64_t units = entry[2];
+    double x = (double) (inp > entry[0] ? inp - entry[0] : 0);
+    double sum = 0.0;
+    for (uint64_t i = 0; i < units; i++)
+        sum += std::fmax(0.0, std::fma(x, nn[i], nn[2 * units + i])) * nn[units + i];
+    double pos = sum + nn[3 * units] + (double) entry[3];
+    if (!(pos > 0.0)) return 0;
+    pos = std::floor(pos);
+    return pos >= 18446744073709551616.0 ? UINT64_MAX : (uint64_t) pos;
 }",
         );
     }
 
     fn function_name(&self) -> String {
-        return String::from("LearnedFIB");
+        if self.fixed.is_some() {
+            return String::from("learned_fib_fixed_leaf");
+        }
+        return String::from("learned_fib");
+    }
+
+    fn required_includes(&self) -> Vec<&'static str> {
+        if self.fixed.is_some() {
+            return vec!["<cstddef>", "<cstdint>"];
+        }
+        return vec!["<cmath>", "<cstdint>"];
     }
 
     fn needs_bounds_check(&self) -> bool {
@@ -197,14 +1327,1018 @@ inline uint64 learned_fib(char *mod_path, double inp) {
         return ModelRestriction::MustBeBottom;
     }
 
+    fn retrain(&mut self, data: &RMITrainingData<u64>,
+               changed: Range<usize>) -> Result<(), Error> {
+        let training = self.training.ok_or_else(|| Error::InvalidArgument {
+            reason: String::from(
+                "LearnedFIB built from boundaries or its state cannot be retrained"),
+        })?;
+        self.retrain_buckets(data, changed, &training);
+        return Ok(());
+    }
+
+    // every network predicts the constant, keeping the layout (bucket
+    // count, offset table, fixed point) the same as the other leaves'
+    fn set_to_constant_model(&mut self, constant: u64) -> bool {
+        let nn = neural_network::NN::constant(constant as f64);
+        self.neural_networks.iter_mut().for_each(|n| *n = nn.clone());
+        if let Some(offsets) = self.offsets.as_mut() {
+            offsets.iter_mut().for_each(|o| *o = 0);
+        }
+        if let Some(scale) = self.fixed.as_ref().map(|f| f.scale) {
+            self.fixed = Some(FixedPoint { scale, networks: self.fixed_networks(scale) });
+        }
+
+        self.bucket_errors.iter_mut().for_each(|e| *e = 0);
+        self.dirty.iter_mut().for_each(|d| *d = false);
+        self.ranges = Vec::new();
+        self.max_error = 0;
+        return true;
+    }
+
     fn error_bound(&self) -> Option<u64> {
         return Some(self.max_error);
     }
+    fn key_domain(&self) -> Option<(u64, u64)> {
+        return self.domain;
+    }
+    fn supports_range_query(&self) -> bool {
+        return true;
+    }
+    fn predict_range_bounds(&self, lo: &ModelInput, hi: &ModelInput) -> (u64, u64) {
+        // Each network is monotonic, but buckets are not monotonic with
+        // respect to each other: a network extrapolates past its last key,
+        // and empty buckets predict 0 unless there is an offset table. A
+        // key in `lo`'s bucket is found from `lo`'s prediction, and a key
+        // in a later bucket is at or after that bucket's first position.
+        let populated = |b: usize| self.offsets.is_some()
+            || !self.neural_networks[b].is_untrained();
+        let first_position = |b: usize| self.bucket_prediction(b, &ModelInput::Int(0));
+
+        let bucket = clip(lo.as_int(), self.prefix);
+        let mut start = self.bucket_prediction(bucket, lo);
+        if let Some(next) = (bucket + 1..self.neural_networks.len()).find(|b| populated(*b)) {
+            start = u64::min(start, first_position(next));
+        }
+
+        // keys up to `hi` are in the last populated bucket at or before
+        // its own, whose network bounds them all from above
+        let bucket = clip(hi.as_int(), self.prefix);
+        let owner = (0..=bucket).rev().find(|b| populated(*b)).unwrap_or(0);
+        let end = self.bucket_prediction(owner, hi);
+
+        return (start.saturating_sub(self.max_error),
+                end.saturating_add(self.max_error).saturating_add(1));
+    }
+    fn kind(&self) -> ModelKind {
+        return ModelKind::LearnedFIB;
+    }
+
+    fn state(&self) -> Option<(ModelKind, Vec<ModelParam>)> {
+        let mut params: Vec<ModelParam> = vec![self.prefix.into(), self.max_error.into()];
+        if let Some(fixed) = &self.fixed {
+            params.push((fixed.scale as u64).into());
+        }
+        match self.offset_table_param() {
+            Some(offsets) => {
+                params.push(offsets);
+                params.extend(self.neural_networks.iter().map(|nn| {
+                    let mut flat = nn.to_flat();
+                    flat.pop();
+                    flat.into()
+                }));
+            }
+            None => params.extend(self.neural_networks.iter().map(|nn| nn.to_flat().into())),
+        }
+        params.push(self.key_bases().into());
+        return Some((self.kind(), params));
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        return self;
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::cell::RefCell;
+
+    // sorted pseudo-random keys spread over the whole key space
+    fn random_keys(n: usize, seed: u64) -> Vec<(u64, usize)> {
+        let mut state = seed;
+        let mut keys: Vec<u64> = (0..n)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state
+            })
+            .collect();
+        keys.sort();
+        keys.dedup();
+        return keys.into_iter().enumerate().map(|(idx, k)| (k, idx)).collect();
+    }
+
+    #[test]
+    fn test_single_bucket() {
+        // every key is below 2^40, so the top 4 bits are always zero
+        let data: Vec<(u64, usize)> = random_keys(4096, 7).into_iter()
+            .map(|(k, idx)| (k >> 24, idx))
+            .collect();
+        let md = RMITrainingData::new(Box::new(data.clone()));
+
+        let ranges = bucket_ranges(&md, 4);
+        assert_eq!(ranges[0], (0, data.len()));
+        assert!(ranges[1..].iter().all(|&(from, to)| from == to));
+
+        let fib = LearnedFIB::new(&md, 16, 4);
+        let bound = fib.error_bound().unwrap();
+        assert!(bound <= 16, "error bound {} exceeds the threshold", bound);
+        for (key, idx) in data {
+            let pred = fib.predict_to_int(&key.into());
+            assert!(u64::max(pred, idx as u64) - u64::min(pred, idx as u64) <= bound);
+        }
+    }
+
+    #[test]
+    fn test_tiny_buckets() {
+        // buckets 0 to 3 of a 2-bit prefix hold 0, 1, 2 and 3 keys, far
+        // enough apart to stay distinct as f64s
+        let keys: Vec<u64> = vec![
+            1 << 62,
+            2 << 62, (2 << 62) | (1 << 40),
+            3 << 62, (3 << 62) | (1 << 40), (3 << 62) | (1 << 50),
+        ];
+        let md = RMITrainingData::from_keys(keys.clone());
+        let ranges = bucket_ranges(&md, 2);
+        assert_eq!(ranges, vec![(0, 0), (0, 1), (1, 3), (3, 6)]);
+
+        for &(from, to) in ranges.iter() {
+            // a bucket's first and last keys are always boundaries
+            let boundary = LearnedFIB::derive_boundaries(&md.slice(from..to), 1.0);
+            assert_eq!(boundary.len(), usize::min(to - from, 2));
+
+            let mut nn = neural_network::NN::new();
+            LearnedFIB::train_subset(&md.slice(from..to), &mut nn, 1.0, usize::MAX, false);
+            for (key, pos) in md.slice(from..to).iter() {
+                let pred = f64_to_pos(nn.predict(&key.to_model_input()), u64::MAX);
+                assert!(u64::max(pred, pos as u64) - u64::min(pred, pos as u64) <= 1);
+            }
+        }
+
+        let fib = LearnedFIB::new(&md, 1, 2);
+        assert!(fib.error_bound().unwrap() <= 1);
+        assert_eq!(verify(&fib, &md), Ok(()));
+
+        // an empty bucket, at either end of the data, has no boundaries
+        assert!(LearnedFIB::derive_boundaries(&md.slice(0..0), 1.0).is_empty());
+        assert!(LearnedFIB::derive_boundaries(&md.slice(6..6), 1.0).is_empty());
+
+        // two copies of a key make a bucket of one distinct key
+        let md = RMITrainingData::from_keys(vec![7u64, 7]);
+        assert_eq!(LearnedFIB::derive_boundaries(&md.slice(0..2), 1.0), vec![(7, 0)]);
+        let fib = LearnedFIB::new(&md, 1, 0);
+        assert_eq!(fib.predict_to_int(&7u64.into()), 0);
+    }
+
+    #[test]
+    fn test_last_boundary_per_bucket() {
+        // every bucket but the last ends before the data does, and the
+        // first of them on copies of a key
+        let mut keys: Vec<u64> = random_keys(4_000, 8).into_iter().map(|(k, _)| k).collect();
+        keys.sort_unstable();
+        let ends: Vec<usize> = bucket_ranges(&RMITrainingData::from_keys(keys.clone()), 3)
+            .into_iter()
+            .filter(|(from, to)| from < to)
+            .map(|(_, to)| to)
+            .collect();
+        keys.extend([keys[ends[0] - 1]; 2]);
+        keys.sort_unstable();
+        let md = RMITrainingData::from_keys(keys);
+        let ranges = bucket_ranges(&md, 3);
+        assert!(ranges.iter().filter(|(from, to)| from < to).count() > 2);
+
+        let fib = LearnedFIB::new(&md, 8, 3);
+        for (bucket, &(from, to)) in ranges.iter().enumerate() {
+            if from == to {
+                continue;
+            }
+            let boundary = LearnedFIB::derive_boundaries(&md.slice(from..to), 8.0);
+            let (last_key, _) = md.get(to - 1);
+            assert_eq!(boundary.last().unwrap().0, last_key, "bucket {}", bucket);
+
+            // the network passes through the bucket's own last boundary
+            let (_, pos) = *boundary.last().unwrap();
+            assert_eq!(fib.predict_with_network(&last_key.into()), (pos as u64, bucket));
+        }
+    }
+
+    #[test]
+    fn test_duplicate_tail() {
+        // copies of the last key, each with its own position, and keys that
+        // are distinct as integers but the same as f64s
+        let mut data: Vec<(u64, usize)> = (0..100u64).map(|i| (i * i, i as usize)).collect();
+        data.extend((100..110).map(|i| (1 << 60, i)));
+        let mut rounded = data.clone();
+        rounded.extend((1..10u64).map(|i| ((1 << 60) + i, 109)));
+
+        for data in [data, rounded] {
+            let md = RMITrainingData::new(Box::new(data.clone()));
+            let bucket = md.slice(0..md.len());
+            let boundary = LearnedFIB::derive_boundaries(&bucket, 1.0);
+            for pair in boundary.windows(2) {
+                assert!(pair[0].0.as_float() < pair[1].0.as_float(),
+                        "boundaries {:?} and {:?} share an x value", pair[0], pair[1]);
+            }
+            assert_eq!(boundary.last().unwrap().0.as_float(), (1u64 << 60) as f64);
+
+            let mut nn = neural_network::NN::new();
+            LearnedFIB::train_subset(&bucket, &mut nn, 1.0, usize::MAX, false);
+            for (key, _) in data.iter() {
+                assert!(nn.predict(&key.to_model_input()).is_finite());
+            }
+        }
+    }
+
+    #[test]
+    fn test_from_boundaries() {
+        let md = RMITrainingData::new(Box::new(random_keys(20_000, 3)));
+        let prefix = 4;
+        let fib = LearnedFIB::new(&md, 8, prefix);
+
+        let boundaries: Vec<Vec<(u64, usize)>> = bucket_ranges(&md, prefix).iter()
+            .map(|&(from, to)| LearnedFIB::derive_boundaries(&md.slice(from..to), 8.0))
+            .collect();
+        let rebuilt = LearnedFIB::from_boundaries(&md, prefix, &boundaries).unwrap();
+        assert_eq!(rebuilt.error_bound(), fib.error_bound());
+        assert_eq!(rebuilt.params_bytes(), fib.params_bytes());
+        for (key, _) in md.iter_model_input() {
+            assert_eq!(rebuilt.predict_to_int(&key), fib.predict_to_int(&key));
+        }
+
+        // out of order, out of range, and missing boundaries are refused
+        let mut bad = boundaries.clone();
+        bad[3].swap(0, 1);
+        assert_eq!(LearnedFIB::from_boundaries(&md, prefix, &bad).err(), Some(3));
+        let mut bad = boundaries.clone();
+        bad[5][0].0 = 0;
+        assert_eq!(LearnedFIB::from_boundaries(&md, prefix, &bad).err(), Some(5));
+        let mut bad = boundaries.clone();
+        bad[7].clear();
+        assert_eq!(LearnedFIB::from_boundaries(&md, prefix, &bad).err(), Some(7));
+        assert_eq!(LearnedFIB::from_boundaries(&md, prefix, &boundaries[1..]).err(), Some(15));
+    }
+
+    #[test]
+    fn test_range_bounds() {
+        // keys in a few of the 16 buckets, spread unevenly within each
+        let keys: Vec<u64> = [1u64, 2, 5, 9].iter()
+            .flat_map(|b| (0..2_000u64).map(move |i| (b << 60) + i * i * i * 977))
+            .collect();
+        let md = RMITrainingData::from_keys(keys.clone());
+        let mut fixed = LearnedFIB::new(&md, 4, 4);
+        fixed.use_fixed_point(&md);
+        let models = [
+            LearnedFIB::new(&md, 4, 4),
+            LearnedFIB::new_with_offset_table(&md, 4, 4),
+            fixed,
+        ];
+
+        for model in models.iter() {
+            assert!(model.supports_range_query());
+            let ranges = [
+                (keys[0], keys[10]), (keys[1_990], keys[2_010]), (keys[500] + 1, keys[7_000]),
+                (keys[3_999] + 1, keys[4_000] - 1), (0, keys[5]), (keys[7_990], u64::MAX),
+                (3 << 60, 6 << 60), (keys[100], 4 << 60),
+            ];
+            for (lo, hi) in ranges.iter().cloned() {
+                let (start, end) = model.predict_range_bounds(&lo.into(), &hi.into());
+                for (pos, key) in keys.iter().enumerate() {
+                    if *key >= lo && *key <= hi {
+                        assert!(start <= pos as u64 && (pos as u64) < end,
+                                "{} at {} is outside {}..{}", key, pos, start, end);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_with_error_bound() {
+        let keys: Vec<u64> = (0..5_000u64).map(|i| i * i * 31).collect();
+        let md = RMITrainingData::from_keys(keys);
+        let fib = LearnedFIB::new(&md, 4, 4);
+        let measured = fib.error_bound().unwrap();
+        let (lo, hi): (ModelInput, ModelInput) = (5_000_000u64.into(), 9_000_000u64.into());
+        let expected = fib.predict_range_bounds(&lo, &hi);
+
+        // with no error the window is just the predictions
+        let fib = fib.with_error_bound(0);
+        assert_eq!(fib.error_bound(), Some(0));
+        let (start, end) = fib.predict_range_bounds(&lo, &hi);
+        assert_eq!(expected, (start - measured, end + measured));
+
+        let mut fib = fib.with_error_bound(100);
+        assert_eq!(fib.error_bound(), Some(100));
+        assert_eq!(fib.predict_range_bounds(&lo, &hi), (start - 100, end + 100));
+        assert_eq!(fib.refresh_error_bound(&md), measured);
+    }
+
+    #[test]
+    fn test_set_to_constant_model() {
+        let md = RMITrainingData::from_keys((0..1_000u64).map(|i| i * 977));
+        let mut fixed = LearnedFIB::new(&md, 4, 3);
+        fixed.use_fixed_point(&md);
+        let mut models = [
+            LearnedFIB::new(&RMITrainingData::<u64>::empty(), 4, 3),
+            LearnedFIB::new_with_offset_table(&md, 4, 3),
+            fixed,
+        ];
+        for model in models.iter_mut() {
+            let params = model.params().len();
+            assert!(model.set_to_constant_model(12_345));
+            assert_eq!(model.error_bound(), Some(0));
+            assert_eq!(model.params().len(), params);
+            for key in [0, 500 * 977, u64::MAX] {
+                assert_eq!(model.predict_to_int(&key.into()), 12_345);
+            }
+        }
+    }
+
+    #[test]
+    fn test_context() {
+        // each bucket's keys are packed at its start, so the slope a
+        // bucket ends with says nothing about where the next one starts
+        let keys: Vec<u64> = (0..64u64)
+            .flat_map(|b| (0..500u64).map(move |i| (b << 58) + i * i * 1_000))
+            .collect();
+        let md = RMITrainingData::from_keys(keys.clone());
+        let prefix = 6;
+
+        // the largest error, from the lower bound, of keys falling between
+        // the last key of a bucket and the first key of the next
+        let edge_error = |fib: &LearnedFIB| {
+            let mut max_error = 0;
+            for &(from, to) in bucket_ranges(&md, prefix).iter() {
+                if from == to || to == keys.len() {
+                    continue;
+                }
+                let (last, next) = (keys[to - 1], keys[to]);
+                for step in 1..16 {
+                    let key = last + (next - last) / 16 * step;
+                    let pred = fib.predict_to_int(&key.into());
+                    let err = u64::max(pred, to as u64) - u64::min(pred, to as u64);
+                    max_error = u64::max(max_error, err);
+                }
+            }
+            max_error
+        };
+
+        let plain = LearnedFIB::new(&md, 4, prefix);
+        let smoothed = LearnedFIB::new_with_context(&md, 4, prefix, 8);
+        assert!(edge_error(&plain) > 1_000_000);
+        assert!(edge_error(&smoothed) <= 8, "edge error {}", edge_error(&smoothed));
+        assert!(smoothed.error_bound() <= plain.error_bound());
+        assert_eq!(verify(&smoothed, &md), Ok(()));
+        assert_eq!(LearnedFIB::new_with_context(&md, 4, prefix, 0).params_bytes(),
+                   plain.params_bytes());
+    }
+
+    #[test]
+    fn test_key_domain() {
+        let data = random_keys(4096, 11);
+        let md = RMITrainingData::new(Box::new(data.clone()));
+        let fib = LearnedFIB::new(&md, 16, 3);
+        assert_eq!(fib.key_domain(), Some((data[0].0, data[data.len() - 1].0)));
+        assert_eq!(LearnedFIB::new_with_offset_table(&md, 16, 3).key_domain(), fib.key_domain());
+
+        let rebuilt = LearnedFIB::from_state(&fib.state().unwrap().1).unwrap();
+        assert_eq!(rebuilt.key_domain(), None);
+    }
+
+    #[test]
+    fn test_high_keys() {
+        // keys near u64::MAX, a few apart, which share their f64 by the
+        // hundred
+        let base = u64::MAX - (1 << 20);
+        let keys: Vec<u64> = (0..2_000u64).map(|i| base + 5 * i + i % 3).collect();
+        let floats = 1 + keys.windows(2).filter(|w| w[0] as f64 != w[1] as f64).count();
+        assert!(floats <= keys.len() / 100, "{} distinct floats", floats);
+        let md = RMITrainingData::from_keys(keys.clone());
+
+        let mut fib = LearnedFIB::new(&md, 1, 0);
+        assert_eq!(fib.key_bases(), vec![base]);
+        assert!(fib.error_bound().unwrap() <= 2, "error bound {:?}", fib.error_bound());
+        assert_eq!(verify(&fib, &md), Ok(()));
+
+        let rebuilt = LearnedFIB::from_state(&fib.state().unwrap().1).unwrap();
+        let path = std::env::temp_dir()
+            .join(format!("rmi_high_keys_{}.lfib", std::process::id()));
+        fib.save(&path).unwrap();
+        let lazy = lazy::LazyLearnedFIB::open(&path).unwrap();
+        for key in keys.iter().step_by(7).chain([u64::MAX].iter()) {
+            let pred = fib.predict_to_int(&(*key).into());
+            assert_eq!(rebuilt.predict_to_int(&(*key).into()), pred);
+            assert_eq!(lazy.predict_to_int(&(*key).into()), pred);
+        }
+        std::fs::remove_file(&path).unwrap();
+
+        // the integer knots of fixed point networks are absolute keys
+        assert!(fib.use_fixed_point(&md) <= 3);
+        assert_eq!(verify(&fib, &md), Ok(()));
+    }
+
+    #[test]
+    fn test_high_keys_past_exact_span() {
+        // a key, then keys 2^53 above it, one apart, which a distance from
+        // the first key only tells apart by twos
+        let lo = 1u64 << 63;
+        let mut keys = vec![lo];
+        keys.extend((0..64).map(|i| lo + (1 << 53) + i));
+        let md = RMITrainingData::from_keys(keys.clone());
+
+        // with a prefix of 11, a bucket spans 2^53 keys, so the far keys fall
+        // into a bucket of their own, based at the first of them: exact
+        let fib = LearnedFIB::new(&md, 1, 11);
+        assert!(fib.key_bases().contains(&(lo + (1 << 53))));
+        assert_eq!(fib.error_bound(), Some(0));
+        assert_eq!(verify(&fib, &md), Ok(()));
+
+        // with 10, every key shares the bucket based at `lo`, and the
+        // distances round
+        let fib = LearnedFIB::new(&md, 1, 10);
+        assert!(fib.key_bases().contains(&lo));
+        assert!(fib.error_bound().unwrap() > 0);
+        assert_eq!(verify(&fib, &md), Ok(()));
+    }
+
+    // records at positions just below 2^53, as for the last leaf of an
+    // RMI over that many keys
+    fn large_positions(end: u64) -> RMITrainingData<u64> {
+        let records: Vec<(u64, usize)> = (0..2_000u64)
+            .map(|i| (1_000 + i * 10 + i % 7, (end - 2_000 + i) as usize))
+            .collect();
+        return RMITrainingData::new(Box::new(records));
+    }
+
+    #[test]
+    fn test_large_positions() {
+        // up to 2^53 every position is an f64, and the networks place them
+        let md = large_positions(neural_network::MAX_EXACT_POSITION + 1);
+        let fib = LearnedFIB::new(&md, 1, 0);
+        let err = fib.error_bound().unwrap();
+        assert!(err <= 1, "error bound {}", err);
+        for (key, pos) in md.iter() {
+            let pred = fib.predict_to_int(&key.into());
+            assert!(u64::max(pred, pos as u64) - u64::min(pred, pos as u64) <= err);
+        }
+
+        // one past it the builder refuses rather than train a network whose
+        // positions round
+        let md = large_positions(neural_network::MAX_EXACT_POSITION + 2);
+        let err = LearnedFIBBuilder::new().prefix(2).build(&md).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(matches!(err, crate::Error::ParamOverflow { .. }));
+        assert!(LearnedFIB::from_boundaries(&md, 0, &[md.iter().collect()]).is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "past 2^53")]
+    fn test_large_positions_panic() {
+        LearnedFIB::new(&large_positions(1 << 60), 1, 0);
+    }
+
+    #[test]
+    fn test_params_bytes_reproducible() {
+        let md = RMITrainingData::new(Box::new(random_keys(20_000, 11)));
+        let builds: [fn(&RMITrainingData<u64>) -> LearnedFIB; 4] = [
+            |md| LearnedFIB::new(md, 8, 6),
+            |md| LearnedFIB::new_with_offset_table(md, 8, 6),
+            |md| LearnedFIBBuilder::new().prefix(6).threshold(8).parallel(true).build(md).unwrap(),
+            |md| {
+                let mut fib = LearnedFIB::new_with_context(md, 8, 6, 20);
+                fib.use_fixed_point(md);
+                fib
+            },
+        ];
+        for build in builds.iter() {
+            let first = build(&md).params_bytes();
+            assert!(!first.is_empty());
+            // each run hashes its training cache with fresh keys
+            for _ in 0..3 {
+                assert!(build(&md).params_bytes() == first);
+            }
+        }
+    }
+
+    #[test]
+    fn test_estimated_flops() {
+        let data: Vec<(u64, usize)> = (0..4_000u64).map(|i| (i * i * i, i as usize)).collect();
+        let md = RMITrainingData::new(Box::new(data.clone()));
+        let key = ModelInput::from(500u64 * 500 * 500);
+
+        // a tighter threshold gives a wider network, and costs five
+        // operations per hidden unit
+        let fibs: Vec<LearnedFIB> = [256, 16, 1].iter()
+            .map(|threshold| LearnedFIB::new(&md, *threshold, 0))
+            .collect();
+        let widths: Vec<usize> = fibs.iter().map(|f| f.neural_networks[0].segments()).collect();
+        assert!(widths.windows(2).all(|w| w[0] < w[1]), "widths {:?}", widths);
+        for (fib, width) in fibs.iter().zip(widths.iter()) {
+            assert_eq!(fib.estimated_flops(&key), 5 * *width as u64 + 2);
+        }
+        let offsets = LearnedFIB::new_with_offset_table(&md, 16, 0);
+        assert_eq!(offsets.estimated_flops(&key), fibs[1].estimated_flops(&key) + 2);
+
+        // the estimate follows the width of the key's own bucket's network
+        let random = RMITrainingData::new(Box::new(random_keys(8_192, 5)));
+        let mut fib = LearnedFIB::new(&random, 16, 3);
+        for (bucket, nn) in fib.neural_networks.iter().enumerate() {
+            let key = ModelInput::from((bucket as u64) << 61);
+            assert_eq!(fib.estimated_flops(&key), 5 * nn.segments() as u64 + 2);
+        }
+        fib.use_fixed_point(&random);
+        let fixed = &fib.fixed.as_ref().unwrap().networks;
+        for (bucket, nn) in fixed.iter().enumerate() {
+            let key = ModelInput::from((bucket as u64) << 61);
+            assert_eq!(fib.estimated_flops(&key), 2 * nn.knots().len() as u64 + 4);
+        }
+    }
+
+    #[test]
+    fn test_fixed_point() {
+        let data = random_keys(8192, 21);
+        let md = RMITrainingData::new(Box::new(data.clone()));
+        for offset_table in [false, true] {
+            let mut fib = if offset_table {
+                LearnedFIB::new_with_offset_table(&md, 16, 3)
+            } else {
+                LearnedFIB::new(&md, 16, 3)
+            };
+            let float_bound = fib.error_bound().unwrap();
+            let bound = fib.use_fixed_point(&md);
+            assert_eq!(fib.error_bound(), Some(bound));
+            assert!(bound <= float_bound + 2, "{} vs {}", bound, float_bound);
+            assert_eq!(verify(&fib, &md), Ok(()));
+
+            let scale = fib.fixed_point_scale().unwrap();
+            assert!(scale > 32);
+            assert!(matches!(fib.params()[1], ModelParam::Int(s) if s == scale as u64));
+            assert!(fib.save(&std::env::temp_dir().join("rmi_fixed.lfib")).is_err());
+
+            let rebuilt = LearnedFIB::from_state(&fib.state().unwrap().1).unwrap();
+            assert_eq!(rebuilt.fixed_point_scale(), Some(scale));
+            for (key, _) in data.iter().step_by(13) {
+                for probe in [*key, key + 1] {
+                    assert_eq!(rebuilt.predict_to_int(&probe.into()),
+                               fib.predict_to_int(&probe.into()));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_predict_batch_sorted() {
+        let data = random_keys(8192, 9);
+        let md = RMITrainingData::new(Box::new(data.clone()));
+        let probes: Vec<u64> = data.iter().flat_map(|(k, _)| [*k, k + 1]).collect();
+
+        let mut fixed = LearnedFIB::new(&md, 16, 4);
+        fixed.use_fixed_point(&md);
+        for fib in [LearnedFIB::new(&md, 16, 4), LearnedFIB::new_with_offset_table(&md, 16, 4),
+                    fixed] {
+            let mut out = vec![0; probes.len()];
+            fib.predict_batch_sorted(&probes, &mut out);
+            for (probe, pred) in probes.iter().zip(out.iter()) {
+                assert_eq!(*pred, fib.predict_to_int(&(*probe).into()));
+            }
+        }
+    }
+
+    #[test]
+    #[ignore]
+    fn bench_predict_batch_sorted() {
+        let md = RMITrainingData::new(Box::new(random_keys(1 << 18, 3)));
+        let fib = LearnedFIB::new(&md, 64, 10);
+        bench_batch_sorted(&fib, 2_000_000, |batch, out| fib.predict_batch_sorted(batch, out));
+    }
+
+    #[test]
+    fn test_fixed_point_matches_cpp() {
+        let data = random_keys(2048, 5);
+        let md = RMITrainingData::new(Box::new(data.clone()));
+        let mut fib = LearnedFIB::new(&md, 16, 2);
+        fib.use_fixed_point(&md);
+        let fixed = fib.fixed.as_ref().unwrap();
+
+        // each bucket's network as arrays, with a trailing entry so none
+        // are empty, then a check of every probe against Rust's answer
+        let mut driver = format!("#include <cstddef>\n#include <cstdint>\n{}\n", fib.code());
+        for (bucket, nn) in fixed.networks.iter().enumerate() {
+            let knots: Vec<String> = nn.knots().iter().map(|k| format!("{}ULL", k)).collect();
+            let slopes: Vec<String> = nn.slopes().iter().map(|s| format!("{}LL", s)).collect();
+            driver += &format!("const uint64_t knots{}[] = {{{}0}};\n", bucket,
+                               knots.iter().map(|k| k.clone() + ", ").collect::<String>());
+            driver += &format!("const int64_t slopes{}[] = {{{}0}};\n", bucket,
+                               slopes.iter().map(|s| s.clone() + ", ").collect::<String>());
+
+            // C++ has no 128-bit literals, so the lines go in as halves
+            let wide = |values: &[u128]| values.iter()
+                .map(|v| format!("((unsigned __int128) {}ULL << 64 | {}ULL)",
+                                 (v >> 64) as u64, *v as u64))
+                .collect::<Vec<String>>()
+                .join(", ");
+            driver += &format!("const unsigned __int128 cum_slopes{}[] = {{{}}};\n", bucket,
+                               wide(nn.cum_slopes()));
+            driver += &format!("const unsigned __int128 cum_intercepts{}[] = {{{}}};\n", bucket,
+                               wide(nn.cum_intercepts()));
+        }
+        driver += "int main() {\n";
+        for (key, _) in data.iter().step_by(5) {
+            for probe in [key.wrapping_sub(1), *key, key + 1] {
+                let bucket = clip(probe, fib.prefix);
+                let nn = &fixed.networks[bucket];
+                driver += &format!(
+                    "  if (learned_fib_fixed(knots{b}, slopes{b}, {}, {}ULL, {}, {}ULL) != {}ULL) return 1;\n",
+                    nn.knots().len(), nn.bias(), fixed.scale, probe,
+                    fib.predict_to_int(&probe.into()), b = bucket);
+                driver += &format!(
+                    "  if (learned_fib_fixed_cumulative(knots{b}, cum_slopes{b}, \
+                     cum_intercepts{b}, {}, {}, {}ULL) != {}ULL) return 1;\n",
+                    nn.knots().len(), fixed.scale, probe,
+                    fib.predict_to_int(&probe.into()), b = bucket);
+            }
+        }
+        driver += "  return 0;\n}\n";
+
+        let dir = std::env::temp_dir().join(format!("rmi_fixed_nn_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("main.cpp"), driver).unwrap();
+        let sources = [dir.join("main.cpp")];
+        let compile = crate::codegen::cpp_test::compile_and_run;
+        let ran = match compile(&sources, &["-std=c++17", "-O2"]) {
+            Some(status) => status,
+            None => {
+                std::fs::remove_dir_all(&dir).unwrap();
+                return;
+            }
+        };
+        assert!(ran.success(), "C++ fixed point inference differs from Rust");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn segment_lookup(segments: &[Vec<(f64, u64)>], prefix: u64, key: u64) -> u64 {
+        let points = &segments[clip(key, prefix)];
+        let x = key as f64;
+        let right = points.iter().position(|(px, _)| *px > x).unwrap_or(points.len() - 1);
+        let ((x0, y0), (x1, y1)) = (points[right - 1], points[right]);
+        let y = y0 as f64 + (x - x0) * (y1 as f64 - y0 as f64) / (x1 - x0);
+        return f64_to_pos(y, u64::MAX);
+    }
+
+    #[test]
+    fn test_export_segments() {
+        let data = random_keys(8192, 5);
+        let md = RMITrainingData::new(Box::new(data.clone()));
+        let prefix = 4;
+        let fib = LearnedFIB::new(&md, 16, prefix);
+        let bound = fib.error_bound().unwrap();
+
+        let segments = fib.export_segments();
+        assert_eq!(segments.len(), 1 << prefix);
+        for points in segments.iter() {
+            assert!(points.len() >= 2);
+            assert!(points.windows(2).all(|w| w[0].0 < w[1].0));
+        }
+
+        for (key, pos) in data {
+            let from_nn = fib.predict_to_int(&key.into());
+            let from_segments = segment_lookup(&segments, prefix, key);
+            let diff = u64::max(from_nn, from_segments) - u64::min(from_nn, from_segments);
+            assert!(diff <= 1, "key {}: segments gave {}, network gave {}",
+                    key, from_segments, from_nn);
+
+            let err = u64::max(from_segments, pos as u64) - u64::min(from_segments, pos as u64);
+            assert!(err <= bound + 1);
+        }
+    }
+
+    #[test]
+    fn test_params_bytes() {
+        let md = RMITrainingData::new(Box::new(random_keys(2048, 3)));
+        let fib = LearnedFIB::new(&md, 16, 3);
+
+        let buf = fib.params_bytes();
+        let layout = fib.params_layout();
+        let (_, params) = fib.state().unwrap();
+        assert_eq!(layout.len(), 2 + (1 << 3) + 1);
+        assert_eq!(buf.len(), params.iter().map(|p| p.size()).sum::<usize>());
+
+        let reread: Vec<ModelParam> = layout.iter().map(|f| f.read(&buf)).collect();
+        let rebuilt = LearnedFIB::from_state(&reread).unwrap();
+        for (key, _) in random_keys(2048, 3) {
+            assert_eq!(rebuilt.predict_to_int(&key.into()), fib.predict_to_int(&key.into()));
+        }
+    }
+
+    #[test]
+    fn test_progress() {
+        let md = RMITrainingData::new(Box::new(random_keys(140_000, 11)));
+        let reports = RefCell::new(Vec::new());
+        let record = |f: f64| reports.borrow_mut().push(f);
+
+        LearnedFIB::new_with_progress(&md, 32, 6, Some(&record));
+
+        let reports = reports.into_inner();
+        assert!(reports.len() > 2);
+        assert!(reports.windows(2).all(|w| w[0] < w[1]), "{:?}", reports);
+        assert_eq!(*reports.last().unwrap(), 1.0);
+    }
+
+    #[test]
+    fn test_mark_bucket_dirty() {
+        let data = random_keys(8192, 13);
+        let md = RMITrainingData::new(Box::new(data.clone()));
+        let mut fib = LearnedFIB::new(&md, 16, 3);
+        let before = fib.bucket_errors.clone();
+        assert_eq!(fib.refresh_error_bound(&md), fib.error_bound().unwrap());
+
+        // shift the positions of buckets 2 and 5, but only mark bucket 2
+        let shifted: Vec<(u64, usize)> = data.iter()
+            .map(|&(k, idx)| match clip(k, 3) {
+                2 | 5 => (k, idx + 1000),
+                _ => (k, idx),
+            })
+            .collect();
+        let md = RMITrainingData::new(Box::new(shifted));
+        fib.mark_bucket_dirty(2);
+        let bound = fib.refresh_error_bound(&md);
+
+        assert!(fib.bucket_errors[2] >= 1000 - before[2]);
+        for bucket in (0..8).filter(|b| *b != 2) {
+            assert_eq!(fib.bucket_errors[bucket], before[bucket]);
+        }
+        assert_eq!(bound, fib.bucket_errors[2]);
+        assert_eq!(fib.error_bound(), Some(bound));
+    }
+
+    #[test]
+    fn test_predict_with_network() {
+        let data = random_keys(4096, 23);
+        let md = RMITrainingData::new(Box::new(data.clone()));
+        for prefix in [0, 1, 4] {
+            let fib = LearnedFIB::new(&md, 16, prefix);
+            let queries = data.iter().map(|(k, _)| *k).chain([0, 1 << 63, u64::MAX]);
+            for key in queries {
+                let (pos, nn_idx) = fib.predict_with_network(&key.into());
+                assert_eq!(nn_idx, clip(key, prefix));
+                assert_eq!(nn_idx as u64, if prefix == 0 { 0 } else { key >> (64 - prefix) });
+                assert_eq!(pos, fib.predict_to_int(&key.into()));
+            }
+        }
+    }
+
+    #[test]
+    fn test_retrain() {
+        let keys: Vec<u64> = random_keys(8192, 19).into_iter().map(|(k, _)| k).collect();
+        let n = keys.len();
+        let md = |keys: &[u64]| RMITrainingData::from_keys(keys.to_vec());
+        let builds: [fn(&RMITrainingData<u64>) -> LearnedFIB; 4] = [
+            |md| LearnedFIB::new(md, 16, 3),
+            |md| LearnedFIB::new_with_offset_table(md, 16, 3),
+            |md| LearnedFIB::new_with_context(md, 16, 3, 8),
+            |md| {
+                let mut fib = LearnedFIB::new(md, 16, 3);
+                fib.use_fixed_point(md);
+                fib
+            },
+        ];
+
+        // an append, keys changed in place, and the tail removed
+        let mut moved = keys.clone();
+        for idx in 4_000..4_010 {
+            moved[idx] = keys[idx] - (keys[idx] - keys[idx - 1]) / 2;
+        }
+        let cases = [
+            (&keys[..n * 4 / 5], &keys[..], n * 4 / 5..n),
+            (&moved[..], &keys[..], 4_000..4_010),
+            (&keys[..], &keys[..n - 100], n - 100..n - 100),
+        ];
+        for build in builds.iter() {
+            for (old, new, changed) in cases.iter() {
+                let mut fib = build(&md(old));
+                fib.retrain(&md(new), changed.clone()).unwrap();
+                let rebuilt = build(&md(new));
+                for key in new.iter().flat_map(|k| [k - 1, *k, k + 1]) {
+                    assert_eq!(fib.predict_to_int(&key.into()),
+                               rebuilt.predict_to_int(&key.into()));
+                }
+                assert_eq!(fib.error_bound(), rebuilt.error_bound());
+            }
+        }
+
+        // a constant model trains every bucket again, and one that does not
+        // know how its buckets were trained cannot be retrained
+        let mut set = LearnedFIB::new(&md(&keys), 16, 3);
+        assert!(set.set_to_constant_model(7));
+        set.retrain(&md(&keys), 0..0).unwrap();
+        assert_eq!(set.predict_to_int(&keys[10].into()),
+                   LearnedFIB::new(&md(&keys), 16, 3).predict_to_int(&keys[10].into()));
+        let boundaries = vec![Vec::new(); 8];
+        let mut fib = LearnedFIB::from_boundaries(&md(&[]), 3, &boundaries).unwrap();
+        let err = fib.retrain(&md(&keys), 0..n).unwrap_err();
+        assert!(matches!(err, Error::InvalidArgument { .. }));
+    }
+
+    #[test]
+    fn test_offset_table() {
+        let data = random_keys(8192, 17);
+        let md = RMITrainingData::new(Box::new(data.clone()));
+        let biased = LearnedFIB::new(&md, 16, 4);
+        let offset = LearnedFIB::new_with_offset_table(&md, 16, 4);
+
+        assert_eq!(offset.error_bound(), biased.error_bound());
+        for (key, _) in data.iter() {
+            assert_eq!(offset.predict_to_int(&(*key).into()),
+                       biased.predict_to_int(&(*key).into()));
+        }
+
+        match &offset.params()[..] {
+            [ModelParam::Int(4), ModelParam::IntArray(entries), ModelParam::FloatArray(_)] => {
+                let table: Vec<u64> = entries.chunks(4).map(|e| e[3]).collect();
+                assert_eq!(&table, offset.offsets.as_ref().unwrap());
+            }
+            _ => panic!("expected the offset table in params"),
+        }
+        assert!(offset.params_bytes().len() < biased.params_bytes().len());
+
+        let rebuilt = LearnedFIB::from_state(&offset.state().unwrap().1).unwrap();
+        for (key, _) in data.iter() {
+            assert_eq!(rebuilt.predict_to_int(&(*key).into()),
+                       offset.predict_to_int(&(*key).into()));
+        }
+    }
+
+    #[test]
+    fn test_offset_table_empty_buckets() {
+        // keys only in buckets 0 and 2 of 4: bucket 1 predicts the start of
+        // bucket 2, and bucket 3 the end of the data
+        let data: Vec<(u64, usize)> = (0..100u64).map(|i| i << 40)
+            .chain((0..100u64).map(|i| (2 << 62) | (i << 40)))
+            .zip(0..)
+            .collect();
+        let md = RMITrainingData::new(Box::new(data));
+        let fib = LearnedFIB::new_with_offset_table(&md, 4, 2);
+
+        assert_eq!(fib.predict_to_int(&(1u64 << 62).into()), 100);
+        assert_eq!(fib.predict_to_int(&(3u64 << 62).into()), 200);
+    }
+
+    #[test]
+    fn test_max_segments() {
+        // randomly wide and narrow gaps put a kink every few keys
+        let (mut key, mut state) = (0u64, 99u64);
+        let data: Vec<(u64, usize)> = (0..8192usize)
+            .map(|i| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                key += if state % 2 == 0 { 1 } else { 1 << 30 };
+                (key, i)
+            })
+            .collect();
+        let md = RMITrainingData::new(Box::new(data.clone()));
+
+        let uncapped = LearnedFIB::new(&md, 1, 0);
+        assert!(uncapped.neural_networks[0].segments() > 1000);
+
+        let capped = LearnedFIB::new_with_max_segments(&md, 1, 0, 64);
+        assert!(capped.neural_networks[0].segments() <= 64);
+        let bound = capped.error_bound().unwrap();
+        assert!(bound > uncapped.error_bound().unwrap());
+        for (key, pos) in data {
+            let pred = capped.predict_to_int(&key.into());
+            assert!(u64::max(pred, pos as u64) - u64::min(pred, pos as u64) <= bound);
+        }
+
+        // buckets already within the cap are trained as usual
+        let fib = LearnedFIB::new_with_max_segments(&md, 1, 0, usize::MAX);
+        assert_eq!(fib.error_bound(), uncapped.error_bound());
+    }
+
+    #[test]
+    fn test_second_layer() {
+        // 12 clusters of densely packed keys with wide gaps between them,
+        // more steps than 16 segments can follow
+        let keys: Vec<u64> = (0..12u64)
+            .flat_map(|c| (0..128u64).map(move |i| (c << 32) + i * ((c % 5) + 1)))
+            .collect();
+        let md = RMITrainingData::from_keys(keys.clone());
+        let train = |second_layer| {
+            let training = BucketTraining {
+                max_segments: 16,
+                second_layer,
+                ..BucketTraining::new(4)
+            };
+            let mut no_progress = Progress::new(None, 0, 0);
+            LearnedFIB::train_cached(&md, 0, &training, &mut HashMap::new(),
+                                     &mut no_progress, None)
+        };
+        let (single, mut stacked) = (train(false), train(true));
+        let (single_err, stacked_err) = (single.error_bound().unwrap(),
+                                         stacked.error_bound().unwrap());
+        assert!(stacked_err < single_err, "{} is not below {}", stacked_err, single_err);
+        assert!(stacked.neural_networks[0].segments() <= 2 * 16);
+        assert_eq!(verify(&stacked, &md), Ok(()));
+
+        // the two layers are stored as one network
+        let restored = LearnedFIB::from_state(&stacked.state().unwrap().1).unwrap();
+        for key in keys.iter().step_by(7) {
+            assert_eq!(restored.predict_to_int(&(*key).into()),
+                       stacked.predict_to_int(&(*key).into()));
+        }
+        stacked.use_fixed_point(&md);
+        assert_eq!(verify(&stacked, &md), Ok(()));
+
+        // buckets that fit in their segments are trained as usual
+        let smooth = RMITrainingData::new(Box::new(random_keys(2_000, 3)));
+        for max_segments in [4, usize::MAX] {
+            let train = |second_layer| {
+                let training = BucketTraining {
+                    max_segments,
+                    second_layer,
+                    ..BucketTraining::new(4)
+                };
+                let mut no_progress = Progress::new(None, 0, 0);
+                LearnedFIB::train_cached(&smooth, 2, &training, &mut HashMap::new(),
+                                         &mut no_progress, None)
+            };
+            let (single, stacked) = (train(false), train(true));
+            assert!(stacked.error_bound() <= single.error_bound());
+            if max_segments == usize::MAX {
+                assert_eq!(stacked.export_segments(), single.export_segments());
+            }
+        }
+    }
+
+    #[test]
+    fn test_error_stats() {
+        let data = random_keys(50_000, 42);
+        let md = RMITrainingData::new(Box::new(data.clone()));
+        let (fib, approx) = LearnedFIB::new_with_error_stats(&md, 64, 6);
+        assert_eq!(fib.error_bound(), LearnedFIB::new(&md, 64, 6).error_bound());
+
+        let errors: Vec<u64> = data.iter()
+            .map(|(key, pos)| {
+                let predicted = fib.predict_to_int(&(*key).into());
+                u64::max(predicted, *pos as u64) - u64::min(predicted, *pos as u64)
+            })
+            .collect();
+        assert_eq!(errors.iter().max().cloned(), fib.error_bound());
+
+        // the approximate p99 sits within a percentile of the exact rank
+        let n = errors.len() as f64;
+        let below = errors.iter().filter(|e| **e < approx.p99).count() as f64;
+        let at_or_below = errors.iter().filter(|e| **e <= approx.p99).count() as f64;
+        assert!(below <= 0.99 * n + 0.01 * n, "{} of {} below p99 {}", below, n, approx.p99);
+        assert!(at_or_below >= 0.99 * n - 0.01 * n, "{} of {} at or below p99 {}",
+                at_or_below, n, approx.p99);
+        assert!(approx.p50 <= approx.p95 && approx.p95 <= approx.p99);
+    }
+
+    #[test]
+    fn test_auto_prefix() {
+        let md = RMITrainingData::new(Box::new(random_keys(8192, 42)));
+        let target = 6;
+
+        let small = LearnedFIB::new(&md, 32, 1);
+        assert!(small.error_bound().unwrap() > target);
+
+        let (prefix, model) = LearnedFIB::auto_prefix(&md, 32, target);
+        assert!(prefix > 1);
+        assert_eq!(model.prefix, prefix);
+        assert!(model.error_bound().unwrap() <= target,
+                "prefix {} had error {}", prefix, model.error_bound().unwrap());
+    }
+
+    #[test]
+    fn test_fractional_threshold() {
+        let md = RMITrainingData::new(Box::new(random_keys(1_000_000, 7)));
+        assert_eq!(md.len(), 1_000_000);
+        assert_eq!(Threshold::Fraction(0.001).positions(md.len()), 1_000);
+        assert_eq!(Threshold::Fraction(0.001).positions(10), 0);
+        assert_eq!(Threshold::Absolute(16).positions(10), 16);
+
+        // enough buckets to keep each one's segments short to train
+        let absolute = LearnedFIB::new(&md, Threshold::Absolute(1_000), 16);
+        let fraction = LearnedFIB::new(&md, Threshold::Fraction(0.001), 16);
+        assert_eq!(fraction.error_bound(), absolute.error_bound());
+        assert_eq!(fraction.export_segments(), absolute.export_segments());
+        for (key, _) in md.iter().step_by(997) {
+            assert_eq!(fraction.predict_to_int(&key.into()), absolute.predict_to_int(&key.into()));
+        }
+    }
 
     // #[test]
     // fn test_linear_spline1() {
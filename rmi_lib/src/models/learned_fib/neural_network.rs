@@ -1,26 +1,148 @@
 use crate::models::*;
-use std::convert::{TryFrom, TryInto};
+use crate::models::utils::{f64_to_pos, pos_to_f64};
+use crate::error::Error;
 
-// Assumed LittleEndian machine
-use byteorder::{ByteOrder, LittleEndian, ReadBytesExt, WriteBytesExt};
+// NaN inputs give 0 rather than propagating
+fn relu(inp: f64) -> f64 {
+    return f64::max(0.0, inp);
+}
 
-use std::fs::File;
-use std::io::prelude::*;
-use std::path::Path;
+// Keeps `bias << scale` and every slope times a key within an i128.
+pub const MAX_FIXED_SCALE: u32 = 62;
 
-fn relu(inp: f64) -> f64 {
-    match inp {
-        x if x < 0.0 => 0.0,
-        x if x >= 0.0 => x,
-        _ => panic!("relu: type doesn't match"),
+// The largest position a network is trained on. Networks fit and predict
+// positions as f64s, which hold every integer only up to 2^53; past it,
+// positions round to their neighbours whatever the fit.
+pub const MAX_EXACT_POSITION: u64 = 1 << 53;
+
+// Fails with `Error::ParamOverflow` if `data` has a position past
+// `MAX_EXACT_POSITION`. Positions never decrease, so only the last one
+// needs checking.
+pub fn check_positions<TKey: TrainingKey>(data: &RMITrainingData<TKey>) -> Result<(), Error> {
+    return match data.len().checked_sub(1).and_then(|idx| data.try_get(idx)) {
+        Some((_, pos)) if pos as u64 > MAX_EXACT_POSITION => Err(Error::ParamOverflow {
+            reason: format!("position {} is past 2^53, the last one an f64 holds exactly", pos),
+        }),
+        _ => Ok(()),
+    };
+}
+
+// A network as `bias + sum(slope * max(0, x - knot)) >> scale`, computed
+// in wrapping 128-bit integer arithmetic. Wrapping is exact whenever the
+// result fits, and unlike floating point it gives the same answer in any
+// language on any target.
+#[derive(Clone)]
+pub struct FixedNN {
+    // sorted by knot, which the sum does not depend on
+    knots: Vec<u64>,
+    slopes: Vec<i64>,
+    bias: u64,
+    scale: u32,
+
+    // for the keys past exactly the first `j` knots, the sum is the line
+    // `cum_slopes[j] * x + cum_intercepts[j]`, scaled, with both terms
+    // wrapping like the sum itself
+    cum_slopes: Vec<u128>,
+    cum_intercepts: Vec<u128>,
+}
+
+impl FixedNN {
+    pub fn new(knots: Vec<u64>, slopes: Vec<i64>, bias: u64, scale: u32) -> FixedNN {
+        let mut units: Vec<(u64, i64)> = knots.into_iter().zip(slopes).collect();
+        units.sort_by_key(|(knot, _)| *knot);
+        let (knots, slopes): (Vec<u64>, Vec<i64>) = units.into_iter().unzip();
+
+        let mut cum_slopes = vec![0u128];
+        let mut cum_intercepts = vec![(bias as u128) << scale];
+        for (knot, slope) in knots.iter().zip(slopes.iter()) {
+            let slope = *slope as i128 as u128;
+            cum_slopes.push(cum_slopes.last().unwrap().wrapping_add(slope));
+            cum_intercepts.push(cum_intercepts.last().unwrap()
+                .wrapping_sub(slope.wrapping_mul(*knot as u128)));
+        }
+        return FixedNN { knots, slopes, bias, scale, cum_slopes, cum_intercepts };
+    }
+
+    pub fn inference(&self, x: u64) -> u64 {
+        let mut acc: u128 = (self.bias as u128) << self.scale;
+        for (knot, slope) in self.knots.iter().zip(self.slopes.iter()) {
+            if x > *knot {
+                acc = acc.wrapping_add((*slope as i128 as u128).wrapping_mul((x - knot) as u128));
+            }
+        }
+        return FixedNN::unscale(acc, self.scale);
+    }
+
+    // the same answer as `inference`, bit for bit, from the line of the
+    // segment `x` falls in. Counting the knots below `x` is the only loop,
+    // and it has no branch, where `inference` tests every knot.
+    pub fn inference_cumulative(&self, x: u64) -> u64 {
+        let segment: usize = self.knots.iter().map(|knot| (x > *knot) as usize).sum();
+        let acc = self.cum_intercepts[segment]
+            .wrapping_add(self.cum_slopes[segment].wrapping_mul(x as u128));
+        return FixedNN::unscale(acc, self.scale);
+    }
+
+    fn unscale(acc: u128, scale: u32) -> u64 {
+        // the sum went negative
+        if acc >> 127 != 0 {
+            return 0;
+        }
+        return u128::min(acc >> scale, u64::MAX as u128) as u64;
+    }
+
+    // the operations of `inference_cumulative`, which predictions run: a
+    // comparison and add per knot, then the segment's multiply and add and
+    // the final shift
+    pub fn inference_flops(&self) -> u64 {
+        return 2 * self.knots.len() as u64 + 3;
     }
+
+    #[cfg(test)]
+    pub fn cum_slopes(&self) -> &[u128] {
+        return &self.cum_slopes;
+    }
+
+    #[cfg(test)]
+    pub fn cum_intercepts(&self) -> &[u128] {
+        return &self.cum_intercepts;
+    }
+
+    pub fn knots(&self) -> &[u64] {
+        return &self.knots;
+    }
+
+    pub fn slopes(&self) -> &[i64] {
+        return &self.slopes;
+    }
+
+    pub fn bias(&self) -> u64 {
+        return self.bias;
+    }
+}
+
+// `key` as a network whose first training key is `base` sees it: its
+// distance above the base. An f64 holds that distance exactly up to 2^53,
+// where the key itself would lose its low bits past that, so keys near
+// `u64::MAX` that differ only there stay apart. Integer keys below the
+// base go to 0, where networks are flat anyway.
+pub fn relative_key(key: &ModelInput, base: u64) -> f64 {
+    return match key {
+        ModelInput::Int(k) => k.saturating_sub(base) as f64,
+        ModelInput::Float(k) => k - base as f64,
+    };
 }
 
+#[derive(Clone)]
 pub struct NN {
     weights1: Vec<f64>,
     weights2: Vec<f64>,
     biases1: Vec<f64>,
     bias2: f64,
+
+    // the first key the network was trained on, which its inputs are
+    // relative to. It is kept apart from the flat layout of the weights.
+    key_base: u64,
 }
 
 impl NN {
@@ -30,29 +152,65 @@ impl NN {
             weights2: Vec::new(),
             biases1: Vec::new(),
             bias2: 0.0,
+            key_base: 0,
         };
     }
 
-    pub fn train<TKey: TrainingKey>(&mut self, dataset: &RMITrainingData<TKey>) {
-        let start_idx: usize = 0;
-        let end_idx: usize = dataset.len() - 1;
+    // a network predicting `pos` for every key
+    pub fn constant(pos: f64) -> NN {
+        return NN { bias2: pos, ..NN::new() };
+    }
 
-        self.bias2 = dataset.get(start_idx).1 as f64;
-        let mut prev_slope: f64 = 0.0;
-        // return type of RMITrainingData.get() -> (T: TrainingKey, usize)
-        for idx in start_idx..(end_idx - 1) {
-            let x1: f64 = dataset.get(idx).0.as_float();
-            let y1: f64 = u64::try_from(dataset.get(idx).1).unwrap() as f64;
-            let x2: f64 = dataset.get(idx + 1).0.as_float();
-            let y2: f64 = u64::try_from(dataset.get(idx + 1).1).unwrap() as f64;
+    // fits a line through each pair of neighbouring points. A single point
+    // gives a constant network and two points a single slope; no points is
+    // `Error::EmptyData`, as there is no position to predict, and positions
+    // past `MAX_EXACT_POSITION` are an error too.
+    pub fn train<TKey: TrainingKey>(&mut self, dataset: &RMITrainingData<TKey>)
+                                    -> Result<(), Error> {
+        if dataset.len() == 0 {
+            return Err(Error::EmptyData);
+        }
+        check_positions(dataset)?;
+        if dataset.len() == 1 {
+            *self = NN::constant(pos_to_f64(dataset.get(0).1));
+            return Ok(());
+        }
 
+        let base = dataset.get_key(0).as_uint();
+        let points: Vec<(f64, f64)> = (0..dataset.len())
+            .map(|idx| {
+                let (key, pos) = dataset.get(idx);
+                (relative_key(&key.to_model_input(), base), pos_to_f64(pos))
+            })
+            .collect();
+        *self = NN::through_points(base, &points);
+        return Ok(());
+    }
+
+    // the network through `points`, given as (key relative to `base`,
+    // position) with strictly increasing keys: a line through each pair
+    // of neighbours, flat before the first point and carrying on the last
+    // line past the end
+    pub fn through_points(base: u64, points: &[(f64, f64)]) -> NN {
+        let mut nn = NN { key_base: base, ..NN::new() };
+        let (x1, y1) = match points.first() {
+            Some(first) => *first,
+            None => return nn,
+        };
+        nn.bias2 = y1;
+        let mut prev = (x1, y1);
+        let mut prev_slope: f64 = 0.0;
+        for &(x2, y2) in points[1..].iter() {
+            let (x1, y1) = prev;
             let cur_slope: f64 = (y2 - y1) / (x2 - x1);
-            self.weights1.push((cur_slope - prev_slope).abs());
-            self.biases1.push(-(x1 * self.weights1.last().unwrap()));
-            self.weights2
+            nn.weights1.push((cur_slope - prev_slope).abs());
+            nn.biases1.push(-(x1 * nn.weights1.last().unwrap()));
+            nn.weights2
                 .push(if cur_slope > prev_slope { 1.0 } else { -1.0 });
+            prev = (x2, y2);
             prev_slope = cur_slope;
         }
+        return nn;
     }
 
     pub fn inference(&self, input: f64) -> f64 {
@@ -70,57 +228,188 @@ impl NN {
             .sum();
         return result + self.bias2;
     }
-    pub fn load(&self, model_path: &String) -> NN {
-        let path = Path::new(model_path);
-        let display = path.display();
 
-        // open file
-        let mut file = match File::open(&path) {
-            Err(why) => panic!("couldn't open {}: {}", display, why),
-            Ok(file) => file,
-        };
+    // the network's prediction for `key`, rather than for an input that is
+    // already relative to its base
+    pub fn predict(&self, key: &ModelInput) -> f64 {
+        return self.inference(relative_key(key, self.key_base));
+    }
 
-        // read contents
-        let mut contents: Vec<f64> = Vec::new();
-        file.read_f64_into::<LittleEndian>(&mut contents).unwrap();
-
-        // return with NN struct
-        let contents_len: usize = contents.len();
-        match (contents_len - 1) % 3 {
-            0 => {
-                let slice_len: usize = (contents_len - 1) / 3;
-
-                let mut w1: Vec<f64> = Vec::new();
-                let mut w2: Vec<f64> = Vec::new();
-                let mut b1: Vec<f64> = Vec::new();
-
-                w1.extend_from_slice(&contents[0..slice_len]);
-                w2.extend_from_slice(&contents[slice_len..2 * slice_len]);
-                b1.extend_from_slice(&contents[2 * slice_len..3 * slice_len]);
-
-                return NN {
-                    weights1: w1,
-                    weights2: w2,
-                    biases1: b1,
-                    bias2: *contents.last().unwrap(),
-                };
-            }
-            _ => panic!("number of parameter is wierd!"),
+    pub fn key_base(&self) -> u64 {
+        return self.key_base;
+    }
+
+    // the network with its inputs relative to `base`, for weights read
+    // back from their flat layout
+    pub fn with_key_base(mut self, base: u64) -> NN {
+        self.key_base = base;
+        return self;
+    }
+
+    // number of linear segments, one per hidden unit
+    pub fn segments(&self) -> usize {
+        return self.weights1.len();
+    }
+
+    // the operations of an inference: a multiply-add, a relu, and a
+    // multiply and add into the sum per hidden unit, then the bias
+    pub fn inference_flops(&self) -> u64 {
+        return 5 * self.segments() as u64 + 1;
+    }
+
+    // the bytes of weights and biases an inference reads
+    pub fn size_bytes(&self) -> usize {
+        let params = self.weights1.len() + self.weights2.len() + self.biases1.len() + 1;
+        return params * std::mem::size_of::<f64>();
+    }
+
+    // whether the network is as `new` left it, predicting 0 for every key
+    pub fn is_untrained(&self) -> bool {
+        return self.weights1.is_empty() && self.bias2 == 0.0;
+    }
+
+    // removes the network's constant term, returning it
+    pub fn take_bias(&mut self) -> f64 {
+        return std::mem::replace(&mut self.bias2, 0.0);
+    }
+
+    // keys at which the network's slope changes, in increasing order and
+    // relative to the key base. Units with zero weight do not bend the
+    // line and are skipped.
+    pub fn knots(&self) -> Vec<f64> {
+        return self.weights1.iter()
+            .zip(self.biases1.iter())
+            .filter(|(w, _)| **w != 0.0)
+            .map(|(w, b)| -b / w)
+            .collect();
+    }
+
+    // weights1, weights2, biases1, then bias2
+    pub fn to_flat(&self) -> Vec<f64> {
+        let mut flat = Vec::with_capacity(3 * self.weights1.len() + 1);
+        flat.extend_from_slice(&self.weights1);
+        flat.extend_from_slice(&self.weights2);
+        flat.extend_from_slice(&self.biases1);
+        flat.push(self.bias2);
+        return flat;
+    }
+
+    // largest fixed point scale, up to `MAX_FIXED_SCALE`, at which every
+    // unit's slope still fits in an i64 with a bit to spare
+    pub fn max_fixed_scale(&self) -> u32 {
+        return self.weights1.iter()
+            .zip(self.weights2.iter())
+            .map(|(w1, w2)| (w1 * w2).abs())
+            .filter(|slope| *slope > 0.0)
+            .map(|slope| (61.0 - slope.log2().ceil()).clamp(0.0, MAX_FIXED_SCALE as f64) as u32)
+            .min()
+            .unwrap_or(MAX_FIXED_SCALE);
+    }
+
+    // the same function with integer knots and slopes scaled by
+    // `2^scale`, shifted up by `offset` positions. The knots are moved
+    // back to absolute keys, where integers keep every bit.
+    pub fn to_fixed(&self, scale: u32, offset: u64) -> FixedNN {
+        let (knots, slopes) = self.weights1.iter()
+            .zip(self.weights2.iter())
+            .zip(self.biases1.iter())
+            .filter(|((w1, _), _)| **w1 != 0.0)
+            .map(|((w1, w2), b1)| {
+                let knot = f64_to_pos(-b1 / w1, u64::MAX).saturating_add(self.key_base);
+                let slope = (w1 * w2 * (1u64 << scale) as f64).round() as i64;
+                (knot, slope)
+            })
+            .unzip();
+        let bias = f64_to_pos(self.bias2, u64::MAX).saturating_add(offset);
+        return FixedNN::new(knots, slopes, bias, scale);
+    }
+
+    pub fn from_flat(flat: &[f64]) -> Option<NN> {
+        if flat.is_empty() || !(flat.len() - 1).is_multiple_of(3) {
+            return None;
         }
+
+        let n = (flat.len() - 1) / 3;
+        return Some(NN {
+            weights1: flat[0..n].to_vec(),
+            weights2: flat[n..2 * n].to_vec(),
+            biases1: flat[2 * n..3 * n].to_vec(),
+            bias2: flat[3 * n],
+            key_base: 0,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trained(points: Vec<(u64, usize)>) -> Result<NN, Error> {
+        let mut nn = NN::new();
+        nn.train(&RMITrainingData::new(Box::new(points)))?;
+        return Ok(nn);
     }
 
-    pub fn save(&self, model_path: &String) -> std::io::Result<()> {
-        let mut file = File::open(model_path)?;
-        for weight1 in &self.weights1 {
-            file.write_f64::<LittleEndian>(*weight1)?;
+    #[test]
+    fn test_train_few_points() {
+        let err = trained(Vec::new()).err().unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        assert!(matches!(err, Error::EmptyData));
+
+        let one = trained(vec![(40, 7)]).unwrap();
+        assert_eq!(one.segments(), 0);
+        for x in [0, 40, 1 << 60] {
+            assert_eq!(one.predict(&ModelInput::Int(x)), 7.0);
+        }
+
+        let two = trained(vec![(10, 3), (30, 13)]).unwrap();
+        assert_eq!(two.segments(), 1);
+        assert_eq!(two.predict(&ModelInput::Int(10)), 3.0);
+        assert_eq!(two.predict(&ModelInput::Int(20)), 8.0);
+        assert_eq!(two.predict(&ModelInput::Int(30)), 13.0);
+
+        let three = trained(vec![(10, 3), (30, 13), (40, 33)]).unwrap();
+        assert_eq!(three.segments(), 2);
+        for (x, y) in [(10, 3.0), (30, 13.0), (35, 23.0), (40, 33.0)] {
+            let pred = three.predict(&ModelInput::Int(x));
+            assert!((pred - y).abs() < 1e-9, "{} at {}", pred, x);
         }
-        for weight2 in &self.weights2 {
-            file.write_f64::<LittleEndian>(*weight2)?;
+        for nn in [one, two, three] {
+            assert!(nn.to_flat().iter().all(|p| p.is_finite()));
         }
-        for bias1 in &self.biases1 {
-            file.write_f64::<LittleEndian>(*bias1)?;
+    }
+
+    #[test]
+    fn test_cumulative_inference() {
+        // knots out of order, slopes of either sign, sums that wrap or go
+        // negative, and a network with no knots at all
+        let mut state = 0x9e37_79b9_7f4a_7c15u64;
+        let mut random = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        let mut networks = vec![FixedNN::new(Vec::new(), Vec::new(), 42, 3)];
+        for units in [1, 2, 7, 40] {
+            for scale in [0, 20, MAX_FIXED_SCALE] {
+                let knots: Vec<u64> = (0..units).map(|_| random()).collect();
+                let slopes: Vec<i64> = (0..units).map(|_| random() as i64 >> 2).collect();
+                networks.push(FixedNN::new(knots, slopes, random() >> 8, scale));
+            }
+        }
+        let keys = [0, 1, 1 << 20, 1 << 40, u64::MAX - 1, u64::MAX];
+        let trained = trained(vec![(10, 3), (30, 13), (40, 33), (1 << 50, 40)]).unwrap();
+        networks.push(trained.to_fixed(trained.max_fixed_scale(), 7));
+
+        for nn in networks.iter() {
+            let near_knots = nn.knots().iter()
+                .flat_map(|k| [k.wrapping_sub(1), *k, k.wrapping_add(1)]);
+            let probes: Vec<u64> = near_knots.chain(keys).chain((0..200).map(|_| random()))
+                .collect();
+            for x in probes {
+                assert_eq!(nn.inference_cumulative(x), nn.inference(x), "at {}", x);
+            }
         }
-        file.write_f64::<LittleEndian>(self.bias2)?;
-        Ok(())
     }
 }
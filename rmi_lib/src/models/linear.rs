@@ -81,6 +81,13 @@ impl LinearModel {
                          .map(|(inp, offset)| (inp.as_float(), offset as f64)));
         return LinearModel { params };
     }
+
+    pub(crate) fn from_state(params: &[ModelParam]) -> Option<LinearModel> {
+        return match params {
+            [ModelParam::Float(a), ModelParam::Float(b)] => Some(LinearModel { params: (*a, *b) }),
+            _ => None,
+        };
+    }
 }
 
 impl Model for LinearModel {
@@ -109,6 +116,10 @@ inline double linear(double alpha, double beta, double inp) {
         );
     }
 
+    fn required_includes(&self) -> Vec<&'static str> {
+        return vec!["<cmath>"];
+    }
+
     fn function_name(&self) -> String {
         return String::from("linear");
     }
@@ -117,6 +128,24 @@ inline double linear(double alpha, double beta, double inp) {
         self.params = (constant as f64, 0.0);
         return true;
     }
+
+    fn retrain(&mut self, data: &RMITrainingData<u64>,
+               _changed: Range<usize>) -> Result<(), Error> {
+        *self = LinearModel::new(data);
+        return Ok(());
+    }
+
+    fn kind(&self) -> ModelKind {
+        return ModelKind::Linear;
+    }
+
+    fn state(&self) -> Option<(ModelKind, Vec<ModelParam>)> {
+        return Some((self.kind(), self.params()));
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        return self;
+    }
 }
 
 #[cfg(test)]
@@ -125,26 +154,26 @@ mod tests {
 
     #[test]
     fn test_linear1() {
-        let md = ModelData::IntKeyToIntPos(vec![(1, 2), (2, 3), (3, 4)]);
+        let md = RMITrainingData::<u64>::new(Box::new(vec![(1, 2), (2, 3), (3, 4)]));
 
         let lin_mod = LinearModel::new(&md);
 
-        assert_eq!(lin_mod.predict_to_int(1.into()), 2);
-        assert_eq!(lin_mod.predict_to_int(6.into()), 7);
+        assert_eq!(lin_mod.predict_to_int(&1.into()), 2);
+        assert_eq!(lin_mod.predict_to_int(&6.into()), 7);
     }
 
     #[test]
     fn test_linear_single() {
-        let md = ModelData::IntKeyToIntPos(vec![(1, 2)]);
+        let md = RMITrainingData::<u64>::new(Box::new(vec![(1, 2)]));
 
         let lin_mod = LinearModel::new(&md);
 
-        assert_eq!(lin_mod.predict_to_int(1.into()), 2);
+        assert_eq!(lin_mod.predict_to_int(&1.into()), 2);
     }
 
     #[test]
     fn test_empty() {
-        LinearModel::new(&ModelData::empty());
+        LinearModel::new(&RMITrainingData::<u64>::empty());
     }
 
 }
@@ -171,6 +200,13 @@ impl LogLinearModel {
             params: loglinear_slr(&data),
         };
     }
+
+    pub(crate) fn from_state(params: &[ModelParam]) -> Option<LogLinearModel> {
+        return match params {
+            [ModelParam::Float(a), ModelParam::Float(b)] => Some(LogLinearModel { params: (*a, *b) }),
+            _ => None,
+        };
+    }
 }
 
 impl Model for LogLinearModel {
@@ -199,6 +235,10 @@ inline double loglinear(double alpha, double beta, double inp) {
         );
     }
 
+    fn required_includes(&self) -> Vec<&'static str> {
+        return vec!["<cmath>"];
+    }
+
     fn function_name(&self) -> String {
         return String::from("loglinear");
     }
@@ -207,6 +247,24 @@ inline double loglinear(double alpha, double beta, double inp) {
         to_r.insert(StdFunctions::EXP1);
         return to_r;
     }
+
+    fn retrain(&mut self, data: &RMITrainingData<u64>,
+               _changed: Range<usize>) -> Result<(), Error> {
+        *self = LogLinearModel::new(data);
+        return Ok(());
+    }
+
+    fn kind(&self) -> ModelKind {
+        return ModelKind::LogLinear;
+    }
+
+    fn state(&self) -> Option<(ModelKind, Vec<ModelParam>)> {
+        return Some((self.kind(), self.params()));
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        return self;
+    }
 }
 
 #[cfg(test)]
@@ -215,17 +273,17 @@ mod loglin_tests {
 
     #[test]
     fn test_loglinear1() {
-        let md = ModelData::IntKeyToIntPos(vec![(2, 2), (3, 4), (4, 16)]);
+        let md = RMITrainingData::<u64>::new(Box::new(vec![(2, 2), (3, 4), (4, 16)]));
 
         let loglin_mod = LogLinearModel::new(&md);
 
-        assert_eq!(loglin_mod.predict_to_int(2.into()), 1);
-        assert_eq!(loglin_mod.predict_to_int(4.into()), 13);
+        assert_eq!(loglin_mod.predict_to_int(&2.into()), 1);
+        assert_eq!(loglin_mod.predict_to_int(&4.into()), 13);
     }
 
     #[test]
     fn test_empty() {
-        LogLinearModel::new(&ModelData::empty());
+        LogLinearModel::new(&RMITrainingData::<u64>::empty());
     }
 }
 
@@ -258,6 +316,13 @@ impl RobustLinearModel {
             params: robust_params
         };
     }
+
+    pub(crate) fn from_state(params: &[ModelParam]) -> Option<RobustLinearModel> {
+        return match params {
+            [ModelParam::Float(a), ModelParam::Float(b)] => Some(RobustLinearModel { params: (*a, *b) }),
+            _ => None,
+        };
+    }
 }
 
 impl Model for RobustLinearModel {
@@ -286,6 +351,10 @@ inline double linear(double alpha, double beta, double inp) {
         );
     }
     
+    fn required_includes(&self) -> Vec<&'static str> {
+        return vec!["<cmath>"];
+    }
+
     fn function_name(&self) -> String {
         return String::from("linear");
     }
@@ -294,4 +363,22 @@ inline double linear(double alpha, double beta, double inp) {
         self.params = (constant as f64, 0.0);
         return true;
     }
+
+    fn retrain(&mut self, data: &RMITrainingData<u64>,
+               _changed: Range<usize>) -> Result<(), Error> {
+        *self = RobustLinearModel::new(data);
+        return Ok(());
+    }
+
+    fn kind(&self) -> ModelKind {
+        return ModelKind::RobustLinear;
+    }
+
+    fn state(&self) -> Option<(ModelKind, Vec<ModelParam>)> {
+        return Some((self.kind(), self.params()));
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        return self;
+    }
 }
@@ -44,6 +44,14 @@ impl LinearSplineModel {
             params: linear_splines(data),
         };
     }
+
+    pub(crate) fn from_state(params: &[ModelParam]) -> Option<LinearSplineModel> {
+        return match params {
+            [ModelParam::Float(a), ModelParam::Float(b)] =>
+                Some(LinearSplineModel { params: (*a, *b) }),
+            _ => None,
+        };
+    }
 }
 
 impl Model for LinearSplineModel {
@@ -72,6 +80,10 @@ inline double linear(double alpha, double beta, double inp) {
         );
     }
 
+    fn required_includes(&self) -> Vec<&'static str> {
+        return vec!["<cmath>"];
+    }
+
     fn function_name(&self) -> String {
         return String::from("linear");
     }
@@ -80,6 +92,24 @@ inline double linear(double alpha, double beta, double inp) {
         self.params = (constant as f64, 0.0);
         return true;
     }
+
+    fn retrain(&mut self, data: &RMITrainingData<u64>,
+               _changed: Range<usize>) -> Result<(), Error> {
+        *self = LinearSplineModel::new(data);
+        return Ok(());
+    }
+
+    fn kind(&self) -> ModelKind {
+        return ModelKind::LinearSpline;
+    }
+
+    fn state(&self) -> Option<(ModelKind, Vec<ModelParam>)> {
+        return Some((self.kind(), self.params()));
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        return self;
+    }
 }
 
 #[cfg(test)]
@@ -88,26 +118,26 @@ mod tests {
 
     #[test]
     fn test_linear_spline1() {
-        let md = ModelData::IntKeyToIntPos(vec![(1, 2), (2, 3), (3, 8)]);
+        let md = RMITrainingData::<u64>::new(Box::new(vec![(1, 2), (2, 3), (3, 8)]));
 
         let lin_mod = LinearSplineModel::new(&md);
 
-        assert_eq!(lin_mod.predict_to_int(1.into()), 2);
-        assert_eq!(lin_mod.predict_to_int(3.into()), 8);
+        assert_eq!(lin_mod.predict_to_int(&1.into()), 2);
+        assert_eq!(lin_mod.predict_to_int(&3.into()), 8);
     }
 
     #[test]
     fn test_linear_spline_single() {
-        let md = ModelData::IntKeyToIntPos(vec![(1, 2)]);
+        let md = RMITrainingData::<u64>::new(Box::new(vec![(1, 2)]));
 
         let lin_mod = LinearSplineModel::new(&md);
 
-        assert_eq!(lin_mod.predict_to_int(1.into()), 2);
+        assert_eq!(lin_mod.predict_to_int(&1.into()), 2);
     }
 
     #[test]
     fn test_empty() {
-        LinearSplineModel::new(&ModelData::empty());
+        LinearSplineModel::new(&RMITrainingData::<u64>::empty());
     }
 
 }
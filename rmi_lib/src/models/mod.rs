@@ -25,14 +25,19 @@ pub use linear::RobustLinearModel;
 pub use linear_spline::LinearSplineModel;
 pub use normal::LogNormalModel;
 pub use normal::NormalModel;
-pub use radix::RadixModel;
-pub use radix::RadixTable;
+pub use radix::{RadixModel, FixedRadixModel, KeyTransform, PlainRadix, Multiplicative};
+pub use radix::{RadixTable, RelativeRadixTable, OnOverflow, HintPolicy};
+pub use learned_fib::{LearnedFIB, LearnedFIBBuilder, LazyLearnedFIB, ErrorPercentiles, Threshold};
 pub use stdlib::StdFunctions;
 
-use byteorder::{LittleEndian, WriteBytesExt};
+use byteorder::{ByteOrder, LittleEndian, WriteBytesExt};
+use std::any::Any;
 use std::cmp::Ordering;
 use std::collections::HashSet;
+use crate::error::Error;
+use std::io;
 use std::io::Write;
+use std::ops::Range;
 use std::sync::Arc;
 
 #[derive(Clone, Copy)]
@@ -152,13 +157,30 @@ impl TrainingKey for f64 {
     }
 }
 
+/// Records whose keys are borrowed from a provider.
+pub type BorrowedRecords<'a, K> = Box<dyn Iterator<Item = (&'a K, usize)> + 'a>;
+
 pub trait RMITrainingDataIteratorProvider: Send + Sync {
     type InpType: TrainingKey;
     fn len(&self) -> usize;
     fn cdf_iter(&self) -> Box<dyn Iterator<Item = (Self::InpType, usize)> + '_>;
     fn key_type(&self) -> KeyType;
     fn get(&self, idx: usize) -> Option<(Self::InpType, usize)> {
-        return Some(self.cdf_iter().nth(idx).unwrap());
+        return self.cdf_iter().nth(idx);
+    }
+
+    /// The records from index `from` on, borrowing rather than copying the
+    /// keys. Only providers that keep their keys in memory can offer this;
+    /// the rest (such as ones decoding a file) return `None`.
+    fn cdf_iter_borrowed(&self, _from: usize) -> Option<BorrowedRecords<'_, Self::InpType>> {
+        return None;
+    }
+
+    /// How many consecutive positions, starting at its own, the record at
+    /// `idx` stands for. Only collapsed data has records covering more
+    /// than one.
+    fn run_len(&self, _idx: usize) -> usize {
+        return 1;
     }
 }
 
@@ -184,6 +206,40 @@ impl<K: TrainingKey> RMITrainingDataIteratorProvider for Vec<(K, usize)> {
             .get(idx)
             .map(|(key, offset)| ((*key).into(), *offset))
     }
+
+    fn cdf_iter_borrowed(&self, from: usize) -> Option<BorrowedRecords<'_, Self::InpType>> {
+        return Some(Box::new(self[from..].iter().map(|(key, offset)| (key, *offset))));
+    }
+}
+
+// Distinct keys, each with the first position and the number of copies of
+// its run in the original data.
+struct Runs<K>(Vec<(K, usize, usize)>);
+
+impl<K: TrainingKey> RMITrainingDataIteratorProvider for Runs<K> {
+    type InpType = K;
+    fn len(&self) -> usize {
+        return self.0.len();
+    }
+
+    fn cdf_iter(&self) -> Box<dyn Iterator<Item = (Self::InpType, usize)> + '_> {
+        return Box::new(self.0.iter().map(|(key, start, _)| (*key, *start)));
+    }
+
+    fn key_type(&self) -> KeyType {
+        return KeyType::U64;
+    }
+    fn get(&self, idx: usize) -> Option<(Self::InpType, usize)> {
+        return self.0.get(idx).map(|(key, start, _)| (*key, *start));
+    }
+
+    fn cdf_iter_borrowed(&self, from: usize) -> Option<BorrowedRecords<'_, Self::InpType>> {
+        return Some(Box::new(self.0[from..].iter().map(|(key, start, _)| (key, *start))));
+    }
+
+    fn run_len(&self, idx: usize) -> usize {
+        return self.0[idx].2;
+    }
 }
 
 struct FixDupsIter<K, T: Iterator<Item = (K, usize)>> {
@@ -203,7 +259,7 @@ impl<K, T: Iterator<Item = (K, usize)>> FixDupsIter<K, T> {
 impl<K, T> Iterator for FixDupsIter<K, T>
 where
     T: Iterator<Item = (K, usize)>,
-    K: TrainingKey,
+    K: PartialEq + Copy,
 {
     type Item = (K, usize);
 
@@ -225,7 +281,7 @@ where
                         return Some(nxt);
                     }
                 }
-                None => self.last_item.take(),
+                None => None,
             },
         }
     }
@@ -290,7 +346,7 @@ pub struct RMITrainingData<T> {
 macro_rules! map_scale {
     ($self: expr, $inp: expr) => {{
         let sf = ($self).scale;
-        let use_sf = (sf - 1.0).abs() > std::f64::EPSILON;
+        let use_sf = (sf - 1.0).abs() > f64::EPSILON;
         ($inp).map(move |(key, offset)| {
             if use_sf {
                 (key, (offset as f64 * sf) as usize)
@@ -315,26 +371,163 @@ impl<T: TrainingKey> RMITrainingData<T> {
         return RMITrainingData::<T>::new(Box::new(vec![]));
     }
 
+    /// Training data for sorted `keys`, each paired with its index as its
+    /// position. Duplicate keys are stored with sequential positions, but
+    /// like any other training data, `iter` reports every copy at the
+    /// position of the first.
+    pub fn from_keys(keys: impl IntoIterator<Item = T>) -> RMITrainingData<T> {
+        let data: Vec<(T, usize)> = keys.into_iter()
+            .enumerate()
+            .map(|(idx, key)| (key, idx))
+            .collect();
+        return RMITrainingData::<T>::new(Box::new(data));
+    }
+
+    /// Training data from `(key, position)` records sorted by key, after
+    /// checking with `validate_positions_monotone` that their positions
+    /// never decrease. Returns `Error::Unsorted` with the index of the
+    /// first record whose position is smaller than the one before it.
+    pub fn from_sorted(records: Vec<(T, usize)>) -> Result<RMITrainingData<T>, Error> {
+        let data = RMITrainingData::<T>::new(Box::new(records));
+        data.validate_positions_monotone()?;
+        return Ok(data);
+    }
+
+    /// Checks that the records' positions never decrease, as every model
+    /// assumes. Equal positions are fine, as for copies of a key. Returns
+    /// `Error::Unsorted` with the index of the first record whose position
+    /// is smaller than the one before it.
+    pub fn validate_positions_monotone(&self) -> Result<(), Error> {
+        let mut last = 0;
+        for (idx, (_, pos)) in self.iterable.cdf_iter().enumerate() {
+            if pos < last {
+                return Err(Error::Unsorted { index: idx });
+            }
+            last = pos;
+        }
+        return Ok(());
+    }
+
+    /// Drops records that repeat the one before them exactly, key and
+    /// position both, which only a data error produces. Copies of a key at
+    /// different positions are kept. Returns how many records were dropped;
+    /// when there are none the data is left untouched, so collapsed data
+    /// keeps its runs.
+    pub fn compact(&mut self) -> usize {
+        let mut records: Vec<(T, usize)> = Vec::with_capacity(self.len());
+        for record in self.iterable.cdf_iter() {
+            if records.last() != Some(&record) {
+                records.push(record);
+            }
+        }
+
+        let removed = self.len() - records.len();
+        if removed > 0 {
+            self.iterable = Arc::new(Box::new(records));
+        }
+        return removed;
+    }
+
+    /// Training data for sorted `keys` with every run of equal keys
+    /// collapsed into one record: the key, at the position of its first
+    /// copy, standing for as many positions as there are copies (see
+    /// `run_len`). Models train on the distinct keys only, and
+    /// `lookup_positions` recovers the full run.
+    ///
+    /// Positions no longer match indices, and `len` counts distinct keys;
+    /// `num_positions` counts the original records.
+    pub fn collapsed(keys: impl IntoIterator<Item = T>) -> RMITrainingData<T> {
+        let mut runs: Vec<(T, usize, usize)> = Vec::new();
+        for (idx, key) in keys.into_iter().enumerate() {
+            match runs.last_mut() {
+                Some((last, _, count)) if *last == key => *count += 1,
+                _ => runs.push((key, idx, 1)),
+            }
+        }
+        return RMITrainingData::new(Box::new(Runs(runs)));
+    }
+
     pub fn len(&self) -> usize {
         return self.iterable.len();
     }
 
+    /// How many consecutive positions the record at `idx` stands for: the
+    /// number of copies of its key for collapsed data, 1 otherwise. Not
+    /// affected by `set_scale`.
+    pub fn run_len(&self, idx: usize) -> usize {
+        return self.iterable.run_len(idx);
+    }
+
+    /// The number of positions the records cover, which is `len` unless
+    /// the data is collapsed.
+    pub fn num_positions(&self) -> usize {
+        let last = self.len().checked_sub(1);
+        return match last.and_then(|idx| self.iterable.get(idx)) {
+            None => 0,
+            Some((_, pos)) => pos + self.run_len(last.unwrap()),
+        };
+    }
+
     pub fn set_scale(&mut self, scale: f64) {
         self.scale = scale;
     }
 
+    /// The record at `idx`, or `None` past the end. For indices computed
+    /// next to the ends of the data, such as `len() - 1`, where `get`
+    /// would panic.
+    pub fn try_get(&self, idx: usize) -> Option<(T, usize)> {
+        return map_scale!(self, self.iterable.get(idx));
+    }
+
+    /// The record at `idx`. Panics past the end; see `try_get`.
     pub fn get(&self, idx: usize) -> (T, usize) {
-        return map_scale!(self, self.iterable.get(idx)).unwrap();
+        return self.try_get(idx).unwrap_or_else(
+            || panic!("Index {} out of bounds for data of length {}", idx, self.len()));
     }
 
     pub fn get_key(&self, idx: usize) -> T {
-        return map_scale!(self, self.iterable.get(idx)).unwrap().0;
+        return self.get(idx).0;
+    }
+
+    /// The smallest and largest key, as the integers models read them with
+    /// `ModelInput::as_int`, or `None` for empty data.
+    pub fn key_domain(&self) -> Option<(u64, u64)> {
+        let as_int = |(key, _): (T, usize)| key.to_model_input().as_int();
+        let first = self.try_get(0)?;
+        let last = self.try_get(self.len() - 1)?;
+        return Some((as_int(first), as_int(last)));
+    }
+
+    /// `n` samples of the empirical CDF, as `(key, fraction of positions
+    /// before the key)` pairs, at records spread evenly by index. The
+    /// first and last records are always sampled, so the samples span the
+    /// whole key range. Positions are read unscaled, without scanning the
+    /// data. Empty data, or `n` of 0, gives no samples, and `n` larger than
+    /// the data samples every record once.
+    pub fn sample_cdf(&self, n: usize) -> Vec<(f64, f64)> {
+        let n = usize::min(n, self.len());
+        let total = self.num_positions() as f64;
+        return (0..n)
+            .map(|i| {
+                let idx = if n == 1 { 0 } else { i * (self.len() - 1) / (n - 1) };
+                let (key, pos) = self.iterable.get(idx).unwrap();
+                (key.as_float(), pos as f64 / total)
+            })
+            .collect();
     }
 
     pub fn iter(&self) -> impl Iterator<Item = (T, usize)> + '_ {
         map_scale!(self, FixDupsIter::new(self.iterable.cdf_iter()))
     }
 
+    /// Like `iter`, but borrows the keys instead of copying them, if the
+    /// provider keeps them in memory (see `cdf_iter_borrowed`). Both
+    /// report the same records.
+    pub fn iter_borrowed(&self) -> Option<impl Iterator<Item = (&T, usize)> + '_> {
+        let inner = self.iterable.cdf_iter_borrowed(0)?;
+        return Some(map_scale!(self, FixDupsIter::new(inner)));
+    }
+
     pub fn iter_model_input(&self) -> impl Iterator<Item = (ModelInput, usize)> + '_ {
         return map_scale!(self, FixDupsIter::new(self.iterable.cdf_iter()))
             .map(|(k, o)| (k.to_model_input(), o));
@@ -366,12 +559,179 @@ impl<T: TrainingKey> RMITrainingData<T> {
         let cmp = f(self.get(base));
         base + (cmp == Ordering::Less) as usize
     }
+    /// The index of the first copy of `key` within `err` positions of
+    /// `pred`, if there is one. Starting at `pred`, positions are scanned
+    /// outward, nearest first, so this relies only on the key lying in the
+    /// window and not on the model that made the prediction being monotone.
+    /// Takes `O(err)` comparisons.
+    pub fn search_window(&self, key: T, pred: u64, err: u64) -> Option<usize> {
+        if self.len() == 0 {
+            return None;
+        }
+        let last = self.len() as u64 - 1;
+        let pred = u64::min(pred, last);
+        let lo = pred.saturating_sub(err) as usize;
+        let hi = u64::min(pred.saturating_add(err), last) as usize;
+        let pred = pred as usize;
+
+        let mut found = None;
+        for dist in 0..=usize::max(pred - lo, hi - pred) {
+            if pred >= lo + dist && self.get_key(pred - dist) == key {
+                found = Some(pred - dist);
+                break;
+            }
+            if pred + dist <= hi && self.get_key(pred + dist) == key {
+                found = Some(pred + dist);
+                break;
+            }
+        }
+
+        // duplicates sit next to each other, so walk back to the first
+        let mut idx = found?;
+        while idx > lo && self.get_key(idx - 1) == key {
+            idx -= 1;
+        }
+        return Some(idx);
+    }
+
+    /// The index of the first copy of `key`, searched for around `model`'s
+    /// prediction within its error bound (or the whole data, if it has
+    /// none). The model is given the key as a `ModelInput`, but the search
+    /// compares the key itself, so keys that only differ past what an f64
+    /// can hold still resolve to their own positions.
+    pub fn lookup(&self, model: &dyn Model, key: T) -> Option<usize> {
+        let err = model.error_bound().unwrap_or(self.len() as u64);
+        let pred = model.predict_to_int(&key.to_model_input());
+        return self.search_window(key, pred, err);
+    }
+
+    /// The positions covered by `key`'s copies, found by scanning the
+    /// records whose position is within `model`'s error bound of its
+    /// prediction. Unlike `lookup`, this works in positions rather than
+    /// indices, so it also serves collapsed data, where one record covers
+    /// a whole run.
+    pub fn lookup_positions(&self, model: &dyn Model, key: T) -> Option<Range<usize>> {
+        let err = model.error_bound().unwrap_or(u64::MAX);
+        let pred = model.predict_to_int(&key.to_model_input());
+        let lo = u64::max(pred.saturating_sub(err), model.min_value());
+        let hi = pred.saturating_add(err);
+
+        let mut idx = self.lower_bound_by(|(_, pos)| (pos as u64).cmp(&lo));
+        while idx < self.len() && self.get(idx).1 as u64 <= hi {
+            if self.get_key(idx) != key {
+                idx += 1;
+                continue;
+            }
+
+            let start = self.get(idx).1;
+            let mut last = idx;
+            while last + 1 < self.len() && self.get_key(last + 1) == key {
+                last += 1;
+            }
+            return Some(start..self.get(last).1 + self.run_len(last));
+        }
+        return None;
+    }
+
     pub fn soft_copy(&self) -> RMITrainingData<T> {
         return RMITrainingData {
             scale: self.scale,
             iterable: Arc::clone(&self.iterable),
         };
     }
+
+    /// A borrowed view of the records in `range`, without copying them.
+    pub fn slice(&self, range: Range<usize>) -> RMITrainingDataView<'_, T> {
+        assert!(range.start <= range.end && range.end <= self.len(),
+                "Slice {:?} out of bounds for data of length {}", range, self.len());
+        return RMITrainingDataView { data: self, start: range.start, end: range.end };
+    }
+
+    /// Splits the records into up to `k` contiguous views of about equal
+    /// length, so each can be trained on its own. A shard only ends where
+    /// the key changes, so every copy of a key is in one shard, and runs
+    /// longer than a shard leave fewer than `k`. Each view comes with the
+    /// position of its first record, which models trained on it from
+    /// position 0 are offset by. Empty data has no shards.
+    pub fn shards(&self, k: usize) -> Vec<(RMITrainingDataView<'_, T>, u64)> {
+        assert!(k > 0, "Cannot split data into 0 shards");
+        let mut shards = Vec::new();
+        let mut start = 0;
+        for shard in 1..=k {
+            let mut end = (self.len() as u128 * shard as u128 / k as u128) as usize;
+            if end <= start {
+                continue;
+            }
+            while end < self.len() && self.get_key(end) == self.get_key(end - 1) {
+                end += 1;
+            }
+
+            shards.push((self.slice(start..end), self.get(start).1 as u64));
+            start = end;
+        }
+        return shards;
+    }
+}
+
+/// A contiguous range of an `RMITrainingData`, indexed from zero. Records
+/// (and their positions) are the same as in the full data.
+pub struct RMITrainingDataView<'a, T> {
+    data: &'a RMITrainingData<T>,
+    start: usize,
+    end: usize,
+}
+
+impl<'a, T: TrainingKey> RMITrainingDataView<'a, T> {
+    pub fn len(&self) -> usize {
+        return self.end - self.start;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.start == self.end;
+    }
+
+    /// The record at `idx` of the view, or `None` past its end, even where
+    /// the full data goes on.
+    pub fn try_get(&self, idx: usize) -> Option<(T, usize)> {
+        if idx >= self.len() {
+            return None;
+        }
+        return self.data.try_get(self.start + idx);
+    }
+
+    pub fn get(&self, idx: usize) -> (T, usize) {
+        assert!(idx < self.len());
+        return self.data.get(self.start + idx);
+    }
+
+    pub fn get_key(&self, idx: usize) -> T {
+        return self.get(idx).0;
+    }
+
+    /// Iterates the range like `RMITrainingData::iter`, so duplicate keys
+    /// report the position of their first occurrence in the full data.
+    pub fn iter(&self) -> impl Iterator<Item = (T, usize)> + 'a {
+        return self.data.iter().skip(self.start).take(self.end - self.start);
+    }
+
+    /// Like `iter`, but borrows the keys, if the data's provider allows it
+    /// (see `RMITrainingData::iter_borrowed`). Rather than skipping every
+    /// record before the range, iteration starts at the first copy of the
+    /// range's first key, which is all the duplicate positions depend on.
+    pub fn iter_borrowed(&self) -> Option<impl Iterator<Item = (&'a T, usize)> + 'a> {
+        let mut run_start = self.start;
+        if self.start < self.end {
+            let first = self.data.get_key(self.start);
+            while run_start > 0 && self.data.get_key(run_start - 1) == first {
+                run_start -= 1;
+            }
+        }
+
+        let inner = self.data.iterable.cdf_iter_borrowed(run_start)?;
+        return Some(map_scale!(self.data, FixDupsIter::new(inner))
+                    .skip(self.start - run_start)
+                    .take(self.end - self.start));
+    }
 }
 
 /*struct RMITrainingDataIteratorProviderWrapper {
@@ -423,6 +783,10 @@ impl PartialOrd for ModelInput {
     }
 }*/
 
+/// A key as models see it. Models may read an integer key as a float, and a
+/// `u64` above 2^53 loses its low bits doing so, so distinct keys can share
+/// a prediction. Anything that must tell keys apart (such as the search in
+/// `RMITrainingData::lookup`) compares the original keys instead.
 #[derive(Clone, Copy, Debug)]
 pub enum ModelInput {
     Int(u64),
@@ -479,6 +843,70 @@ impl ModelInput {
         };
     }
 
+    /// Builds an integer key from up to 8 little-endian bytes, zero
+    /// extending shorter inputs. The bytes need no particular alignment.
+    /// Panics if more than 8 bytes are given.
+    pub fn from_le_bytes(bytes: &[u8]) -> ModelInput {
+        assert!(bytes.len() <= 8,
+                "An integer key has at most 8 bytes, got {}", bytes.len());
+        let mut buf = [0u8; 8];
+        buf[..bytes.len()].copy_from_slice(bytes);
+        return ModelInput::Int(u64::from_le_bytes(buf));
+    }
+
+    /// Decodes a key of a model taking `input_type` from its raw
+    /// little-endian bytes. An integer key has up to 8 bytes, zero extended
+    /// as for `from_le_bytes`, or up to 16 for a 128-bit key, which no model
+    /// places more finely than by its high 64 bits: those, which order the
+    /// keys, become the key. A float key is the 8 bytes of its bit pattern.
+    /// Other lengths are an `InvalidInput` error.
+    pub fn from_key_bytes(bytes: &[u8], input_type: ModelDataType)
+                          -> Result<ModelInput, std::io::Error> {
+        let len = bytes.len();
+        let invalid = |expected: &str| std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("A {} key has {} bytes, got {}", input_type.c_type(), expected, len));
+        return match input_type {
+            ModelDataType::Float if len == 8 => {
+                let mut buf = [0u8; 8];
+                buf.copy_from_slice(bytes);
+                Ok(ModelInput::from_f64_bits(u64::from_le_bytes(buf)))
+            }
+            ModelDataType::Float => Err(invalid("8")),
+            _ if len <= 8 => Ok(ModelInput::from_le_bytes(bytes)),
+            _ if len <= 16 => {
+                let mut buf = [0u8; 16];
+                buf[..len].copy_from_slice(bytes);
+                Ok(ModelInput::Int((u128::from_le_bytes(buf) >> 64) as u64))
+            }
+            _ => Err(invalid("at most 16")),
+        };
+    }
+
+    /// Builds a float key from its IEEE 754 bit pattern.
+    pub fn from_f64_bits(bits: u64) -> ModelInput {
+        return ModelInput::Float(f64::from_bits(bits));
+    }
+
+    /// A float key, refusing NaN and infinite values, which no model can
+    /// place: they would turn into a meaningless position.
+    pub fn finite_float(x: f64) -> Result<ModelInput, Error> {
+        let key = ModelInput::Float(x);
+        key.check_finite()?;
+        return Ok(key);
+    }
+
+    /// Fails with `Error::InvalidArgument` if this is a NaN or infinite
+    /// float key. Integer keys always pass.
+    pub fn check_finite(&self) -> Result<(), Error> {
+        return match self {
+            ModelInput::Float(x) if !x.is_finite() => Err(Error::InvalidArgument {
+                reason: format!("float key {} is not finite", x),
+            }),
+            _ => Ok(()),
+        };
+    }
+
     pub fn max_value(&self) -> ModelInput {
         return match self {
             ModelInput::Int(_) => std::u64::MAX.into(),
@@ -544,6 +972,7 @@ impl From<f64> for ModelInput {
         ModelInput::Float(f)
     }
 }
+#[non_exhaustive]
 pub enum ModelDataType {
     Int,
     Int128,
@@ -587,7 +1016,7 @@ impl ModelParam {
         match self {
             ModelParam::Int(_) => "uint64_t",
             ModelParam::Float(_) => "double",
-            ModelParam::ShortArray(_) => "short",
+            ModelParam::ShortArray(_) => "uint16_t",
             ModelParam::IntArray(_) => "uint64_t",
             ModelParam::Int32Array(_) => "uint32_t",
             ModelParam::FloatArray(_) => "double",
@@ -664,7 +1093,7 @@ impl ModelParam {
         return std::mem::discriminant(self) == std::mem::discriminant(other);
     }
 
-    pub fn write_to<T: Write>(&self, target: &mut T) -> Result<(), std::io::Error> {
+    pub fn write_to<T: Write + ?Sized>(&self, target: &mut T) -> Result<(), std::io::Error> {
         match self {
             ModelParam::Int(v) => target.write_u64::<LittleEndian>(*v),
             ModelParam::Float(v) => target.write_f64::<LittleEndian>(*v),
@@ -711,6 +1140,17 @@ impl ModelParam {
         }
     }
 
+    pub fn kind(&self) -> ParamKind {
+        match self {
+            ModelParam::Int(_) => ParamKind::Int,
+            ModelParam::Float(_) => ParamKind::Float,
+            ModelParam::ShortArray(_) => ParamKind::ShortArray,
+            ModelParam::IntArray(_) => ParamKind::IntArray,
+            ModelParam::Int32Array(_) => ParamKind::Int32Array,
+            ModelParam::FloatArray(_) => ParamKind::FloatArray,
+        }
+    }
+
     pub fn len(&self) -> usize {
         match self {
             ModelParam::Int(_) => 1,
@@ -723,6 +1163,79 @@ impl ModelParam {
     }
 }
 
+/// The variant of a `ModelParam`, without its value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParamKind {
+    Int,
+    Float,
+    ShortArray,
+    IntArray,
+    Int32Array,
+    FloatArray,
+}
+
+/// Where one parameter sits in the buffer returned by `Model::params_bytes`:
+/// its kind, byte offset, and number of elements (1 for scalars).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParamField {
+    pub kind: ParamKind,
+    pub offset: usize,
+    pub len: usize,
+}
+
+impl ParamField {
+    /// Reads this field back out of a `params_bytes` buffer.
+    pub fn read(&self, buf: &[u8]) -> ModelParam {
+        let buf = &buf[self.offset..];
+        return match self.kind {
+            ParamKind::Int => ModelParam::Int(LittleEndian::read_u64(buf)),
+            ParamKind::Float => ModelParam::Float(LittleEndian::read_f64(buf)),
+            ParamKind::ShortArray => {
+                let mut v = vec![0; self.len];
+                LittleEndian::read_u16_into(&buf[..2 * self.len], &mut v);
+                ModelParam::ShortArray(v)
+            }
+            ParamKind::IntArray => {
+                let mut v = vec![0; self.len];
+                LittleEndian::read_u64_into(&buf[..8 * self.len], &mut v);
+                ModelParam::IntArray(v)
+            }
+            ParamKind::Int32Array => {
+                let mut v = vec![0; self.len];
+                LittleEndian::read_u32_into(&buf[..4 * self.len], &mut v);
+                ModelParam::Int32Array(v)
+            }
+            ParamKind::FloatArray => {
+                let mut v = vec![0.0; self.len];
+                LittleEndian::read_f64_into(&buf[..8 * self.len], &mut v);
+                ModelParam::FloatArray(v)
+            }
+        };
+    }
+}
+
+/// Packs parameters back to back, with no padding, in the order given.
+pub fn flatten_params(params: &[ModelParam]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(params.iter().map(|p| p.size()).sum());
+    for p in params {
+        // writing into a Vec cannot fail
+        p.write_to(&mut buf).unwrap();
+    }
+    return buf;
+}
+
+/// The layout `flatten_params` produces for `params`.
+pub fn params_layout_of(params: &[ModelParam]) -> Vec<ParamField> {
+    let mut offset = 0;
+    return params.iter()
+        .map(|p| {
+            let field = ParamField { kind: p.kind(), offset, len: p.len() };
+            offset += p.size();
+            field
+        })
+        .collect();
+}
+
 impl From<usize> for ModelParam {
     fn from(i: usize) -> Self {
         ModelParam::Int(i as u64)
@@ -771,19 +1284,159 @@ impl From<Vec<f64>> for ModelParam {
     }
 }
 
+/// How many records a training pass processes between progress reports.
+pub(crate) const PROGRESS_STRIDE: usize = 1 << 16;
+
+/// Reports the fraction of a multi-pass build that is finished to an
+/// optional callback, at most once every `PROGRESS_STRIDE` records so that
+/// a hot training loop pays only for a comparison.
+pub(crate) struct Progress<'a> {
+    callback: Option<&'a dyn Fn(f64)>,
+    total: usize,
+    next_report: usize,
+}
+
+impl<'a> Progress<'a> {
+    pub fn new(callback: Option<&'a dyn Fn(f64)>, num_passes: usize, len: usize) -> Progress<'a> {
+        return Progress { callback, total: num_passes * len, next_report: 0 };
+    }
+
+    /// Record `idx` of the `pass`-th pass (each pass is over `len` records)
+    /// is being processed.
+    #[inline]
+    pub fn update(&mut self, pass: usize, len: usize, idx: usize) {
+        if let Some(callback) = self.callback {
+            let done = pass * len + idx;
+            if done >= self.next_report && done < self.total {
+                callback(done as f64 / self.total as f64);
+                self.next_report = done + PROGRESS_STRIDE;
+            }
+        }
+    }
+
+    pub fn finish(&self) {
+        if let Some(callback) = self.callback {
+            callback(1.0);
+        }
+    }
+}
+
+#[non_exhaustive]
 pub enum ModelRestriction {
     None,
     MustBeTop,
     MustBeBottom,
+    /// Like `MustBeBottom`, for models that only predict from Rust and
+    /// have no generated code. The code generator rejects RMIs using them.
+    RustOnlyBottom,
 }
 
+/// The type of a model. Every built-in model has its own kind, with a tag
+/// that names it in serialized RMIs; models registered with
+/// `register_model` are `Custom`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ModelKind {
+    Linear,
+    RobustLinear,
+    LinearSpline,
+    Cubic,
+    LogLinear,
+    Normal,
+    LogNormal,
+    Radix,
+    MultiplicativeRadix,
+    RadixTable,
+    RelativeRadixTable,
+    BalancedRadix,
+    Histogram,
+    LearnedFIB,
+    Custom,
+}
+
+impl ModelKind {
+    pub(crate) const TAGGED: [ModelKind; 14] = [
+        ModelKind::Linear, ModelKind::RobustLinear, ModelKind::LinearSpline,
+        ModelKind::Cubic, ModelKind::LogLinear, ModelKind::Normal,
+        ModelKind::LogNormal, ModelKind::Radix, ModelKind::MultiplicativeRadix,
+        ModelKind::RadixTable, ModelKind::RelativeRadixTable, ModelKind::BalancedRadix,
+        ModelKind::Histogram, ModelKind::LearnedFIB,
+    ];
+
+    /// A stable name for the kind. Changing one breaks every RMI
+    /// serialized with it.
+    pub fn tag(&self) -> &'static str {
+        return match self {
+            ModelKind::Linear => "linear",
+            ModelKind::RobustLinear => "robust_linear",
+            ModelKind::LinearSpline => "linear_spline",
+            ModelKind::Cubic => "cubic",
+            ModelKind::LogLinear => "loglinear",
+            ModelKind::Normal => "normal",
+            ModelKind::LogNormal => "lognormal",
+            ModelKind::Radix => "radix",
+            ModelKind::MultiplicativeRadix => "radix_mul",
+            ModelKind::RadixTable => "radix_table",
+            ModelKind::RelativeRadixTable => "relative_radix_table",
+            ModelKind::BalancedRadix => "bradix",
+            ModelKind::Histogram => "histogram",
+            ModelKind::LearnedFIB => "learned_fib",
+            ModelKind::Custom => "custom",
+        };
+    }
+
+    pub fn from_tag(tag: &str) -> Option<ModelKind> {
+        return ModelKind::TAGGED.iter().cloned().find(|k| k.tag() == tag);
+    }
+}
+
+/// The cache line size `Model::lookup_cache_lines` counts in.
+pub const CACHE_LINE_BYTES: usize = 64;
+
+/// A trained model. Models are never mutated by inference, and the
+/// `Sync + Send` bounds let a single `Box<dyn Model>` or `Arc<dyn Model>`
+/// serve lookups from many threads at once.
+///
+/// Models outside this crate can be plugged in with `register_model`. The
+/// code generator relies on the following contract:
+///
+/// * `code()` defines a C++ function named `function_name()` returning the
+///   C type of `output_type()`, whose arguments are the model's parameters
+///   in `params()` order (each as its `ModelParam::c_type`, arrays as
+///   pointers) followed by the key as the C type of `input_type()`.
+///   The function should be `inline`, since it is emitted once per RMI.
+/// * Every model in a layer returns the same number and kinds of
+///   `params()`, in the same order, regardless of its training data.
+/// * `function_name()` is unique to the emitted code: two models with the
+///   same name must have identical `code()`, as only one copy is emitted.
+/// * Functions listed in `standard_functions()` are emitted before the
+///   model code and may be called from it.
 pub trait Model: Sync + Send {
     fn predict_to_float(&self, inp: &ModelInput) -> f64 {
         return self.predict_to_int(inp) as f64;
     }
 
     fn predict_to_int(&self, inp: &ModelInput) -> u64 {
-        return f64::max(0.0, self.predict_to_float(inp).floor()) as u64;
+        return utils::f64_to_pos(self.predict_to_float(inp), u64::MAX);
+    }
+
+    /// `predict_to_int` computed the slow, plain way, as an oracle for
+    /// tests of the optimized paths (such as `predict_batch_sorted` or a
+    /// `FixedRadixModel`). Models whose prediction is already that simple
+    /// leave it as `predict_to_int`.
+    fn reference_predict(&self, inp: &ModelInput) -> u64 {
+        return self.predict_to_int(inp);
+    }
+
+    /// `predict_to_int` of a key given as raw little-endian bytes, decoded
+    /// for `input_type()` by `ModelInput::from_key_bytes`, so callers
+    /// across the FFI or generic over the key width need not build a
+    /// `ModelInput` themselves. Panics if the key has a length the model's
+    /// input type cannot take.
+    fn predict_bytes(&self, key: &[u8]) -> u64 {
+        let inp = ModelInput::from_key_bytes(key, self.input_type())
+            .unwrap_or_else(|e| panic!("{}", e));
+        return self.predict_to_int(&inp);
     }
 
     fn input_type(&self) -> ModelDataType;
@@ -791,6 +1444,42 @@ pub trait Model: Sync + Send {
 
     fn params(&self) -> Vec<ModelParam>;
 
+    /// `params()` packed into one little-endian buffer with no padding,
+    /// which is exactly how the generated data files store them for
+    /// `code()` to read. `params_layout` gives each parameter's offset.
+    fn params_bytes(&self) -> Vec<u8> {
+        return flatten_params(&self.params());
+    }
+
+    fn params_layout(&self) -> Vec<ParamField> {
+        return params_layout_of(&self.params());
+    }
+
+    /// How many 64-byte cache lines of parameters one prediction reads, as
+    /// input to layout and cost decisions. By default a prediction reads
+    /// every parameter; models that read only part of theirs, such as one
+    /// entry of a table, say so.
+    fn lookup_cache_lines(&self) -> usize {
+        return self.params_bytes().len().div_ceil(CACHE_LINE_BYTES);
+    }
+
+    /// Roughly how many arithmetic operations and loads predicting `inp`
+    /// takes, for choosing models by compute, as on devices with a tight
+    /// energy budget, rather than by latency. By default each scalar
+    /// parameter costs a multiply and an add, as for a polynomial, and
+    /// each array a single load.
+    fn estimated_flops(&self, _inp: &ModelInput) -> u64 {
+        return self.params().iter().map(|p| if p.is_array() { 1 } else { 2 }).sum();
+    }
+
+    /// Whether `predict_to_int` works on integers alone, never converting
+    /// the key or its prediction to or from floating point. Such a model
+    /// predicts the same position for a key on every target, whatever its
+    /// float rounding or use of fused multiply-adds.
+    fn integer_only(&self) -> bool {
+        return false;
+    }
+
     fn code(&self) -> String;
     fn function_name(&self) -> String;
 
@@ -798,19 +1487,218 @@ pub trait Model: Sync + Send {
         return HashSet::new();
     }
 
+    /// The headers, such as `"<cmath>"`, that `code()` and this model's
+    /// `standard_functions()` need to compile on their own.
+    fn required_includes(&self) -> Vec<&'static str> {
+        return Vec::new();
+    }
+
     fn needs_bounds_check(&self) -> bool {
         return true;
     }
     fn restriction(&self) -> ModelRestriction {
         return ModelRestriction::None;
     }
+    /// The maximum distance between this model's prediction and the true
+    /// position of any key it was trained on. Models restricted to the
+    /// bottom layer must report one, since nothing below them can
+    /// correct their predictions.
     fn error_bound(&self) -> Option<u64> {
         return None;
     }
+    /// The smallest and largest key this model was trained on, as
+    /// integers. `None` if unknown, e.g. for models rebuilt from their
+    /// state, which does not record it.
+    fn key_domain(&self) -> Option<(u64, u64)> {
+        return None;
+    }
+
+    /// The lowest position a search around this model's predictions needs
+    /// to reach, for models trained on positions that do not start at 0,
+    /// such as the leaves of a larger RMI. Windows of the prediction minus
+    /// the error bound are clamped to it.
+    fn min_value(&self) -> u64 {
+        return 0;
+    }
+
+    /// Whether `predict_range_bounds` can be called on this model.
+    fn supports_range_query(&self) -> bool {
+        return false;
+    }
+
+    /// The window of positions `start..end` holding every trained key in
+    /// `lo..=hi`: `start` is at or before the first such key, and `end` is
+    /// past the last. By default this widens the predictions for `lo` and
+    /// `hi` by the error bound, which only holds for monotonic models.
+    /// Panics if `supports_range_query` is false.
+    fn predict_range_bounds(&self, lo: &ModelInput, hi: &ModelInput) -> (u64, u64) {
+        assert!(self.supports_range_query(),
+                "{} does not support range queries", self.function_name());
+        let err = self.error_bound().unwrap();
+        let start = u64::max(self.predict_to_int(lo).saturating_sub(err), self.min_value());
+        return (start, self.predict_to_int(hi).saturating_add(err).saturating_add(1));
+    }
 
     fn set_to_constant_model(&mut self, _constant: u64) -> bool {
         return false;
     }
+
+    /// Moves the model's parameters into `arena`, so that its parameter
+    /// arrays live in the arena's buffer instead of allocations of their
+    /// own, and returns the index of its entry there. The model predicts
+    /// as before, and writing to its arrays, as by `retrain`, first copies
+    /// them back out. Models without arrays to move return `None`, as do
+    /// all models once the arena is full.
+    #[cfg(feature = "arena")]
+    fn move_into(&mut self, _arena: &crate::arena::ParamArena) -> Option<usize> {
+        return None;
+    }
+
+    /// Updates the model for `data`, the keys it was trained on (as `u64`,
+    /// like `CustomModelTrainer`) after the records in `changed` changed.
+    /// Records before and after `changed` must be the ones the model was
+    /// trained on, at the same positions: an append changes the records
+    /// past the old end, while an insert changes every record after it.
+    /// Models retrain only the parts the change reaches where they can,
+    /// and otherwise train again from scratch; either way they predict as
+    /// a model newly trained on `data` would. The default is for models
+    /// that cannot be trained from `data` alone, and returns an
+    /// `Error::InvalidArgument`.
+    fn retrain(&mut self, _data: &RMITrainingData<u64>,
+               _changed: Range<usize>) -> Result<(), Error> {
+        return Err(Error::InvalidArgument {
+            reason: format!("{} cannot be retrained", self.function_name()),
+        });
+    }
+
+    /// Which type of model this is, for code that needs to tell models
+    /// apart without matching on `function_name`.
+    fn kind(&self) -> ModelKind {
+        return ModelKind::Custom;
+    }
+
+    /// This model's kind, plus every parameter needed to rebuild it with
+    /// `model_from_state`. Used to serialize a trained RMI; models that
+    /// cannot be rebuilt return `None`.
+    fn state(&self) -> Option<(ModelKind, Vec<ModelParam>)> {
+        return None;
+    }
+
+    /// Writes `state()` to `w`, tagged with the model's kind, for
+    /// `load_model` to read back. This is how `TrainedRMI::to_bytes` stores
+    /// each model. Models without a state return an `Unsupported` error.
+    fn save(&self, w: &mut dyn Write) -> io::Result<()> {
+        let (kind, params) = self.state().ok_or_else(|| io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("Model {} cannot be serialized", self.function_name())))?;
+        return crate::serialize::write_model(w, kind, &params);
+    }
+
+    /// This model as `Any`, so a `dyn Model` can be downcast to its
+    /// concrete type with `downcast_ref`. Implementations return `self`.
+    fn as_any(&self) -> &dyn Any;
+}
+
+/// Checks that `search_window`, given `model`'s prediction and error bound,
+/// finds every key of `data` at its position. Returns the index of the first
+/// key it does not. A model without an error bound is searched over the
+/// whole data.
+pub fn verify<T: TrainingKey>(model: &dyn Model, data: &RMITrainingData<T>) -> Result<(), usize> {
+    for (idx, (key, pos)) in data.iter().enumerate() {
+        if data.lookup(model, key) != Some(pos) {
+            return Err(idx);
+        }
+    }
+    return Ok(());
+}
+
+/// The error of every key's full path through two layers: `top` routes the
+/// key to one of the `bottom` models, whose prediction is compared with the
+/// key's position. Returns, per bottom model, how many keys were routed to
+/// it and the largest error among them. This is the error a lookup sees,
+/// so unlike each bottom model's own bound it includes keys `top` sends to
+/// a model that was not trained on them. Predictions past the end of the
+/// data count as the end. With no bottom models there is nothing to route
+/// to, and the result is empty.
+pub fn merge_errors<T: TrainingKey>(top: &dyn Model, bottom: &[Box<dyn Model>],
+                                    data: &RMITrainingData<T>) -> Vec<(u64, u64)> {
+    if bottom.is_empty() {
+        return Vec::new();
+    }
+
+    let end = data.len() as u64;
+    let mut errors = vec![(0, 0); bottom.len()];
+    for (x, y) in data.iter_model_input() {
+        let target = u64::min(bottom.len() as u64 - 1, top.predict_to_int(&x)) as usize;
+        let pred = u64::min(bottom[target].predict_to_int(&x), end);
+        let actual = u64::min(y as u64, end);
+        let err = u64::max(pred, actual) - u64::min(pred, actual);
+
+        let (count, max) = errors[target];
+        errors[target] = (count + 1, u64::max(max, err));
+    }
+    return errors;
+}
+
+// Prints how long `predict_batch_sorted` takes per key of a batch, sorted
+// and in random order, against `model` predicting each key one by one. A
+// first, untimed batch warms the caches. Only the benchmarks use it.
+#[cfg(test)]
+pub(crate) fn bench_batch_sorted(model: &dyn Model, num_queries: u64,
+                                 predict_batch_sorted: impl Fn(&[u64], &mut [u64])) {
+    use std::hint::black_box;
+    use std::time::Instant;
+
+    let mut sorted: Vec<u64> = (0..num_queries).map(|i| i.wrapping_mul(0x2545_F491_4F6C_DD1D))
+        .collect();
+    let random = sorted.clone();
+    sorted.sort_unstable();
+
+    let time = |batch: &[u64], sorted_path: bool| {
+        let mut out = vec![0; batch.len()];
+        let start = Instant::now();
+        if sorted_path {
+            predict_batch_sorted(black_box(batch), &mut out);
+        } else {
+            for (key, pred) in batch.iter().zip(out.iter_mut()) {
+                *pred = model.predict_to_int(&black_box(*key).into());
+            }
+        }
+        black_box(&out);
+        return start.elapsed().as_nanos() as f64 / batch.len() as f64;
+    };
+
+    time(&sorted, true);
+    println!("sorted batch:         {:.2} ns per key", time(&sorted, true));
+    println!("sorted, one by one:   {:.2} ns per key", time(&sorted, false));
+    println!("random batch:         {:.2} ns per key", time(&random, true));
+    println!("random, one by one:   {:.2} ns per key", time(&random, false));
+}
+
+/// Rebuilds a model from a kind and parameters produced by `Model::state`.
+/// Returns `None` if the kind cannot be rebuilt or the parameters do not
+/// match it.
+pub fn model_from_state(kind: ModelKind, params: &[ModelParam]) -> Option<Box<dyn Model>> {
+    let model: Box<dyn Model> = match kind {
+        ModelKind::Linear => Box::new(LinearModel::from_state(params)?),
+        ModelKind::RobustLinear => Box::new(RobustLinearModel::from_state(params)?),
+        ModelKind::LinearSpline => Box::new(LinearSplineModel::from_state(params)?),
+        ModelKind::Cubic => Box::new(CubicSplineModel::from_state(params)?),
+        ModelKind::LogLinear => Box::new(LogLinearModel::from_state(params)?),
+        ModelKind::Normal => Box::new(NormalModel::from_state(params)?),
+        ModelKind::LogNormal => Box::new(LogNormalModel::from_state(params)?),
+        ModelKind::Radix => Box::new(RadixModel::from_state(params)?),
+        ModelKind::MultiplicativeRadix =>
+            Box::new(RadixModel::from_state_with(params, Multiplicative)?),
+        ModelKind::RadixTable => Box::new(RadixTable::from_state(params)?),
+        ModelKind::RelativeRadixTable => Box::new(RelativeRadixTable::from_state(params)?),
+        ModelKind::BalancedRadix => Box::new(BalancedRadixModel::from_state(params)?),
+        ModelKind::Histogram => Box::new(EquidepthHistogramModel::from_state(params)?),
+        ModelKind::LearnedFIB => Box::new(LearnedFIB::from_state(params)?),
+        ModelKind::Custom => return None,
+    };
+
+    return Some(model);
 }
 
 #[cfg(test)]
@@ -819,24 +1707,733 @@ mod tests {
 
     #[test]
     fn test_scale() {
-        let mut v = ModelData::IntKeyToIntPos(vec![(0, 0), (1, 1), (3, 2), (100, 3)]);
+        let mut v = RMITrainingData::<u64>::new(Box::new(vec![(0, 0), (1, 1), (3, 2), (100, 3)]));
 
-        v.scale_targets_to(50, 4);
+        v.set_scale(50.0 / 4.0);
 
-        let results = v.as_int_int();
+        let results: Vec<(u64, usize)> = v.iter().collect();
         assert_eq!(results[0].1, 0);
         assert_eq!(results[1].1, 12);
         assert_eq!(results[2].1, 25);
         assert_eq!(results[3].1, 37);
     }
 
+    #[test]
+    fn test_try_get() {
+        let empty = RMITrainingData::<u64>::empty();
+        assert_eq!(empty.try_get(0), None);
+        assert_eq!(empty.slice(0..0).try_get(0), None);
+        assert_eq!(empty.key_domain(), None);
+        assert_eq!(empty.num_positions(), 0);
+
+        let mut v = RMITrainingData::<u64>::new(Box::new(vec![(0, 0), (1, 1), (3, 2), (100, 3)]));
+        v.set_scale(10.0);
+        assert_eq!(v.try_get(3), Some((100, 30)));
+        assert_eq!(v.try_get(4), None);
+        assert_eq!(v.key_domain(), Some((0, 100)));
+
+        // a view ends where its range does, not where the data does
+        let view = v.slice(1..3);
+        assert_eq!(view.try_get(1), Some((3, 20)));
+        assert_eq!(view.try_get(2), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Index 4 out of bounds for data of length 4")]
+    fn test_get_out_of_bounds() {
+        RMITrainingData::<u64>::new(Box::new(vec![(0, 0), (1, 1), (3, 2), (100, 3)])).get(4);
+    }
+
+    fn assert_send_sync<T: Send + Sync + ?Sized>() {}
+
+    #[test]
+    fn test_models_are_send_sync() {
+        assert_send_sync::<dyn Model>();
+        assert_send_sync::<Box<dyn Model>>();
+        assert_send_sync::<RadixModel>();
+        assert_send_sync::<RadixTable>();
+        assert_send_sync::<LearnedFIB>();
+    }
+
+    #[test]
+    fn test_concurrent_predict() {
+        let mut keys: Vec<u64> = (0..10_000u64)
+            .map(|i| i.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+            .collect();
+        keys.sort_unstable();
+        let data: Vec<(u64, usize)> = keys.iter().cloned().zip(0..).collect();
+        let md = RMITrainingData::new(Box::new(data.clone()));
+
+        let models: Vec<Arc<dyn Model>> = vec![
+            Arc::new(RadixTable::new(&md, 10)),
+            Arc::new(LearnedFIB::new(&md, 16, 4)),
+        ];
+
+        for model in models {
+            let expected: Vec<u64> = data.iter()
+                .map(|(k, _)| model.predict_to_int(&(*k).into()))
+                .collect();
+            let expected = Arc::new(expected);
+
+            let handles: Vec<_> = (0..4)
+                .map(|_| {
+                    let model = Arc::clone(&model);
+                    let expected = Arc::clone(&expected);
+                    let keys = keys.clone();
+                    std::thread::spawn(move || {
+                        for (key, exp) in keys.iter().zip(expected.iter()) {
+                            assert_eq!(model.predict_to_int(&(*key).into()), *exp);
+                        }
+                    })
+                })
+                .collect();
+
+            for h in handles {
+                h.join().unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn test_state_round_trip() {
+        let data: Vec<(u64, usize)> = (1..2000u64).map(|i| i * i + 3).zip(0..).collect();
+        let md = RMITrainingData::new(Box::new(data.clone()));
+
+        let models: Vec<Box<dyn Model>> = vec![
+            Box::new(LinearModel::new(&md)),
+            Box::new(RobustLinearModel::new(&md)),
+            Box::new(LinearSplineModel::new(&md)),
+            Box::new(CubicSplineModel::new(&md)),
+            Box::new(LogLinearModel::new(&md)),
+            Box::new(NormalModel::new(&md)),
+            Box::new(LogNormalModel::new(&md)),
+            Box::new(RadixModel::new(&md)),
+            Box::new(RadixTable::new(&md, 8)),
+            Box::new(RelativeRadixTable::new(&md, 8, 4)),
+            Box::new(EquidepthHistogramModel::new(&md)),
+            Box::new(LearnedFIB::new(&md, 8, 3)),
+            // bradix training overflows on unscaled data, so build it directly
+            model_from_state(ModelKind::BalancedRadix, &[4u64.into(), 8u64.into(), 100u64.into(), 1u64.into()])
+                .unwrap(),
+        ];
+
+        for model in models {
+            let (kind, params) = model.state().unwrap();
+            let rebuilt = model_from_state(kind, &params).unwrap();
+            for (key, _) in data.iter() {
+                assert_eq!(rebuilt.predict_to_int(&(*key).into()),
+                           model.predict_to_int(&(*key).into()),
+                           "{:?} changed after a round trip", kind);
+            }
+
+            assert!(model_from_state(kind, &params[1..]).is_none());
+        }
+
+        assert!(model_from_state(ModelKind::Custom, &[]).is_none());
+    }
+
+    #[test]
+    fn test_model_kind() {
+        let data: Vec<(u64, usize)> = (1..2000u64).map(|i| i * i + 3).zip(0..).collect();
+        let md = RMITrainingData::new(Box::new(data));
+
+        let radix = RadixModel::from_state(&[0u64.into(), 12u64.into()]).unwrap();
+        let models: Vec<(Box<dyn Model>, ModelKind)> = vec![
+            (Box::new(LinearModel::new(&md)), ModelKind::Linear),
+            (Box::new(RobustLinearModel::new(&md)), ModelKind::RobustLinear),
+            (Box::new(LinearSplineModel::new(&md)), ModelKind::LinearSpline),
+            (Box::new(CubicSplineModel::new(&md)), ModelKind::Cubic),
+            (Box::new(LogLinearModel::new(&md)), ModelKind::LogLinear),
+            (Box::new(NormalModel::new(&md)), ModelKind::Normal),
+            (Box::new(LogNormalModel::new(&md)), ModelKind::LogNormal),
+            (Box::new(FixedRadixModel::<12>::from_dynamic(&radix).unwrap()), ModelKind::Radix),
+            (Box::new(radix), ModelKind::Radix),
+            (Box::new(RadixTable::new(&md, 8)), ModelKind::RadixTable),
+            (Box::new(RelativeRadixTable::new(&md, 8, 4)), ModelKind::RelativeRadixTable),
+            (Box::new(EquidepthHistogramModel::new(&md)), ModelKind::Histogram),
+            (Box::new(LearnedFIB::new(&md, 8, 3)), ModelKind::LearnedFIB),
+            (model_from_state(ModelKind::BalancedRadix,
+                              &[4u64.into(), 8u64.into(), 100u64.into(), 1u64.into()]).unwrap(),
+             ModelKind::BalancedRadix),
+            (Box::new(Swapped), ModelKind::Custom),
+        ];
+
+        for (model, kind) in models {
+            assert_eq!(model.kind(), kind, "{}", model.function_name());
+            if let Some((state_kind, _)) = model.state() {
+                assert_eq!(state_kind, kind);
+            }
+        }
+
+        for kind in ModelKind::TAGGED.iter() {
+            assert_eq!(ModelKind::from_tag(kind.tag()), Some(*kind));
+        }
+        assert_eq!(ModelKind::from_tag("custom"), None);
+        assert_eq!(ModelKind::from_tag("no_such_model"), None);
+    }
+
+    #[test]
+    fn test_slice() {
+        let data: Vec<(u64, usize)> = vec![(1, 0), (3, 1), (3, 2), (4, 3), (9, 4), (9, 5)];
+        let md = RMITrainingData::new(Box::new(data));
+
+        let full: Vec<(u64, usize)> = md.iter().collect();
+        for start in 0..=md.len() {
+            for end in start..=md.len() {
+                let view = md.slice(start..end);
+                assert_eq!(view.len(), end - start);
+                assert_eq!(view.is_empty(), start == end);
+
+                let from_view: Vec<(u64, usize)> = view.iter().collect();
+                assert_eq!(from_view, full[start..end].to_vec());
+
+                for i in 0..view.len() {
+                    assert_eq!(view.get(i), md.get(start + i));
+                    assert_eq!(view.get_key(i).as_uint(), md.get_key(start + i));
+                    assert_eq!(view.get_key(i).as_float(), md.get_key(start + i) as f64);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_shards() {
+        let keys: Vec<u64> = (0..1_000u64).map(|i| if (100..400).contains(&i) { 100 } else { i })
+            .collect();
+        let md = RMITrainingData::from_keys(keys.clone());
+        for k in [1, 2, 3, 7, 10, 999, 1_000, 5_000] {
+            let shards = md.shards(k);
+            assert!(!shards.is_empty() && shards.len() <= k);
+
+            let mut joined = Vec::new();
+            for (view, offset) in shards.iter() {
+                assert!(!view.is_empty());
+                assert_eq!(*offset, md.get(joined.len()).1 as u64);
+                joined.extend((0..view.len()).map(|i| view.get_key(i)));
+            }
+            assert_eq!(joined, keys);
+
+            // a run of copies is never split between shards
+            for pair in shards.windows(2) {
+                let (prev, next) = (&pair[0].0, &pair[1].0);
+                assert_ne!(prev.get_key(prev.len() - 1), next.get_key(0));
+            }
+        }
+        assert_eq!(md.shards(1_000).len(), 701);
+
+        // offsets follow scaled positions
+        let mut scaled = RMITrainingData::from_keys(0..100u64);
+        scaled.set_scale(2.0);
+        let offsets: Vec<u64> = scaled.shards(4).into_iter().map(|(_, off)| off).collect();
+        assert_eq!(offsets, vec![0, 50, 100, 150]);
+
+        assert!(RMITrainingData::<u64>::empty().shards(3).is_empty());
+    }
+
+    #[test]
+    fn test_from_keys() {
+        let md = RMITrainingData::from_keys(vec![10u64, 20, 30]);
+        let pairs: Vec<(u64, usize)> = (0..md.len()).map(|i| md.get(i)).collect();
+        assert_eq!(pairs, vec![(10, 0), (20, 1), (30, 2)]);
+
+        let md = RMITrainingData::from_keys(vec![1u64, 5, 5, 5, 8]);
+        let pairs: Vec<(u64, usize)> = (0..md.len()).map(|i| md.get(i)).collect();
+        assert_eq!(pairs, vec![(1, 0), (5, 1), (5, 2), (5, 3), (8, 4)]);
+        let iterated: Vec<(u64, usize)> = md.iter().collect();
+        assert_eq!(iterated, vec![(1, 0), (5, 1), (5, 1), (5, 1), (8, 4)]);
+
+        let keys: Vec<u64> = (0..10_000u64).map(|i| i * i).collect();
+        let md = RMITrainingData::from_keys(keys.clone());
+        let model = RadixTable::new(&md, 8);
+        let bound = model.error_bound().unwrap();
+        for (pos, key) in keys.into_iter().enumerate() {
+            let pred = model.predict_to_int(&key.into());
+            assert!(u64::max(pred, pos as u64) - u64::min(pred, pos as u64) <= bound);
+        }
+    }
+
+    // predicts each key's position, but with neighbouring pairs swapped,
+    // so predictions are not monotone in the key
+    struct Swapped;
+
+    impl Model for Swapped {
+        fn predict_to_int(&self, inp: &ModelInput) -> u64 {
+            return (inp.as_int() / 10) ^ 1;
+        }
+        fn input_type(&self) -> ModelDataType { return ModelDataType::Int; }
+        fn output_type(&self) -> ModelDataType { return ModelDataType::Int; }
+        fn params(&self) -> Vec<ModelParam> { return Vec::new(); }
+        fn code(&self) -> String { return String::new(); }
+        fn function_name(&self) -> String { return String::from("swapped"); }
+        fn error_bound(&self) -> Option<u64> { return Some(1); }
+        fn as_any(&self) -> &dyn Any { return self; }
+    }
+
+    #[test]
+    fn test_search_window_non_monotone() {
+        let md = RMITrainingData::from_keys((0..1000u64).map(|i| i * 10));
+        let model = Swapped;
+        assert_eq!(verify(&model, &md), Ok(()));
+
+        // a binary search over the predictions goes wrong once it lands on
+        // one of the swapped pairs
+        let mut bisect_failures = 0;
+        for idx in 0..md.len() {
+            let key = md.get_key(idx);
+            let target = model.predict_to_int(&key.into());
+            let found = md.lower_bound_by(|(k, _)| {
+                model.predict_to_int(&k.into()).cmp(&target)
+            });
+            if found != idx {
+                bisect_failures += 1;
+            }
+            assert_eq!(md.search_window(key, target, 1), Some(idx));
+        }
+        assert!(bisect_failures > 0);
+
+        // keys outside the window, or missing altogether, are not found
+        assert_eq!(md.search_window(500, 10, 1), None);
+        assert_eq!(md.search_window(505, 50, 1), None);
+        assert_eq!(verify(&Swapped, &RMITrainingData::from_keys(vec![50u64, 60, 70])), Err(0));
+    }
+
+    // predicts key `x` at position `base + x / 10`, as a leaf of positions
+    // from `base` on
+    struct Based(u64);
+
+    impl Model for Based {
+        fn predict_to_int(&self, inp: &ModelInput) -> u64 {
+            return self.0 + inp.as_int() / 10;
+        }
+        fn input_type(&self) -> ModelDataType { return ModelDataType::Int; }
+        fn output_type(&self) -> ModelDataType { return ModelDataType::Int; }
+        fn params(&self) -> Vec<ModelParam> { return Vec::new(); }
+        fn code(&self) -> String { return String::new(); }
+        fn function_name(&self) -> String { return String::from("based"); }
+        fn error_bound(&self) -> Option<u64> { return Some(50); }
+        fn min_value(&self) -> u64 { return self.0; }
+        fn supports_range_query(&self) -> bool { return true; }
+        fn as_any(&self) -> &dyn Any { return self; }
+    }
+
+    #[test]
+    fn test_min_value() {
+        // windows near the first key stop at the base, not 50 below it
+        let based = Based(1_000);
+        assert_eq!(based.predict_range_bounds(&0.into(), &100.into()), (1_000, 1_061));
+        assert_eq!(based.predict_range_bounds(&1_000.into(), &1_000.into()), (1_050, 1_151));
+
+        let keys: Vec<(u64, usize)> = (0..500u64).map(|i| (i * 10, 1_000 + i as usize)).collect();
+        let md = RMITrainingData::new(Box::new(keys));
+        assert_eq!(md.lookup_positions(&based, 0), Some(1_000..1_001));
+        assert_eq!(md.lookup_positions(&based, 4_990), Some(1_499..1_500));
+        assert_eq!(LinearModel::new(&md).min_value(), 0);
+
+        // tables start their windows at their first hint, and end them
+        // past the last key's position
+        let table = RadixTable::new(&md, 8);
+        assert_eq!(table.min_value(), 1_000);
+        assert_eq!(table.predict_range_bounds(&0.into(), &u64::MAX.into()), (1_000, 1_500));
+        assert_eq!(RelativeRadixTable::new(&md, 8, 4).min_value(), 1_000);
+        let mut leaf = RadixTable::new_leaf(&RMITrainingData::<u64>::empty(), 8);
+        assert!(leaf.set_to_constant_model(1_300));
+        assert_eq!(leaf.min_value(), 1_300);
+    }
+
+    // routes key `x` to bottom model `(x + shift) / 500`
+    struct Route(u64);
+
+    impl Model for Route {
+        fn predict_to_int(&self, inp: &ModelInput) -> u64 {
+            return (inp.as_int() + self.0) / 500;
+        }
+        fn input_type(&self) -> ModelDataType { return ModelDataType::Int; }
+        fn output_type(&self) -> ModelDataType { return ModelDataType::Int; }
+        fn params(&self) -> Vec<ModelParam> { return Vec::new(); }
+        fn code(&self) -> String { return String::new(); }
+        fn function_name(&self) -> String { return String::from("route"); }
+        fn as_any(&self) -> &dyn Any { return self; }
+    }
+
+    #[test]
+    fn test_merge_errors() {
+        // keys 0..500 at their own positions, then keys spaced by 2 from
+        // 1000 on, so each half is exactly linear but with another slope
+        let keys: Vec<u64> = (0..500u64).chain((0..500u64).map(|i| 1000 + 2 * i)).collect();
+        let md = RMITrainingData::from_keys(keys.clone());
+        let bottom: Vec<Box<dyn Model>> = vec![
+            Box::new(LinearModel::new(&RMITrainingData::new(Box::new(md.slice(0..500)
+                .iter().collect::<Vec<_>>())))),
+            Box::new(LinearModel::new(&RMITrainingData::new(Box::new(md.slice(500..1000)
+                .iter().collect::<Vec<_>>())))),
+        ];
+
+        // routed correctly, every key sees only its own model's (zero) error
+        let exact = merge_errors(&Route(0), &bottom, &md);
+        assert_eq!(exact, vec![(500, 0), (500, 0)]);
+
+        // routing the last 10 keys of the first half to the second model
+        // puts them hundreds of positions off
+        let off = merge_errors(&Route(10), &bottom, &md);
+        assert_eq!(off[0], (490, 0));
+        assert_eq!(off[1].0, 510);
+        let pred = bottom[1].predict_to_int(&499u64.into());
+        assert_eq!(off[1].1, u64::max(pred, 499) - u64::min(pred, 499));
+        assert!(off[1].1 > 200);
+
+        assert!(merge_errors(&Route(0), &[], &md).is_empty());
+    }
+
+    #[test]
+    fn test_lookup_past_f64_precision() {
+        // neighbouring keys only differ below the 53 bits an f64 keeps
+        let keys: Vec<u64> = (0..500u64).map(|i| (1 << 60) + 3 * i).collect();
+        assert_eq!(keys[0] as f64, keys[1] as f64);
+        let md = RMITrainingData::from_keys(keys.clone());
+
+        let model = LinearModel::new(&md);
+        for (pos, key) in keys.iter().enumerate() {
+            assert_eq!(md.lookup(&model, *key), Some(pos));
+        }
+        assert_eq!(md.lookup(&model, (1 << 60) + 1), None);
+        assert_eq!(verify(&model, &md), Ok(()));
+    }
+
+    #[test]
+    fn test_search_window_duplicates() {
+        let md = RMITrainingData::from_keys(vec![1u64, 5, 5, 5, 5, 8]);
+        assert_eq!(md.search_window(5, 4, 3), Some(1));
+        assert_eq!(md.search_window(5, 4, 1), Some(3));
+        assert_eq!(md.search_window(8, 100, 0), Some(5));
+        assert_eq!(RMITrainingData::<u64>::empty().search_window(1, 0, 10), None);
+    }
+
+    #[test]
+    fn test_iter_borrowed() {
+        let keys: Vec<u64> = vec![1, 2, 2, 2, 5, 7, 7, 9, 9, 9, 9, 12];
+        let mut md = RMITrainingData::from_keys(keys.clone());
+        let unscaled = RMITrainingData::from_keys(keys.clone());
+
+        for scale in [1.0, 0.5] {
+            md.set_scale(scale);
+            let owned: Vec<(u64, usize)> = md.iter().collect();
+            let borrowed: Vec<(u64, usize)> = md.iter_borrowed().unwrap()
+                .map(|(key, pos)| (*key, pos))
+                .collect();
+            assert_eq!(owned, borrowed);
+
+            // views starting inside and at the end of a run of duplicates
+            for (from, to) in [(0, 12), (2, 6), (3, 3), (8, 12), (10, 11), (11, 12)] {
+                let view = md.slice(from..to);
+                let borrowed: Vec<(u64, usize)> = view.iter_borrowed().unwrap()
+                    .map(|(key, pos)| (*key, pos))
+                    .collect();
+                assert_eq!(view.iter().collect::<Vec<_>>(), borrowed, "{}..{}", from, to);
+            }
+        }
+
+        let borrowed: Vec<(u64, usize)> = unscaled.slice(5..10).iter_borrowed().unwrap()
+            .map(|(key, pos)| (*key, pos))
+            .collect();
+        assert_eq!(borrowed, vec![(7, 5), (7, 5), (9, 7), (9, 7), (9, 7)]);
+    }
+
+    // Per-bucket passes over views of the data, as `LearnedFIB` makes: the
+    // owning iterator copies (and skips) every record before each view,
+    // while the borrowing one starts at the view.
+    #[test]
+    #[ignore]
+    fn bench_iter_borrowed() {
+        use std::hint::black_box;
+        use std::time::Instant;
+
+        let md = RMITrainingData::from_keys((0..1u64 << 20).map(|i| i * 3));
+        let buckets = 256;
+        let per_bucket = md.len() / buckets;
+
+        let start = Instant::now();
+        let mut acc = 0u64;
+        for b in 0..buckets {
+            let view = md.slice(b * per_bucket..(b + 1) * per_bucket);
+            for (key, pos) in view.iter() {
+                acc = acc.wrapping_add(key ^ pos as u64);
+            }
+        }
+        black_box(acc);
+        let owned = start.elapsed();
+
+        let start = Instant::now();
+        let mut acc = 0u64;
+        for b in 0..buckets {
+            let view = md.slice(b * per_bucket..(b + 1) * per_bucket);
+            for (key, pos) in view.iter_borrowed().unwrap() {
+                acc = acc.wrapping_add(*key ^ pos as u64);
+            }
+        }
+        black_box(acc);
+        let borrowed = start.elapsed();
+
+        println!("{} buckets of {} keys: owned {:?}, borrowed {:?}",
+                 buckets, per_bucket, owned, borrowed);
+    }
+
+    #[test]
+    fn test_finite_float() {
+        assert_eq!(ModelInput::finite_float(2.5).unwrap(), ModelInput::Float(2.5));
+        assert!(ModelInput::Int(u64::MAX).check_finite().is_ok());
+        for bad in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            let err = ModelInput::finite_float(bad).unwrap_err();
+            assert!(matches!(err, Error::InvalidArgument { .. }));
+            assert!(ModelInput::Float(bad).check_finite().is_err());
+        }
+    }
+
+    #[test]
+    fn test_as_any() {
+        let md = RMITrainingData::from_keys((0..1_000u64).map(|i| i * 7));
+        let table: Box<dyn Model> = Box::new(RadixTable::new(&md, 8));
+        let radix: Box<dyn Model> = Box::new(RadixModel::new(&md));
+
+        let concrete = table.as_any().downcast_ref::<RadixTable>().unwrap();
+        let (lo, hi) = concrete.predict_window(&70.into());
+        assert!(lo <= 10 && 10 < hi);
+        assert!(radix.as_any().downcast_ref::<RadixTable>().is_none());
+        assert!(radix.as_any().downcast_ref::<RadixModel>().is_some());
+    }
+
+    #[test]
+    fn test_reference_predict() {
+        // pseudo-random keys sharing their top 4 bits, and queries inside
+        // and outside that range
+        let random = |i: u64| i.wrapping_mul(0x9E37_79B9_7F4A_7C15).rotate_left(17);
+        let mut keys: Vec<u64> = (0..5_000u64).map(|i| (3 << 60) | (random(i) >> 4)).collect();
+        keys.sort_unstable();
+        let mut queries: Vec<u64> = (0..100_000u64).map(|i| random(i + 5_000)).collect();
+        queries.extend(keys.iter().cloned());
+        queries.extend(keys.iter().map(|k| k + 1));
+        queries.sort_unstable();
+
+        let md = RMITrainingData::from_keys(keys.clone());
+        let radix = RadixModel::new(&md);
+        let mut fixed_fib = LearnedFIB::new(&md, 4, 6);
+        fixed_fib.use_fixed_point(&md);
+        let models: Vec<Box<dyn Model>> = vec![
+            Box::new(LinearModel::new(&md)),
+            Box::new(FixedRadixModel::<12>::from_dynamic(&radix).unwrap()),
+            Box::new(radix),
+            Box::new(RadixTable::new(&md, 10)),
+            Box::new(RadixTable::new_leaf(&md, 10)),
+            Box::new(RadixTable::new_with_hint_policy(&md, 10, HintPolicy::Midpoint)),
+            Box::new(RelativeRadixTable::new(&md, 12, 4)),
+            Box::new(LearnedFIB::new(&md, 4, 6)),
+            Box::new(LearnedFIB::new_with_offset_table(&md, 4, 6)),
+            Box::new(fixed_fib),
+        ];
+
+        for model in models.iter() {
+            let reference: Vec<u64> = queries.iter()
+                .map(|q| model.reference_predict(&(*q).into()))
+                .collect();
+            for (query, expected) in queries.iter().zip(reference.iter()) {
+                assert_eq!(model.predict_to_int(&(*query).into()), *expected,
+                           "{} disagrees with its reference at {}", model.function_name(), query);
+            }
+
+            let mut batch = vec![0; queries.len()];
+            if let Some(table) = model.as_any().downcast_ref::<RadixTable>() {
+                table.predict_batch_sorted(&queries, &mut batch);
+                assert_eq!(batch, reference);
+            } else if let Some(fib) = model.as_any().downcast_ref::<LearnedFIB>() {
+                fib.predict_batch_sorted(&queries, &mut batch);
+                assert_eq!(batch, reference);
+            }
+        }
+    }
+
+    #[test]
+    fn test_lookup_cache_lines() {
+        let keys: Vec<u64> = (0..100_000u64).map(|i| i * i * 13).collect();
+        let md = RMITrainingData::from_keys(keys);
+        assert_eq!(RadixModel::new(&md).lookup_cache_lines(), 0);
+        assert_eq!(LinearModel::new(&md).lookup_cache_lines(), 1);
+        assert_eq!(CubicSplineModel::new(&md).lookup_cache_lines(), 1);
+
+        // one hint whatever the size of the table
+        let (small, large) = (RadixTable::new(&md, 4), RadixTable::new(&md, 20));
+        assert!(large.params_bytes().len() > 1_000 * small.params_bytes().len());
+        assert_eq!(small.lookup_cache_lines(), 1);
+        assert_eq!(large.lookup_cache_lines(), 1);
+        assert_eq!(RadixTable::new_leaf(&md, 8).lookup_cache_lines(), 2);
+        assert_eq!(RelativeRadixTable::new(&md, 12, 4).lookup_cache_lines(), 2);
+
+        // a network with a single segment fits in one line, and a finer
+        // fit has more segments to read
+        let fib = LearnedFIB::new(&RMITrainingData::from_keys(0..10_000u64), 1, 4);
+        assert_eq!(fib.lookup_cache_lines(), 1);
+        let md = RMITrainingData::from_keys((0..5_000u64).map(|i| i * i * 13));
+        let coarse = LearnedFIB::new(&md, 64, 4).lookup_cache_lines();
+        let fine = LearnedFIB::new(&md, 1, 4).lookup_cache_lines();
+        assert!(fine > coarse, "{} lines at threshold 1, {} at 64", fine, coarse);
+        assert_eq!(LearnedFIB::new_with_offset_table(&md, 1, 4).lookup_cache_lines(), fine + 1);
+    }
+
+    #[test]
+    fn test_sample_cdf() {
+        let keys: Vec<u64> = (0..10_000u64).map(|i| 5 + i * i).collect();
+        let mut md = RMITrainingData::from_keys(keys.clone());
+        // scaling the positions does not change the CDF
+        md.set_scale(0.01);
+
+        let samples = md.sample_cdf(100);
+        assert_eq!(samples.len(), 100);
+        assert!(samples.windows(2).all(|w| w[0].0 < w[1].0 && w[0].1 < w[1].1));
+        assert_eq!(samples[0], (5.0, 0.0));
+        assert_eq!(samples[99], (keys[9_999] as f64, 9_999.0 / 10_000.0));
+
+        // one sample is the first record, and no more samples than records
+        assert_eq!(md.sample_cdf(1), vec![(5.0, 0.0)]);
+        assert_eq!(RMITrainingData::from_keys(vec![1u64, 2, 3]).sample_cdf(10).len(), 3);
+        assert!(RMITrainingData::<u64>::empty().sample_cdf(10).is_empty());
+        assert!(md.sample_cdf(0).is_empty());
+    }
+
+    #[test]
+    fn test_compact() {
+        // keys 3 and 8 have two copies each, at their own positions
+        let clean: Vec<(u64, usize)> = vec![(1, 0), (3, 1), (3, 2), (5, 3), (8, 4), (8, 5), (9, 6)];
+        let mut planted = Vec::new();
+        for (idx, record) in clean.iter().enumerate() {
+            let copies = [1, 3, 2, 1, 1, 4, 2][idx];
+            planted.extend(std::iter::repeat_n(*record, copies));
+        }
+
+        let mut md = RMITrainingData::new(Box::new(planted));
+        md.set_scale(2.0);
+        assert_eq!(md.compact(), 7);
+        assert_eq!(md.len(), clean.len());
+        let raw: Vec<(u64, usize)> = md.iterable.cdf_iter().collect();
+        assert_eq!(raw, clean);
+        // the scale survives
+        assert_eq!(md.get(6), (9, 12));
+        assert_eq!(md.compact(), 0);
+
+        let mut expected = RMITrainingData::new(Box::new(clean));
+        expected.set_scale(2.0);
+        let (model, baseline) = (LinearModel::new(&md), LinearModel::new(&expected));
+        for key in 0..12u64 {
+            assert_eq!(model.predict_to_int(&key.into()), baseline.predict_to_int(&key.into()));
+        }
+
+        // collapsed data has no exact repeats and keeps its runs
+        let mut collapsed = RMITrainingData::collapsed(vec![1u64, 1, 1, 4]);
+        assert_eq!(collapsed.compact(), 0);
+        assert_eq!(collapsed.run_len(0), 3);
+    }
+
+    #[test]
+    fn test_validate_positions_monotone() {
+        let md = RMITrainingData::from_sorted(vec![(1u64, 0), (5, 1), (9, 4)]).unwrap();
+        assert!(md.validate_positions_monotone().is_ok());
+        assert_eq!(md.len(), 3);
+
+        // copies of a key may share a position
+        let equal = RMITrainingData::from_sorted(vec![(1u64, 0), (5, 1), (5, 1), (9, 3)]);
+        assert!(equal.unwrap().validate_positions_monotone().is_ok());
+
+        let decreasing = vec![(1u64, 0), (5, 3), (7, 4), (9, 2), (11, 1)];
+        assert!(matches!(RMITrainingData::from_sorted(decreasing.clone()),
+                         Err(Error::Unsorted { index: 3 })));
+        assert!(matches!(RMITrainingData::new(Box::new(decreasing)).validate_positions_monotone(),
+                         Err(Error::Unsorted { index: 3 })));
+
+        assert!(RMITrainingData::<u64>::empty().validate_positions_monotone().is_ok());
+        assert!(RMITrainingData::collapsed(vec![2u64, 2, 2, 3]).validate_positions_monotone()
+                .is_ok());
+    }
+
+    #[test]
+    fn test_collapsed() {
+        // every key repeated, some many times
+        let keys: Vec<u64> = (0..5_000u64).map(|i| (i / 10) * 7 + (i / 4_000) * 3).collect();
+        let full = RMITrainingData::from_keys(keys.clone());
+        let collapsed = RMITrainingData::collapsed(keys.clone());
+        assert_eq!(collapsed.len(), 500);
+        assert_eq!(collapsed.num_positions(), keys.len());
+        assert_eq!(full.num_positions(), keys.len());
+
+        // expanding each run gives back the original positions
+        let mut expanded: Vec<(u64, usize)> = Vec::new();
+        for (idx, (key, start)) in collapsed.iter().enumerate() {
+            for pos in start..start + collapsed.run_len(idx) {
+                expanded.push((key, pos));
+            }
+        }
+        assert_eq!(expanded, keys.iter().cloned().zip(0..).collect::<Vec<_>>());
+        assert_eq!(collapsed.iter().collect::<Vec<_>>(), full.iter_unique().collect::<Vec<_>>());
+
+        // a model trained on the distinct keys finds every run
+        let model = LinearModel::new(&collapsed);
+        for (key, start) in full.iter_unique() {
+            let end = keys.iter().rposition(|k| *k == key).unwrap() + 1;
+            assert_eq!(collapsed.lookup_positions(&model, key), Some(start..end));
+            assert_eq!(full.lookup_positions(&model, key), Some(start..end));
+        }
+        assert_eq!(collapsed.lookup_positions(&model, 1), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "out of bounds")]
+    fn test_slice_out_of_bounds() {
+        let md = RMITrainingData::new(Box::new(vec![(1u64, 0), (2, 1)]));
+        md.slice(1..3);
+    }
+
     #[test]
     fn test_iter() {
-        let data = vec![(0, 1), (1, 2), (3, 3), (100, 4)];
+        let data: Vec<(u64, usize)> = vec![(0, 1), (1, 2), (3, 3), (100, 4)];
 
-        let v = ModelData::IntKeyToIntPos(data.clone());
+        let v = RMITrainingData::new(Box::new(data.clone()));
 
-        let iterated: Vec<(u64, u64)> = v.iter_uint_uint().collect();
+        let iterated: Vec<(u64, usize)> = v.iter().collect();
         assert_eq!(data, iterated);
     }
+
+    #[test]
+    fn test_retrain() {
+        // the models trained from their data alone are simply trained again
+        let old = RMITrainingData::from_keys((1..2_000u64).map(|i| i * i));
+        let new = RMITrainingData::from_keys((1..3_000u64).map(|i| i * i + i));
+        type Build = fn(&RMITrainingData<u64>) -> Box<dyn Model>;
+        let builds: Vec<Build> = vec![
+            |md| Box::new(LinearModel::new(md)),
+            |md| Box::new(LogLinearModel::new(md)),
+            |md| Box::new(RobustLinearModel::new(md)),
+            |md| Box::new(LinearSplineModel::new(md)),
+            |md| Box::new(CubicSplineModel::new(md)),
+            |md| Box::new(NormalModel::new(md)),
+            |md| Box::new(LogNormalModel::new(md)),
+            |md| Box::new(RadixModel::new(md)),
+            |md| Box::new(EquidepthHistogramModel::new(md)),
+            |md| Box::new(RelativeRadixTable::new(md, 10, 4)),
+        ];
+        for build in builds.iter() {
+            let mut model = build(&old);
+            model.retrain(&new, 1_999..new.len()).unwrap();
+            let rebuilt = build(&new);
+            for key in (2..10_000_000u64).step_by(997) {
+                assert_eq!(model.predict_to_int(&key.into()), rebuilt.predict_to_int(&key.into()),
+                           "{} differs at {}", model.function_name(), key);
+            }
+        }
+
+        // a fixed radix model keeps its width, so it cannot take keys that
+        // need another
+        let mut fixed = FixedRadixModel::<10>::from_dynamic(&RadixModel::new(&old)).unwrap();
+        let err = fixed.retrain(&new, 1_999..new.len()).unwrap_err();
+        assert!(matches!(err, Error::InvalidArgument { .. }));
+        fixed.retrain(&old, 0..old.len()).unwrap();
+
+        let err = Route(0).retrain(&new, 0..new.len()).unwrap_err();
+        assert!(matches!(err, Error::InvalidArgument { .. }));
+    }
 }
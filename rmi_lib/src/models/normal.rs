@@ -83,6 +83,14 @@ impl NormalModel {
     pub fn new<T: TrainingKey>(data: &RMITrainingData<T>) -> NormalModel {
         return NormalModel { params: ncdf(data) };
     }
+
+    pub(crate) fn from_state(params: &[ModelParam]) -> Option<NormalModel> {
+        return match params {
+            [ModelParam::Float(a), ModelParam::Float(b), ModelParam::Float(c)] =>
+                Some(NormalModel { params: (*a, *b, *c) }),
+            _ => None,
+        };
+    }
 }
 
 impl Model for NormalModel {
@@ -124,6 +132,24 @@ inline double ncdf(double mean, double stdev, double scale, double inp) {
         to_r.insert(StdFunctions::PHI);
         return to_r;
     }
+
+    fn retrain(&mut self, data: &RMITrainingData<u64>,
+               _changed: Range<usize>) -> Result<(), Error> {
+        *self = NormalModel::new(data);
+        return Ok(());
+    }
+
+    fn kind(&self) -> ModelKind {
+        return ModelKind::Normal;
+    }
+
+    fn state(&self) -> Option<(ModelKind, Vec<ModelParam>)> {
+        return Some((self.kind(), self.params()));
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        return self;
+    }
 }
 
 #[cfg(test)]
@@ -132,17 +158,17 @@ mod ncdf_tests {
 
     #[test]
     fn test_ncdf1() {
-        let md = ModelData::IntKeyToIntPos(vec![(1, 1), (2, 3), (3, 5)]);
+        let md = RMITrainingData::<u64>::new(Box::new(vec![(1, 1), (2, 3), (3, 5)]));
 
         let ncdf_mod = NormalModel::new(&md);
 
-        assert_eq!(ncdf_mod.predict_to_int(2.into()), 2);
-        assert_eq!(ncdf_mod.predict_to_int(1.into()), 0);
+        assert_eq!(ncdf_mod.predict_to_int(&2.into()), 2);
+        assert_eq!(ncdf_mod.predict_to_int(&1.into()), 0);
     }
 
     #[test]
     fn test_empty() {
-        NormalModel::new(&ModelData::empty());
+        NormalModel::new(&RMITrainingData::<u64>::empty());
     }
 
 }
@@ -157,6 +183,14 @@ impl LogNormalModel {
             params: lncdf(data),
         };
     }
+
+    pub(crate) fn from_state(params: &[ModelParam]) -> Option<LogNormalModel> {
+        return match params {
+            [ModelParam::Float(a), ModelParam::Float(b), ModelParam::Float(c)] =>
+                Some(LogNormalModel { params: (*a, *b, *c) }),
+            _ => None,
+        };
+    }
 }
 
 impl Model for LogNormalModel {
@@ -190,6 +224,10 @@ inline double lncdf(double mean, double stdev, double scale, double inp) {
         );
     }
 
+    fn required_includes(&self) -> Vec<&'static str> {
+        return vec!["<cmath>"];
+    }
+
     fn function_name(&self) -> String {
         return String::from("lncdf");
     }
@@ -199,6 +237,24 @@ inline double lncdf(double mean, double stdev, double scale, double inp) {
         to_r.insert(StdFunctions::PHI);
         return to_r;
     }
+
+    fn retrain(&mut self, data: &RMITrainingData<u64>,
+               _changed: Range<usize>) -> Result<(), Error> {
+        *self = LogNormalModel::new(data);
+        return Ok(());
+    }
+
+    fn kind(&self) -> ModelKind {
+        return ModelKind::LogNormal;
+    }
+
+    fn state(&self) -> Option<(ModelKind, Vec<ModelParam>)> {
+        return Some((self.kind(), self.params()));
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        return self;
+    }
 }
 
 #[cfg(test)]
@@ -207,17 +263,17 @@ mod lncdf_tests {
 
     #[test]
     fn test_lncdf1() {
-        let md = ModelData::IntKeyToIntPos(vec![(1, 1), (2, 2), (3, 20)]);
+        let md = RMITrainingData::<u64>::new(Box::new(vec![(1, 1), (2, 2), (3, 20)]));
 
         let lncdf_mod = LogNormalModel::new(&md);
 
-        assert_eq!(lncdf_mod.predict_to_int(2.into()), 11);
-        assert_eq!(lncdf_mod.predict_to_int(1.into()), 2);
+        assert_eq!(lncdf_mod.predict_to_int(&2.into()), 11);
+        assert_eq!(lncdf_mod.predict_to_int(&1.into()), 2);
     }
 
     #[test]
     fn test_empty() {
-        LogNormalModel::new(&ModelData::empty());
+        LogNormalModel::new(&RMITrainingData::<u64>::empty());
     }
 
 }
@@ -6,49 +6,204 @@
 // < end copyright > 
  
 
-use crate::models::utils::{common_prefix_size, num_bits};
+#[cfg(feature = "arena")]
+use crate::arena::ParamArena;
+use crate::error::Error;
+use crate::models::utils::{common_prefix_size, fit_radix, num_bits};
 use crate::models::*;
 use log::*;
+#[cfg(feature = "mmap")]
+use std::{convert::TryInto, fs, io, path::Path};
+use std::marker::PhantomData;
+use std::mem::size_of;
+use std::ops::{Deref, DerefMut};
+use std::slice;
 
-pub struct RadixModel {
+/// How a `RadixModel` turns a key into its prediction, once the keys'
+/// common prefix has been shifted out of the top of the key. Each transform
+/// names its own C++ function, so the choice is made when the model type
+/// is, and costs nothing at lookup time.
+pub trait KeyTransform: Copy + Default + Send + Sync + 'static {
+    /// The kind and function name of radix models using the transform.
+    const KIND: ModelKind;
+    const NAME: &'static str;
+
+    /// Whether `transform` never decreases with the key, which range
+    /// queries rely on.
+    const MONOTONE: bool;
+
+    /// Maps `key` to a value of `bits` bits, for `bits` from 1 to 64.
+    fn transform(&self, key: u64, bits: u8) -> u64;
+
+    /// `transform` computed the slow, plain way, as an oracle for tests.
+    fn reference_transform(&self, key: u64, bits: u8) -> u64 {
+        return self.transform(key, bits);
+    }
+
+    /// The C++ expression computing `transform` of the `uint64_t`
+    /// expressions `key` and `bits`.
+    fn c_expr(&self, key: &str, bits: &str) -> String;
+}
+
+/// The key's top `bits` bits, which keep the keys' order.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PlainRadix;
+
+impl KeyTransform for PlainRadix {
+    const KIND: ModelKind = ModelKind::Radix;
+    const NAME: &'static str = "radix";
+    const MONOTONE: bool = true;
+
+    fn transform(&self, key: u64, bits: u8) -> u64 {
+        return key >> (64 - bits);
+    }
+
+    fn reference_transform(&self, key: u64, bits: u8) -> u64 {
+        return (key as u128 / (1u128 << (64 - bits))) as u64;
+    }
+
+    fn c_expr(&self, key: &str, bits: &str) -> String {
+        return format!("{} >> (64 - {})", key, bits);
+    }
+}
+
+/// The top `bits` bits of the key times a large odd constant (Fibonacci
+/// hashing), which spreads keys clustered in a few ranges evenly over the
+/// outputs. The outputs no longer follow the keys' order.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Multiplicative;
+
+// 2^64 divided by the golden ratio, rounded to odd
+const FIBONACCI_MULTIPLIER: u64 = 0x9E37_79B9_7F4A_7C15;
+
+impl KeyTransform for Multiplicative {
+    const KIND: ModelKind = ModelKind::MultiplicativeRadix;
+    const NAME: &'static str = "radix_mul";
+    const MONOTONE: bool = false;
+
+    fn transform(&self, key: u64, bits: u8) -> u64 {
+        return key.wrapping_mul(FIBONACCI_MULTIPLIER) >> (64 - bits);
+    }
+
+    fn reference_transform(&self, key: u64, bits: u8) -> u64 {
+        let product = (key as u128 * FIBONACCI_MULTIPLIER as u128) % (1u128 << 64);
+        return (product / (1u128 << (64 - bits))) as u64;
+    }
+
+    fn c_expr(&self, key: &str, bits: &str) -> String {
+        return format!("({} * 0x{:X}ull) >> (64 - {})", key, FIBONACCI_MULTIPLIER, bits);
+    }
+}
+
+pub struct RadixModel<K: KeyTransform = PlainRadix> {
     params: (u8, u8),
+    transform: K,
+    domain: Option<(u64, u64)>,
+
+    // the largest distance of a prediction from its training position,
+    // unknown for models rebuilt from their state
+    max_error: Option<u64>,
+}
+
+// The `bits` bits after the first `prefix` of `key`, found by dividing
+// instead of shifting: the remainder drops the prefix and the quotient
+// drops the bits below the ones kept. Only the reference predictions use it.
+fn reference_radix(key: u64, prefix: u8, bits: u8) -> u64 {
+    let rest = key as u128 % (1u128 << (64 - prefix));
+    return (rest / (1u128 << (64 - prefix - bits))) as u64;
 }
 
 impl RadixModel {
     pub fn new<T: TrainingKey>(data: &RMITrainingData<T>) -> RadixModel {
+        return RadixModel::new_with_transform(data, PlainRadix);
+    }
+
+    pub(crate) fn from_state(params: &[ModelParam]) -> Option<RadixModel> {
+        return RadixModel::from_state_with(params, PlainRadix);
+    }
+}
+
+impl<K: KeyTransform> RadixModel<K> {
+    /// Like `RadixModel::new`, with the same layout, but predicting
+    /// `transform` of the keys' bits after their common prefix.
+    pub fn new_with_transform<T: TrainingKey>(data: &RMITrainingData<T>,
+                                              transform: K) -> RadixModel<K> {
         if data.len() == 0 {
-            return RadixModel { params: (0, 0) };
+            return RadixModel { params: (0, 0), transform, domain: None, max_error: Some(0) };
         }
 
         let largest_value = data.iter().map(|(_x, y)| y).max().unwrap() as u64;
-        let bits = num_bits(largest_value);
+        let (common_prefix, bits) = fit_radix(common_prefix_size(data),
+                                              num_bits(u64::max(largest_value, 1)));
         trace!(
             "Radix layer using {} bits, from largest value {} (max layers: {})",
             bits,
             largest_value,
-            (1 << (bits + 1)) - 1
+            (1u128 << (bits + 1)) - 1
         );
-
-
-        let common_prefix =  common_prefix_size(data);
         trace!("Radix layer common prefix: {}", common_prefix);
 
-        return RadixModel {
+        let mut model = RadixModel {
             params: (common_prefix, bits),
+            transform,
+            domain: data.key_domain(),
+            max_error: None,
+        };
+        // in integers throughout, as an f64 would round bounds past 2^53
+        // and leave the emitted bounds check short
+        let max_error = data.iter_model_input()
+            .map(|(inp, y)| {
+                let pred = model.predict_to_int(&inp);
+                u64::max(pred, y as u64) - u64::min(pred, y as u64)
+            })
+            .max()
+            .unwrap_or(0);
+        trace!("Radix layer has max error {}", max_error);
+        model.max_error = Some(max_error);
+        return model;
+    }
+
+    pub(crate) fn from_state_with(params: &[ModelParam], transform: K) -> Option<RadixModel<K>> {
+        return match params {
+            [ModelParam::Int(prefix), ModelParam::Int(bits)]
+                if *prefix < 64 && *bits >= 1 && prefix + bits <= 64 =>
+                Some(RadixModel { params: (*prefix as u8, *bits as u8), transform,
+                                  domain: None, max_error: None }),
+            _ => None,
         };
     }
 }
 
-impl Model for RadixModel {
+impl<K: KeyTransform> Model for RadixModel<K> {
     fn predict_to_int(&self, inp: &ModelInput) -> u64 {
         let (left_shift, num_bits) = self.params;
 
         let as_int: u64 = inp.as_int();
-        let res = (as_int << left_shift) >> (64 - num_bits);
+        let res = self.transform.transform(as_int << left_shift, num_bits);
 
         return res;
     }
 
+    fn reference_predict(&self, inp: &ModelInput) -> u64 {
+        // the prefix is dropped by a remainder rather than a shift
+        let (prefix, bits) = self.params;
+        let rest = inp.as_int() as u128 % (1u128 << (64 - prefix));
+        return self.transform.reference_transform((rest << prefix) as u64, bits);
+    }
+
+    // the shifts are compiled into the lookup
+    fn lookup_cache_lines(&self) -> usize {
+        return 0;
+    }
+
+    fn estimated_flops(&self, _inp: &ModelInput) -> u64 {
+        return 2;
+    }
+
+    fn integer_only(&self) -> bool {
+        return true;
+    }
+
     fn input_type(&self) -> ModelDataType {
         return ModelDataType::Int;
     }
@@ -61,75 +216,113 @@ impl Model for RadixModel {
     }
 
     fn code(&self) -> String {
-        return String::from(
+        return format!(
             "
-inline uint64_t radix(uint64_t prefix_length, uint64_t bits, uint64_t inp) {
-    return (inp << prefix_length) >> (64 - bits);
-}",
+inline uint64_t {}(uint64_t prefix_length, uint64_t bits, uint64_t inp) {{
+    return {};
+}}",
+            K::NAME, self.transform.c_expr("(inp << prefix_length)", "bits")
         );
     }
 
+    fn required_includes(&self) -> Vec<&'static str> {
+        return vec!["<cstdint>"];
+    }
+
     fn function_name(&self) -> String {
-        return String::from("radix");
+        return String::from(K::NAME);
     }
+    // predictions only stay in range if none of them was off
     fn needs_bounds_check(&self) -> bool {
-        return false;
+        return self.max_error != Some(0);
     }
     fn restriction(&self) -> ModelRestriction {
         return ModelRestriction::MustBeTop;
     }
-}
-
-pub struct RadixTable {
-    prefix_bits: u8,
-    table_bits: u8,
-    hint_table: Vec<u32>
-}
+    fn error_bound(&self) -> Option<u64> {
+        return self.max_error;
+    }
+    fn key_domain(&self) -> Option<(u64, u64)> {
+        return self.domain;
+    }
+    fn supports_range_query(&self) -> bool {
+        return K::MONOTONE && self.max_error.is_some() && self.domain.is_some();
+    }
+    fn predict_range_bounds(&self, lo: &ModelInput, hi: &ModelInput) -> (u64, u64) {
+        // keys outside the domain do not share its prefix, and would wrap
+        // around; clamping them keeps the prediction monotonic
+        assert!(self.supports_range_query(),
+                "radix model has no error bound or domain, or does not keep the keys' order");
+        let (min, max) = self.domain.unwrap();
+        let err = self.max_error.unwrap();
+        let lo = self.predict_to_int(&lo.as_int().clamp(min, max).into());
+        let hi = self.predict_to_int(&hi.as_int().clamp(min, max).into());
+        return (lo.saturating_sub(err), hi.saturating_add(err).saturating_add(1));
+    }
 
-impl RadixTable {
-    pub fn new<T: TrainingKey>(data: &RMITrainingData<T>, bits: u8) -> RadixTable {
-        let prefix = common_prefix_size(data);
-        let mut hint_table: Vec<u32> = vec![0 ; 1 << bits];
+    fn retrain(&mut self, data: &RMITrainingData<u64>,
+               _changed: Range<usize>) -> Result<(), Error> {
+        *self = RadixModel::new_with_transform(data, self.transform);
+        return Ok(());
+    }
 
-        let mut last_radix = 0;
-        for (inp, y) in data.iter_model_input() {
-            let x = inp.as_int();
-            let num_bits = if prefix + bits > 64 { 0 } else { 64 - (prefix + bits) };
-            let current_radix = ((x << prefix) >> prefix) >> num_bits;
-            if current_radix == last_radix { continue; }
-            assert!(current_radix < hint_table.len() as u64);
+    fn kind(&self) -> ModelKind {
+        return K::KIND;
+    }
 
-            hint_table[current_radix as usize] = y as u32;
+    fn state(&self) -> Option<(ModelKind, Vec<ModelParam>)> {
+        return Some((self.kind(), self.params()));
+    }
 
-            for i in (last_radix + 1)..current_radix {
-                hint_table[i as usize] = y as u32;
-            }
+    fn as_any(&self) -> &dyn Any {
+        return self;
+    }
+}
 
-            last_radix = current_radix;
-        }
+/// A radix model with the number of bits fixed at compile time, so that
+/// the final shift of a prediction is a constant. It predicts, emits code
+/// and serializes exactly like the `RadixModel` it is built from.
+pub struct FixedRadixModel<const BITS: u8> {
+    prefix: u8,
+    domain: Option<(u64, u64)>,
+    max_error: Option<u64>,
+}
 
-        for i in (last_radix as usize + 1)..hint_table.len() {
-            hint_table[i as usize] = hint_table.len() as u32; 
+impl<const BITS: u8> FixedRadixModel<BITS> {
+    /// The fixed version of `model`, if it uses `BITS` bits.
+    pub fn from_dynamic(model: &RadixModel) -> Option<FixedRadixModel<BITS>> {
+        let (prefix, bits) = model.params;
+        if bits != BITS {
+            return None;
         }
+        return Some(FixedRadixModel { prefix, domain: model.domain, max_error: model.max_error });
+    }
 
-        return RadixTable {
-            prefix_bits: prefix,
-            table_bits: bits,
-            hint_table
-        };
+    fn dynamic(&self) -> RadixModel {
+        return RadixModel { params: (self.prefix, BITS), transform: PlainRadix,
+                            domain: self.domain, max_error: self.max_error };
     }
 }
 
-impl Model for RadixTable {
+impl<const BITS: u8> Model for FixedRadixModel<BITS> {
     fn predict_to_int(&self, inp: &ModelInput) -> u64 {
-        let as_int: u64 = inp.as_int();
-        let prefix = self.prefix_bits;
-        let bits = self.table_bits;
-        let num_bits = if prefix + bits > 64 { 0 } else { 64 - (prefix + bits) };
-        let res = ((as_int << prefix) >> prefix) >> num_bits;
-        let idx = self.hint_table[res as usize] as u64;
-        
-        return idx;
+        return (inp.as_int() << self.prefix) >> (64 - BITS);
+    }
+
+    fn reference_predict(&self, inp: &ModelInput) -> u64 {
+        return reference_radix(inp.as_int(), self.prefix, BITS);
+    }
+
+    fn lookup_cache_lines(&self) -> usize {
+        return 0;
+    }
+
+    fn estimated_flops(&self, _inp: &ModelInput) -> u64 {
+        return 2;
+    }
+
+    fn integer_only(&self) -> bool {
+        return true;
     }
 
     fn input_type(&self) -> ModelDataType {
@@ -140,42 +333,3110 @@ impl Model for RadixTable {
     }
 
     fn params(&self) -> Vec<ModelParam> {
-        return vec![self.hint_table.clone().into()];
+        return self.dynamic().params();
     }
 
     fn code(&self) -> String {
-        let num_bits = if self.prefix_bits + self.table_bits > 64 {
-            0
-        } else {
-            64 - (self.prefix_bits + self.table_bits)
-        };
-        
-        return format!(
-            "
-inline uint64_t radix_table(const uint32_t* table, const uint64_t inp) {{
-    return table[((inp << {0}) >> {0}) >> {1}];
-}}", self.prefix_bits, num_bits
-        );
+        return self.dynamic().code();
+    }
+
+    fn required_includes(&self) -> Vec<&'static str> {
+        return self.dynamic().required_includes();
     }
 
     fn function_name(&self) -> String {
-        return String::from("radix_table");
+        return self.dynamic().function_name();
     }
     fn needs_bounds_check(&self) -> bool {
-        return false;
+        return self.dynamic().needs_bounds_check();
     }
     fn restriction(&self) -> ModelRestriction {
-        return ModelRestriction::None;
+        return ModelRestriction::MustBeTop;
+    }
+    fn error_bound(&self) -> Option<u64> {
+        return self.max_error;
+    }
+    fn key_domain(&self) -> Option<(u64, u64)> {
+        return self.domain;
+    }
+    fn supports_range_query(&self) -> bool {
+        return self.dynamic().supports_range_query();
+    }
+    fn predict_range_bounds(&self, lo: &ModelInput, hi: &ModelInput) -> (u64, u64) {
+        return self.dynamic().predict_range_bounds(lo, hi);
+    }
+
+    fn retrain(&mut self, data: &RMITrainingData<u64>,
+               _changed: Range<usize>) -> Result<(), Error> {
+        let model = RadixModel::new(data);
+        *self = FixedRadixModel::from_dynamic(&model).ok_or_else(|| Error::InvalidArgument {
+            reason: format!("the keys need a radix model of {} bits, not {}",
+                            model.params.1, BITS),
+        })?;
+        return Ok(());
+    }
+
+    fn kind(&self) -> ModelKind {
+        return ModelKind::Radix;
+    }
+
+    fn state(&self) -> Option<(ModelKind, Vec<ModelParam>)> {
+        return self.dynamic().state();
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        return self;
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+#[derive(Clone, Copy)]
+#[repr(C, align(64))]
+struct CacheLine([u64; 8]);
 
-    #[test]
-    fn test_empty() {
-        RadixModel::new(&ModelData::empty());
+// Where an `AlignedTable` keeps its entries: its own lines, a private
+// copy-on-write mapping of a flat file, whose table starts `offset` bytes
+// in, or the buffer of a `ParamArena`, from `offset` on
+enum Lines {
+    Owned(Vec<CacheLine>),
+    #[cfg(feature = "mmap")]
+    Mapped { map: memmap::MmapMut, offset: usize },
+    #[cfg(feature = "arena")]
+    Arena { arena: ParamArena, offset: usize },
+}
+
+// A table of plain integers whose storage starts on a 64-byte boundary, so
+// that where a bucket's hint falls within a cache line depends only on its
+// index.
+struct AlignedTable<T> {
+    lines: Lines,
+    len: usize,
+    marker: PhantomData<T>,
+}
+
+impl<T: Copy> AlignedTable<T> {
+    fn zeroed(len: usize) -> AlignedTable<T> {
+        let num_lines = (len * size_of::<T>()).div_ceil(size_of::<CacheLine>());
+        return AlignedTable {
+            lines: Lines::Owned(vec![CacheLine([0; 8]); num_lines]),
+            len,
+            marker: PhantomData,
+        };
+    }
+
+    // The `len` entries `offset` bytes into `map`, which the caller has
+    // checked hold that many and start on a 64-byte boundary. Mappings start
+    // on a page, so the offset alone decides the alignment.
+    #[cfg(feature = "mmap")]
+    fn mapped(map: memmap::MmapMut, offset: usize, len: usize) -> AlignedTable<T> {
+        assert!(offset.is_multiple_of(size_of::<CacheLine>())
+                && offset + len * size_of::<T>() <= map.len());
+        return AlignedTable { lines: Lines::Mapped { map, offset }, len, marker: PhantomData };
+    }
+
+    // The `len` entries `offset` bytes into `arena`'s buffer, which the
+    // caller has checked hold that many. The buffer starts on a 64-byte
+    // boundary, so again the offset decides the alignment.
+    #[cfg(feature = "arena")]
+    fn in_arena(arena: &ParamArena, offset: usize, len: usize) -> AlignedTable<T> {
+        assert!(offset.is_multiple_of(size_of::<CacheLine>())
+                && offset + len * size_of::<T>() <= arena.used_bytes());
+        return AlignedTable {
+            lines: Lines::Arena { arena: arena.clone(), offset },
+            len,
+            marker: PhantomData,
+        };
+    }
+
+    fn from_slice(values: &[T]) -> AlignedTable<T> {
+        let mut table = AlignedTable::zeroed(values.len());
+        table.copy_from_slice(values);
+        return table;
+    }
+
+    // A copy of `values` in an anonymous mapping that starts on a huge page,
+    // advised to be backed by them. `None` if nothing could be mapped; if
+    // the advice is refused, as off Linux or with huge pages disabled, the
+    // copy is on normal pages and works the same.
+    #[cfg(feature = "huge_pages")]
+    fn huge(values: &[T]) -> Option<AlignedTable<T>> {
+        let bytes = size_of_val(values);
+        let mut map = memmap::MmapMut::map_anon(bytes + HUGE_PAGE_BYTES).ok()?;
+        let start = map.as_ptr() as usize;
+        let offset = start.next_multiple_of(HUGE_PAGE_BYTES) - start;
+        #[cfg(target_os = "linux")]
+        {
+            let addr = map[offset..].as_mut_ptr() as *mut libc::c_void;
+            // the range is inside the mapping and starts on a page
+            unsafe { libc::madvise(addr, bytes, libc::MADV_HUGEPAGE) };
+        }
+
+        let mut table = AlignedTable::mapped(map, offset, values.len());
+        table.copy_from_slice(values);
+        return Some(table);
+    }
+}
+
+// the huge page size of x86-64 and most aarch64 kernels
+#[cfg(feature = "huge_pages")]
+const HUGE_PAGE_BYTES: usize = 2 << 20;
+
+impl<T> Deref for AlignedTable<T> {
+    type Target = [T];
+    fn deref(&self) -> &[T] {
+        let ptr = match &self.lines {
+            Lines::Owned(lines) => lines.as_ptr() as *const T,
+            #[cfg(feature = "mmap")]
+            Lines::Mapped { map, offset } => map[*offset..].as_ptr() as *const T,
+            #[cfg(feature = "arena")]
+            Lines::Arena { arena, offset } => arena.ptr(*offset) as *const T,
+        };
+        // the table only holds u16s, u32s or u64s, for which any bytes are
+        // valid, and `len` never exceeds the number the storage holds
+        return unsafe { slice::from_raw_parts(ptr, self.len) };
+    }
+}
+
+// other models may share an arena's buffer, so a table writing to its
+// entries first copies them out of it
+impl<T: Copy> DerefMut for AlignedTable<T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        #[cfg(feature = "arena")]
+        if let Lines::Arena { .. } = self.lines {
+            *self = AlignedTable::from_slice(&self[..]);
+        }
+        let ptr = match &mut self.lines {
+            Lines::Owned(lines) => lines.as_mut_ptr() as *mut T,
+            #[cfg(feature = "mmap")]
+            Lines::Mapped { map, offset } => map[*offset..].as_mut_ptr() as *mut T,
+            #[cfg(feature = "arena")]
+            Lines::Arena { .. } => unreachable!(),
+        };
+        return unsafe { slice::from_raw_parts_mut(ptr, self.len) };
+    }
+}
+
+/// What `RadixTable` does when a position does not fit in the 32-bit
+/// entries of its table.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum OnOverflow {
+    /// Refuse to build the table.
+    #[default]
+    Error,
+
+    /// Store `u32::MAX` for positions past it. The error bound accounts for
+    /// the clamped hints, so it grows with the overflow.
+    Clamp,
+
+    /// Switch to 64-bit entries, doubling the size of the table.
+    WidenAutomatically,
+}
+
+/// Which position of its bucket `RadixTable` predicts for a key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum HintPolicy {
+    /// The bucket's first position. Keys are found at or after the
+    /// prediction, up to the bucket's size away.
+    #[default]
+    FirstPosition,
+
+    /// The middle of the bucket's span, which about halves the distance to
+    /// its furthest key. The table gets one more entry, holding the end of
+    /// the last bucket, and a lookup reads two neighbouring entries.
+    Midpoint,
+}
+
+enum HintTable {
+    Narrow(AlignedTable<u32>),
+    Wide(AlignedTable<u64>),
+}
+
+impl HintTable {
+    fn len(&self) -> usize {
+        return match self {
+            HintTable::Narrow(t) => t.len(),
+            HintTable::Wide(t) => t.len(),
+        };
+    }
+
+    fn get(&self, idx: usize) -> Option<u64> {
+        return match self {
+            HintTable::Narrow(t) => t.get(idx).map(|h| *h as u64),
+            HintTable::Wide(t) => t.get(idx).cloned(),
+        };
+    }
+
+    fn hint(&self, idx: usize) -> u64 {
+        return match self {
+            HintTable::Narrow(t) => t[idx] as u64,
+            HintTable::Wide(t) => t[idx],
+        };
+    }
+
+    fn set(&mut self, idx: usize, hint: u64) {
+        match self {
+            HintTable::Narrow(t) => t[idx] = hint as u32,
+            HintTable::Wide(t) => t[idx] = hint,
+        }
+    }
+
+    // narrow hints saturate, as the end of data whose last key is at
+    // `u32::MAX` is one past what they hold
+    fn fill(&mut self, range: Range<usize>, hint: u64) {
+        match self {
+            HintTable::Narrow(t) => t[range].fill(u64::min(hint, u32::MAX as u64) as u32),
+            HintTable::Wide(t) => t[range].fill(hint),
+        }
+    }
+
+    fn c_type(&self) -> &'static str {
+        return match self {
+            HintTable::Narrow(_) => "uint32_t",
+            HintTable::Wide(_) => "uint64_t",
+        };
+    }
+
+    fn to_param(&self) -> ModelParam {
+        return match self {
+            HintTable::Narrow(t) => t.to_vec().into(),
+            HintTable::Wide(t) => t.to_vec().into(),
+        };
+    }
+
+    // the same hints, read from `len` entries `offset` bytes into `arena`
+    #[cfg(feature = "arena")]
+    fn in_arena(&self, arena: &ParamArena, (offset, len): (usize, usize)) -> HintTable {
+        return match self {
+            HintTable::Narrow(_) => HintTable::Narrow(AlignedTable::in_arena(arena, offset, len)),
+            HintTable::Wide(_) => HintTable::Wide(AlignedTable::in_arena(arena, offset, len)),
+        };
+    }
+
+    #[cfg(all(test, feature = "arena"))]
+    fn as_ptr(&self) -> *const u8 {
+        return match self {
+            HintTable::Narrow(t) => t.as_ptr() as *const u8,
+            HintTable::Wide(t) => t.as_ptr() as *const u8,
+        };
+    }
+}
+
+pub struct RadixTable {
+    prefix_bits: u8,
+    table_bits: u8,
+    shift: u8,
+    hint_table: HintTable,
+    hint_policy: HintPolicy,
+    num_keys: u64,
+    max_error: u64,
+    domain: Option<(u64, u64)>,
+
+    // bottom layer tables take their layout and domain as parameters, and
+    // clamp keys to the domain before looking them up
+    leaf: bool,
+
+    // one bit per bucket, set if no trained key landed in it
+    empty: Vec<u64>,
+
+    // how far below its hint a bucket's first position may be, for tables
+    // built from a CDF sample; 0 for tables built from every key
+    window_slack: u64,
+
+    // the most distinct keys in one bucket, if the keys were counted
+    max_bucket_keys: Option<u64>,
+}
+
+// How far a key with its prefix cleared is shifted right to leave `bits`
+// bits. The layout must come from `fit_radix` or `table_layout`, so the
+// shift is in range.
+fn radix_shift(prefix: u8, bits: u8) -> u8 {
+    assert!(prefix + bits >= 1 && prefix + bits <= 64,
+            "radix layout of {} prefix and {} bits does not fit a 64-bit key", prefix, bits);
+    return 64 - (prefix + bits);
+}
+
+// the most bits a table can be built with
+const MAX_TABLE_BITS: u8 = 31;
+
+// The layout of a table of `bits` bits over keys sharing `prefix` leading
+// bits. A table of 0 bits has a single bucket, which the layout reaches
+// by clearing all but the lowest bit and shifting that out too.
+fn table_layout(prefix: u8, bits: u8) -> (u8, u8) {
+    assert!(bits <= MAX_TABLE_BITS,
+            "radix table of {} bits is larger than the {} supported", bits, MAX_TABLE_BITS);
+    if bits == 0 {
+        return (63, 0);
+    }
+    return fit_radix(prefix, bits);
+}
+
+// whether a stored layout is one `table_layout` gives
+fn valid_table_layout(prefix: u64, bits: u64) -> bool {
+    if bits == 0 {
+        return prefix == 63;
+    }
+    return prefix < 64 && bits <= MAX_TABLE_BITS as u64 && prefix + bits <= 64;
+}
+
+// Marks the first `buckets` buckets of `hints` that hold no keys: those
+// whose span, up to the next hint (or `num_keys` for the last bucket), is
+// empty.
+fn empty_buckets(hints: &HintTable, buckets: usize, num_keys: u64) -> Vec<u64> {
+    let mut empty = vec![0u64; buckets.div_ceil(64)];
+    for idx in 0..buckets {
+        let next = hints.get(idx + 1).unwrap_or(num_keys);
+        if hints.hint(idx) == next {
+            empty[idx / 64] |= 1 << (idx % 64);
+        }
+    }
+    return empty;
+}
+
+// The layout of a table of `bits` bits over `data`. Leaves give up prefix
+// bits rather than table bits.
+fn data_layout<T: TrainingKey>(data: &RMITrainingData<T>, bits: u8, leaf: bool) -> (u8, u8) {
+    if leaf && bits > 0 {
+        let (_, bits) = table_layout(0, bits);
+        return (u8::min(common_prefix_size(data), 64 - bits), bits);
+    }
+    return table_layout(common_prefix_size(data), bits);
+}
+
+// positions never decrease, so the largest hint of a key is either the
+// last key's position or the length of the data
+fn largest_hint<T: TrainingKey>(data: &RMITrainingData<T>) -> u64 {
+    return match data.len() {
+        0 => 0,
+        n => u64::max(n as u64, data.get(n - 1).1 as u64),
+    };
+}
+
+// The position just past the last key, where the last bucket ends. That
+// is the length of the data, unless its positions start above 0, as for a
+// leaf of a larger RMI.
+fn end_position<T: TrainingKey>(data: &RMITrainingData<T>) -> u64 {
+    return match data.len() {
+        0 => 0,
+        n => u64::max(n as u64, data.get(n - 1).1 as u64 + 1),
+    };
+}
+
+// How much dearer `build` takes a read at random to be than the next read
+// of a scan.
+const SEARCH_READ_COST: usize = 64;
+
+// Whether `build` should binary search for where each bucket starts rather
+// than scan every key. The searches need data held in memory, where a read
+// at random is cheap, and only pay off with many keys per bucket: each
+// bucket takes two searches of about `log2(n)` reads.
+fn search_is_faster<T: TrainingKey>(data: &RMITrainingData<T>, buckets: usize) -> bool {
+    let n = data.len();
+    if n == 0 || data.iter_borrowed().is_none() {
+        return false;
+    }
+    let steps = n.ilog2() as usize + 1;
+    return n / buckets >= 2 * steps * SEARCH_READ_COST;
+}
+
+// Fills `hints` and `empty` as `build`'s scan does, from a binary search
+// for the first key of each bucket, and returns the error bound. A bucket's
+// keys lie between the positions of its first key and of the first copy of
+// its last key, so its error is the further of those from its prediction.
+#[allow(clippy::too_many_arguments)]
+fn fill_by_search<T: TrainingKey>(data: &RMITrainingData<T>, prefix: u8, bits: u8,
+                                  clamp: u64, hint_policy: HintPolicy, hints: &mut HintTable,
+                                  empty: &mut [u64], progress: &mut Progress) -> u64 {
+    let shift = radix_shift(prefix, bits);
+    let radix = |key: T| ((key.to_model_input().as_int() << prefix) >> prefix) >> shift;
+    let n = data.len();
+    let buckets = 1usize << bits;
+    let end = u64::min(end_position(data), clamp);
+    // the hint of a bucket whose first key is at `start`
+    let hint_at = |start: usize| {
+        return if start < n { u64::min(data.get(start).1 as u64, clamp) } else { end };
+    };
+
+    let (mut start, mut max_error) = (0, 0);
+    for idx in 0..buckets {
+        progress.update(0, buckets, idx);
+        let next = if idx + 1 < buckets {
+            data.lower_bound_by(|(key, _)| radix(key).cmp(&(idx as u64 + 1)))
+        } else {
+            n
+        };
+        let hint = hint_at(start);
+        hints.set(idx, hint);
+
+        if start < next {
+            empty[idx / 64] &= !(1 << (idx % 64));
+            let pred = match hint_policy {
+                HintPolicy::FirstPosition => hint,
+                HintPolicy::Midpoint => hint + (hint_at(next) - hint) / 2,
+            };
+            let last = data.get_key(next - 1).to_model_input();
+            let last_copy = data.lower_bound_by(|(key, _)| {
+                return if key.to_model_input() < last { Ordering::Less } else { Ordering::Greater };
+            });
+            let (lo, hi) = (data.get(start).1 as u64, data.get(last_copy).1 as u64);
+            max_error = u64::max(max_error, u64::max(pred.abs_diff(lo), pred.abs_diff(hi)));
+        }
+        start = next;
+    }
+    // the end of the last bucket, for midpoints
+    if hints.len() > buckets {
+        hints.set(buckets, end);
+    }
+    return max_error;
+}
+
+impl RadixTable {
+    /// A table of `1 << bits` buckets over the bits after the keys' common
+    /// prefix, for `bits` up to 31 (more panics). A table of 0 bits is a
+    /// single bucket: it predicts the first key's position for every key,
+    /// and its window is the whole data.
+    pub fn new<T: TrainingKey>(data: &RMITrainingData<T>, bits: u8) -> RadixTable {
+        return RadixTable::new_with_progress(data, bits, None);
+    }
+
+    /// Like `new`, but reports the fraction of training done to `progress`
+    /// as the table is filled and its error bound computed.
+    pub fn new_with_progress<T: TrainingKey>(data: &RMITrainingData<T>, bits: u8,
+                                             progress: Option<&dyn Fn(f64)>) -> RadixTable {
+        return RadixTable::build(data, bits, OnOverflow::Error, HintPolicy::FirstPosition,
+                                 false, progress)
+            .unwrap_or_else(|e| panic!("{}", e));
+    }
+
+    /// A table for the bottom layer of an RMI, under a coarser top model,
+    /// usually another `RadixTable`. Its common prefix and shift are
+    /// measured over the keys of its own bucket, but always leave `bits`
+    /// bits, so every table in the layer has the same size. As the layout
+    /// differs between tables, it is passed to the emitted code as
+    /// parameters along with the smallest and largest trained key, which
+    /// queries are clamped to before the lookup. Clamping keeps keys that
+    /// fall between two buckets from wrapping around the prefix.
+    pub fn new_leaf<T: TrainingKey>(data: &RMITrainingData<T>, bits: u8) -> RadixTable {
+        return RadixTable::build(data, bits, OnOverflow::Error, HintPolicy::FirstPosition,
+                                 true, None)
+            .unwrap_or_else(|e| panic!("{}", e));
+    }
+
+    /// Like `new`, but predicting the position `hint_policy` picks within
+    /// each key's bucket. The error bound is measured from that position.
+    pub fn new_with_hint_policy<T: TrainingKey>(data: &RMITrainingData<T>, bits: u8,
+                                                hint_policy: HintPolicy) -> RadixTable {
+        return RadixTable::build(data, bits, OnOverflow::Error, hint_policy, false, None)
+            .unwrap_or_else(|e| panic!("{}", e));
+    }
+
+    /// Like `new`, but with a choice of what to do when a position is past
+    /// `u32::MAX`. `new` uses `OnOverflow::Error` and panics on the
+    /// `Error::ParamOverflow` returned here.
+    pub fn new_with_overflow<T: TrainingKey>(data: &RMITrainingData<T>, bits: u8,
+                                             on_overflow: OnOverflow)
+                                             -> Result<RadixTable, Error> {
+        return RadixTable::build(data, bits, on_overflow, HintPolicy::FirstPosition, false,
+                                 None);
+    }
+
+    /// An approximate table for `n` keys, built from a sample of their CDF
+    /// (as from `RMITrainingData::sample_cdf`) rather than by scanning
+    /// them all. The sample must include the first and last keys, which
+    /// `sample_cdf` does. Keys are read back from the sample's `f64`s, so
+    /// only keys below 2^53 are exact.
+    ///
+    /// Between two samples the keys' positions are unknown, so each
+    /// bucket's first position is only known to lie between the sample
+    /// before the bucket and the first one in or after it. The table
+    /// predicts the latter, and both its error bound and its windows allow
+    /// for any positions in between.
+    pub fn from_cdf(samples: &[(f64, f64)], bits: u8, n: usize) -> RadixTable {
+        let points: Vec<(u64, usize)> = samples.iter()
+            .map(|(key, frac)| (*key as u64, usize::min((frac * n as f64).round() as usize, n)))
+            .collect();
+        let sample = RMITrainingData::new(Box::new(points.clone()));
+        let (prefix, bits) = table_layout(common_prefix_size(&sample), bits);
+        let shift = radix_shift(prefix, bits);
+        let buckets = 1usize << bits;
+
+        // upper[b] is the position of the first sample in bucket b or
+        // later, and lower[b] one past that of the last sample before it.
+        // Bucket b's first position is in lower[b]..=upper[b].
+        let mut upper = vec![n as u64; buckets + 1];
+        let mut lower = vec![0u64; buckets + 1];
+        let mut next = 0;
+        for (key, pos) in points.iter() {
+            let radix = (((key << prefix) >> prefix) >> shift) as usize;
+            if next <= radix {
+                upper[next..=radix].fill(*pos as u64);
+            }
+            next = usize::max(next, radix + 1);
+            lower[radix + 1] = u64::max(lower[radix + 1], *pos as u64 + 1);
+        }
+        for b in 1..=buckets {
+            lower[b] = u64::max(lower[b], lower[b - 1]);
+        }
+
+        let largest = n as u64;
+        let mut hint_table = if largest <= u32::MAX as u64 {
+            HintTable::Narrow(AlignedTable::zeroed(buckets))
+        } else {
+            HintTable::Wide(AlignedTable::zeroed(buckets))
+        };
+        let mut max_error = 0;
+        let mut window_slack = 0;
+        for b in 0..buckets {
+            hint_table.set(b, upper[b]);
+            let lower = u64::min(lower[b], upper[b]);
+            window_slack = u64::max(window_slack, upper[b] - lower);
+            // the bucket's keys are somewhere in lower..upper[b + 1]
+            if upper[b + 1] > lower {
+                max_error = u64::max(max_error, upper[b] - lower);
+                max_error = u64::max(max_error, (upper[b + 1] - 1).saturating_sub(upper[b]));
+            }
+        }
+
+        return RadixTable {
+            prefix_bits: prefix,
+            table_bits: bits,
+            shift,
+            hint_table,
+            hint_policy: HintPolicy::FirstPosition,
+            num_keys: n as u64,
+            max_error,
+            domain: sample.key_domain(),
+            leaf: false,
+            empty: vec![0; buckets.div_ceil(64)],
+            window_slack,
+            max_bucket_keys: None,
+        };
     }
 
+    /// The table `new` would build with `bits` fewer than this table's,
+    /// derived from this table's entries without the data, in time linear
+    /// in its size. Each coarser bucket starts where the first key of the
+    /// buckets merged into it does, so the predictions equal the rebuilt
+    /// table's. The error bound is measured from the buckets' spans: it is
+    /// the rebuilt table's if no bucket ends in copies of a key, and
+    /// larger otherwise. A table built from a CDF sample keeps its window
+    /// slack, so its windows may be wider than the rebuilt table's. Panics
+    /// if `bits` is more than the table has.
+    pub fn coarsen(&self, bits: u8) -> RadixTable {
+        assert!(bits <= self.table_bits,
+                "cannot coarsen a radix table of {} bits to {}", self.table_bits, bits);
+        // leaves may have given up prefix bits for table bits, which the
+        // keys' common prefix (read from their domain) gives back
+        let (prefix, bits) = if bits == 0 {
+            table_layout(0, 0)
+        } else if self.leaf {
+            let common = self.domain.map(|(lo, hi)| (lo ^ hi).leading_zeros() as u8);
+            (u8::min(common.unwrap_or(0), 64 - bits), bits)
+        } else {
+            (self.prefix_bits, bits)
+        };
+        let shift = radix_shift(prefix, bits);
+
+        // the new index is read from bits of the old one, as the new
+        // layout skips at least the old prefix and ends no later
+        let buckets = 1usize << bits;
+        let coarser = |idx: usize| if bits == 0 {
+            0
+        } else {
+            (idx >> (shift - self.shift)) & (buckets - 1)
+        };
+        let entries = match self.hint_policy {
+            HintPolicy::FirstPosition => buckets,
+            HintPolicy::Midpoint => buckets + 1,
+        };
+        let mut hint_table = match self.hint_table {
+            HintTable::Narrow(_) => HintTable::Narrow(AlignedTable::zeroed(entries)),
+            HintTable::Wide(_) => HintTable::Wide(AlignedTable::zeroed(entries)),
+        };
+
+        // as in `build`, a bucket starts at the first key at or after it
+        let mut next = 0;
+        for idx in 0..1usize << self.table_bits {
+            if self.is_empty_bucket(idx) {
+                continue;
+            }
+            let end = coarser(idx) + 1;
+            if end > next {
+                hint_table.fill(next..end, self.hint_table.hint(idx));
+                next = end;
+            }
+        }
+        hint_table.fill(next..entries, self.num_keys);
+
+        // a bucket's keys lie between its hint (less the slack) and the
+        // next one, and a saturated narrow hint may stand for any position
+        // up to the end
+        let saturated = match hint_table {
+            HintTable::Narrow(_) => u32::MAX as u64,
+            HintTable::Wide(_) => u64::MAX,
+        };
+        let mut max_error = 0;
+        for idx in 0..buckets {
+            let first = hint_table.hint(idx);
+            let next = match hint_table.get(idx + 1) {
+                Some(hint) if first < saturated => hint,
+                _ => self.num_keys,
+            };
+            if next == first && self.window_slack == 0 {
+                continue;
+            }
+            let err = match self.hint_policy {
+                HintPolicy::FirstPosition => (next - 1).saturating_sub(first),
+                HintPolicy::Midpoint => {
+                    let mid = (first + next) / 2;
+                    u64::max(mid - first, (next - 1).saturating_sub(mid))
+                }
+            };
+            max_error = u64::max(max_error, u64::max(err, self.window_slack));
+        }
+
+        let empty = if self.window_slack > 0 {
+            vec![0; buckets.div_ceil(64)]
+        } else {
+            empty_buckets(&hint_table, buckets, self.num_keys)
+        };
+        return RadixTable {
+            prefix_bits: prefix,
+            table_bits: bits,
+            shift,
+            hint_table,
+            hint_policy: self.hint_policy,
+            num_keys: self.num_keys,
+            max_error,
+            domain: self.domain,
+            leaf: self.leaf,
+            empty,
+            window_slack: self.window_slack,
+            max_bucket_keys: None,
+        };
+    }
+
+    fn build<T: TrainingKey>(data: &RMITrainingData<T>, bits: u8, on_overflow: OnOverflow,
+                             hint_policy: HintPolicy, leaf: bool,
+                             progress: Option<&dyn Fn(f64)>) -> Result<RadixTable, Error> {
+        let buckets = 1usize << data_layout(data, bits, leaf).1;
+        let by_search = search_is_faster(data, buckets);
+        return RadixTable::build_with(data, bits, on_overflow, hint_policy, leaf, progress,
+                                      by_search);
+    }
+
+    // `build`, scanning every key or, if `by_search`, binary searching for
+    // where each bucket starts. Both give the same table, except that a
+    // search does not count the distinct keys of each bucket.
+    fn build_with<T: TrainingKey>(data: &RMITrainingData<T>, bits: u8, on_overflow: OnOverflow,
+                                  hint_policy: HintPolicy, leaf: bool,
+                                  progress: Option<&dyn Fn(f64)>,
+                                  by_search: bool) -> Result<RadixTable, Error> {
+        let largest = largest_hint(data);
+        let fits = largest <= u32::MAX as u64;
+        if !fits && on_overflow == OnOverflow::Error {
+            return Err(Error::ParamOverflow {
+                reason: format!("radix table position {} does not fit in 32 bits", largest),
+            });
+        }
+        let clamp = if on_overflow == OnOverflow::Clamp { u32::MAX as u64 } else { u64::MAX };
+
+        let (prefix, bits) = data_layout(data, bits, leaf);
+        // midpoints need the end of the last bucket as well
+        let buckets = 1usize << bits;
+        let entries = match hint_policy {
+            HintPolicy::FirstPosition => buckets,
+            HintPolicy::Midpoint => buckets + 1,
+        };
+        let mut hint_table = if fits || on_overflow == OnOverflow::Clamp {
+            HintTable::Narrow(AlignedTable::zeroed(entries))
+        } else {
+            HintTable::Wide(AlignedTable::zeroed(entries))
+        };
+        let mut empty = vec![u64::MAX; buckets.div_ceil(64)];
+
+        if by_search {
+            let mut progress = Progress::new(progress, 1, buckets);
+            let max_error = fill_by_search(data, prefix, bits, clamp, hint_policy,
+                                           &mut hint_table, &mut empty, &mut progress);
+            progress.finish();
+            return Ok(RadixTable {
+                prefix_bits: prefix,
+                table_bits: bits,
+                shift: radix_shift(prefix, bits),
+                hint_table,
+                hint_policy,
+                num_keys: end_position(data),
+                max_error,
+                domain: data.key_domain(),
+                leaf,
+                empty,
+                window_slack: 0,
+                max_bucket_keys: None,
+            });
+        }
+
+        let mut progress = Progress::new(progress, 2, data.len());
+        // radixes are below `buckets`, at most 1 << MAX_TABLE_BITS, so one
+        // past any of them still fits
+        let mut last_radix = 0;
+        // distinct keys in the current bucket, where copies of a key count once
+        let (mut last_key, mut bucket_keys, mut max_bucket_keys) = (0, 0, 0);
+        for (idx, (inp, y)) in data.iter_model_input().enumerate() {
+            progress.update(0, data.len(), idx);
+            let x = inp.as_int();
+            let current_radix = ((x << prefix) >> prefix) >> radix_shift(prefix, bits);
+            assert!(current_radix < buckets as u64);
+            let current_radix = current_radix as usize;
+            empty[current_radix / 64] &= !(1 << (current_radix % 64));
+            let hint = u64::min(y as u64, clamp);
+
+            if idx == 0 || current_radix != last_radix {
+                bucket_keys = 1;
+            } else if x != last_key {
+                bucket_keys += 1;
+            }
+            last_key = x;
+            max_bucket_keys = u64::max(max_bucket_keys, bucket_keys);
+
+            // buckets up to the first key's start at its position, which
+            // need not be 0
+            if idx == 0 {
+                hint_table.fill(0..current_radix + 1, hint);
+                last_radix = current_radix;
+                continue;
+            }
+            if current_radix == last_radix { continue; }
+            assert!(current_radix > last_radix, "radix table keys must be sorted");
+
+            // this key's bucket, and the empty ones skipped since the last key
+            hint_table.fill(last_radix + 1..current_radix + 1, hint);
+            last_radix = current_radix;
+        }
+
+        // buckets past the last key (and the end of the last bucket) are
+        // at the end of the data
+        let end = hint_table.len();
+        hint_table.fill(last_radix + 1..end, u64::min(end_position(data), clamp));
+
+        let mut table = RadixTable {
+            prefix_bits: prefix,
+            table_bits: bits,
+            shift: radix_shift(prefix, bits),
+            hint_table,
+            hint_policy,
+            num_keys: end_position(data),
+            max_error: 0,
+            domain: data.key_domain(),
+            leaf,
+            empty,
+            window_slack: 0,
+            max_bucket_keys: Some(max_bucket_keys),
+        };
+
+        // every key sits somewhere in its bucket's span. The bound is the
+        // furthest any is from its bucket's prediction, in integers, so it
+        // stays exact past 2^53.
+        table.max_error = data.iter_model_input()
+            .enumerate()
+            .map(|(idx, (inp, y))| {
+                progress.update(1, data.len(), idx);
+                let hint = table.predict_to_int(&inp);
+                u64::max(hint, y as u64) - u64::min(hint, y as u64)
+            })
+            .max()
+            .unwrap_or(0);
+        trace!("Radix table with {} bits has max bucket error {}", bits, table.max_error);
+        debug!("Radix table with {} bits has up to {} distinct keys in one bucket, \
+                which more bits would split", bits, max_bucket_keys);
+        progress.finish();
+
+        return Ok(table);
+    }
+
+    // Whether `retrain_buckets` can update the table for `data`: only when
+    // the layout, the width of the entries and (for leaves) the domain the
+    // keys are clamped to stay the same, and the table was built from keys.
+    fn can_retrain_in_place(&self, data: &RMITrainingData<u64>) -> bool {
+        let fits = match self.hint_table {
+            HintTable::Narrow(_) => largest_hint(data) <= u32::MAX as u64,
+            HintTable::Wide(_) => true,
+        };
+        return data.len() > 0 && fits && self.window_slack == 0
+            && data_layout(data, self.table_bits, self.leaf) == (self.prefix_bits, self.table_bits)
+            && (!self.leaf || data.key_domain() == self.domain);
+    }
+
+    // Rewrites the hints of the buckets whose first position may have
+    // changed with the records in `changed`, which lie between the bucket
+    // after the last record before the change and the bucket of the first
+    // record after it. The error bound grows to cover those buckets (and
+    // the one before them, whose midpoint reads the first hint), but does
+    // not shrink.
+    fn retrain_buckets(&mut self, data: &RMITrainingData<u64>, changed: Range<usize>) {
+        let len = data.len();
+        let end = end_position(data);
+        let bucket_of = |table: &RadixTable, idx: usize| table.bucket(&data.get_key(idx).into());
+        let first = if changed.start == 0 { 0 } else { bucket_of(self, changed.start - 1) + 1 };
+        let last = if changed.end < len {
+            bucket_of(self, changed.end)
+        } else {
+            self.hint_table.len() - 1
+        };
+
+        // as in `build`, a bucket starts at the position of the first key
+        // at or after it, or at the end of the data
+        let mut next = first;
+        for idx in changed.start..usize::min(changed.end + 1, len) {
+            let (key, pos) = data.get(idx);
+            let end = usize::min(self.bucket(&key.into()), last) + 1;
+            if end > next {
+                self.hint_table.fill(next..end, pos as u64);
+                next = end;
+            }
+        }
+        if next <= last {
+            self.hint_table.fill(next..last + 1, end);
+        }
+        self.num_keys = end;
+        self.domain = data.key_domain();
+
+        let buckets = 1usize << self.table_bits;
+        for idx in first..=usize::min(last, buckets - 1) {
+            let next_hint = self.hint_table.get(idx + 1).unwrap_or(end);
+            if self.hint_table.hint(idx) == next_hint {
+                self.empty[idx / 64] |= 1 << (idx % 64);
+            } else {
+                self.empty[idx / 64] &= !(1 << (idx % 64));
+            }
+        }
+
+        let mut from = changed.start;
+        while from > 0 && bucket_of(self, from - 1) + 1 >= first {
+            from -= 1;
+        }
+        let mut to = usize::min(changed.end, len);
+        while to < len && bucket_of(self, to) <= last {
+            to += 1;
+        }
+        // whole buckets, so their keys are counted as `build` would. A table
+        // whose keys were never counted has no count to raise.
+        let mut bucket_keys = 0;
+        for idx in from..to {
+            let (key, pos) = data.get(idx);
+            let hint = self.predict_to_int(&key.into());
+            self.max_error = u64::max(self.max_error, u64::max(hint, pos as u64)
+                                      - u64::min(hint, pos as u64));
+
+            if idx == from || bucket_of(self, idx - 1) != bucket_of(self, idx) {
+                bucket_keys = 1;
+            } else if data.get_key(idx - 1) != key {
+                bucket_keys += 1;
+            }
+            if let Some(max_bucket_keys) = self.max_bucket_keys.as_mut() {
+                *max_bucket_keys = u64::max(*max_bucket_keys, bucket_keys);
+            }
+        }
+    }
+
+    // the domain leaves clamp keys to. Empty leaves have none, and
+    // send every key to their first bucket.
+    fn leaf_domain(&self) -> (u64, u64) {
+        return self.domain.unwrap_or((0, 0));
+    }
+
+    fn bucket(&self, inp: &ModelInput) -> usize {
+        let as_int: u64 = if self.leaf {
+            let (lo, hi) = self.leaf_domain();
+            inp.as_int().clamp(lo, hi)
+        } else {
+            inp.as_int()
+        };
+        let prefix = self.prefix_bits;
+        return (((as_int << prefix) >> prefix) >> self.shift) as usize;
+    }
+
+    /// Reports `err` as the error bound without rebuilding the table, so
+    /// that search code can be tested with windows of a known size. The
+    /// bucket windows of `predict_window` and `predict_range_bounds` are
+    /// exact, and stay as they are.
+    pub fn with_error_bound(mut self, err: u64) -> RadixTable {
+        self.max_error = err;
+        return self;
+    }
+
+    /// The most distinct keys that fell into one bucket. The table predicts
+    /// the same position for all of them, so a count well above 1 means
+    /// `bits` is too small for the data. Retraining only raises it. Tables
+    /// built from a CDF sample, coarsened, loaded from their state or built
+    /// by searching for each bucket's start have not counted the keys, and
+    /// report `None`.
+    pub fn max_bucket_keys(&self) -> Option<u64> {
+        return self.max_bucket_keys;
+    }
+
+    /// The bucket the key falls into, which is the table entry its
+    /// prediction is read from, for counting how queries spread over the
+    /// buckets. It never decreases with the key for keys in the trained
+    /// domain; keys outside it that do not share the domain's common
+    /// prefix wrap around (leaves clamp them to the domain instead).
+    pub fn bucket_index(&self, inp: &ModelInput) -> usize {
+        return self.bucket(inp);
+    }
+
+    /// The table entries that differ between `self` and `other`, as the
+    /// entry's index (its bucket, or for `HintPolicy::Midpoint` tables the
+    /// end of the last bucket after them) with its old and new hints, for
+    /// checking which buckets a retrain touched. Entries are `u64`, as
+    /// tables that widened them hold positions past `u32::MAX`. Panics if
+    /// the tables have different layouts, whose entries do not correspond.
+    pub fn diff(&self, other: &RadixTable) -> Vec<(usize, u64, u64)> {
+        assert!(self.prefix_bits == other.prefix_bits && self.table_bits == other.table_bits
+                && self.hint_table.len() == other.hint_table.len(),
+                "cannot diff a table of {} prefix and {} bits against one of {} and {}",
+                self.prefix_bits, self.table_bits, other.prefix_bits, other.table_bits);
+        return (0..self.hint_table.len())
+            .map(|idx| (idx, self.hint_table.hint(idx), other.hint_table.hint(idx)))
+            .filter(|(_, old, new)| old != new)
+            .collect();
+    }
+
+    fn is_empty_bucket(&self, idx: usize) -> bool {
+        return self.empty[idx / 64] & (1 << (idx % 64)) != 0;
+    }
+
+    /// If no trained key fell into the key's bucket, the position the key
+    /// would be inserted at, which is the next populated bucket's hint.
+    /// No search is needed for such keys.
+    pub fn empty_bucket_position(&self, inp: &ModelInput) -> Option<u64> {
+        let idx = self.bucket(inp);
+        if !self.is_empty_bucket(idx) {
+            return None;
+        }
+        return Some(self.hint_table.hint(idx));
+    }
+
+    /// The span of positions of the key's bucket: its first position and
+    /// the next bucket's (or the number of keys, for the last bucket),
+    /// whichever `HintPolicy` the table predicts with.
+    /// A trained key's position `p` satisfies `lo <= p < hi`, and any key's
+    /// lower bound lies in `lo..=hi`, so searching the window needs no
+    /// error bound.
+    ///
+    /// Empty buckets get the zero-width window `(p, p)`, where `p` is the
+    /// position from `empty_bucket_position`. For a table built `from_cdf`
+    /// no bucket is known to be empty, and windows start far enough below
+    /// the hint to take in any bucket's first position.
+    pub fn predict_window(&self, inp: &ModelInput) -> (u64, u64) {
+        let idx = self.bucket(inp);
+        if self.is_empty_bucket(idx) {
+            let pos = self.hint_table.hint(idx);
+            return (pos, pos);
+        }
+
+        let lo = self.hint_table.hint(idx).saturating_sub(self.window_slack);
+        let hi = self.hint_table.get(idx + 1).unwrap_or(self.num_keys);
+        return (lo, hi);
+    }
+
+    /// Writes `predict_to_int` of each key to `out`, which must be as long
+    /// as `sorted_keys`. A run of keys falling into the same bucket reads
+    /// the table once, which in a sorted batch is most of them. Keys in any
+    /// order give the same answers, just without the savings.
+    pub fn predict_batch_sorted(&self, sorted_keys: &[u64], out: &mut [u64]) {
+        assert_eq!(sorted_keys.len(), out.len(), "one output per key");
+        let mut last: Option<(usize, u64)> = None;
+        for (key, pred) in sorted_keys.iter().zip(out.iter_mut()) {
+            let idx = self.bucket(&(*key).into());
+            *pred = match last {
+                Some((last_idx, last_pred)) if last_idx == idx => last_pred,
+                _ => self.bucket_prediction(idx),
+            };
+            last = Some((idx, *pred));
+        }
+    }
+
+    fn bucket_prediction(&self, idx: usize) -> u64 {
+        return match self.hint_policy {
+            HintPolicy::FirstPosition => self.hint_table.hint(idx),
+            HintPolicy::Midpoint =>
+                (self.hint_table.hint(idx) + self.hint_table.hint(idx + 1)) / 2,
+        };
+    }
+
+    pub(crate) fn from_state(params: &[ModelParam]) -> Option<RadixTable> {
+        // leaves store their domain after the hints, and tables built from
+        // a CDF sample their window slack
+        let (params, domain, window_slack) = match params {
+            [params @ .., ModelParam::Int(lo), ModelParam::Int(hi)] if params.len() == 5 =>
+                (params, Some((*lo, *hi)), 0),
+            [params @ .., ModelParam::Int(slack)] if params.len() == 5 => (params, None, *slack),
+            params => (params, None, 0),
+        };
+        let (prefix, bits, num_keys, max_error, hint_table) = match params {
+            [ModelParam::Int(prefix), ModelParam::Int(bits), ModelParam::Int(num_keys),
+             ModelParam::Int(max_error), hints] if valid_table_layout(*prefix, *bits) =>
+                (*prefix, *bits, *num_keys, *max_error, match hints {
+                    ModelParam::Int32Array(h) => HintTable::Narrow(AlignedTable::from_slice(h)),
+                    ModelParam::IntArray(h) => HintTable::Wide(AlignedTable::from_slice(h)),
+                    _ => return None,
+                }),
+            _ => return None,
+        };
+        // the policy is implied by whether the end of the last bucket is stored
+        let buckets = 1usize << bits;
+        let hint_policy = if hint_table.len() == buckets {
+            HintPolicy::FirstPosition
+        } else if hint_table.len() == buckets + 1 && domain.is_none() {
+            HintPolicy::Midpoint
+        } else {
+            return None;
+        };
+
+        // with slack, a bucket without hints of its own may still hold keys
+        let empty = if window_slack > 0 {
+            vec![0; buckets.div_ceil(64)]
+        } else {
+            empty_buckets(&hint_table, buckets, num_keys)
+        };
+
+        return Some(RadixTable {
+            prefix_bits: prefix as u8,
+            table_bits: bits as u8,
+            shift: radix_shift(prefix as u8, bits as u8),
+            empty,
+            hint_table,
+            hint_policy,
+            num_keys,
+            max_error,
+            domain,
+            leaf: domain.is_some(),
+            window_slack,
+            max_bucket_keys: None,
+        });
+    }
+}
+
+// A flat table file is a 64-byte header of little-endian integers,
+//
+//   magic "RMIR" | version: u32 | prefix: u8 | bits: u8 | width: u8 |
+//   flags: u8 | padding: 4 | num keys | max error | window slack |
+//   domain lo | domain hi | entries
+//
+// followed by the raw hints, `width` bytes each. Ending the header on a
+// cache line keeps the mapped hints as aligned as built ones.
+#[cfg(feature = "mmap")]
+const FLAT_MAGIC: &[u8; 4] = b"RMIR";
+#[cfg(feature = "mmap")]
+const FLAT_VERSION: u32 = 1;
+#[cfg(feature = "mmap")]
+const FLAT_HEADER_BYTES: usize = 64;
+
+// flags of a flat table file
+#[cfg(feature = "mmap")]
+const FLAT_LEAF: u8 = 1;
+#[cfg(feature = "mmap")]
+const FLAT_MIDPOINT: u8 = 2;
+#[cfg(feature = "mmap")]
+const FLAT_DOMAIN: u8 = 4;
+
+#[cfg(feature = "mmap")]
+impl RadixTable {
+    /// Writes the table to `path` as a header followed by its raw hints,
+    /// for `open_flat` to map back in without reading them one by one.
+    pub fn write_flat<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let (width, hints): (u8, Vec<u8>) = match &self.hint_table {
+            HintTable::Narrow(t) => (4, t.iter().flat_map(|h| h.to_le_bytes()).collect()),
+            HintTable::Wide(t) => (8, t.iter().flat_map(|h| h.to_le_bytes()).collect()),
+        };
+        let mut flags = 0;
+        if self.leaf {
+            flags |= FLAT_LEAF;
+        }
+        if self.hint_policy == HintPolicy::Midpoint {
+            flags |= FLAT_MIDPOINT;
+        }
+        if self.domain.is_some() {
+            flags |= FLAT_DOMAIN;
+        }
+        let (lo, hi) = self.leaf_domain();
+
+        let mut out = Vec::with_capacity(FLAT_HEADER_BYTES + hints.len());
+        out.extend_from_slice(FLAT_MAGIC);
+        out.extend_from_slice(&FLAT_VERSION.to_le_bytes());
+        out.extend_from_slice(&[self.prefix_bits, self.table_bits, width, flags, 0, 0, 0, 0]);
+        for field in [self.num_keys, self.max_error, self.window_slack, lo, hi,
+                      self.hint_table.len() as u64] {
+            out.extend_from_slice(&field.to_le_bytes());
+        }
+        debug_assert_eq!(out.len(), FLAT_HEADER_BYTES);
+        out.extend_from_slice(&hints);
+        return fs::write(path, out);
+    }
+
+    /// Maps a table written by `write_flat`, checking its header, and
+    /// serves lookups from the mapped hints rather than a copy of them. The
+    /// mapping is private, so retraining the table in place never changes
+    /// the file. Only little-endian machines can use the hints as they are
+    /// stored; elsewhere this returns an `Unsupported` io error. A file
+    /// whose header does not check out is `Error::Corrupt`.
+    pub fn open_flat<P: AsRef<Path>>(path: P) -> Result<RadixTable, Error> {
+        let invalid = |msg: &str| Error::Corrupt { reason: msg.to_string() };
+        if cfg!(target_endian = "big") {
+            return Err(io::Error::new(io::ErrorKind::Unsupported,
+                                      "flat radix tables are little endian").into());
+        }
+
+        let file = fs::File::open(path)?;
+        // the mapping is copy-on-write, and reads of it go through the
+        // length checks below
+        let map = unsafe { memmap::MmapOptions::new().map_copy(&file)? };
+        if map.len() < FLAT_HEADER_BYTES || &map[0..4] != FLAT_MAGIC {
+            return Err(invalid("not a flat radix table"));
+        }
+        let field = |idx: usize| {
+            let start = 16 + 8 * idx;
+            return u64::from_le_bytes(map[start..start + 8].try_into().unwrap());
+        };
+        let version = u32::from_le_bytes(map[4..8].try_into().unwrap());
+        if version != FLAT_VERSION {
+            return Err(invalid("unsupported flat radix table version"));
+        }
+        let (prefix, bits, width, flags) = (map[8], map[9], map[10] as usize, map[11]);
+        let (num_keys, max_error, window_slack) = (field(0), field(1), field(2));
+        let (lo, hi, entries) = (field(3), field(4), field(5));
+        if !valid_table_layout(prefix as u64, bits as u64) {
+            return Err(invalid("flat radix table layout does not fit a 64-bit key"));
+        }
+
+        let leaf = flags & FLAT_LEAF != 0;
+        let hint_policy = if flags & FLAT_MIDPOINT != 0 {
+            HintPolicy::Midpoint
+        } else {
+            HintPolicy::FirstPosition
+        };
+        let buckets = 1u64 << bits;
+        let expected = if hint_policy == HintPolicy::Midpoint { buckets + 1 } else { buckets };
+        if entries != expected || (leaf && hint_policy == HintPolicy::Midpoint)
+            || (leaf && flags & FLAT_DOMAIN == 0) {
+            return Err(invalid("flat radix table header is inconsistent"));
+        }
+        let fits = (entries as usize).checked_mul(width)
+            .map(|size| FLAT_HEADER_BYTES + size <= map.len())
+            .unwrap_or(false);
+        if !fits {
+            return Err(invalid("flat radix table is truncated"));
+        }
+
+        let entries = entries as usize;
+        let hint_table = match width {
+            4 => HintTable::Narrow(AlignedTable::mapped(map, FLAT_HEADER_BYTES, entries)),
+            8 => HintTable::Wide(AlignedTable::mapped(map, FLAT_HEADER_BYTES, entries)),
+            _ => return Err(invalid("flat radix table hints are neither 4 nor 8 bytes")),
+        };
+        // with slack, a bucket without hints of its own may still hold keys
+        let empty = if window_slack > 0 {
+            vec![0; (buckets as usize).div_ceil(64)]
+        } else {
+            empty_buckets(&hint_table, buckets as usize, num_keys)
+        };
+
+        return Ok(RadixTable {
+            prefix_bits: prefix,
+            table_bits: bits,
+            shift: radix_shift(prefix, bits),
+            empty,
+            hint_table,
+            hint_policy,
+            num_keys,
+            max_error,
+            domain: if flags & FLAT_DOMAIN != 0 { Some((lo, hi)) } else { None },
+            leaf,
+            window_slack,
+            max_bucket_keys: None,
+        });
+    }
+}
+
+#[cfg(feature = "huge_pages")]
+impl RadixTable {
+    /// Moves the hints onto transparent huge pages, where the OS offers
+    /// them. A table of 28 bits or more spans hundreds of thousands of
+    /// normal pages, so its lookups mostly miss the TLB; on huge pages they
+    /// miss far less. Lookups are otherwise unchanged. If the pages cannot
+    /// be mapped, the table keeps the storage it has.
+    pub fn into_huge_pages(mut self) -> RadixTable {
+        let huge = match &self.hint_table {
+            HintTable::Narrow(t) => AlignedTable::huge(t).map(HintTable::Narrow),
+            HintTable::Wide(t) => AlignedTable::huge(t).map(HintTable::Wide),
+        };
+        if let Some(hint_table) = huge {
+            self.hint_table = hint_table;
+        }
+        return self;
+    }
+}
+
+// the hints are the fifth parameter of a table's state
+#[cfg(feature = "arena")]
+const HINTS_PARAM: usize = 4;
+
+#[cfg(feature = "arena")]
+impl RadixTable {
+    /// Like `new`, but keeps the hints in `arena`, if they fit, rather than
+    /// in an allocation of their own (see `Model::move_into`).
+    pub fn new_in<T: TrainingKey>(data: &RMITrainingData<T>, bits: u8,
+                                  arena: &ParamArena) -> RadixTable {
+        let mut table = RadixTable::new(data, bits);
+        table.move_into(arena);
+        return table;
+    }
+
+    // Reads the hints from entry `idx` of `arena`, which holds this table's
+    // state, dropping the table's own.
+    pub(crate) fn attach(&mut self, arena: &ParamArena, idx: usize) {
+        self.hint_table = self.hint_table.in_arena(arena, arena.param_location(idx, HINTS_PARAM));
+    }
+
+    #[cfg(test)]
+    pub(crate) fn array_ptrs(&self) -> Vec<*const u8> {
+        return vec![self.hint_table.as_ptr()];
+    }
+}
+
+impl Model for RadixTable {
+    fn predict_to_int(&self, inp: &ModelInput) -> u64 {
+        return self.bucket_prediction(self.bucket(inp));
+    }
+
+    fn reference_predict(&self, inp: &ModelInput) -> u64 {
+        let mut key = inp.as_int();
+        if self.leaf {
+            let (lo, hi) = self.leaf_domain();
+            key = u64::max(lo, u64::min(key, hi));
+        }
+        let idx = reference_radix(key, self.prefix_bits, self.table_bits) as usize;
+        return match self.hint_policy {
+            HintPolicy::FirstPosition => self.hint_table.hint(idx),
+            HintPolicy::Midpoint => {
+                let (first, next) = (self.hint_table.hint(idx), self.hint_table.hint(idx + 1));
+                first + (next - first) / 2
+            }
+        };
+    }
+
+    // one hint, or two adjacent ones for midpoints (which may straddle a
+    // line, but usually do not). Leaves read their layout as well, which
+    // sits apart from the table.
+    fn lookup_cache_lines(&self) -> usize {
+        return if self.leaf { 2 } else { 1 };
+    }
+
+    // the bucket's two shifts and a load, plus clamping a leaf's key to its
+    // domain, or loading the next hint and averaging for a midpoint
+    fn estimated_flops(&self, _inp: &ModelInput) -> u64 {
+        let extra = match self.hint_policy {
+            HintPolicy::FirstPosition => 0,
+            HintPolicy::Midpoint => 3,
+        };
+        return 3 + extra + if self.leaf { 2 } else { 0 };
+    }
+
+    fn integer_only(&self) -> bool {
+        return true;
+    }
+
+    fn input_type(&self) -> ModelDataType {
+        return ModelDataType::Int;
+    }
+    fn output_type(&self) -> ModelDataType {
+        return ModelDataType::Int;
+    }
+
+    fn params(&self) -> Vec<ModelParam> {
+        if self.leaf {
+            let (lo, hi) = self.leaf_domain();
+            return vec![lo.into(), hi.into(), self.prefix_bits.into(), self.shift.into(),
+                        self.hint_table.to_param()];
+        }
+        return vec![self.hint_table.to_param()];
+    }
+
+    fn code(&self) -> String {
+        if self.leaf {
+            return format!(
+                "
+inline uint64_t {0}(const uint64_t lo, const uint64_t hi, const uint64_t prefix,
+                    const uint64_t shift, const {1}* table, const uint64_t inp) {{
+    const uint64_t key = inp < lo ? lo : (inp > hi ? hi : inp);
+    return table[((key << prefix) >> prefix) >> shift];
+}}", self.function_name(), self.hint_table.c_type());
+        }
+
+        // without a common prefix there are no leading bits to clear
+        let index = if self.prefix_bits == 0 {
+            format!("inp >> {}", self.shift)
+        } else {
+            format!("((inp << {0}) >> {0}) >> {1}", self.prefix_bits, self.shift)
+        };
+
+        let body = match self.hint_policy {
+            HintPolicy::FirstPosition => format!("return table[{}];", index),
+            HintPolicy::Midpoint => format!(
+                "const uint64_t idx = {};
+    return ((uint64_t) table[idx] + table[idx + 1]) >> 1;", index),
+        };
+
+        return format!(
+            "
+inline uint64_t {0}(const {1}* table, const uint64_t inp) {{
+    {2}
+}}", self.function_name(), self.hint_table.c_type(), body
+        );
+    }
+
+    fn required_includes(&self) -> Vec<&'static str> {
+        return vec!["<cstdint>"];
+    }
+
+    fn function_name(&self) -> String {
+        let width = match self.hint_table {
+            HintTable::Narrow(_) => "",
+            HintTable::Wide(_) => "64",
+        };
+        let policy = match self.hint_policy {
+            HintPolicy::FirstPosition => "",
+            HintPolicy::Midpoint => "_mid",
+        };
+        let leaf = if self.leaf { "_leaf" } else { "" };
+        return format!("radix_table{}{}{}", leaf, width, policy);
+    }
+    fn needs_bounds_check(&self) -> bool {
+        return false;
+    }
+    fn restriction(&self) -> ModelRestriction {
+        if self.leaf {
+            return ModelRestriction::MustBeBottom;
+        }
+        return ModelRestriction::None;
+    }
+    fn error_bound(&self) -> Option<u64> {
+        return Some(self.max_error);
+    }
+    fn key_domain(&self) -> Option<(u64, u64)> {
+        return self.domain;
+    }
+    fn min_value(&self) -> u64 {
+        // every window starts at a hint, less the slack of a CDF table
+        return self.hint_table.hint(0).saturating_sub(self.window_slack);
+    }
+    fn supports_range_query(&self) -> bool {
+        return self.domain.is_some();
+    }
+    fn predict_range_bounds(&self, lo: &ModelInput, hi: &ModelInput) -> (u64, u64) {
+        // the bucket windows are exact, so no error bound is needed. Keys
+        // are clamped to the domain so they do not wrap around its prefix.
+        assert!(self.supports_range_query(), "radix table has no domain");
+        let (min, max) = self.domain.unwrap();
+        let (start, _) = self.predict_window(&lo.as_int().clamp(min, max).into());
+        let (_, end) = self.predict_window(&hi.as_int().clamp(min, max).into());
+        return (start, end);
+    }
+
+    fn retrain(&mut self, data: &RMITrainingData<u64>,
+               changed: Range<usize>) -> Result<(), Error> {
+        if self.can_retrain_in_place(data) {
+            self.retrain_buckets(data, changed);
+            return Ok(());
+        }
+
+        let on_overflow = match self.hint_table {
+            HintTable::Narrow(_) => OnOverflow::Error,
+            HintTable::Wide(_) => OnOverflow::WidenAutomatically,
+        };
+        *self = RadixTable::build(data, self.table_bits, on_overflow, self.hint_policy,
+                                  self.leaf, None)?;
+        return Ok(());
+    }
+
+    #[cfg(feature = "arena")]
+    fn move_into(&mut self, arena: &ParamArena) -> Option<usize> {
+        let (kind, params) = self.state()?;
+        let idx = arena.push(kind, &params)?;
+        self.attach(arena, idx);
+        return Some(idx);
+    }
+
+    fn set_to_constant_model(&mut self, constant: u64) -> bool {
+        if !self.leaf {
+            return false;
+        }
+        let end = self.hint_table.len();
+        self.hint_table.fill(0..end, constant);
+        self.max_error = 0;
+        self.window_slack = 0;
+        return true;
+    }
+
+    fn kind(&self) -> ModelKind {
+        return ModelKind::RadixTable;
+    }
+
+    fn state(&self) -> Option<(ModelKind, Vec<ModelParam>)> {
+        let mut params = vec![
+            self.prefix_bits.into(),
+            self.table_bits.into(),
+            self.num_keys.into(),
+            self.max_error.into(),
+            self.hint_table.to_param()
+        ];
+        if self.leaf {
+            let (lo, hi) = self.leaf_domain();
+            params.extend([lo.into(), hi.into()]);
+        } else if self.window_slack > 0 {
+            params.push(self.window_slack.into());
+        }
+        return Some((self.kind(), params));
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        return self;
+    }
+}
+
+// The offsets of a `RelativeRadixTable`, in the narrowest integers that
+// hold the largest of them.
+enum OffsetTable {
+    Short(AlignedTable<u16>),
+    Narrow(AlignedTable<u32>),
+    Wide(AlignedTable<u64>),
+}
+
+impl OffsetTable {
+    fn from_offsets(offsets: &[u64]) -> OffsetTable {
+        let largest = offsets.iter().cloned().max().unwrap_or(0);
+        if largest <= u16::MAX as u64 {
+            let short: Vec<u16> = offsets.iter().map(|o| *o as u16).collect();
+            return OffsetTable::Short(AlignedTable::from_slice(&short));
+        }
+        if largest <= u32::MAX as u64 {
+            let narrow: Vec<u32> = offsets.iter().map(|o| *o as u32).collect();
+            return OffsetTable::Narrow(AlignedTable::from_slice(&narrow));
+        }
+        return OffsetTable::Wide(AlignedTable::from_slice(offsets));
+    }
+
+    fn len(&self) -> usize {
+        return match self {
+            OffsetTable::Short(t) => t.len(),
+            OffsetTable::Narrow(t) => t.len(),
+            OffsetTable::Wide(t) => t.len(),
+        };
+    }
+
+    fn offset(&self, idx: usize) -> u64 {
+        return match self {
+            OffsetTable::Short(t) => t[idx] as u64,
+            OffsetTable::Narrow(t) => t[idx] as u64,
+            OffsetTable::Wide(t) => t[idx],
+        };
+    }
+
+    fn bits(&self) -> u8 {
+        return match self {
+            OffsetTable::Short(_) => 16,
+            OffsetTable::Narrow(_) => 32,
+            OffsetTable::Wide(_) => 64,
+        };
+    }
+
+    fn to_param(&self) -> ModelParam {
+        return match self {
+            OffsetTable::Short(t) => t.to_vec().into(),
+            OffsetTable::Narrow(t) => t.to_vec().into(),
+            OffsetTable::Wide(t) => t.to_vec().into(),
+        };
+    }
+
+    #[cfg(feature = "arena")]
+    fn in_arena(&self, arena: &ParamArena, (offset, len): (usize, usize)) -> OffsetTable {
+        return match self {
+            OffsetTable::Short(_) => OffsetTable::Short(AlignedTable::in_arena(arena, offset, len)),
+            OffsetTable::Narrow(_) =>
+                OffsetTable::Narrow(AlignedTable::in_arena(arena, offset, len)),
+            OffsetTable::Wide(_) => OffsetTable::Wide(AlignedTable::in_arena(arena, offset, len)),
+        };
+    }
+
+    #[cfg(all(test, feature = "arena"))]
+    fn as_ptr(&self) -> *const u8 {
+        return match self {
+            OffsetTable::Short(t) => t.as_ptr() as *const u8,
+            OffsetTable::Narrow(t) => t.as_ptr() as *const u8,
+            OffsetTable::Wide(t) => t.as_ptr() as *const u8,
+        };
+    }
+}
+
+/// A `RadixTable` whose buckets store their first position relative to a
+/// base, kept once per group of `1 << group_bits` buckets. Within a group
+/// the offsets are small, so the per-bucket table is usually `u16`. The
+/// width is picked from the largest offset, and is widened rather than
+/// allowed to overflow. Predictions are the same as `RadixTable::new`'s.
+pub struct RelativeRadixTable {
+    prefix_bits: u8,
+    table_bits: u8,
+    shift: u8,
+    group_bits: u8,
+    bases: AlignedTable<u64>,
+    offsets: OffsetTable,
+    max_error: u64,
+    domain: Option<(u64, u64)>,
+}
+
+impl RelativeRadixTable {
+    pub fn new<T: TrainingKey>(data: &RMITrainingData<T>, bits: u8,
+                               group_bits: u8) -> RelativeRadixTable {
+        let table = RadixTable::new(data, bits);
+        let group_bits = u8::min(group_bits, table.table_bits);
+
+        // hints never decrease, so a group's base is its first hint
+        let hints: Vec<u64> = (0..table.hint_table.len())
+            .map(|idx| table.hint_table.hint(idx))
+            .collect();
+        let bases: Vec<u64> = hints.iter().cloned().step_by(1 << group_bits).collect();
+        let offsets: Vec<u64> = hints.iter()
+            .enumerate()
+            .map(|(idx, hint)| hint - bases[idx >> group_bits])
+            .collect();
+
+        return RelativeRadixTable {
+            prefix_bits: table.prefix_bits,
+            table_bits: table.table_bits,
+            shift: table.shift,
+            group_bits,
+            bases: AlignedTable::from_slice(&bases),
+            offsets: OffsetTable::from_offsets(&offsets),
+            max_error: table.max_error,
+            domain: table.domain,
+        };
+    }
+
+    /// How many bits each bucket's offset takes: 16, 32 or 64.
+    pub fn offset_bits(&self) -> u8 {
+        return self.offsets.bits();
+    }
+
+    fn bucket(&self, inp: &ModelInput) -> usize {
+        let prefix = self.prefix_bits;
+        return (((inp.as_int() << prefix) >> prefix) >> self.shift) as usize;
+    }
+
+    // Reads the bases and offsets, the fifth and sixth parameters of the
+    // state, from entry `idx` of `arena`, dropping the table's own.
+    #[cfg(feature = "arena")]
+    pub(crate) fn attach(&mut self, arena: &ParamArena, idx: usize) {
+        let (offset, len) = arena.param_location(idx, 4);
+        self.bases = AlignedTable::in_arena(arena, offset, len);
+        self.offsets = self.offsets.in_arena(arena, arena.param_location(idx, 5));
+    }
+
+    #[cfg(all(test, feature = "arena"))]
+    pub(crate) fn array_ptrs(&self) -> Vec<*const u8> {
+        return vec![self.bases.as_ptr() as *const u8, self.offsets.as_ptr()];
+    }
+
+    pub(crate) fn from_state(params: &[ModelParam]) -> Option<RelativeRadixTable> {
+        let (prefix, bits, group_bits, max_error, bases, offsets) = match params {
+            [ModelParam::Int(prefix), ModelParam::Int(bits), ModelParam::Int(group_bits),
+             ModelParam::Int(max_error), ModelParam::IntArray(bases), offsets]
+                if valid_table_layout(*prefix, *bits) && group_bits <= bits =>
+                (*prefix, *bits, *group_bits, *max_error, bases, match offsets {
+                    ModelParam::ShortArray(o) => OffsetTable::Short(AlignedTable::from_slice(o)),
+                    ModelParam::Int32Array(o) => OffsetTable::Narrow(AlignedTable::from_slice(o)),
+                    ModelParam::IntArray(o) => OffsetTable::Wide(AlignedTable::from_slice(o)),
+                    _ => return None,
+                }),
+            _ => return None,
+        };
+        if offsets.len() != 1 << bits || bases.len() != 1 << (bits - group_bits) {
+            return None;
+        }
+
+        return Some(RelativeRadixTable {
+            prefix_bits: prefix as u8,
+            table_bits: bits as u8,
+            shift: radix_shift(prefix as u8, bits as u8),
+            group_bits: group_bits as u8,
+            bases: AlignedTable::from_slice(bases),
+            offsets,
+            max_error,
+            domain: None,
+        });
+    }
+}
+
+impl Model for RelativeRadixTable {
+    fn predict_to_int(&self, inp: &ModelInput) -> u64 {
+        let idx = self.bucket(inp);
+        return self.bases[idx >> self.group_bits] + self.offsets.offset(idx);
+    }
+
+    fn reference_predict(&self, inp: &ModelInput) -> u64 {
+        let idx = reference_radix(inp.as_int(), self.prefix_bits, self.table_bits) as usize;
+        let group = idx / (1 << self.group_bits);
+        return self.bases[group] + self.offsets.offset(idx);
+    }
+
+    // a base and an offset, from separate tables
+    fn lookup_cache_lines(&self) -> usize {
+        return 2;
+    }
+
+    // the bucket's two shifts, the shift to its group, both loads and
+    // their sum
+    fn estimated_flops(&self, _inp: &ModelInput) -> u64 {
+        return 6;
+    }
+
+    fn integer_only(&self) -> bool {
+        return true;
+    }
+
+    fn input_type(&self) -> ModelDataType {
+        return ModelDataType::Int;
+    }
+    fn output_type(&self) -> ModelDataType {
+        return ModelDataType::Int;
+    }
+
+    fn params(&self) -> Vec<ModelParam> {
+        return vec![self.bases.to_vec().into(), self.offsets.to_param()];
+    }
+
+    fn code(&self) -> String {
+        // without a common prefix there are no leading bits to clear
+        let index = if self.prefix_bits == 0 {
+            format!("inp >> {}", self.shift)
+        } else {
+            format!("((inp << {0}) >> {0}) >> {1}", self.prefix_bits, self.shift)
+        };
+
+        return format!(
+            "
+inline uint64_t {0}(const uint64_t* bases, const uint{1}_t* offsets, const uint64_t inp) {{
+    const uint64_t idx = {2};
+    return bases[idx >> {3}] + offsets[idx];
+}}", self.function_name(), self.offsets.bits(), index, self.group_bits
+        );
+    }
+
+    fn required_includes(&self) -> Vec<&'static str> {
+        return vec!["<cstdint>"];
+    }
+
+    fn function_name(&self) -> String {
+        return format!("relative_radix_table{}", self.offsets.bits());
+    }
+    fn needs_bounds_check(&self) -> bool {
+        return false;
+    }
+    fn restriction(&self) -> ModelRestriction {
+        return ModelRestriction::None;
+    }
+    fn error_bound(&self) -> Option<u64> {
+        return Some(self.max_error);
+    }
+    fn key_domain(&self) -> Option<(u64, u64)> {
+        return self.domain;
+    }
+    fn min_value(&self) -> u64 {
+        return self.bases[0];
+    }
+
+    fn retrain(&mut self, data: &RMITrainingData<u64>,
+               _changed: Range<usize>) -> Result<(), Error> {
+        *self = RelativeRadixTable::new(data, self.table_bits, self.group_bits);
+        return Ok(());
+    }
+
+    #[cfg(feature = "arena")]
+    fn move_into(&mut self, arena: &ParamArena) -> Option<usize> {
+        let (kind, params) = self.state()?;
+        let idx = arena.push(kind, &params)?;
+        self.attach(arena, idx);
+        return Some(idx);
+    }
+
+    fn kind(&self) -> ModelKind {
+        return ModelKind::RelativeRadixTable;
+    }
+
+    fn state(&self) -> Option<(ModelKind, Vec<ModelParam>)> {
+        return Some((self.kind(), vec![
+            self.prefix_bits.into(),
+            self.table_bits.into(),
+            self.group_bits.into(),
+            self.max_error.into(),
+            self.bases.to_vec().into(),
+            self.offsets.to_param()
+        ]));
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        return self;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_empty() {
+        RadixModel::new(&RMITrainingData::<u64>::empty());
+    }
+
+    #[test]
+    fn test_equal_keys() {
+        // a 64-bit common prefix must not turn into a 64-bit shift
+        let md = RMITrainingData::new(Box::new(vec![(42u64, 0), (42, 1), (42, 2)]));
+
+        let radix = RadixModel::new(&md);
+        assert!(radix.predict_to_int(&42.into()) <= 1);
+
+        let table = RadixTable::new(&md, 8);
+        assert_eq!(table.predict_to_int(&42.into()), 0);
+        assert_eq!(table.error_bound(), Some(0));
+    }
+
+    #[test]
+    fn test_degenerate_keys() {
+        // identical keys all land in one bucket, at the first copy
+        let md = RMITrainingData::from_keys(vec![43u64; 5]);
+        let table = RadixTable::new(&md, 8);
+        assert_eq!(table.predict_window(&43.into()), (0, 5));
+        assert_eq!(table.error_bound(), Some(0));
+        assert_eq!(verify(&RadixModel::new(&md), &md), Ok(()));
+
+        // two keys far apart, and two differing only in the lowest bit,
+        // each get a bucket of their own
+        for keys in [vec![1000u64, 5000], vec![42, 43], vec![0, u64::MAX]] {
+            let md = RMITrainingData::from_keys(keys.clone());
+            let radix = RadixModel::new(&md);
+            let table = RadixTable::new(&md, 8);
+            assert_eq!(table.error_bound(), Some(0), "{:?}", keys);
+            assert_eq!(table.predict_to_int(&keys[1].into()), 1);
+            assert_eq!(verify(&radix, &md), Ok(()));
+            assert_eq!(verify(&table, &md), Ok(()));
+        }
+    }
+
+    #[test]
+    fn test_plain_radix_transform() {
+        let keys: Vec<u64> = (0..20_000u64).map(|i| i.wrapping_mul(0x9E37_79B9_7F4A_7C15)).collect();
+        let mut sorted = keys.clone();
+        sorted.sort_unstable();
+        let mut md = RMITrainingData::from_keys(sorted);
+        md.set_scale(1024.0 / md.len() as f64);
+
+        let radix = RadixModel::new(&md);
+        let plain = RadixModel::new_with_transform(&md, PlainRadix);
+        let (prefix, bits) = radix.params;
+        assert_eq!(plain.params, radix.params);
+        assert_eq!(plain.error_bound(), radix.error_bound());
+        for key in keys.iter() {
+            let pred = radix.predict_to_int(&(*key).into());
+            assert_eq!(pred, (key << prefix) >> (64 - bits));
+            assert_eq!(radix.reference_predict(&(*key).into()), pred);
+            assert_eq!(plain.predict_to_int(&(*key).into()), pred);
+        }
+        assert_eq!(radix.function_name(), "radix");
+        assert_eq!(radix.code(), "
+inline uint64_t radix(uint64_t prefix_length, uint64_t bits, uint64_t inp) {
+    return (inp << prefix_length) >> (64 - bits);
+}");
+    }
+
+    #[test]
+    fn test_multiplicative_transform() {
+        // four tight clusters of keys, which a plain radix model sends to
+        // four outputs
+        let keys: Vec<u64> = (0..4u64)
+            .flat_map(|c| (0..2_500u64).map(move |i| (c << 62) + i * 17))
+            .collect();
+        let mut md = RMITrainingData::from_keys(keys.clone());
+        md.set_scale(1024.0 / md.len() as f64);
+
+        let most_per_output = |model: &dyn Model| {
+            let mut counts = HashMap::new();
+            for key in keys.iter() {
+                *counts.entry(model.predict_to_int(&(*key).into())).or_insert(0) += 1;
+            }
+            return counts.values().cloned().max().unwrap();
+        };
+        let plain = RadixModel::new(&md);
+        let mul = RadixModel::new_with_transform(&md, Multiplicative);
+        assert_eq!(mul.params, plain.params);
+        assert_eq!(most_per_output(&plain), 2_500);
+        assert!(most_per_output(&mul) < 50, "{} keys in one output", most_per_output(&mul));
+
+        let (_, bits) = mul.params;
+        for key in keys.iter().step_by(7) {
+            let pred = mul.predict_to_int(&(*key).into());
+            assert!(pred < 1 << bits);
+            assert_eq!(mul.reference_predict(&(*key).into()), pred);
+        }
+        assert!(!mul.supports_range_query());
+        assert!(mul.error_bound().is_some());
+
+        assert_eq!(mul.kind(), ModelKind::MultiplicativeRadix);
+        assert!(mul.code().contains("inline uint64_t radix_mul(") && mul.code().contains("ull)"));
+        let (kind, params) = mul.state().unwrap();
+        let rebuilt = model_from_state(kind, &params).unwrap();
+        assert_eq!(rebuilt.function_name(), "radix_mul");
+        for key in keys.iter().step_by(11) {
+            assert_eq!(rebuilt.predict_to_int(&(*key).into()), mul.predict_to_int(&(*key).into()));
+        }
+    }
+
+    #[test]
+    fn test_exact_error_bounds() {
+        // positions past 2^53, where an f64 only holds every fourth
+        // integer, and keys a few below their positions
+        let base = 3usize << 53;
+        let data: Vec<(u64, usize)> = (0..5_000usize)
+            .map(|i| ((base + 8 * i - i % 4) as u64, base + 8 * i))
+            .collect();
+        let md = RMITrainingData::new(Box::new(data.clone()));
+        let brute_force = |pred: &dyn Fn(u64) -> u64| data.iter()
+            .map(|(key, pos)| (pred(*key) as i128 - *pos as i128).unsigned_abs() as u64)
+            .max()
+            .unwrap();
+
+        // the model drops the keys' common prefix, so it is off by about
+        // the base, and by the keys' offsets below their positions on top
+        let radix = RadixModel::new(&md);
+        let bound = radix.error_bound().unwrap();
+        assert_eq!(bound, brute_force(&|key| radix.reference_predict(&key.into())));
+        assert_eq!(bound, base as u64 + 3);
+        assert_ne!(bound as f64 as u64, bound);
+
+        let table = RadixTable::new_with_overflow(&md, 10, OnOverflow::WidenAutomatically)
+            .unwrap();
+        // each key's distance from the first position of its bucket
+        let mut first = HashMap::new();
+        for (key, pos) in data.iter() {
+            first.entry(table.bucket_index(&(*key).into())).or_insert(*pos as u64);
+        }
+        let bound = table.error_bound().unwrap();
+        assert_eq!(bound, brute_force(&|key| first[&table.bucket_index(&key.into())]));
+    }
+
+    #[test]
+    fn test_radix_table_error_bound() {
+        let data: Vec<(u64, usize)> = (0..10_000u64)
+            .map(|i| (i * i * 1_000_003, i as usize))
+            .collect();
+        let md = RMITrainingData::new(Box::new(data.clone()));
+
+        let table = RadixTable::new(&md, 8);
+        let bound = table.error_bound().unwrap();
+        assert!(bound > 0);
+
+        for (key, pos) in data {
+            let pred = table.predict_to_int(&key.into());
+            let err = u64::max(pred, pos as u64) - u64::min(pred, pos as u64);
+            assert!(err <= bound, "key {} had error {} > bound {}", key, err, bound);
+        }
+    }
+
+    fn assert_params_eq(a: &[ModelParam], b: &[ModelParam]) {
+        assert_eq!(a.len(), b.len());
+        for (x, y) in a.iter().zip(b.iter()) {
+            assert!(x.is_same_type(y));
+            assert_eq!(x.c_val(), y.c_val());
+        }
+    }
+
+    #[test]
+    fn test_params_bytes() {
+        let data: Vec<(u64, usize)> = (1..3_000u64)
+            .map(|i| (i * 104_729, i as usize - 1))
+            .collect();
+        let md = RMITrainingData::new(Box::new(data));
+
+        let models: Vec<Box<dyn Model>> = vec![
+            Box::new(RadixModel::new(&md)),
+            Box::new(RadixTable::new(&md, 6)),
+        ];
+        for model in models {
+            let buf = model.params_bytes();
+            let layout = model.params_layout();
+            assert_eq!(buf.len(), model.params().iter().map(|p| p.size()).sum::<usize>());
+
+            let reread: Vec<ModelParam> = layout.iter().map(|f| f.read(&buf)).collect();
+            assert_params_eq(&reread, &model.params());
+        }
+
+        // a radix model is two packed u64s
+        let radix = RadixModel::new(&md);
+        let layout = radix.params_layout();
+        assert_eq!(layout[1], ParamField { kind: ParamKind::Int, offset: 8, len: 1 });
+    }
+
+    fn narrow(table: &RadixTable) -> &[u32] {
+        return match &table.hint_table {
+            HintTable::Narrow(t) => t,
+            HintTable::Wide(_) => panic!("expected 32-bit hints"),
+        };
+    }
+
+    #[test]
+    fn test_radix_table_alignment() {
+        for bits in [2, 5, 8, 12] {
+            let data: Vec<(u64, usize)> = (1..1000u64)
+                .map(|i| (i << 40, i as usize - 1))
+                .collect();
+            let table = RadixTable::new(&RMITrainingData::new(Box::new(data)), bits);
+            assert_eq!(narrow(&table).as_ptr() as usize % 64, 0);
+            assert_eq!(narrow(&table).len(), 1 << bits);
+
+            let rebuilt = RadixTable::from_state(&table.state().unwrap().1).unwrap();
+            assert_eq!(narrow(&rebuilt).as_ptr() as usize % 64, 0);
+            assert_eq!(narrow(&rebuilt), narrow(&table));
+        }
+    }
+
+    // The median and 99th percentile of the latency benchmarks' samples.
+    fn spread(samples: &mut [u128]) -> (u128, u128) {
+        samples.sort_unstable();
+        return (samples[samples.len() / 2], samples[samples.len() * 99 / 100]);
+    }
+
+    // Nanoseconds `lookup` takes over every key in `chunk`. The results are
+    // summed into a black box, so the lookups cannot be optimized away.
+    fn time_lookups(chunk: &[u64], lookup: impl Fn(u64) -> u64) -> u128 {
+        let start = std::time::Instant::now();
+        let mut acc = 0u64;
+        for k in chunk {
+            acc = acc.wrapping_add(lookup(*k));
+        }
+        std::hint::black_box(acc);
+        return start.elapsed().as_nanos();
+    }
+
+    // Compares lookup latency spread on the aligned table against the same
+    // hints stored 4 bytes off a cache line. Timing-dependent, so it only
+    // reports: run with `cargo test --release -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn bench_radix_table_alignment() {
+        let data: Vec<(u64, usize)> = (1..2_000_000u64)
+            .map(|i| (i.wrapping_mul(0x9E37_79B9_7F4A_7C15) >> 1, 0))
+            .collect();
+        let mut keys: Vec<u64> = data.iter().map(|(k, _)| *k).collect();
+        keys.sort_unstable();
+        let data: Vec<(u64, usize)> = keys.iter().cloned().zip(0..).collect();
+        let table = RadixTable::new(&RMITrainingData::new(Box::new(data)), 20);
+
+        let mut shifted = AlignedTable::zeroed(table.hint_table.len() + 1);
+        shifted[1..].copy_from_slice(narrow(&table));
+        let misaligned = &shifted[1..];
+
+        let time = |hints: &[u32], chunk: &[u64]| {
+            return time_lookups(chunk, |k| hints[table.bucket(&k.into())] as u64);
+        };
+
+        // alternate which table goes first, since the first pass over a
+        // chunk warms the cache for the second
+        let (mut aligned_ns, mut misaligned_ns) = (Vec::new(), Vec::new());
+        for (i, chunk) in keys.chunks(1024).enumerate() {
+            if i % 2 == 0 {
+                aligned_ns.push(time(narrow(&table), chunk));
+                misaligned_ns.push(time(misaligned, chunk));
+            } else {
+                misaligned_ns.push(time(misaligned, chunk));
+                aligned_ns.push(time(narrow(&table), chunk));
+            }
+        }
+
+        let (p50, p99) = spread(&mut aligned_ns);
+        println!("aligned:    p50 {} ns, p99 {} ns per 1024 lookups", p50, p99);
+        let (p50, p99) = spread(&mut misaligned_ns);
+        println!("misaligned: p50 {} ns, p99 {} ns per 1024 lookups", p50, p99);
+    }
+
+    // Compares lookup latency on a 28-bit table, a gigabyte of hints, on
+    // normal pages against the same table on huge pages. Keys are looked up
+    // in random order, so nearly every lookup lands on a different page.
+    // Run with `cargo test --release --features huge_pages -- --ignored
+    // --nocapture`.
+    #[cfg(feature = "huge_pages")]
+    #[test]
+    #[ignore]
+    fn bench_radix_table_huge_pages() {
+        let mut keys: Vec<u64> = (1..4_000_000u64)
+            .map(|i| i.wrapping_mul(0x9E37_79B9_7F4A_7C15) >> 1)
+            .collect();
+        let queries = keys.clone();
+        keys.sort_unstable();
+        let md = RMITrainingData::from_keys(keys);
+        let normal = RadixTable::new(&md, 28);
+        let huge = RadixTable::new(&md, 28).into_huge_pages();
+
+        let time = |table: &RadixTable, chunk: &[u64]| {
+            return time_lookups(chunk, |k| table.predict_to_int(&k.into()));
+        };
+
+        // alternate which table goes first, as in the alignment benchmark
+        let (mut normal_ns, mut huge_ns) = (Vec::new(), Vec::new());
+        for (i, chunk) in queries.chunks(1024).enumerate() {
+            if i % 2 == 0 {
+                normal_ns.push(time(&normal, chunk));
+                huge_ns.push(time(&huge, chunk));
+            } else {
+                huge_ns.push(time(&huge, chunk));
+                normal_ns.push(time(&normal, chunk));
+            }
+        }
+
+        let (p50, p99) = spread(&mut normal_ns);
+        println!("normal pages: p50 {} ns, p99 {} ns per 1024 lookups", p50, p99);
+        let (p50, p99) = spread(&mut huge_ns);
+        println!("huge pages:   p50 {} ns, p99 {} ns per 1024 lookups", p50, p99);
+    }
+
+    #[test]
+    fn test_radix_table_no_common_prefix() {
+        // keys on both sides of the top bit share no prefix
+        let data: Vec<(u64, usize)> = (0..1000u64)
+            .map(|i| (i * (u64::MAX / 1000), i as usize))
+            .collect();
+        let md = RMITrainingData::new(Box::new(data.clone()));
+        let table = RadixTable::new(&md, 10);
+        assert_eq!((table.prefix_bits, table.table_bits, table.shift), (0, 10, 54));
+        assert!(table.code().contains("table[inp >> 54]"));
+
+        for (key, pos) in data {
+            let (lo, hi) = table.predict_window(&key.into());
+            assert!(lo <= pos as u64 && (pos as u64) < hi);
+        }
+    }
+
+    #[test]
+    fn test_radix_table_long_common_prefix() {
+        // every key starts with the same 40 bits
+        let base = 0xDEAD_BEEF_AA00_0000u64;
+        let data: Vec<(u64, usize)> = (0..1000u64)
+            .map(|i| (base | (i * 16_000), i as usize))
+            .collect();
+        let md = RMITrainingData::new(Box::new(data.clone()));
+        assert_eq!(common_prefix_size(&md), 40);
+
+        // there are only 24 bits left, so a 30-bit table is cut down
+        let table = RadixTable::new(&md, 30);
+        assert_eq!((table.prefix_bits, table.table_bits, table.shift), (40, 24, 0));
+        assert!(table.code().contains("((inp << 40) >> 40) >> 0"));
+
+        let table = RadixTable::new(&md, 12);
+        assert_eq!((table.prefix_bits, table.table_bits, table.shift), (40, 12, 12));
+        for (key, pos) in data {
+            let (lo, hi) = table.predict_window(&key.into());
+            assert!(lo <= pos as u64 && (pos as u64) < hi);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "does not fit a 64-bit key")]
+    fn test_radix_shift_out_of_range() {
+        radix_shift(40, 30);
+    }
+
+    #[test]
+    fn test_fixed_radix() {
+        let data: Vec<(u64, usize)> = (0..5000u64)
+            .map(|i| ((i * 0x9E37_79B9) << 20, i as usize))
+            .collect();
+        let md = RMITrainingData::new(Box::new(data.clone()));
+        let radix = RadixModel::new(&md);
+        assert_eq!(radix.params.1, 12);
+
+        assert!(FixedRadixModel::<11>::from_dynamic(&radix).is_none());
+        let fixed = FixedRadixModel::<12>::from_dynamic(&radix).unwrap();
+        for (key, _) in data {
+            assert_eq!(fixed.predict_to_int(&key.into()), radix.predict_to_int(&key.into()));
+        }
+        assert_eq!(fixed.code(), radix.code());
+        assert_eq!(fixed.state().unwrap().1.len(), 2);
+    }
+
+    #[test]
+    fn test_radix_error_bound() {
+        // uniform keys at their own positions, scaled as for a top layer
+        let keys: Vec<u64> = (0..20_000u64).map(|i| i.wrapping_mul(0x9E37_79B9_7F4A_7C15)).collect();
+        let mut sorted = keys.clone();
+        sorted.sort_unstable();
+        let mut md = RMITrainingData::from_keys(sorted);
+        md.set_scale(1024.0 / md.len() as f64);
+
+        let radix = RadixModel::new(&md);
+        let bound = radix.error_bound().unwrap();
+        assert!(bound > 0);
+        assert!(radix.needs_bounds_check());
+        for (inp, y) in md.iter_model_input() {
+            let pred = radix.predict_to_int(&inp);
+            assert!(u64::max(pred, y as u64) - u64::min(pred, y as u64) <= bound);
+        }
+        let fixed = FixedRadixModel::<10>::from_dynamic(&radix).unwrap();
+        assert_eq!(fixed.error_bound(), Some(bound));
+
+        // keys whose top bits are exactly their positions
+        let exact = RMITrainingData::new(Box::new(
+            (0..256u64).map(|i| (i << 56, i as usize)).collect::<Vec<_>>()));
+        let radix = RadixModel::new(&exact);
+        assert_eq!(radix.error_bound(), Some(0));
+        assert!(!radix.needs_bounds_check());
+
+        // the state does not record the bound
+        let rebuilt = RadixModel::from_state(&radix.state().unwrap().1).unwrap();
+        assert_eq!(rebuilt.error_bound(), None);
+        assert!(rebuilt.needs_bounds_check());
+    }
+
+    // Compares the fixed-width radix model against the dynamic one on the
+    // same keys. Timing-dependent, so it only reports: run with
+    // `cargo test --release -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn bench_fixed_radix() {
+        use std::hint::black_box;
+        use std::time::Instant;
+
+        let data: Vec<(u64, usize)> = (0..1u64 << 16).map(|i| (i << 40, i as usize)).collect();
+        let radix = black_box(RadixModel::new(&RMITrainingData::new(Box::new(data))));
+        let fixed = FixedRadixModel::<16>::from_dynamic(&radix).unwrap();
+        let keys: Vec<u64> = (0..10_000_000u64).map(|i| i.wrapping_mul(0x9E37_79B9_7F4A_7C15))
+            .collect();
+
+        fn time(model: &impl Model, keys: &[u64]) -> u128 {
+            let start = Instant::now();
+            let mut acc = 0u64;
+            for k in keys {
+                acc = acc.wrapping_add(model.predict_to_int(&black_box(*k).into()));
+            }
+            black_box(acc);
+            return start.elapsed().as_nanos();
+        }
+
+        // warm both up, then time each twice
+        time(&radix, &keys);
+        time(&fixed, &keys);
+        let dynamic_ns = time(&radix, &keys) + time(&radix, &keys);
+        let fixed_ns = time(&fixed, &keys) + time(&fixed, &keys);
+        println!("dynamic: {:.2} ns per lookup", dynamic_ns as f64 / (2 * keys.len()) as f64);
+        println!("fixed:   {:.2} ns per lookup", fixed_ns as f64 / (2 * keys.len()) as f64);
+    }
+
+    #[test]
+    fn test_predict_batch_sorted() {
+        let keys: Vec<u64> = (0..5000u64).map(|i| i * i * 1_000_003).collect();
+        let md = RMITrainingData::from_keys(keys.clone());
+        let probes: Vec<u64> = keys.iter().flat_map(|k| [*k, k + 1]).collect();
+        for table in [RadixTable::new(&md, 10),
+                      RadixTable::new_with_hint_policy(&md, 10, HintPolicy::Midpoint),
+                      RadixTable::new_leaf(&md, 10)] {
+            let mut out = vec![0; probes.len()];
+            table.predict_batch_sorted(&probes, &mut out);
+            for (probe, pred) in probes.iter().zip(out.iter()) {
+                assert_eq!(*pred, table.predict_to_int(&(*probe).into()));
+            }
+
+            // unsorted keys still give the same answers
+            let reversed: Vec<u64> = probes.iter().rev().cloned().collect();
+            table.predict_batch_sorted(&reversed, &mut out);
+            assert!(out.iter().rev().zip(probes.iter())
+                    .all(|(pred, probe)| *pred == table.predict_to_int(&(*probe).into())));
+        }
+    }
+
+    #[test]
+    #[ignore]
+    fn bench_predict_batch_sorted() {
+        let mut keys: Vec<u64> = (0..1u64 << 20).map(|i| i.wrapping_mul(0x9E37_79B9_7F4A_7C15))
+            .collect();
+        keys.sort_unstable();
+        let table = RadixTable::new(&RMITrainingData::from_keys(keys.clone()), 20);
+        bench_batch_sorted(&table, 10_000_000, |batch, out| table.predict_batch_sorted(batch, out));
+    }
+
+    // positions just past what 32-bit hints can hold
+    fn past_u32() -> Vec<(u64, usize)> {
+        return (0..1000u64)
+            .map(|i| (i << 50, (1usize << 32) + 10 * i as usize))
+            .collect();
+    }
+
+    #[test]
+    fn test_radix_table_overflow_error() {
+        let md = RMITrainingData::new(Box::new(past_u32()));
+        let err = RadixTable::new_with_overflow(&md, 8, OnOverflow::Error).err().unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        assert!(matches!(err, Error::ParamOverflow { .. }));
+
+        // positions that fit are unaffected by the policy
+        let md = RMITrainingData::new(Box::new(vec![(1u64, 0), (2, u32::MAX as usize)]));
+        assert!(RadixTable::new_with_overflow(&md, 4, OnOverflow::Error).is_ok());
+    }
+
+    #[test]
+    #[should_panic(expected = "does not fit in 32 bits")]
+    fn test_radix_table_overflow_new_panics() {
+        RadixTable::new(&RMITrainingData::new(Box::new(past_u32())), 8);
+    }
+
+    #[test]
+    fn test_radix_table_overflow_clamp() {
+        let data = past_u32();
+        let md = RMITrainingData::new(Box::new(data.clone()));
+        let table = RadixTable::new_with_overflow(&md, 8, OnOverflow::Clamp).unwrap();
+        assert!(matches!(table.hint_table, HintTable::Narrow(_)));
+
+        let bound = table.error_bound().unwrap();
+        for (key, pos) in data {
+            assert_eq!(table.predict_to_int(&key.into()), u32::MAX as u64);
+            assert!(pos as u64 - u32::MAX as u64 <= bound);
+        }
+    }
+
+    #[test]
+    fn test_radix_table_overflow_widen() {
+        let data = past_u32();
+        let md = RMITrainingData::new(Box::new(data.clone()));
+        let table = RadixTable::new_with_overflow(&md, 8, OnOverflow::WidenAutomatically)
+            .unwrap();
+        assert!(matches!(table.hint_table, HintTable::Wide(_)));
+        assert!(matches!(table.params()[0], ModelParam::IntArray(_)));
+        assert_eq!(table.function_name(), "radix_table64");
+        assert!(table.code().contains("const uint64_t* table"));
+
+        let bound = table.error_bound().unwrap();
+        for (key, pos) in data.iter() {
+            let pred = table.predict_to_int(&(*key).into());
+            assert!(pred > u32::MAX as u64);
+            assert!(*pos as u64 - pred <= bound);
+        }
+
+        let rebuilt = RadixTable::from_state(&table.state().unwrap().1).unwrap();
+        assert!(matches!(rebuilt.hint_table, HintTable::Wide(_)));
+        assert_eq!(rebuilt.predict_to_int(&(999u64 << 50).into()),
+                   table.predict_to_int(&(999u64 << 50).into()));
+
+        // widening is only done when needed
+        let md = RMITrainingData::new(Box::new(vec![(1u64, 0), (2, 1)]));
+        let table = RadixTable::new_with_overflow(&md, 4, OnOverflow::WidenAutomatically)
+            .unwrap();
+        assert!(matches!(table.hint_table, HintTable::Narrow(_)));
+    }
+
+    #[test]
+    fn test_build_by_search() {
+        // repeated keys, gaps that leave buckets empty, positions that start
+        // above 0, scaled positions, and positions past u32
+        let keys: Vec<u64> = (0..20_000u64).map(|i| (1 << 40) + (i / 3) * (i / 3) * 17).collect();
+        let mut scaled = RMITrainingData::from_keys(keys.clone());
+        scaled.set_scale(0.01);
+        let mut offset: Vec<(u64, usize)> = keys.iter()
+            .map(|k| (*k, 1000 + (*k as usize % 7)))
+            .collect();
+        for idx in 1..offset.len() {
+            offset[idx].1 = usize::max(offset[idx].1, offset[idx - 1].1);
+        }
+        let data = [
+            RMITrainingData::from_keys(keys.clone()),
+            scaled,
+            RMITrainingData::new(Box::new(offset)),
+            RMITrainingData::new(Box::new(past_u32())),
+        ];
+        let queries: Vec<u64> = keys.iter()
+            .flat_map(|k| [k - 1, *k, k + 1])
+            .chain(past_u32().into_iter().map(|(k, _)| k))
+            .chain([0, u64::MAX])
+            .collect();
+
+        let settings = [(HintPolicy::FirstPosition, false), (HintPolicy::Midpoint, false),
+                        (HintPolicy::FirstPosition, true)];
+        for md in data.iter() {
+            for bits in [0, 4, 10] {
+                for (policy, leaf) in settings {
+                    for on_overflow in [OnOverflow::Clamp, OnOverflow::WidenAutomatically] {
+                        let build = |by_search| RadixTable::build_with(
+                            md, bits, on_overflow, policy, leaf, None, by_search).unwrap();
+                        let (scan, search) = (build(false), build(true));
+                        assert_eq!(format!("{:?}", search.state()), format!("{:?}", scan.state()));
+                        assert_eq!(search.empty, scan.empty);
+                        assert_eq!(search.error_bound(), scan.error_bound());
+                        assert_eq!(search.key_domain(), scan.key_domain());
+                        assert_eq!(search.max_bucket_keys(), None);
+                        assert!(scan.max_bucket_keys().is_some());
+                        for query in queries.iter() {
+                            let inp = (*query).into();
+                            assert_eq!(search.predict_to_int(&inp), scan.predict_to_int(&inp));
+                            assert_eq!(search.predict_window(&inp), scan.predict_window(&inp));
+                        }
+                    }
+                }
+            }
+        }
+
+        // float keys that truncate to the same integer are still distinct
+        let floats = RMITrainingData::from_keys((0..5_000).map(|i| i as f64 / 3.0));
+        for bits in [4, 8] {
+            let build = |by_search| RadixTable::build_with(
+                &floats, bits, OnOverflow::Error, HintPolicy::Midpoint, false, None,
+                by_search).unwrap();
+            let (scan, search) = (build(false), build(true));
+            assert_eq!(format!("{:?}", search.state()), format!("{:?}", scan.state()));
+            assert_eq!(search.error_bound(), scan.error_bound());
+        }
+
+        // searching is chosen with many keys per bucket
+        let md = RMITrainingData::from_keys(0..1_000_000u64);
+        assert!(search_is_faster(&md, 16));
+        assert!(!search_is_faster(&md, 1 << 12));
+        assert!(!search_is_faster(&RMITrainingData::<u64>::empty(), 1));
+    }
+
+    #[test]
+    fn test_radix_table_progress() {
+        let data: Vec<(u64, usize)> = (1..300_000u64)
+            .map(|i| (i * 7919, i as usize - 1))
+            .collect();
+        let md = RMITrainingData::new(Box::new(data));
+        let reports = RefCell::new(Vec::new());
+        let record = |f: f64| reports.borrow_mut().push(f);
+
+        RadixTable::new_with_progress(&md, 12, Some(&record));
+
+        let reports = reports.into_inner();
+        assert!(reports.len() > 2);
+        assert!(reports.windows(2).all(|w| w[0] < w[1]), "{:?}", reports);
+        assert_eq!(*reports.last().unwrap(), 1.0);
+    }
+
+    #[test]
+    fn test_radix_table_window() {
+        // keys spread unevenly, with gaps that leave many buckets empty
+        let data: Vec<(u64, usize)> = (1..5_000u64)
+            .map(|i| (i * i * i * 7919, i as usize - 1))
+            .collect();
+        let md = RMITrainingData::new(Box::new(data.clone()));
+        let table = RadixTable::new(&md, 10);
+
+        for (key, pos) in data.iter() {
+            let (lo, hi) = table.predict_window(&(*key).into());
+            assert!(lo <= *pos as u64 && (*pos as u64) < hi,
+                    "key {} at {} outside window [{}, {})", key, pos, lo, hi);
+        }
+
+        // keys that were not trained on still have their lower bound in the window
+        for w in data.windows(2) {
+            let probe = w[0].0 + 1;
+            if probe == w[1].0 { continue; }
+            let (lo, hi) = table.predict_window(&probe.into());
+            assert!(lo <= w[1].1 as u64 && w[1].1 as u64 <= hi);
+        }
+
+        let (_, hi) = table.predict_window(&u64::MAX.into());
+        assert_eq!(hi, data.len() as u64);
+    }
+
+    #[test]
+    fn test_radix_table_bucket_boundaries() {
+        // every third bucket holds exactly its smallest and largest key, plus
+        // one in between; the largest keys of some buckets are duplicated
+        let mut keys: Vec<u64> = Vec::new();
+        for bucket in (0..256u64).step_by(3) {
+            let first = bucket << 56;
+            let last = first | ((1 << 56) - 1);
+            keys.extend([first, first + 1000, last]);
+            if bucket % 2 == 0 {
+                keys.push(last);
+            }
+        }
+        let md = RMITrainingData::from_keys(keys.clone());
+        let table = RadixTable::new(&md, 8);
+        assert_eq!(table.prefix_bits, 0);
+
+        for (key, pos) in md.iter() {
+            let bucket = key >> 56;
+            let start = keys.iter().position(|k| k >> 56 == bucket).unwrap() as u64;
+            let end = keys.iter().rposition(|k| k >> 56 == bucket).unwrap() as u64 + 1;
+            let pos = pos as u64;
+            assert_eq!(keys[pos as usize], key);
+
+            assert_eq!(table.predict_window(&key.into()), (start, end),
+                       "key {:#x} in bucket {}", key, bucket);
+            assert_eq!(table.predict_to_int(&key.into()), start);
+            assert!(pos - start <= table.error_bound().unwrap());
+        }
+    }
+
+    #[test]
+    fn test_radix_table_hint_policy() {
+        // few, crowded buckets, so the distance within a bucket dominates
+        let data: Vec<(u64, usize)> = (0..20_000u64)
+            .map(|i| (i.wrapping_mul(0x9E37_79B9_7F4A_7C15), 0))
+            .collect();
+        let mut keys: Vec<u64> = data.iter().map(|(k, _)| *k).collect();
+        keys.sort_unstable();
+        let md = RMITrainingData::from_keys(keys.clone());
+
+        let first = RadixTable::new_with_hint_policy(&md, 6, HintPolicy::FirstPosition);
+        let mid = RadixTable::new_with_hint_policy(&md, 6, HintPolicy::Midpoint);
+        assert_eq!(narrow(&mid).len(), narrow(&first).len() + 1);
+
+        let worst = |table: &RadixTable| {
+            return md.iter()
+                .map(|(key, pos)| {
+                    let pred = table.predict_to_int(&key.into());
+                    u64::max(pred, pos as u64) - u64::min(pred, pos as u64)
+                })
+                .max()
+                .unwrap();
+        };
+        assert_eq!(worst(&first), first.error_bound().unwrap());
+        assert_eq!(worst(&mid), mid.error_bound().unwrap());
+        assert!(mid.error_bound().unwrap() <= first.error_bound().unwrap() / 2 + 1,
+                "midpoint bound {} vs first position bound {}",
+                mid.error_bound().unwrap(), first.error_bound().unwrap());
+
+        // the windows are still the buckets' spans
+        for key in keys.iter().step_by(97) {
+            assert_eq!(mid.predict_window(&(*key).into()), first.predict_window(&(*key).into()));
+        }
+
+        assert!(mid.function_name().ends_with("_mid"));
+        assert!(mid.code().contains("table[idx + 1]"));
+        assert_ne!(mid.function_name(), first.function_name());
+
+        let rebuilt = RadixTable::from_state(&mid.state().unwrap().1).unwrap();
+        assert_eq!(rebuilt.hint_policy, HintPolicy::Midpoint);
+        for key in keys.iter().step_by(97) {
+            assert_eq!(rebuilt.predict_to_int(&(*key).into()), mid.predict_to_int(&(*key).into()));
+        }
+    }
+
+    #[test]
+    fn test_radix_table_empty_buckets() {
+        // two clusters of keys with every bucket between them left empty
+        let keys: Vec<u64> = (0..100u64).map(|i| i << 48)
+            .chain((0..100u64).map(|i| (200 << 56) | (i << 48)))
+            .collect();
+        let data: Vec<(u64, usize)> = keys.iter().cloned().zip(0..).collect();
+        let md = RMITrainingData::new(Box::new(data.clone()));
+        let table = RadixTable::new(&md, 8);
+
+        for (key, pos) in data.iter() {
+            assert_eq!(table.empty_bucket_position(&(*key).into()), None);
+            let (lo, hi) = table.predict_window(&(*key).into());
+            assert!(lo <= *pos as u64 && (*pos as u64) < hi);
+        }
+
+        // every key between the clusters is inserted before the second one
+        for bucket in [1u64, 100, 199] {
+            let probe = (bucket << 56) | 12345;
+            assert_eq!(table.empty_bucket_position(&probe.into()), Some(100));
+            assert_eq!(table.predict_window(&probe.into()), (100, 100));
+        }
+
+        // and every key past the second cluster at the end
+        let probe = 255u64 << 56;
+        assert_eq!(table.empty_bucket_position(&probe.into()), Some(200));
+        assert_eq!(table.predict_window(&probe.into()), (200, 200));
+
+        let rebuilt = RadixTable::from_state(&table.state().unwrap().1).unwrap();
+        assert_eq!(rebuilt.empty, table.empty);
+    }
+
+    #[test]
+    fn test_key_domain() {
+        let keys: Vec<u64> = (1..1000u64).map(|i| (i << 40) + 7).collect();
+        let md = RMITrainingData::from_keys(keys.clone());
+        let domain = Some((keys[0], keys[keys.len() - 1]));
+        assert_eq!(md.key_domain(), domain);
+
+        let radix = RadixModel::new(&md);
+        assert_eq!(radix.key_domain(), domain);
+        assert_eq!(radix.params.1, 9);
+        assert_eq!(FixedRadixModel::<9>::from_dynamic(&radix).unwrap().key_domain(), domain);
+
+        let table = RadixTable::new(&md, 8);
+        assert_eq!(table.key_domain(), domain);
+
+        // the state does not record the domain
+        assert_eq!(RadixTable::from_state(&table.state().unwrap().1).unwrap().key_domain(), None);
+        assert_eq!(RadixModel::new(&RMITrainingData::<u64>::empty()).key_domain(), None);
+    }
+
+    #[test]
+    fn test_radix_table_leaf() {
+        // the keys of one bucket of a coarser table, at global positions
+        let data: Vec<(u64, usize)> = (0..300u64).map(|i| ((5 << 56) | (i << 20), 1000 + i as usize))
+            .collect();
+        let md = RMITrainingData::new(Box::new(data.clone()));
+        let leaf = RadixTable::new_leaf(&md, 8);
+        assert_eq!(leaf.table_bits, 8);
+        assert!(matches!(leaf.restriction(), ModelRestriction::MustBeBottom));
+        assert!(leaf.code().contains("const uint64_t prefix"));
+
+        for (key, pos) in data.iter() {
+            let pred = leaf.predict_to_int(&(*key).into());
+            assert!(u64::max(pred, *pos as u64) - u64::min(pred, *pos as u64)
+                    <= leaf.error_bound().unwrap());
+        }
+        // keys outside the bucket are clamped to its ends
+        assert_eq!(leaf.predict_to_int(&0u64.into()), 1000);
+        assert_eq!(leaf.predict_to_int(&u64::MAX.into()),
+                   leaf.predict_to_int(&data[299].0.into()));
+
+        // a leaf with a 64-bit common prefix still has all its bits
+        let equal = RadixTable::new_leaf(&RMITrainingData::from_keys(vec![42u64; 3]), 8);
+        assert_eq!((equal.prefix_bits, equal.table_bits), (56, 8));
+
+        let rebuilt = RadixTable::from_state(&leaf.state().unwrap().1).unwrap();
+        assert!(rebuilt.leaf);
+        assert_eq!(rebuilt.code(), leaf.code());
+        for probe in [0u64, data[17].0, data[17].0 + 1, u64::MAX] {
+            assert_eq!(rebuilt.predict_to_int(&probe.into()), leaf.predict_to_int(&probe.into()));
+        }
+
+        let mut empty = RadixTable::new_leaf(&RMITrainingData::<u64>::empty(), 8);
+        assert_eq!(empty.params().iter().map(|p| p.size()).sum::<usize>(),
+                   leaf.params().iter().map(|p| p.size()).sum::<usize>());
+        assert!(empty.set_to_constant_model(1300));
+        assert_eq!(empty.predict_to_int(&12345u64.into()), 1300);
+        assert!(!RadixTable::new(&md, 8).set_to_constant_model(1300));
+    }
+
+    #[test]
+    fn test_relative_radix_table() {
+        let keys: Vec<u64> = (0..100_000u64).map(|i| i * i).collect();
+        let md = RMITrainingData::from_keys(keys.clone());
+        let absolute = RadixTable::new(&md, 12);
+
+        for group_bits in [0, 4, 8, 12] {
+            let relative = RelativeRadixTable::new(&md, 12, group_bits);
+            assert_eq!(relative.error_bound(), absolute.error_bound());
+            // probe every bucket, empty or not
+            let last = keys[keys.len() - 1];
+            for key in (0..=last).step_by((last >> 14) as usize) {
+                assert_eq!(relative.predict_to_int(&key.into()),
+                           absolute.predict_to_int(&key.into()));
+            }
+        }
+
+        // one base per bucket leaves nothing to offset, while one base for
+        // the whole table needs offsets as large as the data
+        assert_eq!(RelativeRadixTable::new(&md, 12, 0).offset_bits(), 16);
+        assert_eq!(RelativeRadixTable::new(&md, 12, 12).offset_bits(), 32);
+        let params = RelativeRadixTable::new(&md, 12, 4).params();
+        assert!(matches!(&params[..], [ModelParam::IntArray(b), ModelParam::ShortArray(o)]
+                         if b.len() == 1 << 8 && o.len() == 1 << 12));
+    }
+
+    // every key in `lo..=hi` must lie in the window the model gives
+    fn check_range_bounds(model: &dyn Model, keys: &[u64], lo: u64, hi: u64) {
+        let (start, end) = model.predict_range_bounds(&lo.into(), &hi.into());
+        for (pos, key) in keys.iter().enumerate() {
+            if *key >= lo && *key <= hi {
+                assert!(start <= pos as u64 && (pos as u64) < end,
+                        "{} at {} is outside {}..{} for range {}..={}",
+                        key, pos, start, end, lo, hi);
+            }
+        }
+    }
+
+    #[test]
+    fn test_range_bounds() {
+        // a gap in the middle leaves many empty buckets
+        let keys: Vec<u64> = (0..5_000u64).map(|i| (1 << 40) + i * i)
+            .chain((0..5_000u64).map(|i| (1 << 41) + 7 * i))
+            .collect();
+        let md = RMITrainingData::from_keys(keys.clone());
+        let models: Vec<Box<dyn Model>> = vec![
+            Box::new(RadixModel::new(&md)),
+            Box::new(RadixTable::new(&md, 8)),
+            Box::new(RadixTable::new(&md, 16)),
+        ];
+
+        let last = keys[keys.len() - 1];
+        for model in models.iter() {
+            assert!(model.supports_range_query());
+            for (lo_idx, width) in [(0, 10), (17, 4_000), (4_990, 20), (2_500, 7_000)] {
+                check_range_bounds(model.as_ref(), &keys, keys[lo_idx],
+                                   keys[usize::min(lo_idx + width, keys.len() - 1)]);
+                check_range_bounds(model.as_ref(), &keys, keys[lo_idx] + 1,
+                                   keys[usize::min(lo_idx + width, keys.len() - 1)] - 1);
+            }
+            // ranges in the gap, and past either end of the domain
+            check_range_bounds(model.as_ref(), &keys, keys[4_999] + 1, keys[5_000] - 1);
+            check_range_bounds(model.as_ref(), &keys, 0, keys[3]);
+            check_range_bounds(model.as_ref(), &keys, keys[9_000], u64::MAX);
+            check_range_bounds(model.as_ref(), &keys, last / 2, last + (1 << 50));
+        }
+
+        let (kind, params) = RadixModel::new(&md).state().unwrap();
+        assert!(!model_from_state(kind, &params).unwrap().supports_range_query());
+    }
+
+    #[test]
+    fn test_bucket_index() {
+        let keys: Vec<u64> = (0..20_000u64).map(|i| (1 << 50) + i * i * 31).collect();
+        let md = RMITrainingData::from_keys(keys.clone());
+        let (first, last) = (keys[0], keys[keys.len() - 1]);
+
+        for table in [RadixTable::new(&md, 10), RadixTable::new_leaf(&md, 10),
+                      RadixTable::new_with_hint_policy(&md, 10, HintPolicy::Midpoint)] {
+            let probes = (first..=last).step_by(((last - first) / 50_000) as usize)
+                .chain(keys.iter().cloned());
+            let mut probes: Vec<u64> = probes.collect();
+            probes.sort_unstable();
+
+            let mut prev = 0;
+            for key in probes {
+                let idx = table.bucket_index(&key.into());
+                assert!(idx >= prev, "bucket of {} went back from {} to {}", key, prev, idx);
+                assert!(idx < table.hint_table.len());
+                assert_eq!(table.predict_to_int(&key.into()), table.bucket_prediction(idx));
+                prev = idx;
+            }
+            assert_eq!(table.bucket_index(&first.into()), 0);
+            assert!(table.bucket_index(&last.into()) >= 1 << 9);
+        }
+
+        // leaves clamp keys outside their domain to its ends
+        let leaf = RadixTable::new_leaf(&md, 10);
+        assert_eq!(leaf.bucket_index(&0.into()), leaf.bucket_index(&first.into()));
+        assert_eq!(leaf.bucket_index(&u64::MAX.into()), leaf.bucket_index(&last.into()));
+    }
+
+    #[test]
+    fn test_with_error_bound() {
+        let keys: Vec<u64> = (0..5_000u64).map(|i| i * i * 31).collect();
+        let md = RMITrainingData::from_keys(keys);
+        let table = RadixTable::new_leaf(&md, 8);
+        let (lo, hi): (ModelInput, ModelInput) = (1_000u64.into(), 2_000_000u64.into());
+        let window = table.predict_range_bounds(&lo, &hi);
+
+        let table = table.with_error_bound(1234);
+        assert_eq!(table.error_bound(), Some(1234));
+        assert_eq!(table.predict_range_bounds(&lo, &hi), window);
+        match table.state().unwrap().1[3] {
+            ModelParam::Int(err) => assert_eq!(err, 1234),
+            _ => panic!("radix table state has no error bound"),
+        }
+    }
+
+    #[test]
+    fn test_from_cdf() {
+        let keys: Vec<u64> = (0..200_000u64).map(|i| (1 << 40) + i * i / 3 + 11 * i).collect();
+        let md = RMITrainingData::from_keys(keys.clone());
+        let full = RadixTable::new(&md, 12);
+        let sampled = RadixTable::from_cdf(&md.sample_cdf(2_000), 12, keys.len());
+        let err = sampled.error_bound().unwrap();
+        assert!(err >= full.error_bound().unwrap());
+        assert_eq!(sampled.key_domain(), full.key_domain());
+
+        // the reported bound holds for every key, and the windows still
+        // hold every key and every lower bound
+        let mut close = 0;
+        for (pos, key) in keys.iter().enumerate() {
+            let pred = sampled.predict_to_int(&(*key).into());
+            assert!(u64::max(pred, pos as u64) - u64::min(pred, pos as u64) <= err);
+            let exact = full.predict_to_int(&(*key).into());
+            close += (u64::max(pred, exact) - u64::min(pred, exact) <= 100) as usize;
+
+            let (lo, hi) = sampled.predict_window(&(*key).into());
+            assert!(lo <= pos as u64 && (pos as u64) < hi);
+            let gap = keys.partition_point(|k| *k < key + 1) as u64;
+            let (lo, hi) = sampled.predict_window(&(key + 1).into());
+            assert!(lo <= gap && gap <= hi);
+        }
+        assert!(close > keys.len() * 9 / 10, "only {} predictions near the full table's", close);
+
+        // every key sampled gives the full table back
+        let exact = RadixTable::from_cdf(&md.sample_cdf(keys.len()), 12, keys.len());
+        for key in keys.iter().step_by(97) {
+            assert_eq!(exact.predict_to_int(&(*key).into()), full.predict_to_int(&(*key).into()));
+        }
+
+        // the slack survives a round trip through the state
+        let (kind, params) = sampled.state().unwrap();
+        let loaded = model_from_state(kind, &params).unwrap();
+        let loaded = loaded.as_any().downcast_ref::<RadixTable>().unwrap();
+        assert_eq!(loaded.predict_window(&keys[777].into()),
+                   sampled.predict_window(&keys[777].into()));
+        assert_eq!(loaded.error_bound(), Some(err));
+    }
+
+    #[test]
+    fn test_zero_bits() {
+        let keys: Vec<u64> = (0..1_000u64).map(|i| i * i * 7 + 3).collect();
+        let md = RMITrainingData::from_keys(keys.clone());
+        for table in [RadixTable::new(&md, 0), RadixTable::new_leaf(&md, 0)] {
+            assert_eq!(table.hint_table.len(), 1);
+            assert_eq!(table.error_bound(), Some(999));
+            for key in [0, keys[0], keys[500], keys[999], u64::MAX] {
+                assert_eq!(table.bucket_index(&key.into()), 0);
+                assert_eq!(table.predict_to_int(&key.into()), 0);
+                assert_eq!(table.reference_predict(&key.into()), 0);
+            }
+            assert_eq!(table.predict_window(&keys[500].into()), (0, 1_000));
+            assert_eq!(verify(&table, &md), Ok(()));
+
+            let (kind, params) = table.state().unwrap();
+            let loaded = model_from_state(kind, &params).unwrap();
+            assert_eq!(loaded.predict_to_int(&keys[500].into()), 0);
+        }
+        let relative = RelativeRadixTable::new(&md, 0, 4);
+        assert_eq!(relative.predict_to_int(&keys[500].into()), 0);
+        assert_eq!(RadixTable::from_cdf(&md.sample_cdf(10), 0, 1_000).predict_window(&5.into()),
+                   (0, 1_000));
+
+        // a stored zero-bit layout must be the one `table_layout` gives
+        let (kind, mut params) = RadixTable::new(&md, 0).state().unwrap();
+        params[0] = 5u64.into();
+        assert!(model_from_state(kind, &params).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "larger than the 31 supported")]
+    fn test_too_many_bits() {
+        RadixTable::new(&RMITrainingData::from_keys(vec![1u64, 2, 3]), 40);
+    }
+
+    #[test]
+    fn test_gap_fill() {
+        // keys at both ends of the key space leave every bucket but the
+        // first and last empty, and the first key may be in the last bucket
+        let ends = RMITrainingData::from_keys(vec![0u64, 1, u64::MAX - 1, u64::MAX]);
+        let top = RMITrainingData::from_keys(vec![u64::MAX]);
+        for policy in [HintPolicy::FirstPosition, HintPolicy::Midpoint] {
+            let table = RadixTable::new_with_hint_policy(&ends, 24, policy);
+            assert_eq!(table.bucket_index(&u64::MAX.into()), (1 << 24) - 1);
+            for key in [1 << 41, 1 << 63, u64::MAX - (1 << 41)] {
+                assert_eq!(table.predict_window(&key.into()), (2, 2));
+            }
+            assert_eq!(table.predict_window(&0.into()), (0, 2));
+            assert_eq!(table.predict_window(&u64::MAX.into()), (2, 4));
+            assert_eq!(verify(&table, &ends), Ok(()));
+
+            let table = RadixTable::new_with_hint_policy(&top, 24, policy);
+            assert_eq!(table.predict_window(&0.into()), (0, 0));
+            assert_eq!(table.predict_window(&u64::MAX.into()), (0, 1));
+        }
+    }
+
+    #[test]
+    fn test_max_bucket_keys() {
+        // three keys in the first of 16 buckets, one in the second, and five
+        // (one of them twice) in the last
+        let mut keys = vec![0, 1, 2, 1 << 60];
+        keys.extend([5, 4, 3, 3, 2, 1].iter().map(|i| u64::MAX - i));
+        let md = RMITrainingData::from_keys(keys.clone());
+        assert_eq!(RadixTable::new(&md, 4).max_bucket_keys(), Some(5));
+        assert_eq!(RadixTable::new_leaf(&md, 4).max_bucket_keys(), Some(5));
+        assert_eq!(RadixTable::new(&md, 0).max_bucket_keys(), Some(9));
+        let approximate = RadixTable::from_cdf(&md.sample_cdf(4), 4, md.len());
+        assert_eq!(approximate.max_bucket_keys(), None);
+
+        // seven keys added to the second bucket
+        let mut new = keys.clone();
+        new.splice(4..4, (1..=6).map(|i| (1 << 60) + i));
+        let mut table = RadixTable::new(&md, 4);
+        table.retrain(&RMITrainingData::from_keys(new.clone()), 4..10).unwrap();
+        assert_eq!(table.max_bucket_keys(), Some(7));
+
+        // a count that is unknown stays unknown
+        let mut coarse = RadixTable::new(&md, 4).coarsen(2);
+        coarse.retrain(&RMITrainingData::from_keys(new), 4..10).unwrap();
+        assert_eq!(coarse.max_bucket_keys(), None);
+    }
+
+    // `coarse`, coarsened from a finer table, predicts as `fresh` does,
+    // and its windows hold the fresh table's
+    fn check_coarsened(coarse: &RadixTable, fresh: &RadixTable, keys: &[u64]) {
+        assert_eq!((coarse.prefix_bits, coarse.table_bits), (fresh.prefix_bits, fresh.table_bits));
+        let hints = |t: &RadixTable| (0..t.hint_table.len()).map(|i| t.hint_table.hint(i))
+            .collect::<Vec<u64>>();
+        assert_eq!(hints(coarse), hints(fresh));
+        for key in keys.iter().flat_map(|k| [k.wrapping_sub(1), *k, k.wrapping_add(1)])
+            .chain([0, u64::MAX]) {
+            assert_eq!(coarse.predict_to_int(&key.into()), fresh.predict_to_int(&key.into()));
+            let (lo, hi) = coarse.predict_window(&key.into());
+            let (fresh_lo, fresh_hi) = fresh.predict_window(&key.into());
+            assert!(lo <= fresh_lo && hi >= fresh_hi);
+            if fresh.window_slack == 0 {
+                assert_eq!((lo, hi), (fresh_lo, fresh_hi));
+            }
+        }
+    }
+
+    #[test]
+    fn test_coarsen() {
+        let spread: Vec<u64> = (0..5_000u64).map(|i| (1 << 40) + i * i * 31).collect();
+        // a common prefix that leaves give up with 12 bits, but not with 4
+        let mut narrow: Vec<u64> = (0..3_000u64)
+            .map(|i| (7 << 40) + i * 1_000_003 % (1 << 10))
+            .collect();
+        narrow.sort_unstable();
+        narrow.dedup();
+        let builds: [fn(&RMITrainingData<u64>, u8) -> RadixTable; 3] = [
+            |md, bits| RadixTable::new(md, bits),
+            |md, bits| RadixTable::new_with_hint_policy(md, bits, HintPolicy::Midpoint),
+            |md, bits| RadixTable::new_leaf(md, bits),
+        ];
+        for keys in [&spread, &narrow] {
+            let md = RMITrainingData::from_keys(keys.clone());
+            for build in builds.iter() {
+                let table = build(&md, 12);
+                for bits in 0..=table.table_bits {
+                    let fresh = build(&md, bits);
+                    let coarse = table.coarsen(bits);
+                    check_coarsened(&coarse, &fresh, keys);
+                    assert_eq!(coarse.error_bound(), fresh.error_bound());
+                    assert_eq!(verify(&coarse, &md), Ok(()));
+                }
+            }
+
+            let sample = md.sample_cdf(64);
+            let table = RadixTable::from_cdf(&sample, 12, md.len());
+            for bits in 0..=table.table_bits {
+                let coarse = table.coarsen(bits);
+                check_coarsened(&coarse, &RadixTable::from_cdf(&sample, bits, md.len()), keys);
+                assert_eq!(verify(&coarse, &md), Ok(()));
+            }
+        }
+
+        // copies of a bucket's last key make the bound looser, not wrong
+        let mut keys = spread.clone();
+        keys.extend([keys[4_999]; 10]);
+        let md = RMITrainingData::from_keys(keys.clone());
+        let coarse = RadixTable::new(&md, 12).coarsen(6);
+        let fresh = RadixTable::new(&md, 6);
+        check_coarsened(&coarse, &fresh, &keys);
+        assert!(coarse.error_bound() >= fresh.error_bound());
+        assert_eq!(verify(&coarse, &md), Ok(()));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_coarsen_more_bits() {
+        let md = RMITrainingData::from_keys(0..100u64);
+        RadixTable::new(&md, 4).coarsen(5);
+    }
+
+    // `table` retrained from `old` to `new` predicts as if built on `new`
+    fn check_retrain(mut table: RadixTable, new: &[u64], changed: Range<usize>,
+                     build: fn(&RMITrainingData<u64>) -> RadixTable) {
+        let md = RMITrainingData::from_keys(new.to_vec());
+        table.retrain(&md, changed).unwrap();
+        let rebuilt = build(&md);
+        for key in new.iter().flat_map(|k| [k - 1, *k, k + 1]).chain([0, u64::MAX]) {
+            assert_eq!(table.predict_to_int(&key.into()), rebuilt.predict_to_int(&key.into()));
+            assert_eq!(table.predict_window(&key.into()), rebuilt.predict_window(&key.into()));
+        }
+        assert!(table.error_bound() >= rebuilt.error_bound());
+        assert_eq!(verify(&table, &md), Ok(()));
+    }
+
+    #[test]
+    fn test_retrain() {
+        // keys spread over [2^40, 2^41), which keeps their common prefix
+        let mut keys: Vec<u64> = (0..5_000u64)
+            .map(|i| (1 << 40) | (i.wrapping_mul(0x9E37_79B9_7F4A_7C15) >> 24))
+            .collect();
+        keys.sort_unstable();
+        keys.dedup();
+        let n = keys.len();
+        let builds: [fn(&RMITrainingData<u64>) -> RadixTable; 3] = [
+            |md| RadixTable::new(md, 8),
+            |md| RadixTable::new_with_hint_policy(md, 8, HintPolicy::Midpoint),
+            |md| RadixTable::new_leaf(md, 8),
+        ];
+        for build in builds.iter() {
+            let train = |keys: &[u64]| build(&RMITrainingData::from_keys(keys.to_vec()));
+
+            // an append, which for leaves also moves the domain
+            check_retrain(train(&keys[..n * 4 / 5]), &keys, n * 4 / 5..n, *build);
+
+            // an insert, which moves every later key
+            let mut old = keys.clone();
+            old.drain(2_000..2_300);
+            check_retrain(train(&old), &keys, 2_000..n, *build);
+
+            // keys changed in place
+            let mut old = keys.clone();
+            for (j, idx) in (2_000..2_010).enumerate() {
+                old[idx] = keys[1_999] + 1 + j as u64;
+            }
+            check_retrain(train(&old), &keys, 2_000..2_010, *build);
+
+            // the tail removed
+            check_retrain(train(&keys), &keys[..n - 100], n - 100..n - 100, *build);
+        }
+
+        // keys outside the old prefix, which change the layout
+        let mut old = keys.clone();
+        old.retain(|k| *k < (1 << 40) + (1 << 36));
+        let table = RadixTable::new(&RMITrainingData::from_keys(old.clone()), 8);
+        check_retrain(table, &keys, old.len()..n, |md| RadixTable::new(md, 8));
+    }
+
+    #[test]
+    fn test_diff() {
+        // keys with and without the top bit, so an append keeps the layout
+        let keys: Vec<u64> = (0..4_000u64).map(|i| i * (u64::MAX / 10_000))
+            .chain([1 << 63])
+            .collect();
+        let n = keys.len() as u64;
+        let mut appended = keys.clone();
+        appended.push(u64::MAX / 4 * 3);
+        let md = RMITrainingData::from_keys(appended.clone());
+
+        for policy in [HintPolicy::FirstPosition, HintPolicy::Midpoint] {
+            let old = RadixTable::new_with_hint_policy(
+                &RMITrainingData::from_keys(keys.clone()), 10, policy);
+            assert!(old.diff(&old).is_empty());
+
+            let mut new = RadixTable::new_with_hint_policy(
+                &RMITrainingData::from_keys(keys.clone()), 10, policy);
+            new.retrain(&md, keys.len()..appended.len()).unwrap();
+            assert!(new.diff(&RadixTable::new_with_hint_policy(&md, 10, policy)).is_empty());
+
+            // the buckets after the new key's now end one position later;
+            // its own bucket, like the empty ones before it, still starts
+            // at the old end of the data
+            let first = old.bucket_index(&appended[keys.len()].into()) + 1;
+            let expected: Vec<(usize, u64, u64)> = (first..old.hint_table.len())
+                .map(|idx| (idx, n, n + 1))
+                .collect();
+            assert!(!expected.is_empty());
+            assert_eq!(old.diff(&new), expected);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot diff")]
+    fn test_diff_layouts() {
+        let md = RMITrainingData::from_keys((0..1_000u64).map(|i| i << 50));
+        RadixTable::new(&md, 8).diff(&RadixTable::new(&md, 9));
+    }
+
+    #[test]
+    fn test_estimated_flops() {
+        let keys: Vec<u64> = (1..5_000u64).map(|i| i * i * 31).collect();
+        let md = RMITrainingData::from_keys(keys.clone());
+        let key = ModelInput::from(keys[1_000]);
+        assert_eq!(RadixModel::new(&md).estimated_flops(&key), 2);
+        assert_eq!(RadixTable::new(&md, 10).estimated_flops(&key), 3);
+        assert_eq!(RadixTable::new_leaf(&md, 10).estimated_flops(&key), 5);
+        assert_eq!(RadixTable::new_with_hint_policy(&md, 10, HintPolicy::Midpoint)
+                   .estimated_flops(&key), 6);
+
+        // a lookup costs what each model on its path does; linear leaves
+        // have two parameters, and so cost four by default
+        let rmi = crate::train(&md, "radix,linear", 64);
+        assert_eq!(rmi.estimated_flops(&key), 2 + 4);
+    }
+
+    #[cfg(feature = "huge_pages")]
+    #[test]
+    fn test_huge_pages() {
+        let keys: Vec<u64> = (1..20_000u64).map(|i| (1 << 40) + i * i * 31).collect();
+        let md = RMITrainingData::from_keys(keys.clone());
+        let wide = RMITrainingData::new(Box::new(past_u32()));
+        let tables = || [
+            RadixTable::new(&md, 12),
+            RadixTable::new_with_hint_policy(&md, 10, HintPolicy::Midpoint),
+            RadixTable::new_leaf(&md, 8),
+            RadixTable::new_with_overflow(&wide, 8, OnOverflow::WidenAutomatically).unwrap(),
+        ];
+        let queries: Vec<u64> = keys.iter()
+            .flat_map(|k| [k - 1, *k, k + 1])
+            .chain(past_u32().into_iter().map(|(k, _)| k))
+            .chain([0, u64::MAX])
+            .collect();
+
+        for (table, huge) in tables().iter().zip(tables()) {
+            let huge = huge.into_huge_pages();
+            let (ptr, lines) = match &huge.hint_table {
+                HintTable::Narrow(t) => (t.as_ptr() as usize, &t.lines),
+                HintTable::Wide(t) => (t.as_ptr() as usize, &t.lines),
+            };
+            assert!(matches!(lines, Lines::Mapped { .. }));
+            assert_eq!(ptr % HUGE_PAGE_BYTES, 0);
+            assert_eq!(format!("{:?}", huge.state()), format!("{:?}", table.state()));
+            for query in queries.iter() {
+                let inp = (*query).into();
+                assert_eq!(huge.predict_to_int(&inp), table.predict_to_int(&inp));
+                assert_eq!(huge.predict_window(&inp), table.predict_window(&inp));
+            }
+        }
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn test_flat_round_trip() {
+        let keys: Vec<u64> = (1..20_000u64).map(|i| (1 << 40) + i * i * 31).collect();
+        let md = RMITrainingData::from_keys(keys.clone());
+        let wide = RMITrainingData::new(Box::new(past_u32()));
+        let tables = [
+            RadixTable::new(&md, 12),
+            RadixTable::new_with_hint_policy(&md, 10, HintPolicy::Midpoint),
+            RadixTable::new_leaf(&md, 8),
+            RadixTable::from_cdf(&md.sample_cdf(64), 12, md.len()),
+            RadixTable::new_with_overflow(&wide, 8, OnOverflow::WidenAutomatically).unwrap(),
+        ];
+        let queries: Vec<u64> = keys.iter()
+            .flat_map(|k| [k - 1, *k, k + 1])
+            .chain(past_u32().into_iter().map(|(k, _)| k))
+            .chain([0, u64::MAX])
+            .collect();
+
+        let path = std::env::temp_dir()
+            .join(format!("rmi_flat_test_{}.bin", std::process::id()));
+        for table in tables.iter() {
+            table.write_flat(&path).unwrap();
+            let mapped = RadixTable::open_flat(&path).unwrap();
+            let lines = match &mapped.hint_table {
+                HintTable::Narrow(t) => &t.lines,
+                HintTable::Wide(t) => &t.lines,
+            };
+            assert!(matches!(lines, Lines::Mapped { .. }));
+            assert_eq!(format!("{:?}", mapped.state()), format!("{:?}", table.state()));
+            assert_eq!(mapped.error_bound(), table.error_bound());
+            assert_eq!(mapped.key_domain(), table.key_domain());
+            for query in queries.iter() {
+                let inp = (*query).into();
+                assert_eq!(mapped.predict_to_int(&inp), table.predict_to_int(&inp));
+                assert_eq!(mapped.predict_window(&inp), table.predict_window(&inp));
+                assert_eq!(mapped.empty_bucket_position(&inp), table.empty_bucket_position(&inp));
+            }
+        }
+
+        // headers that do not describe their hints are refused
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &bytes[..bytes.len() - 1]).unwrap();
+        assert_eq!(RadixTable::open_flat(&path).err().unwrap().kind(), io::ErrorKind::InvalidData);
+        let mut bad = bytes.clone();
+        bad[10] = 2;
+        std::fs::write(&path, &bad).unwrap();
+        assert_eq!(RadixTable::open_flat(&path).err().unwrap().kind(), io::ErrorKind::InvalidData);
+        std::fs::write(&path, b"RMIB").unwrap();
+        assert_eq!(RadixTable::open_flat(&path).err().unwrap().kind(), io::ErrorKind::InvalidData);
+        assert!(matches!(RadixTable::open_flat(&path), Err(Error::Corrupt { .. })));
+        std::fs::remove_file(&path).unwrap();
+    }
 }
@@ -7,6 +7,7 @@
  
 
 #[derive(Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
 pub enum StdFunctions {
     EXP1,
     PHI,
@@ -10,17 +10,42 @@ use superslice::*;
 use log::*;
 
 
+/// The largest `n` (at most 64) such that `2^n - 1 <= largest_target`.
+/// Panics if that is zero, i.e. if `largest_target` is zero.
 pub fn num_bits(largest_target: u64) -> u8 {
-  let mut nbits = 0;
-  while (1 << (nbits+1)) - 1 <= largest_target {
+  // compare in 128 bits so that u64::MAX gives 64 rather than overflowing
+  let mut nbits: u8 = 0;
+  while (1u128 << (nbits+1)) - 1 <= largest_target as u128 {
     nbits += 1;
   }
   assert!(nbits >= 1);
+  assert!(nbits <= 64);
   
   return nbits;
 }
 
+/// Fits a radix layout, which skips the top `prefix` bits of a key and
+/// then reads the next `bits` bits, into a 64-bit key so that
+/// `prefix + bits <= 64` and every shift it implies is in range. When the
+/// two do not fit, `bits` is reduced first (down to a single bit), and
+/// only then `prefix`.
+pub fn fit_radix(prefix: u8, bits: u8) -> (u8, u8) {
+  let bits = u8::max(1, u8::min(bits, 64));
+  let bits = u8::min(bits, 64 - u8::min(prefix, 63));
+  let prefix = u8::min(prefix, 64 - bits);
+  assert!(prefix < 64 && bits >= 1 && prefix + bits <= 64);
+  
+  return (prefix, bits);
+}
+
+/// The number of leading bits shared by every key, between 0 and 64.
+/// Equal keys share all 64; empty data has no common prefix and gives 0.
+/// Callers must pass the result through `fit_radix` before shifting by it.
 pub fn common_prefix_size<T: TrainingKey>(data: &RMITrainingData<T>) -> u8 {
+  if data.len() == 0 {
+    return 0;
+  }
+  
   let mut any_ones: u64 = 0;
   let mut no_ones: u64 = !0;
 
@@ -32,7 +57,9 @@ pub fn common_prefix_size<T: TrainingKey>(data: &RMITrainingData<T>) -> u8 {
   let any_zeros = !no_ones;
 
   let prefix_bits = any_zeros ^ any_ones;
-  return (!prefix_bits).leading_zeros() as u8;        
+  let prefix = (!prefix_bits).leading_zeros() as u8;
+  assert!(prefix <= 64);
+  return prefix;
 }
 
 fn common_prefix_size2(data: &[u64]) -> u8 {
@@ -51,6 +78,23 @@ fn common_prefix_size2(data: &[u64]) -> u8 {
 }
 
 
+/// Converts a predicted position to an index into `n` keys: rounds down,
+/// then clamps to `[0, n]` so that negative and huge predictions land on
+/// the ends. NaN gives 0. Pass `u64::MAX` when the number of keys is not
+/// known.
+pub fn f64_to_pos(x: f64, n: u64) -> u64 {
+  if x.is_nan() || x <= 0.0 {
+    return 0;
+  }
+  // casting saturates at u64::MAX, so huge values only need the clamp
+  return u64::min(x.floor() as u64, n);
+}
+
+/// Converts a position in the training data to the float the models fit.
+/// Exact for positions below 2^53.
+pub fn pos_to_f64(p: usize) -> f64 {
+  return p as f64;
+}
 
 pub fn radix_index(points: &[u64], num_bits: u8) -> Vec<u64> {
   // build the radix index
@@ -109,19 +153,127 @@ mod tests {
   
   #[test]
   fn test_common_prefix1() {
-    let data = ModelData::IntKeyToIntPos(vec![
+    let data = RMITrainingData::<u64>::new(Box::new(vec![
       (1, 0), (4, 4), (8, 8)
-    ]);
+    ]));
 
     assert_eq!(common_prefix_size(&data), 64-4);
   }
 
   #[test]
   fn test_common_prefix2() {
-    let data = ModelData::IntKeyToIntPos(vec![
+    let data = RMITrainingData::<u64>::new(Box::new(vec![
       (1, 0), (8, 1), (9, 4), (12, 8)
-    ]);
+    ]));
 
     assert_eq!(common_prefix_size(&data), 64-4);
   }
+
+  #[test]
+  fn test_common_prefix_equal_keys() {
+    let data = RMITrainingData::<u64>::new(Box::new(vec![
+      (42, 0), (42, 1), (42, 2)
+    ]));
+
+    assert_eq!(common_prefix_size(&data), 64);
+    assert_eq!(fit_radix(64, 8), (63, 1));
+  }
+
+  #[test]
+  fn test_common_prefix_single_bit_keys() {
+    // keys differing from a shared base in exactly one of the low 8 bits
+    let base = 0xABCD_0000_0000_0000;
+    let data = RMITrainingData::<u64>::new(Box::new(
+      (0..8).map(|i| (base | (1 << i), i)).collect::<Vec<(u64, usize)>>()
+    ));
+    assert_eq!(common_prefix_size(&data), 64-8);
+    assert_eq!(fit_radix(64-8, 10), (64-8, 8));
+
+    // ... and in the top bit, leaving nothing in common
+    let data = RMITrainingData::<u64>::new(Box::new(vec![
+      (1, 0), (1 | (1 << 63), 1)
+    ]));
+    assert_eq!(common_prefix_size(&data), 0);
+  }
+
+  #[test]
+  fn test_common_prefix_empty() {
+    assert_eq!(common_prefix_size(&RMITrainingData::<u64>::empty()), 0);
+  }
+
+  #[test]
+  fn test_num_bits() {
+    assert_eq!(num_bits(1), 1);
+    assert_eq!(num_bits(2), 1);
+    assert_eq!(num_bits(3), 2);
+    assert_eq!(num_bits(1 << 40), 40);
+    assert_eq!(num_bits(u64::MAX - 1), 63);
+    assert_eq!(num_bits(u64::MAX), 64);
+  }
+
+  #[test]
+  #[should_panic]
+  fn test_num_bits_zero() {
+    num_bits(0);
+  }
+
+  // the layout `RadixModel` picks: the common prefix, and enough bits for
+  // the largest position (at least 1, so that `num_bits` never sees 0)
+  fn radix_layout(keys: &[u64]) -> (u8, u8) {
+    let data = RMITrainingData::from_keys(keys.to_vec());
+    let largest = u64::max(data.iter().map(|(_, y)| y as u64).max().unwrap(), 1);
+    return fit_radix(common_prefix_size(&data), num_bits(largest));
+  }
+
+  #[test]
+  fn test_degenerate_radix_layouts() {
+    // identical keys share all 64 bits, which leaves the last bit to read
+    assert_eq!(radix_layout(&[42, 42, 42]), (63, 1));
+    assert_eq!(radix_layout(&[u64::MAX; 4]), (63, 1));
+    assert_eq!(radix_layout(&[7]), (63, 1));
+
+    // two keys share the bits above their highest differing bit
+    assert_eq!(radix_layout(&[1000, 5000]), (51, 1));
+    assert_eq!(radix_layout(&[0, u64::MAX]), (0, 1));
+
+    // keys differing only in the lowest bit share the other 63
+    assert_eq!(radix_layout(&[42, 43]), (63, 1));
+    assert_eq!(radix_layout(&[42, 42, 43, 43]), (63, 1));
+  }
+
+  #[test]
+  fn test_f64_to_pos() {
+    assert_eq!(f64_to_pos(-1.5, 100), 0);
+    assert_eq!(f64_to_pos(-0.0, 100), 0);
+    assert_eq!(f64_to_pos(f64::NEG_INFINITY, 100), 0);
+    assert_eq!(f64_to_pos(f64::NAN, 100), 0);
+
+    assert_eq!(f64_to_pos(0.0, 100), 0);
+    assert_eq!(f64_to_pos(41.99, 100), 41);
+    assert_eq!(f64_to_pos(100.0, 100), 100);
+
+    assert_eq!(f64_to_pos(100.5, 100), 100);
+    assert_eq!(f64_to_pos(1e300, 100), 100);
+    assert_eq!(f64_to_pos(f64::INFINITY, 100), 100);
+    assert_eq!(f64_to_pos(1e300, u64::MAX), u64::MAX);
+
+    assert_eq!(pos_to_f64(0), 0.0);
+    assert_eq!(pos_to_f64(1 << 40), (1u64 << 40) as f64);
+    assert_eq!(f64_to_pos(pos_to_f64(123_456), u64::MAX), 123_456);
+  }
+
+  #[test]
+  fn test_fit_radix() {
+    for prefix in 0..=64 {
+      for bits in 0..=64 {
+        let (p, b) = fit_radix(prefix, bits);
+        assert!(p + b <= 64);
+        assert!(b >= 1 && p < 64);
+        // bits give way before the prefix does
+        if prefix < 64 && bits >= 1 {
+          assert_eq!(p, prefix);
+        }
+      }
+    }
+  }
 }
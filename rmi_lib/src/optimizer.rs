@@ -56,11 +56,27 @@ fn get_branching_factors() -> Vec<u64> {
     return range.map(|i| (2 as u64).pow(i)).collect();
 }
 
-fn pareto_front(results: &[RMIStatistics]) -> Vec<RMIStatistics> {
+// the most keys whose lookups are counted to estimate an RMI's flops
+const FLOPS_SAMPLE: usize = 1_000;
+
+/// What the optimizer trades accuracy off against.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Objective {
+    /// The in-memory size of the RMI.
+    #[default]
+    Size,
+
+    /// The operations of an average lookup, as estimated by
+    /// `Model::estimated_flops`, for devices where compute costs more
+    /// than memory.
+    Flops,
+}
+
+fn pareto_front(results: &[RMIStatistics], objective: Objective) -> Vec<RMIStatistics> {
     let mut on_front: Vec<RMIStatistics> = Vec::new();
 
     for result in results.iter() {
-        if results.iter().any(|v| result.dominated_by(v)) {
+        if results.iter().any(|v| result.dominated_by(v, objective)) {
             // not on the front
             continue;
         }
@@ -71,7 +87,8 @@ fn pareto_front(results: &[RMIStatistics]) -> Vec<RMIStatistics> {
     return on_front;
 }
 
-fn narrow_front(results: &[RMIStatistics], desired_size: usize) -> Vec<RMIStatistics> {
+fn narrow_front(results: &[RMIStatistics], desired_size: usize,
+                objective: Objective) -> Vec<RMIStatistics> {
     assert!(desired_size >= 2);
     if results.len() <= desired_size {
         return results.to_vec();
@@ -79,16 +96,16 @@ fn narrow_front(results: &[RMIStatistics], desired_size: usize) -> Vec<RMIStatis
 
     let mut tmp = results.to_vec();
     tmp.sort_by(
-        |a, b| a.size.partial_cmp(&b.size).unwrap()
+        |a, b| a.cost(objective).partial_cmp(&b.cost(objective)).unwrap()
     );
 
     let best_mod = tmp.remove(0);
     while tmp.len() > desired_size - 1 {
-        // find the two items closest in size and remove less accuracte one.
+        // find the two items closest in cost and remove less accuracte one.
         let smallest_gap =
             (0..tmp.len()-1).zip(1..tmp.len())
             .map(|(idx1, idx2)| (idx1, idx2,
-                                 (tmp[idx2].size as f64) / (tmp[idx1].size as f64)))
+                                 tmp[idx2].cost(objective) / tmp[idx1].cost(objective)))
             .min_by(|(_, _, v1), (_, _, v2)| v1.partial_cmp(v2).unwrap()).unwrap();
 
         let err1 = tmp[smallest_gap.0].average_log2_error;
@@ -124,9 +141,10 @@ fn first_phase_configs() -> Vec<(String, u64)> {
     return results;
 }
 
-fn second_phase_configs(first_phase: &[RMIStatistics]) -> Vec<(String, u64)> {
+fn second_phase_configs(first_phase: &[RMIStatistics],
+                        objective: Objective) -> Vec<(String, u64)> {
     let qualifying_model_configs = {
-        let on_front = pareto_front(first_phase);
+        let on_front = pareto_front(first_phase, objective);
         let mut qualifying = BTreeSet::new();
         for result in on_front {
             qualifying.insert(result.models.clone());
@@ -156,30 +174,49 @@ pub struct RMIStatistics {
     pub branching_factor: u64,
     pub average_log2_error: f64,
     pub max_log2_error: f64,
-    pub size: u64
+    pub size: u64,
+    /// The average `TrainedRMI::estimated_flops` of a lookup, over a
+    /// sample of the training keys.
+    pub flops: f64
 }
 
 impl RMIStatistics {
-    fn from_trained(rmi: &train::TrainedRMI) -> RMIStatistics {
+    fn from_trained<T: TrainingKey>(rmi: &train::TrainedRMI,
+                                    data: &RMITrainingData<T>) -> RMIStatistics {
+        let step = usize::max(1, data.len() / FLOPS_SAMPLE);
+        let (lookups, flops) = (0..data.len()).step_by(step)
+            .map(|idx| rmi.estimated_flops(&data.get_key(idx).to_model_input()))
+            .fold((0, 0), |(n, total), flops| (n + 1, total + flops));
+
         return RMIStatistics {
             average_log2_error: rmi.model_avg_log2_error,
             max_log2_error: rmi.model_max_log2_error,
             size: codegen::rmi_size(&rmi),
+            flops: if lookups == 0 { 0.0 } else { flops as f64 / lookups as f64 },
             models: rmi.models.clone(),
             branching_factor: rmi.branching_factor
         };
     }
 
-    fn dominated_by(&self, other: &RMIStatistics) -> bool {
-        if self.size < other.size { return false; }
+    /// What the RMI costs under `objective`.
+    pub fn cost(&self, objective: Objective) -> f64 {
+        return match objective {
+            Objective::Size => self.size as f64,
+            Objective::Flops => self.flops,
+        };
+    }
+
+    fn dominated_by(&self, other: &RMIStatistics, objective: Objective) -> bool {
+        let (cost, other_cost) = (self.cost(objective), other.cost(objective));
+        if cost < other_cost { return false; }
         if self.average_log2_error < other.average_log2_error { return false; }
 
-        if self.size == other.size && self.average_log2_error <= other.average_log2_error {
+        if cost == other_cost && self.average_log2_error <= other.average_log2_error {
             return false;
         }
 
         let log2_diff = (self.average_log2_error - other.average_log2_error).abs();
-        if self.size <= other.size && log2_diff < std::f64::EPSILON {
+        if cost <= other_cost && log2_diff < std::f64::EPSILON {
             return false;
         }
 
@@ -191,15 +228,16 @@ impl RMIStatistics {
     }
 
     pub fn display_table(itms: &[RMIStatistics]) {
-        let mut table = Table::new("{:<} {:>} {:>} {:>} {:>}");
+        let mut table = Table::new("{:<} {:>} {:>} {:>} {:>} {:>}");
         table.add_row(row!("Models", "Branch", "   AvgLg2",
-                           "   MaxLg2", "   Size (b)"));
+                           "   MaxLg2", "   Size (b)", "   Flops"));
         for itm in itms {
             table.add_row(row!(itm.models.clone(),
                                format!("{:10}", itm.branching_factor),
                                format!("     {:2.5}", itm.average_log2_error),
                                format!("     {:2.5}", itm.max_log2_error),
-                               format!("     {}", itm.size)));
+                               format!("     {}", itm.size),
+                               format!("     {:.1}", itm.flops)));
         }
 
         print!("{}", table);
@@ -226,21 +264,29 @@ fn measure_rmis<T: TrainingKey>(data: &RMITrainingData<T>,
             let mut loc_data = data.soft_copy();
             let res = train::train(&mut loc_data, models, *branch_factor);
             pbar.inc(1);
-            RMIStatistics::from_trained(&res)
+            RMIStatistics::from_trained(&res, data)
         }).collect()
 }
 
 pub fn find_pareto_efficient_configs<T: TrainingKey>(
     data: &RMITrainingData<T>, restrict: usize)
     -> Vec<RMIStatistics>{
+    return find_pareto_efficient_configs_with(data, restrict, Objective::Size);
+}
+
+/// Like `find_pareto_efficient_configs`, but trading accuracy off against
+/// `objective` rather than size.
+pub fn find_pareto_efficient_configs_with<T: TrainingKey>(
+    data: &RMITrainingData<T>, restrict: usize, objective: Objective)
+    -> Vec<RMIStatistics>{
     let initial_configs  = first_phase_configs();
     let first_phase_results = measure_rmis(data, &initial_configs);
 
-    let next_configs = second_phase_configs(&first_phase_results);
+    let next_configs = second_phase_configs(&first_phase_results, objective);
     let second_phase_results = measure_rmis(data, &next_configs);
     
-    let mut final_front = pareto_front(&second_phase_results);
-    final_front = narrow_front(&final_front, restrict);
+    let mut final_front = pareto_front(&second_phase_results, objective);
+    final_front = narrow_front(&final_front, restrict, objective);
     final_front.sort_by(
         |a, b| a.average_log2_error.partial_cmp(&b.average_log2_error).unwrap()
     );
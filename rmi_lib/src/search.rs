@@ -0,0 +1,327 @@
+// < begin copyright >
+// Copyright Ryan Marcus 2020
+//
+// See root directory of this project for license terms.
+//
+// < end copyright >
+
+//! Searches of a model's window that count their key comparisons, to
+//! measure the work a lookup does rather than estimate it from the error.
+
+use crate::models::{LearnedFIB, ModelDataType, ModelInput};
+use crate::train::TrainedRMI;
+
+/// How the window around a prediction is searched.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SearchStrategy {
+    /// Halves the window until one position is left.
+    Binary,
+
+    /// Scans the window from its start.
+    Linear,
+
+    /// Gallops away from the prediction in doubling steps, then searches
+    /// the last step in halves.
+    Exponential,
+}
+
+impl SearchStrategy {
+    pub const ALL: [SearchStrategy; 3] =
+        [SearchStrategy::Binary, SearchStrategy::Linear, SearchStrategy::Exponential];
+}
+
+// the first of `keys[lo..hi]` not less than `key`, or `hi`, by halving
+fn binary_search<K: PartialOrd>(keys: &[K], key: &K, mut lo: usize, mut hi: usize,
+                                comparisons: &mut usize) -> usize {
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        *comparisons += 1;
+        if keys[mid] < *key {
+            lo = mid + 1;
+        } else {
+            hi = mid;
+        }
+    }
+    return lo;
+}
+
+// the window `counted_search` searches: the clamped prediction and the
+// indices `lo..hi` around it
+fn search_bounds(len: usize, pred: u64, err: u64) -> (usize, usize, usize) {
+    let len = len as u64;
+    let pred = u64::min(pred, len);
+    let lo = pred.saturating_sub(err) as usize;
+    let hi = u64::min(pred.saturating_add(err), len) as usize;
+    return (pred as usize, lo, hi);
+}
+
+/// The index of the first of the sorted `keys` not less than `key`,
+/// searched for with `strategy` among the indices within `err` of `pred`,
+/// along with the number of keys compared to `key` on the way. The index
+/// is assumed to be in the window, as it is for every trained key; if it
+/// is not, the nearest end of the window is returned. A window of one
+/// index, as for a model without error, takes no comparisons.
+pub fn counted_search<K: PartialOrd>(keys: &[K], key: &K, pred: u64, err: u64,
+                                     strategy: SearchStrategy) -> (usize, usize) {
+    let (pred, lo, hi) = search_bounds(keys.len(), pred, err);
+
+    let mut comparisons = 0;
+    let found = match strategy {
+        SearchStrategy::Binary => binary_search(keys, key, lo, hi, &mut comparisons),
+        SearchStrategy::Linear => {
+            let mut idx = lo;
+            while idx < hi {
+                comparisons += 1;
+                if keys[idx] >= *key {
+                    break;
+                }
+                idx += 1;
+            }
+            idx
+        }
+        SearchStrategy::Exponential => {
+            // bracket the index in [from, to], then halve the bracket
+            let (mut from, mut to) = (lo, pred);
+            let after = if pred < hi {
+                comparisons += 1;
+                keys[pred] < *key
+            } else {
+                false
+            };
+            if after {
+                from = pred + 1;
+                let mut step = 1;
+                to = loop {
+                    let probe = from + step - 1;
+                    if probe >= hi {
+                        break hi;
+                    }
+                    comparisons += 1;
+                    if keys[probe] >= *key {
+                        break probe;
+                    }
+                    from = probe + 1;
+                    step *= 2;
+                };
+            } else {
+                let mut step = 1;
+                while to - lo >= step {
+                    let probe = to - step;
+                    comparisons += 1;
+                    if keys[probe] < *key {
+                        from = probe + 1;
+                        break;
+                    }
+                    to = probe;
+                    step *= 2;
+                }
+            }
+            binary_search(keys, key, from, to, &mut comparisons)
+        }
+    };
+    return (found, comparisons);
+}
+
+/// Comparison counts over a set of lookups.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SearchStats {
+    pub lookups: usize,
+    pub mean_comparisons: f64,
+    pub max_comparisons: usize,
+}
+
+/// Every step of one lookup, from `TrainedRMI::explain`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Explanation {
+    /// What the top model predicted, which picks the model of the next
+    /// layer.
+    pub top_prediction: u64,
+
+    /// The index of the bottom layer model the key was routed to.
+    pub model_idx: usize,
+
+    /// For a `LearnedFIB` bottom model, the bucket whose network made the
+    /// prediction.
+    pub network: Option<usize>,
+
+    /// The position and error bound `predict` gives.
+    pub prediction: u64,
+    pub error_bound: u64,
+
+    /// The indices searched, `lo..hi`. The key's lower bound is in
+    /// `lo..=hi`, `hi` being where a key past every searched one lands.
+    pub window: (usize, usize),
+
+    /// How many keys the search compared to the key.
+    pub comparisons: usize,
+
+    /// Where the search ended: the first of `keys` not less than the key.
+    pub position: usize,
+}
+
+impl TrainedRMI {
+    /// Looks up `key` in `keys`, the sorted keys the RMI was trained on, as
+    /// `search_stats` would with `strategy`, and reports each model's part
+    /// in it, for tracking down a slow or wrong lookup.
+    pub fn explain(&self, keys: &[u64], key: u64, strategy: SearchStrategy) -> Explanation {
+        let inp: ModelInput = key.into();
+        let mut preds = Vec::with_capacity(self.rmi.len());
+        let mut model_idx = 0;
+        for layer in self.rmi.iter() {
+            model_idx = usize::min(preds.last().cloned().unwrap_or(0) as usize, layer.len() - 1);
+            let model = &layer[model_idx];
+            preds.push(match model.output_type() {
+                ModelDataType::Float => f64::max(model.predict_to_float(&inp), 0.0) as u64,
+                _ => model.predict_to_int(&inp),
+            });
+        }
+
+        let network = self.rmi.last()
+            .and_then(|layer| layer[model_idx].as_any().downcast_ref::<LearnedFIB>())
+            .map(|fib| fib.predict_with_network(&inp).1);
+        let (prediction, error_bound) = self.predict(&inp);
+        let (_, lo, hi) = search_bounds(keys.len(), prediction, error_bound);
+        let (position, comparisons) = counted_search(keys, &key, prediction, error_bound,
+                                                     strategy);
+        return Explanation {
+            top_prediction: preds[0],
+            model_idx,
+            network,
+            prediction,
+            error_bound,
+            window: (lo, hi),
+            comparisons,
+            position,
+        };
+    }
+
+    /// Looks up every one of `keys`, the sorted keys the RMI was trained
+    /// on, searching the window of `predict` with `strategy`, and counts
+    /// the comparisons each search takes. Panics if a lookup does not find
+    /// its key's first copy.
+    pub fn search_stats(&self, keys: &[u64], strategy: SearchStrategy) -> SearchStats {
+        let mut total = 0;
+        let mut max_comparisons = 0;
+        for (idx, key) in keys.iter().enumerate() {
+            let (pred, err) = self.predict(&(*key).into());
+            let (found, comparisons) = counted_search(keys, key, pred, err, strategy);
+            assert!(keys[found] == *key && (idx == found || keys[idx - 1] == *key),
+                    "{:?} search for key {} found index {}", strategy, key, found);
+            total += comparisons;
+            max_comparisons = usize::max(max_comparisons, comparisons);
+        }
+
+        return SearchStats {
+            lookups: keys.len(),
+            mean_comparisons: if keys.is_empty() { 0.0 } else { total as f64 / keys.len() as f64 },
+            max_comparisons,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::*;
+
+    #[test]
+    fn test_counted_search() {
+        let keys: Vec<u64> = (0..1_000u64).map(|i| i * 3).collect();
+        for strategy in SearchStrategy::ALL.iter().cloned() {
+            for (idx, key) in keys.iter().enumerate() {
+                for (pred, err) in [(idx, 0), (idx, 7), (idx.saturating_sub(5), 5),
+                                    (idx + 9, 20), (idx, 2_000)] {
+                    let (found, comparisons) =
+                        counted_search(&keys, key, pred as u64, err, strategy);
+                    assert_eq!(found, idx, "{:?} from {} within {}", strategy, pred, err);
+                    if err == 0 {
+                        assert_eq!(comparisons, 0);
+                    }
+                }
+                // keys that are not in the data find their successor
+                let (found, _) = counted_search(&keys, &(key + 1), idx as u64, 4, strategy);
+                assert_eq!(found, usize::min(idx + 1, keys.len()));
+            }
+        }
+
+        // a binary search of 2^k + 1 indices takes k comparisons, a linear
+        // one the key's distance from the window's start, plus one, and an
+        // exponential one checks the prediction and the key before it
+        let (_, comparisons) = counted_search(&keys, &300, 100, 8, SearchStrategy::Binary);
+        assert_eq!(comparisons, 4);
+        let (_, comparisons) = counted_search(&keys, &300, 100, 8, SearchStrategy::Linear);
+        assert_eq!(comparisons, 9);
+        let (_, comparisons) = counted_search(&keys, &300, 100, 8, SearchStrategy::Exponential);
+        assert_eq!(comparisons, 2);
+        assert_eq!(counted_search::<u64>(&[], &5, 0, 10, SearchStrategy::Exponential), (0, 0));
+    }
+
+    #[test]
+    fn test_search_stats() {
+        let keys: Vec<u64> = (1..10_000u64).map(|i| i * i + 7 * i).collect();
+        let md = RMITrainingData::from_keys(keys.clone());
+
+        // a radix table with a bucket per key predicts every key exactly,
+        // so no search is needed
+        let exact: Vec<u64> = (1_024..2_048u64).collect();
+        let exact_md = RMITrainingData::from_keys(exact.clone());
+        let table = RadixTable::new(&exact_md, 10);
+        assert_eq!(table.error_bound(), Some(0));
+        let mut rmi = crate::train(&exact_md, "linear,linear", 4);
+        rmi.rmi = vec![vec![Box::new(table)]];
+        rmi.last_layer_max_l1s = vec![0];
+        for strategy in SearchStrategy::ALL.iter().cloned() {
+            let stats = rmi.search_stats(&exact, strategy);
+            assert_eq!(stats, SearchStats { lookups: 1_024, mean_comparisons: 0.0,
+                                            max_comparisons: 0 });
+        }
+
+        let rmi = crate::train(&md, "linear,linear", 64);
+        let binary = rmi.search_stats(&keys, SearchStrategy::Binary);
+        let linear = rmi.search_stats(&keys, SearchStrategy::Linear);
+        let widest = 2 * rmi.model_max_error as usize + 1;
+        assert!(binary.max_comparisons as f64 <= (widest as f64).log2().ceil());
+        assert!(linear.max_comparisons <= widest);
+        assert!(binary.mean_comparisons > 0.0 && binary.mean_comparisons <= linear.mean_comparisons);
+    }
+
+    #[test]
+    fn test_explain() {
+        let keys: Vec<u64> = (1..10_000u64).map(|i| i * i + 7 * i).collect();
+        let md = RMITrainingData::from_keys(keys.clone());
+        // every leaf is the same LearnedFIB over all the keys
+        let mut rmi = crate::train(&md, "linear,linear", 2);
+        rmi.rmi[1] = (0..2).map(|_| Box::new(LearnedFIB::new(&md, 8, 8)) as Box<dyn Model>)
+            .collect();
+        rmi.last_layer_max_l1s = vec![rmi.rmi[1][0].error_bound().unwrap()];
+
+        for idx in (0..keys.len()).step_by(13) {
+            let key = keys[idx];
+            let explained = rmi.explain(&keys, key, SearchStrategy::Binary);
+            assert_eq!(explained.position, idx);
+            assert_eq!((explained.prediction, explained.error_bound), rmi.predict(&key.into()));
+            assert_eq!(explained.comparisons,
+                       counted_search(&keys, &key, explained.prediction, explained.error_bound,
+                                      SearchStrategy::Binary).1);
+
+            let (lo, hi) = explained.window;
+            assert!(lo <= explained.position && explained.position <= hi);
+            let top = rmi.rmi[0][0].predict_to_float(&key.into());
+            assert_eq!(explained.top_prediction, f64::max(top, 0.0) as u64);
+            assert_eq!(explained.model_idx,
+                       usize::min(explained.top_prediction as usize, rmi.rmi[1].len() - 1));
+
+            let fib = rmi.rmi[1][explained.model_idx].as_any().downcast_ref::<LearnedFIB>();
+            assert_eq!(explained.network, Some(fib.unwrap().predict_with_network(&key.into()).1));
+        }
+
+        let linear = crate::train(&md, "linear,linear", 64);
+        let explained = linear.explain(&keys, keys[77], SearchStrategy::Exponential);
+        assert_eq!((explained.position, explained.network), (77, None));
+
+        // keys that are not in the data end at their successor
+        let explained = rmi.explain(&keys, keys[500] + 1, SearchStrategy::Linear);
+        assert_eq!(explained.position, 501);
+        assert_eq!(rmi.explain(&keys, 0, SearchStrategy::Binary).position, 0);
+    }
+}
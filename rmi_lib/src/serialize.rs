@@ -0,0 +1,528 @@
+// < begin copyright >
+// Copyright Ryan Marcus 2020
+//
+// See root directory of this project for license terms.
+//
+// < end copyright >
+
+//! A single-blob binary format for a trained RMI.
+//!
+//! Layout (all integers little endian):
+//!
+//! ```text
+//! magic "RMIB" | version: u32 | body | checksum: u64
+//! ```
+//!
+//! The body holds the model spec, the training statistics, the cache fix
+//! (if any) and every layer of models. Each model is written as the tag
+//! and parameters returned by `Model::state`, as `Model::save` writes it
+//! and `load_model` reads it. The checksum is a 64-bit FNV-1a hash of
+//! everything before it.
+
+use crate::models::{model_from_state, Model, ModelKind, ModelParam};
+use crate::train::TrainedRMI;
+use byteorder::{ByteOrder, LittleEndian, ReadBytesExt, WriteBytesExt};
+use crate::error::Error;
+use std::io::{self, Cursor, Read, Write};
+
+const MAGIC: &[u8; 4] = b"RMIB";
+const VERSION: u32 = 1;
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for b in bytes {
+        hash ^= u64::from(*b);
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    return hash;
+}
+
+fn corrupt(reason: String) -> Error {
+    return Error::Corrupt { reason };
+}
+
+// Writes into a Vec<u8> cannot fail, so the results below are unwrapped.
+fn write_u64(buf: &mut Vec<u8>, v: u64) {
+    buf.write_u64::<LittleEndian>(v).unwrap();
+}
+
+fn write_str(w: &mut dyn Write, s: &str) -> Result<(), io::Error> {
+    w.write_u64::<LittleEndian>(s.len() as u64)?;
+    return w.write_all(s.as_bytes());
+}
+
+fn write_param(w: &mut dyn Write, param: &ModelParam) -> Result<(), io::Error> {
+    let tag: u8 = match param {
+        ModelParam::Int(_) => 0,
+        ModelParam::Float(_) => 1,
+        ModelParam::ShortArray(_) => 2,
+        ModelParam::IntArray(_) => 3,
+        ModelParam::Int32Array(_) => 4,
+        ModelParam::FloatArray(_) => 5,
+    };
+    w.write_u8(tag)?;
+    if param.is_array() {
+        w.write_u64::<LittleEndian>(param.len() as u64)?;
+    }
+    return param.write_to(w);
+}
+
+// Writes a model as its kind's tag and its parameters.
+pub(crate) fn write_model(w: &mut dyn Write, kind: ModelKind,
+                          params: &[ModelParam]) -> Result<(), io::Error> {
+    write_str(w, kind.tag())?;
+    w.write_u64::<LittleEndian>(params.len() as u64)?;
+    for p in params.iter() {
+        write_param(w, p)?;
+    }
+    return Ok(());
+}
+
+// Reads a length prefix for `elem_size`-byte items, refusing lengths
+// larger than what is left in the buffer.
+fn read_len(cur: &mut Cursor<&[u8]>, elem_size: usize) -> Result<usize, Error> {
+    let len = cur.read_u64::<LittleEndian>()?;
+    let remaining = (cur.get_ref().len() as u64).saturating_sub(cur.position());
+    if len.saturating_mul(elem_size as u64) > remaining {
+        return Err(corrupt(format!("length {} runs past the end of the buffer", len)));
+    }
+    return Ok(len as usize);
+}
+
+// Reads a length prefix and that many `elem_size`-byte items. A reader
+// does not know how much input is left, so the bytes are read as they come
+// rather than allocated up front, and a corrupt length fails at the end of
+// the input.
+fn read_array(r: &mut dyn Read, elem_size: usize) -> Result<Vec<u8>, Error> {
+    let len = r.read_u64::<LittleEndian>()?;
+    let num_bytes = len.saturating_mul(elem_size as u64);
+    let mut bytes = Vec::new();
+    r.take(num_bytes).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 != num_bytes {
+        return Err(corrupt(format!("length {} runs past the end of the input", len)));
+    }
+    return Ok(bytes);
+}
+
+fn read_str(r: &mut dyn Read) -> Result<String, Error> {
+    let bytes = read_array(r, 1)?;
+    return String::from_utf8(bytes).map_err(|e| corrupt(e.to_string()));
+}
+
+fn read_param(r: &mut dyn Read) -> Result<ModelParam, Error> {
+    let tag = r.read_u8()?;
+    let param = match tag {
+        0 => ModelParam::Int(r.read_u64::<LittleEndian>()?),
+        1 => ModelParam::Float(r.read_f64::<LittleEndian>()?),
+        2 => {
+            let bytes = read_array(r, 2)?;
+            let mut v = vec![0; bytes.len() / 2];
+            LittleEndian::read_u16_into(&bytes, &mut v);
+            ModelParam::ShortArray(v)
+        }
+        3 => {
+            let bytes = read_array(r, 8)?;
+            let mut v = vec![0; bytes.len() / 8];
+            LittleEndian::read_u64_into(&bytes, &mut v);
+            ModelParam::IntArray(v)
+        }
+        4 => {
+            let bytes = read_array(r, 4)?;
+            let mut v = vec![0; bytes.len() / 4];
+            LittleEndian::read_u32_into(&bytes, &mut v);
+            ModelParam::Int32Array(v)
+        }
+        5 => {
+            let bytes = read_array(r, 8)?;
+            let mut v = vec![0.0; bytes.len() / 8];
+            LittleEndian::read_f64_into(&bytes, &mut v);
+            ModelParam::FloatArray(v)
+        }
+        _ => return Err(corrupt(format!("unknown parameter tag {}", tag))),
+    };
+    return Ok(param);
+}
+
+// Reads a model written by `write_model`, along with its kind.
+fn read_model(r: &mut dyn Read) -> Result<(ModelKind, Box<dyn Model>), Error> {
+    let tag = read_str(r)?;
+    let kind = ModelKind::from_tag(&tag)
+        .ok_or_else(|| corrupt(format!("unknown model kind {}", tag)))?;
+    let num_params = r.read_u64::<LittleEndian>()?;
+    let params = (0..num_params)
+        .map(|_| read_param(r))
+        .collect::<Result<Vec<ModelParam>, Error>>()?;
+    let model = model_from_state(kind, &params).ok_or_else(|| Error::ShapeMismatch {
+        reason: format!("invalid parameters for model {}", tag),
+    })?;
+    return Ok((kind, model));
+}
+
+/// Reads a model of the given kind written by `Model::save`. Returns
+/// `Error::Corrupt` if the input holds a model of another kind, and
+/// `Error::ShapeMismatch` for parameters that do not make up a model.
+pub fn load_model(kind: ModelKind, r: &mut dyn Read) -> Result<Box<dyn Model>, Error> {
+    let (found, model) = read_model(r)?;
+    if found != kind {
+        return Err(corrupt(format!("expected a {} model, found {}", kind.tag(), found.tag())));
+    }
+    return Ok(model);
+}
+
+impl TrainedRMI {
+    /// Serializes the whole RMI, every layer included, into one blob that
+    /// `from_bytes` can load. Panics if a model does not support
+    /// `Model::state`.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf: Vec<u8> = Vec::new();
+        buf.extend_from_slice(MAGIC);
+        buf.write_u32::<LittleEndian>(VERSION).unwrap();
+
+        write_str(&mut buf, &self.models).unwrap();
+        write_u64(&mut buf, self.branching_factor);
+        write_u64(&mut buf, self.num_rmi_rows as u64);
+        write_u64(&mut buf, self.num_data_rows as u64);
+        buf.write_f64::<LittleEndian>(self.model_avg_error).unwrap();
+        buf.write_f64::<LittleEndian>(self.model_avg_l2_error).unwrap();
+        buf.write_f64::<LittleEndian>(self.model_avg_log2_error).unwrap();
+        write_u64(&mut buf, self.model_max_error);
+        write_u64(&mut buf, self.model_max_error_idx as u64);
+        buf.write_f64::<LittleEndian>(self.model_max_log2_error).unwrap();
+        write_u64(&mut buf, self.last_layer_max_l1s.len() as u64);
+        for v in self.last_layer_max_l1s.iter() {
+            write_u64(&mut buf, *v);
+        }
+        buf.write_u128::<LittleEndian>(self.build_time).unwrap();
+
+        match &self.cache_fix {
+            None => buf.push(0),
+            Some((line_size, points)) => {
+                buf.push(1);
+                write_u64(&mut buf, *line_size as u64);
+                write_u64(&mut buf, points.len() as u64);
+                for (key, pos) in points.iter() {
+                    write_u64(&mut buf, *key);
+                    write_u64(&mut buf, *pos as u64);
+                }
+            }
+        }
+
+        write_u64(&mut buf, self.rmi.len() as u64);
+        for layer in self.rmi.iter() {
+            write_u64(&mut buf, layer.len() as u64);
+            for model in layer.iter() {
+                model.save(&mut buf).unwrap_or_else(|e| panic!("{}", e));
+            }
+        }
+
+        let checksum = fnv1a(&buf);
+        write_u64(&mut buf, checksum);
+        return buf;
+    }
+
+    /// Loads an RMI written by `to_bytes`, checking the magic, version and
+    /// checksum first. Bytes that fail the checks are `Error::Corrupt`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<TrainedRMI, Error> {
+        if bytes.len() < MAGIC.len() + 4 + 8 {
+            return Err(corrupt(String::from("buffer too short to hold an RMI")));
+        }
+
+        let (body, trailer) = bytes.split_at(bytes.len() - 8);
+        let expected = (&trailer[..]).read_u64::<LittleEndian>()?;
+        if fnv1a(body) != expected {
+            return Err(corrupt(String::from("checksum mismatch")));
+        }
+
+        if &body[0..4] != MAGIC {
+            return Err(corrupt(String::from("not a serialized RMI")));
+        }
+
+        let mut cur = Cursor::new(&body[4..]);
+        let version = cur.read_u32::<LittleEndian>()?;
+        if version != VERSION {
+            return Err(corrupt(format!("unsupported RMI format version {}", version)));
+        }
+
+        let models = read_str(&mut cur)?;
+        let branching_factor = cur.read_u64::<LittleEndian>()?;
+        let num_rmi_rows = cur.read_u64::<LittleEndian>()? as usize;
+        let num_data_rows = cur.read_u64::<LittleEndian>()? as usize;
+        let model_avg_error = cur.read_f64::<LittleEndian>()?;
+        let model_avg_l2_error = cur.read_f64::<LittleEndian>()?;
+        let model_avg_log2_error = cur.read_f64::<LittleEndian>()?;
+        let model_max_error = cur.read_u64::<LittleEndian>()?;
+        let model_max_error_idx = cur.read_u64::<LittleEndian>()? as usize;
+        let model_max_log2_error = cur.read_f64::<LittleEndian>()?;
+        let mut last_layer_max_l1s = vec![0; read_len(&mut cur, 8)?];
+        cur.read_u64_into::<LittleEndian>(&mut last_layer_max_l1s)?;
+        let build_time = cur.read_u128::<LittleEndian>()?;
+
+        let cache_fix = match cur.read_u8()? {
+            0 => None,
+            1 => {
+                let line_size = cur.read_u64::<LittleEndian>()? as usize;
+                let num_points = read_len(&mut cur, 16)?;
+                let mut points = Vec::with_capacity(num_points);
+                for _ in 0..num_points {
+                    let key = cur.read_u64::<LittleEndian>()?;
+                    let pos = cur.read_u64::<LittleEndian>()? as usize;
+                    points.push((key, pos));
+                }
+                Some((line_size, points))
+            }
+            flag => return Err(corrupt(format!("bad cache fix flag {}", flag))),
+        };
+
+        let num_layers = read_len(&mut cur, 8)?;
+        let mut rmi = Vec::with_capacity(num_layers);
+        for _ in 0..num_layers {
+            let num_models = read_len(&mut cur, 8)?;
+            let mut layer = Vec::with_capacity(num_models);
+            for _ in 0..num_models {
+                layer.push(read_model(&mut cur)?.1);
+            }
+            rmi.push(layer);
+        }
+
+        if cur.position() != cur.get_ref().len() as u64 {
+            return Err(corrupt(String::from("trailing bytes after the last layer")));
+        }
+
+        return Ok(TrainedRMI {
+            num_rmi_rows,
+            num_data_rows,
+            model_avg_error,
+            model_avg_l2_error,
+            model_avg_log2_error,
+            model_max_error,
+            model_max_error_idx,
+            model_max_log2_error,
+            last_layer_max_l1s,
+            rmi,
+            models,
+            branching_factor,
+            cache_fix,
+            build_time,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::*;
+    use std::io::ErrorKind;
+
+    fn lookup(rmi: &TrainedRMI, key: u64) -> u64 {
+        let top = rmi.rmi[0][0].predict_to_int(&key.into());
+        let leaf = u64::min(top, rmi.rmi[1].len() as u64 - 1) as usize;
+        return rmi.rmi[1][leaf].predict_to_int(&key.into());
+    }
+
+    // a radix top layer routing into LearnedFIB leaves
+    fn radix_fib_rmi(keys: &[u64]) -> TrainedRMI {
+        let branching_factor = 16;
+        let data: Vec<(u64, usize)> = keys.iter().cloned().zip(0..).collect();
+        let mut md = RMITrainingData::new(Box::new(data.clone()));
+        md.set_scale(branching_factor as f64 / data.len() as f64);
+        let top = RadixModel::new(&md);
+
+        let mut buckets: Vec<Vec<(u64, usize)>> = vec![Vec::new(); branching_factor];
+        for (key, pos) in data.iter() {
+            let leaf = u64::min(top.predict_to_int(&(*key).into()),
+                                branching_factor as u64 - 1);
+            buckets[leaf as usize].push((*key, *pos));
+        }
+
+        let leaves: Vec<Box<dyn Model>> = buckets.into_iter()
+            .map(|b| {
+                let b = if b.is_empty() { vec![(0, 0), (1, 0)] } else { b };
+                let md = RMITrainingData::new(Box::new(b));
+                Box::new(LearnedFIB::new(&md, 8, 2)) as Box<dyn Model>
+            })
+            .collect();
+
+        return TrainedRMI {
+            num_rmi_rows: data.len(),
+            num_data_rows: data.len(),
+            model_avg_error: 1.5,
+            model_avg_l2_error: 2.5,
+            model_avg_log2_error: 0.5,
+            model_max_error: 8,
+            model_max_error_idx: 3,
+            model_max_log2_error: 3.0,
+            last_layer_max_l1s: vec![1; branching_factor],
+            rmi: vec![vec![Box::new(top)], leaves],
+            models: String::from("radix,learned_fib"),
+            branching_factor: branching_factor as u64,
+            cache_fix: Some((4, vec![(1, 0), (500, 4)])),
+            build_time: 1234,
+        };
+    }
+
+    fn test_keys() -> Vec<u64> {
+        return (1..5000u64).map(|i| i * i * 31 + 7 * i).collect();
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let keys = test_keys();
+        let rmi = radix_fib_rmi(&keys);
+        let bytes = rmi.to_bytes();
+        let loaded = TrainedRMI::from_bytes(&bytes).unwrap();
+
+        assert_eq!(loaded.models, rmi.models);
+        assert_eq!(loaded.branching_factor, rmi.branching_factor);
+        assert_eq!(loaded.model_max_error, rmi.model_max_error);
+        assert_eq!(loaded.last_layer_max_l1s, rmi.last_layer_max_l1s);
+        assert_eq!(loaded.cache_fix, rmi.cache_fix);
+        assert_eq!(loaded.build_time, rmi.build_time);
+        assert_eq!(loaded.rmi.len(), 2);
+        assert_eq!(loaded.rmi[1].len(), rmi.rmi[1].len());
+
+        for (a, b) in rmi.rmi[1].iter().zip(loaded.rmi[1].iter()) {
+            assert_eq!(a.error_bound(), b.error_bound());
+        }
+
+        for key in keys {
+            assert_eq!(lookup(&loaded, key), lookup(&rmi, key));
+        }
+
+        // serializing again yields the same blob
+        assert_eq!(loaded.to_bytes(), bytes);
+    }
+
+    #[test]
+    fn test_save_load_each_kind() {
+        let keys = test_keys();
+        let md = RMITrainingData::from_keys(keys.clone());
+        let models: Vec<Box<dyn Model>> = vec![
+            Box::new(LinearModel::new(&md)),
+            Box::new(RobustLinearModel::new(&md)),
+            Box::new(LinearSplineModel::new(&md)),
+            Box::new(CubicSplineModel::new(&md)),
+            Box::new(LogLinearModel::new(&md)),
+            Box::new(NormalModel::new(&md)),
+            Box::new(LogNormalModel::new(&md)),
+            Box::new(RadixModel::new(&md)),
+            Box::new(RadixModel::new_with_transform(&md, Multiplicative)),
+            Box::new(RadixTable::new(&md, 10)),
+            Box::new(RelativeRadixTable::new(&md, 10, 4)),
+            // training one on unscaled positions overflows, so it is
+            // built from its parameters
+            Box::new(BalancedRadixModel::from_state(&[
+                ModelParam::Int(0), ModelParam::Int(10), ModelParam::Int(1_000),
+                ModelParam::Int(1)]).unwrap()),
+            Box::new(EquidepthHistogramModel::new(&md)),
+            Box::new(LearnedFIB::new(&md, 16, 4)),
+        ];
+        let kinds: std::collections::HashSet<ModelKind> = models.iter().map(|m| m.kind()).collect();
+        assert_eq!(kinds.len(), 14);
+
+        for model in models.iter() {
+            let mut buf = Vec::new();
+            model.save(&mut buf).unwrap();
+            let loaded = load_model(model.kind(), &mut &buf[..]).unwrap();
+
+            assert_eq!(loaded.kind(), model.kind());
+            // some models forget their error bound, but none changes it
+            if loaded.error_bound().is_some() {
+                assert_eq!(loaded.error_bound(), model.error_bound(), "{:?}", model.kind());
+            }
+            for key in keys.iter().step_by(7) {
+                assert_eq!(loaded.predict_to_int(&(*key).into()),
+                           model.predict_to_int(&(*key).into()), "{:?}", model.kind());
+            }
+
+            let mut again = Vec::new();
+            loaded.save(&mut again).unwrap();
+            assert_eq!(again, buf, "{:?}", model.kind());
+
+            // the tag must match the kind asked for, and the input must
+            // hold the whole model
+            let other = if model.kind() == ModelKind::Linear {
+                ModelKind::Radix
+            } else {
+                ModelKind::Linear
+            };
+            let err = load_model(other, &mut &buf[..]).err().unwrap();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+            assert!(matches!(err, Error::Corrupt { .. }));
+            assert!(load_model(model.kind(), &mut &buf[..buf.len() - 1]).is_err());
+        }
+    }
+
+    #[test]
+    fn test_save_unsupported() {
+        struct Stateless;
+        impl Model for Stateless {
+            fn predict_to_int(&self, _inp: &ModelInput) -> u64 { return 0; }
+            fn input_type(&self) -> ModelDataType { return ModelDataType::Int; }
+            fn output_type(&self) -> ModelDataType { return ModelDataType::Int; }
+            fn params(&self) -> Vec<ModelParam> { return Vec::new(); }
+            fn code(&self) -> String { return String::new(); }
+            fn function_name(&self) -> String { return String::from("stateless"); }
+            fn as_any(&self) -> &dyn std::any::Any { return self; }
+        }
+
+        let err = Stateless.save(&mut Vec::new()).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::Unsupported);
+    }
+
+    #[test]
+    fn test_rejects_corruption() {
+        let rmi = radix_fib_rmi(&test_keys());
+        let bytes = rmi.to_bytes();
+
+        let mut flipped = bytes.clone();
+        flipped[bytes.len() / 2] ^= 0xff;
+        assert!(TrainedRMI::from_bytes(&flipped).is_err());
+
+        assert!(TrainedRMI::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(TrainedRMI::from_bytes(&[]).is_err());
+        assert!(matches!(TrainedRMI::from_bytes(&flipped), Err(Error::Corrupt { .. })));
+        assert!(matches!(TrainedRMI::from_bytes(&[]), Err(Error::Corrupt { .. })));
+
+        // a well-formed model whose parameters do not fit its kind
+        let mut buf = Vec::new();
+        write_model(&mut buf, ModelKind::Linear, &[ModelParam::Int(1)]).unwrap();
+        let err = load_model(ModelKind::Linear, &mut &buf[..]).err().unwrap();
+        assert!(matches!(err, Error::ShapeMismatch { .. }));
+
+        // running out of input is an io error
+        let err = load_model(ModelKind::Linear, &mut &buf[..3]).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+        assert!(matches!(err, Error::Io(_)));
+    }
+
+    #[test]
+    fn test_rejects_unknown_version() {
+        let rmi = radix_fib_rmi(&test_keys());
+        let mut bytes = rmi.to_bytes();
+        bytes.truncate(bytes.len() - 8);
+        bytes[4] = 99;
+        let checksum = fnv1a(&bytes);
+        write_u64(&mut bytes, checksum);
+
+        let err = TrainedRMI::from_bytes(&bytes).err().unwrap();
+        assert!(err.to_string().contains("version"));
+    }
+
+    #[test]
+    fn test_short_array_param() {
+        let param = ModelParam::ShortArray(vec![0, 1, 0x1234, u16::MAX]);
+        assert_eq!(param.size(), 8);
+
+        let mut buf = Vec::new();
+        write_param(&mut buf, &param).unwrap();
+        // a tag, a length and two little-endian bytes per value
+        assert_eq!(buf.len(), 1 + 8 + 8);
+        assert_eq!(&buf[9..], &[0, 0, 1, 0, 0x34, 0x12, 0xff, 0xff]);
+
+        let read = read_param(&mut Cursor::new(&buf[..])).unwrap();
+        assert!(matches!(read, ModelParam::ShortArray(v) if v == [0, 1, 0x1234, u16::MAX]));
+
+        // a length the buffer cannot hold is refused
+        assert!(read_param(&mut Cursor::new(&buf[..buf.len() - 1])).is_err());
+    }
+}
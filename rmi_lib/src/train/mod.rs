@@ -6,9 +6,11 @@
 // < end copyright > 
  
 
+use crate::error::Error;
 use crate::models::*;
 use crate::cache_fix::cache_fix;
 use log::*;
+use std::sync::RwLock;
 use std::time::SystemTime;
 
 mod two_layer;
@@ -32,28 +34,279 @@ pub struct TrainedRMI {
     pub build_time: u128
 }
 
+/// A query for which an RMI lookup, searched within its error bound, does
+/// not find the key's lower bound.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    pub key: u64,
+    /// The lower bound of `key` in the sorted keys.
+    pub expected: usize,
+    /// What searching the RMI's window for `key` found.
+    pub found: usize,
+}
+
+/// One layer of a trained RMI, as summarized in a `BuildReport`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LayerReport {
+    /// The layer's entry in the model spec, such as `"linear"`.
+    pub model: String,
+    /// How many models the layer has.
+    pub num_models: usize,
+    /// The combined parameter size of the layer's models, in bytes.
+    pub size_bytes: usize,
+    /// The largest `Model::error_bound` of the layer's models, if any of
+    /// them reports one.
+    pub error_bound: Option<u64>,
+}
+
+/// What went into a trained RMI, for deciding whether to keep it without
+/// generating its code first. Built by `train_with_report`, or for any
+/// trained RMI by `TrainedRMI::report`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BuildReport {
+    /// The layers, top first.
+    pub layers: Vec<LayerReport>,
+    /// The size of the RMI in bytes, as `rmi_size` computes it.
+    pub total_bytes: u64,
+    /// The largest error `TrainedRMI::predict` returns for any key.
+    pub error_bound: u64,
+    /// The average log2 of the error over the training keys.
+    pub avg_log2_error: f64,
+    /// The cache lines one lookup reads, as `TrainedRMI::lookup_cache_lines`.
+    pub lookup_cache_lines: usize,
+    /// How long training took, in nanoseconds.
+    pub build_time: u128,
+}
+
+impl TrainedRMI {
+    /// The position and error bound the generated `lookup` returns for
+    /// `key`, computed in Rust by routing the key through every layer
+    /// the same way. The cache fix, if any, is not applied.
+    pub fn predict(&self, key: &ModelInput) -> (u64, u64) {
+        let mut model_idx = 0;
+        let mut pred = 0;
+        for layer in self.rmi.iter() {
+            model_idx = usize::min(pred as usize, layer.len() - 1);
+            let model = &layer[model_idx];
+            pred = match model.output_type() {
+                ModelDataType::Float => f64::max(model.predict_to_float(key), 0.0) as u64,
+                _ => model.predict_to_int(key),
+            };
+        }
+
+        let pos = u64::min(pred, (self.num_rmi_rows as u64).saturating_sub(1));
+        let err = match self.last_layer_max_l1s.len() {
+            1 => self.last_layer_max_l1s[0],
+            _ => self.last_layer_max_l1s[model_idx],
+        };
+        return (pos, err);
+    }
+
+    /// Like `predict`, but fails instead of predicting for a NaN or
+    /// infinite float key, or with `Error::EmptyData` for an RMI with no
+    /// rows to predict a position in.
+    pub fn try_predict(&self, key: &ModelInput) -> Result<(u64, u64), Error> {
+        key.check_finite()?;
+        if self.num_rmi_rows == 0 {
+            return Err(Error::EmptyData);
+        }
+        return Ok(self.predict(key));
+    }
+
+    /// How many cache lines of parameters one lookup reads: for each layer,
+    /// the `Model::lookup_cache_lines` of its largest model.
+    pub fn lookup_cache_lines(&self) -> usize {
+        return self.rmi.iter()
+            .map(|layer| layer.iter().map(|m| m.lookup_cache_lines()).max().unwrap_or(0))
+            .sum();
+    }
+
+    /// Roughly how many operations looking up `key` takes: the
+    /// `Model::estimated_flops` of each model `predict` routes it through.
+    pub fn estimated_flops(&self, key: &ModelInput) -> u64 {
+        let mut flops = 0;
+        let mut pred = 0;
+        for layer in self.rmi.iter() {
+            let model = &layer[usize::min(pred as usize, layer.len() - 1)];
+            flops += model.estimated_flops(key);
+            pred = match model.output_type() {
+                ModelDataType::Float => f64::max(model.predict_to_float(key), 0.0) as u64,
+                _ => model.predict_to_int(key),
+            };
+        }
+        return flops;
+    }
+
+    /// Whether every model of every layer is `Model::integer_only`, so that
+    /// `predict` gives the same answer for a key on every target.
+    pub fn all_integer(&self) -> bool {
+        return self.rmi.iter().flatten().all(|model| model.integer_only());
+    }
+
+    // whether every position `predict` or the cache fix can return fits
+    // in a u32
+    pub(crate) fn positions_fit_u32(&self) -> bool {
+        let rows = usize::max(self.num_rmi_rows, self.num_data_rows);
+        return rows as u64 <= u32::MAX as u64;
+    }
+
+    /// `predict` with the position as a `u32`, like the `lookup` generated
+    /// with `CodeGen::narrow_positions`. Fails for an RMI with more than
+    /// `u32::MAX` positions, whose positions may not fit.
+    pub fn predict_to_u32(&self, key: &ModelInput) -> Result<(u32, u64), Error> {
+        if !self.positions_fit_u32() {
+            return Err(Error::ParamOverflow {
+                reason: format!("positions of an RMI over {} rows do not fit in 32 bits",
+                                self.num_rmi_rows),
+            });
+        }
+        let (pos, err) = self.predict(key);
+        return Ok((pos as u32, err));
+    }
+
+    /// Summarizes this RMI's layers, size, error and build time.
+    pub fn report(&self) -> BuildReport {
+        let names: Vec<&str> = self.models.split(',').collect();
+        let layers = self.rmi.iter().enumerate()
+            .map(|(idx, layer)| LayerReport {
+                model: String::from(names.get(idx).copied().unwrap_or("")),
+                num_models: layer.len(),
+                size_bytes: layer.iter()
+                    .map(|m| m.params().iter().map(|p| p.size()).sum::<usize>())
+                    .sum(),
+                error_bound: layer.iter().filter_map(|m| m.error_bound()).max(),
+            })
+            .collect();
+
+        return BuildReport {
+            layers,
+            total_bytes: crate::codegen::rmi_size(self),
+            error_bound: self.last_layer_max_l1s.iter().copied().max().unwrap_or(0),
+            avg_log2_error: self.model_avg_log2_error,
+            lookup_cache_lines: self.lookup_cache_lines(),
+            build_time: self.build_time,
+        };
+    }
+
+    /// Checks lookups against a plain binary search over `keys`, the sorted
+    /// keys the RMI was trained on. Every key is looked up, along with the
+    /// values just below and above it, which are usually not keys at all.
+    /// A lookup searches the window of `predict`'s position plus or minus
+    /// its error for the first key not less than the query, and must land
+    /// where the binary search does. Returns the first lookup that does not.
+    pub fn verify_against_baseline(&self, keys: &[u64]) -> Result<(), Mismatch> {
+        for key in keys.iter() {
+            for query in [key.saturating_sub(1), *key, key.saturating_add(1)] {
+                let expected = keys.partition_point(|k| *k < query);
+
+                let (pos, err) = self.predict(&query.into());
+                let lo = usize::min(pos.saturating_sub(err) as usize, keys.len());
+                let hi = usize::min(pos.saturating_add(err).saturating_add(1) as usize,
+                                    keys.len());
+                let found = lo + keys[lo..usize::max(lo, hi)].partition_point(|k| *k < query);
+
+                if found != expected {
+                    return Err(Mismatch { key: query, expected, found });
+                }
+            }
+        }
+        return Ok(());
+    }
+}
+
+type BuiltinTrainer<T> = fn(&RMITrainingData<T>) -> Box<dyn Model>;
+
+// Every built-in model, by the name it goes by in a model spec.
+fn builtin_models<T: TrainingKey>() -> [(&'static str, BuiltinTrainer<T>); 20] {
+    return [
+        ("linear", |data| Box::new(LinearModel::new(data))),
+        ("robust_linear", |data| Box::new(RobustLinearModel::new(data))),
+        ("linear_spline", |data| Box::new(LinearSplineModel::new(data))),
+        ("cubic", |data| Box::new(CubicSplineModel::new(data))),
+        ("loglinear", |data| Box::new(LogLinearModel::new(data))),
+        ("normal", |data| Box::new(NormalModel::new(data))),
+        ("lognormal", |data| Box::new(LogNormalModel::new(data))),
+        ("radix", |data| Box::new(RadixModel::new(data))),
+        ("radix_mul", |data| Box::new(RadixModel::new_with_transform(data, Multiplicative))),
+        ("radix8", |data| Box::new(RadixTable::new(data, 8))),
+        ("radix18", |data| Box::new(RadixTable::new(data, 18))),
+        ("radix22", |data| Box::new(RadixTable::new(data, 22))),
+        ("radix26", |data| Box::new(RadixTable::new(data, 26))),
+        ("radix28", |data| Box::new(RadixTable::new(data, 28))),
+        ("radix_leaf8", |data| Box::new(RadixTable::new_leaf(data, 8))),
+        ("radix_leaf12", |data| Box::new(RadixTable::new_leaf(data, 12))),
+        ("radix_rel18", |data| Box::new(RelativeRadixTable::new(data, 18, 8))),
+        ("bradix", |data| Box::new(BalancedRadixModel::new(data))),
+        ("histogram", |data| Box::new(EquidepthHistogramModel::new(data))),
+        ("learned_fib", |data| Box::new(LearnedFIB::new(data, 16, 0))),
+    ];
+}
+
 fn train_model<T: TrainingKey>(model_type: &str,
                               data: &RMITrainingData<T>) -> Box<dyn Model> {
-    let model: Box<dyn Model> = match model_type {
-        "linear" => Box::new(LinearModel::new(data)),
-        "robust_linear" => Box::new(RobustLinearModel::new(data)),
-        "linear_spline" => Box::new(LinearSplineModel::new(data)),
-        "cubic" => Box::new(CubicSplineModel::new(data)),
-        "loglinear" => Box::new(LogLinearModel::new(data)),
-        "normal" => Box::new(NormalModel::new(data)),
-        "lognormal" => Box::new(LogNormalModel::new(data)),
-        "radix" => Box::new(RadixModel::new(data)),
-        "radix8" => Box::new(RadixTable::new(data, 8)),
-        "radix18" => Box::new(RadixTable::new(data, 18)),
-        "radix22" => Box::new(RadixTable::new(data, 22)),
-        "radix26" => Box::new(RadixTable::new(data, 26)),
-        "radix28" => Box::new(RadixTable::new(data, 28)),
-        "bradix" => Box::new(BalancedRadixModel::new(data)),
-        "histogram" => Box::new(EquidepthHistogramModel::new(data)),
-        _ => panic!("Unknown model type: {}", model_type),
+    let builtin = builtin_models::<T>();
+    if let Some((_, trainer)) = builtin.iter().find(|(name, _)| *name == model_type) {
+        return trainer(data);
+    }
+    return match custom_model_trainer(model_type) {
+        Some(trainer) => trainer(&RMITrainingData::new(Box::new(UintKeys(data.soft_copy())))),
+        None => panic!("Unknown model type: {}", model_type),
     };
+}
+
+/// Trains a custom model registered with `register_model`. Keys are given
+/// as `u64` (via `TrainingKey::as_uint`) whatever the RMI's key type. The
+/// data may be empty, because `train` builds every layer's model once on
+/// empty data to check its `restriction()`.
+pub type CustomModelTrainer = fn(&RMITrainingData<u64>) -> Box<dyn Model>;
+
+static CUSTOM_MODELS: RwLock<Vec<(String, CustomModelTrainer)>> = RwLock::new(Vec::new());
+
+/// Makes `name` usable as a layer in the model spec given to `train`, so a
+/// custom `Model` is trained, validated and emitted by the code generator
+/// like a built-in one. Registering a name again replaces its trainer.
+/// Panics if `name` is a built-in model or contains a comma.
+pub fn register_model(name: &str, trainer: CustomModelTrainer) {
+    assert!(!builtin_models::<u64>().iter().any(|(builtin, _)| *builtin == name),
+            "Cannot replace built-in model {}", name);
+    assert!(!name.contains(','), "Model name {} cannot contain a comma", name);
 
-    return model;
+    let mut models = CUSTOM_MODELS.write().unwrap();
+    models.retain(|(existing, _)| existing != name);
+    models.push((String::from(name), trainer));
+}
+
+fn custom_model_trainer(name: &str) -> Option<CustomModelTrainer> {
+    return CUSTOM_MODELS.read().unwrap()
+        .iter()
+        .find(|(existing, _)| existing == name)
+        .map(|(_, trainer)| *trainer);
+}
+
+// Presents training data of any key type to custom trainers as u64 keys.
+struct UintKeys<T>(RMITrainingData<T>);
+
+impl<T: TrainingKey> RMITrainingDataIteratorProvider for UintKeys<T> {
+    type InpType = u64;
+    fn len(&self) -> usize {
+        return self.0.len();
+    }
+
+    fn cdf_iter(&self) -> Box<dyn Iterator<Item = (u64, usize)> + '_> {
+        return Box::new(self.0.iter().map(|(key, offset)| (key.as_uint(), offset)));
+    }
+
+    fn key_type(&self) -> KeyType {
+        return KeyType::U64;
+    }
+
+    fn get(&self, idx: usize) -> Option<(u64, usize)> {
+        if idx >= self.0.len() {
+            return None;
+        }
+        let (key, offset) = self.0.get(idx);
+        return Some((key.as_uint(), offset));
+    }
 }
 
 fn validate(model_spec: &[String]) {
@@ -72,7 +325,7 @@ fn validate(model_spec: &[String]) {
                     model
                 );
             }
-            ModelRestriction::MustBeBottom => {
+            ModelRestriction::MustBeBottom | ModelRestriction::RustOnlyBottom => {
                 assert_eq!(
                     idx,
                     num_layers - 1,
@@ -84,6 +337,19 @@ fn validate(model_spec: &[String]) {
     }
 }
 
+fn validate_bottom_layer(model_type: &str, models: &[Box<dyn Model>]) {
+    for (idx, model) in models.iter().enumerate() {
+        if let ModelRestriction::MustBeBottom | ModelRestriction::RustOnlyBottom =
+            model.restriction() {
+            assert!(
+                model.error_bound().is_some(),
+                "bottom model type {} (leaf {}) must report an error bound",
+                model_type, idx
+            );
+        }
+    }
+}
+
 /*fn test_rmi_input(test_key: u64, data: &RMITrainingData, rmi: &TrainedRMI) {
     let correct = data.lower_bound(test_key);
     println!("Predicting {}", test_key);
@@ -125,6 +391,30 @@ pub fn train<T: TrainingKey>(data: &RMITrainingData<T>,
     panic!(); // TODO
 }
 
+/// Like `train`, but moves every model into `arena` once it is trained
+/// (see `Model::move_into`), so the parameter arrays of the whole RMI live
+/// in one contiguous allocation. Models the arena has no room for keep
+/// arrays of their own.
+#[cfg(feature = "arena")]
+pub fn train_in<T: TrainingKey>(data: &RMITrainingData<T>, model_spec: &str,
+                               branch_factor: u64,
+                               arena: &crate::arena::ParamArena) -> TrainedRMI {
+    let mut rmi = train(data, model_spec, branch_factor);
+    for model in rmi.rmi.iter_mut().flatten() {
+        model.move_into(arena);
+    }
+    return rmi;
+}
+
+/// Like `train`, but also returns the `BuildReport` of the trained RMI.
+pub fn train_with_report<T: TrainingKey>(data: &RMITrainingData<T>,
+                                        model_spec: &str,
+                                        branch_factor: u64) -> (TrainedRMI, BuildReport) {
+    let rmi = train(data, model_spec, branch_factor);
+    let report = rmi.report();
+    return (rmi, report);
+}
+
 pub fn train_for_size<T: TrainingKey>(data: &RMITrainingData<T>,
                                      max_size: usize) -> TrainedRMI {
 
@@ -182,3 +472,281 @@ pub fn train_bounded(data: &RMITrainingData<u64>,
     res.build_time = build_time;
     return res;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::any::Any;
+
+    struct UnboundedBottomModel;
+
+    impl Model for UnboundedBottomModel {
+        fn predict_to_int(&self, _inp: &ModelInput) -> u64 { return 0; }
+        fn input_type(&self) -> ModelDataType { return ModelDataType::Int; }
+        fn output_type(&self) -> ModelDataType { return ModelDataType::Int; }
+        fn params(&self) -> Vec<ModelParam> { return vec![0u64.into()]; }
+        fn code(&self) -> String { return String::new(); }
+        fn function_name(&self) -> String { return String::from("unbounded"); }
+        fn restriction(&self) -> ModelRestriction { return ModelRestriction::MustBeBottom; }
+        fn as_any(&self) -> &dyn Any { return self; }
+    }
+
+    #[test]
+    #[should_panic(expected = "must report an error bound")]
+    fn test_reject_unbounded_bottom() {
+        let models: Vec<Box<dyn Model>> = vec![Box::new(UnboundedBottomModel)];
+        validate_bottom_layer("unbounded", &models);
+    }
+
+    #[test]
+    fn test_accept_bounded_bottom() {
+        let data: Vec<(u64, usize)> = (0..1000u64).map(|i| (i * 7, i as usize)).collect();
+        let md = RMITrainingData::new(Box::new(data));
+        let models: Vec<Box<dyn Model>> = vec![Box::new(RadixTable::new(&md, 8))];
+        validate_bottom_layer("radix8", &models);
+    }
+
+    #[test]
+    fn test_required_includes_compile() {
+        let keys: Vec<u64> = (1..5000u64).map(|i| i * 7919).collect();
+        let md = RMITrainingData::from_keys(keys);
+        let mut fixed = LearnedFIB::new(&md, 16, 2);
+        fixed.use_fixed_point(&md);
+
+        // bradix's code does not depend on its data, and training it can
+        // underflow
+        let empty = RMITrainingData::<u64>::empty();
+        let mut models: Vec<(String, Box<dyn Model>)> = builtin_models::<u64>().iter()
+            .map(|(name, trainer)| {
+                let data = if *name == "bradix" { &empty } else { &md };
+                (name.to_string(), trainer(data))
+            })
+            .collect();
+        models.push((String::from("learned_fib_fixed"), Box::new(fixed)));
+        for kind in ModelKind::TAGGED.iter() {
+            assert!(models.iter().any(|(_, model)| model.kind() == *kind),
+                    "no {} model to check the includes of", kind.tag());
+        }
+
+        // each model's snippet, with only its own includes and standard
+        // functions, as a translation unit of its own
+        let dir = std::env::temp_dir().join(format!("rmi_includes_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut sources = Vec::new();
+        for (name, model) in models.iter() {
+            let mut tu = String::new();
+            for include in model.required_includes() {
+                tu += &format!("#include {}\n", include);
+            }
+            // declarations first, as the generator does, since standard
+            // functions may call each other
+            let stdlibs = model.standard_functions();
+            for stdlib in stdlibs.iter() {
+                tu += stdlib.decl();
+            }
+            for stdlib in stdlibs.iter() {
+                tu += stdlib.code();
+            }
+            tu += &model.code();
+            let file = dir.join(format!("{}.cpp", name));
+            std::fs::write(&file, tu).unwrap();
+            sources.push(file);
+        }
+
+        // compiling fails, and panics, if a model snippet is missing an include
+        crate::codegen::cpp_test::compile_and_run(&sources, &["-std=c++17", "-fsyntax-only"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_try_predict_non_finite() {
+        let keys: Vec<f64> = (0..10_000).map(|i| i as f64 * 0.5).collect();
+        let rmi = train(&RMITrainingData::from_keys(keys), "linear,linear", 16);
+
+        let key = ModelInput::finite_float(1234.5).unwrap();
+        assert_eq!(rmi.try_predict(&key).unwrap(), rmi.predict(&key));
+        for bad in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            let err = rmi.try_predict(&ModelInput::Float(bad)).unwrap_err();
+            assert!(matches!(err, Error::InvalidArgument { .. }));
+        }
+    }
+
+    #[test]
+    fn test_empty_rmi() {
+        // training needs keys, so empty the RMI afterwards
+        let mut rmi = train(&RMITrainingData::from_keys(0..100u64), "linear,linear", 16);
+        rmi.num_rmi_rows = 0;
+
+        let key = ModelInput::from(42u64);
+        assert_eq!(rmi.predict(&key).0, 0);
+        assert!(matches!(rmi.try_predict(&key), Err(Error::EmptyData)));
+        assert_eq!(rmi.predict_to_u32(&key).unwrap().0, 0);
+        assert_eq!(rmi.verify_against_baseline(&[]), Ok(()));
+        assert_eq!(rmi.search_stats(&[], crate::SearchStrategy::Binary).lookups, 0);
+        assert_eq!(rmi.explain(&[], 42, crate::SearchStrategy::Binary).position, 0);
+    }
+
+    #[test]
+    fn test_lookup_cache_lines() {
+        let keys: Vec<u64> = (1..50_000u64).map(|i| i * i).collect();
+        let md = RMITrainingData::from_keys(keys);
+        // the radix layer reads nothing, and each linear model two floats
+        assert_eq!(train(&md, "radix,linear", 256).lookup_cache_lines(), 1);
+        assert_eq!(train(&md, "linear,linear", 256).lookup_cache_lines(), 2);
+        assert_eq!(train(&md, "radix18,radix_leaf8", 1024).lookup_cache_lines(), 3);
+    }
+
+    #[test]
+    fn test_all_integer() {
+        let keys: Vec<u64> = (1..50_000u64).map(|i| i * i * 7 + i % 13).collect();
+        let md = RMITrainingData::from_keys(keys.clone());
+        assert!(!train(&md, "linear,linear", 256).all_integer());
+        assert!(!train(&md, "radix,linear", 256).all_integer());
+
+        let rmi = train(&md, "radix18,radix_leaf8", 1024);
+        assert!(rmi.all_integer());
+        // with nothing to round, the predictions are the same on any target:
+        // this checksum, and each model's portable reference prediction
+        let mut checksum = 0u64;
+        for key in keys.iter().flat_map(|k| [k - 1, *k, k + 1]) {
+            let (pos, err) = rmi.predict(&key.into());
+            checksum = checksum.wrapping_mul(0x0000_0100_0000_01b3) ^ pos ^ (err << 32);
+
+            let top = &rmi.rmi[0][0];
+            let leaf = &rmi.rmi[1][usize::min(top.predict_to_int(&key.into()) as usize, 1023)];
+            for model in [top, leaf] {
+                assert_eq!(model.predict_to_int(&key.into()), model.reference_predict(&key.into()));
+            }
+        }
+        assert_eq!(checksum, 2_612_503_850_921_601_407);
+
+        // a LearnedFIB is integer only once it predicts in fixed point
+        let mut fib = LearnedFIB::new(&md, 16, 4);
+        assert!(!fib.integer_only());
+        fib.use_fixed_point(&md);
+        assert!(fib.integer_only());
+    }
+
+    #[test]
+    fn test_predict_to_u32() {
+        let keys: Vec<u64> = (1..10_000u64).map(|i| i * i * 3).collect();
+        let mut rmi = train(&RMITrainingData::from_keys(keys.clone()), "linear,linear", 64);
+        for key in keys.iter().flat_map(|k| [*k, k + 1]) {
+            let (pos, err) = rmi.predict(&key.into());
+            assert_eq!(rmi.predict_to_u32(&key.into()).unwrap(), (pos as u32, err));
+        }
+
+        rmi.num_rmi_rows = u32::MAX as usize + 2;
+        let err = rmi.predict_to_u32(&keys[0].into()).unwrap_err();
+        assert!(matches!(err, Error::ParamOverflow { .. }));
+    }
+
+    #[test]
+    fn test_train_with_report() {
+        let keys: Vec<u64> = (1..20_000u64).map(|i| i * i * 5).collect();
+        let (rmi, report) = train_with_report(&RMITrainingData::from_keys(keys.clone()),
+                                              "radix,linear", 128);
+        // keys past the last one reach the empty models at the end
+        let max_err = keys.iter().chain([u64::MAX].iter())
+            .map(|k| rmi.predict(&(*k).into()).1)
+            .max().unwrap();
+        assert_eq!(report.error_bound, max_err);
+        assert_eq!(report.total_bytes, crate::rmi_size(&rmi));
+        assert_eq!(report.lookup_cache_lines, rmi.lookup_cache_lines());
+        assert_eq!(report.build_time, rmi.build_time);
+
+        let models: Vec<&str> = report.layers.iter().map(|l| l.model.as_str()).collect();
+        assert_eq!(models, ["radix", "linear"]);
+        assert_eq!(report.layers[1].num_models, 128);
+        assert_eq!(report.layers[1].size_bytes, 128 * 16);
+        // the radix model knows how far off it is; linear models do not
+        assert!(report.layers[0].error_bound.is_some());
+        assert_eq!(report.layers[1].error_bound, None);
+    }
+
+    #[test]
+    fn test_verify_against_baseline() {
+        // clusters of keys with wide gaps between them, and a few repeats
+        let mut keys: Vec<u64> = (0..20_000u64)
+            .map(|i| (i / 1000) * 1_000_000_000 + (i % 1000) * (i % 1000) * 7 + 1)
+            .collect();
+        keys.extend([5_000_000_000, 5_000_000_000, 5_000_000_000]);
+        keys.sort_unstable();
+        let rmi = train(&RMITrainingData::from_keys(keys.clone()), "linear,linear", 256);
+        assert_eq!(rmi.verify_against_baseline(&keys), Ok(()));
+
+        // without its error bounds, the RMI lands on the wrong keys
+        let mut rmi = rmi;
+        for err in rmi.last_layer_max_l1s.iter_mut() {
+            *err = 0;
+        }
+        let mismatch = rmi.verify_against_baseline(&keys).unwrap_err();
+        assert_eq!(mismatch.expected, keys.partition_point(|k| *k < mismatch.key));
+        assert_ne!(mismatch.found, mismatch.expected);
+    }
+
+    #[test]
+    fn test_gap_at_split() {
+        // evenly spaced keys, except for a gap ending right at the middle
+        // leaf, where training splits the leaves in two halves
+        let keys: Vec<u64> = (1..1 << 16)
+            .filter(|i| !(28_000..1 << 15).contains(i))
+            .map(|i| i << 10)
+            .collect();
+        let md = RMITrainingData::from_keys(keys.clone());
+        let rmi = train(&md, "radix,linear", 64);
+        // the leaf before the gap is fit to the key after it, rather than
+        // extrapolating across the gap (an error of about 4800)
+        let errors = &rmi.last_layer_max_l1s;
+        assert!(errors[27] < 1000, "error before the gap was {}", errors[27]);
+        assert_eq!(rmi.verify_against_baseline(&keys), Ok(()));
+    }
+
+    #[test]
+    fn test_two_level_radix() {
+        // uniformly spread keys
+        let mut keys: Vec<u64> = (0..200_000u64)
+            .map(|i| i.wrapping_mul(0x9E37_79B9_7F4A_7C15))
+            .collect();
+        keys.sort_unstable();
+        let md = RMITrainingData::from_keys(keys.clone());
+        let rmi = train(&md, "radix18,radix_leaf8", 4096);
+
+        // every leaf has the same layout and size, whatever its keys
+        let leaves = &rmi.rmi[1];
+        let params = leaves[0].params();
+        assert_eq!(params.len(), 5);
+        for leaf in leaves.iter() {
+            assert_eq!(leaf.function_name(), "radix_table_leaf");
+            let sizes: Vec<usize> = leaf.params().iter().map(|p| p.size()).collect();
+            assert_eq!(sizes, params.iter().map(|p| p.size()).collect::<Vec<usize>>());
+        }
+
+        let check = |key: u64| {
+            let (pos, err) = rmi.predict(&key.into());
+            let correct = keys.partition_point(|k| *k < key) as u64;
+            assert!(u64::max(pos, correct) - u64::min(pos, correct) <= err,
+                    "key {} predicted at {} +- {}, but is at {}", key, pos, err, correct);
+        };
+        for key in keys.iter() {
+            check(*key);
+            check(key.wrapping_add(1));
+            check(key.wrapping_sub(1));
+        }
+        assert!(rmi.model_max_error < 64, "max error {}", rmi.model_max_error);
+    }
+
+    #[test]
+    fn test_learned_fib_spec() {
+        let keys: Vec<u64> = (0..20_000u64).map(|i| i * i + 3 * i).collect();
+        let rmi = train(&RMITrainingData::from_keys(keys.clone()), "radix,learned_fib", 64);
+        assert!(rmi.rmi[1].iter().all(|leaf| leaf.kind() == ModelKind::LearnedFIB));
+        assert_eq!(rmi.verify_against_baseline(&keys), Ok(()));
+    }
+
+    #[test]
+    #[should_panic(expected = "built-in model")]
+    fn test_cannot_register_learned_fib() {
+        register_model("learned_fib", |data| Box::new(LinearModel::new(data)));
+    }
+}
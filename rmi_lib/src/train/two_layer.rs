@@ -7,7 +7,7 @@
  
 use crate::models::TrainingKey;
 use crate::models::*;
-use crate::train::{validate, train_model, TrainedRMI};
+use crate::train::{validate, validate_bottom_layer, train_model, TrainedRMI};
 use crate::train::lower_bound_correction::LowerBoundCorrection;
 use log::*;
 
@@ -35,6 +35,12 @@ fn build_models_from<T: TrainingKey>(data: &RMITrainingData<T>,
                              = Vec::with_capacity(num_models as usize);
     let mut second_layer_data = Vec::with_capacity((end_idx - start_idx) / num_models as usize);
     let mut last_target = first_model_idx;
+
+    // as between leaves, the first leaf of this subset also sees the key
+    // just before the subset, and the last leaf the key just after it.
+    if start_idx > 0 {
+        second_layer_data.push(data.get(start_idx - 1));
+    }
            
     let bounded_it = data.iter()
         .skip(start_idx)
@@ -84,6 +90,9 @@ fn build_models_from<T: TrainingKey>(data: &RMITrainingData<T>,
     }
 
     // train the last remaining model
+    if end_idx < data.len() {
+        second_layer_data.push(data.get(end_idx));
+    }
     assert!(! second_layer_data.is_empty());
     let container = RMITrainingData::new(Box::new(second_layer_data));
     let leaf_model = train_model(model_type, &container);
@@ -164,7 +173,7 @@ pub fn train_two_layer<T: TrainingKey>(md_container: &mut RMITrainingData<T>,
                                                0,
                                                first_half_models),
                           || build_models_from(&md_container, &top_model, layer2_model,
-                                               split_idx + 1, md_container.len(),
+                                               split_idx, md_container.len(),
                                                split_idx_target,
                                                second_half_models));
 
@@ -174,6 +183,8 @@ pub fn train_two_layer<T: TrainingKey>(md_container: &mut RMITrainingData<T>,
         leaf_models
     };
 
+    validate_bottom_layer(layer2_model, &leaf_models);
+
     trace!("Computing lower bound stats...");
     let lb_corrections = LowerBoundCorrection::new(
         |x| top_model.predict_to_int(&x.to_model_input()), num_leaf_models, md_container
@@ -203,18 +214,8 @@ pub fn train_two_layer<T: TrainingKey>(md_container: &mut RMITrainingData<T>,
     
     
     trace!("Computing last level errors...");
-    // evaluate model, compute last level errors
-    let mut last_layer_max_l1s = vec![(0, 0) ; num_leaf_models as usize];
-    for (x, y) in md_container.iter_model_input() {
-        let leaf_idx = top_model.predict_to_int(&x);
-        let target = u64::min(num_leaf_models - 1, leaf_idx) as usize;
-        
-        let pred = leaf_models[target].predict_to_int(&x);
-        let err = error_between(pred, y as u64, md_container.len() as u64);
-
-        let cur_val = last_layer_max_l1s[target];
-        last_layer_max_l1s[target] = (cur_val.0 + 1, u64::max(err, cur_val.1));
-    }    
+    // evaluate model, compute last level errors along each key's full path
+    let mut last_layer_max_l1s = merge_errors(top_model.as_ref(), &leaf_models, md_container);
 
     // for lower bound searches, we need to make sure that:
     //   (1) a query for the first key in the next leaf minus one 
@@ -0,0 +1,129 @@
+// < begin copyright >
+// Copyright Ryan Marcus 2020
+//
+// See root directory of this project for license terms.
+//
+// < end copyright >
+
+use rmi_lib::{register_model, train, CodeGen, KeyType, Model, ModelDataType, ModelInput,
+              ModelParam, RMITrainingData};
+use std::any::Any;
+use std::fs;
+
+#[path = "../src/cpp_test.rs"]
+mod cpp_test;
+
+// A toy top-layer model: a line through the origin and the last key.
+struct ToyScale {
+    slope: f64,
+}
+
+impl Model for ToyScale {
+    fn predict_to_float(&self, inp: &ModelInput) -> f64 {
+        return self.slope * inp.as_float();
+    }
+
+    fn input_type(&self) -> ModelDataType {
+        return ModelDataType::Float;
+    }
+
+    fn output_type(&self) -> ModelDataType {
+        return ModelDataType::Float;
+    }
+
+    fn params(&self) -> Vec<ModelParam> {
+        return vec![self.slope.into()];
+    }
+
+    fn code(&self) -> String {
+        return String::from("
+inline double toy_scale(double slope, double inp) {
+    return slope * inp;
+}");
+    }
+
+    fn function_name(&self) -> String {
+        return String::from("toy_scale");
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        return self;
+    }
+}
+
+fn train_toy_scale(data: &RMITrainingData<u64>) -> Box<dyn Model> {
+    if data.len() == 0 {
+        return Box::new(ToyScale { slope: 0.0 });
+    }
+
+    let (key, pos) = data.get(data.len() - 1);
+    return Box::new(ToyScale { slope: pos as f64 / key as f64 });
+}
+
+fn keys() -> Vec<u64> {
+    return (1..10_000u64).map(|i| i * 37 + (i % 3) * 5).collect();
+}
+
+#[test]
+fn test_custom_model_codegen() {
+    register_model("toy_scale", train_toy_scale);
+
+    let keys = keys();
+    let data: Vec<(u64, usize)> = keys.iter().cloned().zip(0..).collect();
+    let rmi = train(&RMITrainingData::new(Box::new(data)), "toy_scale,linear", 64);
+    assert_eq!(rmi.rmi[0][0].function_name(), "toy_scale");
+
+    let dir = std::env::temp_dir()
+        .join(format!("rmi_custom_model_test_{}", std::process::id()));
+    fs::create_dir_all(dir.join("rmi_data")).unwrap();
+
+    CodeGen::new("toytest", KeyType::U64)
+        .output_dir(&dir)
+        .data_dir(dir.join("rmi_data"))
+        .write(rmi)
+        .unwrap();
+
+    let generated = fs::read_to_string(dir.join("toytest.cpp")).unwrap();
+    assert!(generated.contains("inline double toy_scale(double slope, double inp)"));
+
+    fs::write(dir.join("main.cpp"), "
+#include <algorithm>
+#include <vector>
+#include \"toytest.h\"
+
+int main() {
+  std::vector<uint64_t> keys;
+  for (uint64_t i = 1; i < 10000; i++) keys.push_back(i * 37 + (i % 3) * 5);
+  if (!toytest::load(\"rmi_data\")) return 2;
+
+  for (uint64_t key : keys) {
+    size_t err;
+    uint64_t guess = toytest::lookup(key, &err);
+    uint64_t correct = std::lower_bound(keys.begin(), keys.end(), key) - keys.begin();
+    uint64_t diff = guess > correct ? guess - correct : correct - guess;
+    if (diff > err) return 1;
+  }
+
+  toytest::cleanup();
+  return 0;
+}
+").unwrap();
+
+    let sources = [dir.join("main.cpp"), dir.join("toytest.cpp")];
+    let ran = match cpp_test::compile_and_run(&sources, &["-std=c++17", "-O1"]) {
+        Some(status) => status,
+        None => {
+            fs::remove_dir_all(&dir).unwrap();
+            return;
+        }
+    };
+    assert!(ran.success(), "RMI with a custom model gave a wrong lookup ({:?})", ran.code());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+#[should_panic(expected = "built-in model")]
+fn test_cannot_replace_builtin() {
+    register_model("linear", train_toy_scale);
+}
@@ -0,0 +1,124 @@
+// < begin copyright >
+// Copyright Ryan Marcus 2020
+//
+// See root directory of this project for license terms.
+//
+// < end copyright >
+
+use rmi_lib::{register_model, train, CodeGen, KeyType, LearnedFIB, Model, RMITrainingData};
+use std::fs;
+
+#[path = "../src/cpp_test.rs"]
+mod cpp_test;
+
+// Trains `models` with `branching_factor` bottom models, then compiles the
+// generated RMI against a driver that checks the lower bound of every key,
+// and of every gap between keys, is within the reported error. The keys
+// are shifted up by `shift` bits.
+fn compile_and_check(namespace: &str, models: &str, branching_factor: u64, shift: u32) {
+    let keys: Vec<u64> = (1..50_000u64).map(|i| (i * i / 3 + 17 * i) << shift).collect();
+    let data: Vec<(u64, usize)> = keys.iter().cloned().zip(0..).collect();
+    let rmi = train(&RMITrainingData::new(Box::new(data)), models, branching_factor);
+    assert_eq!(rmi.rmi.len(), 2);
+    assert_eq!(rmi.rmi[1].len(), branching_factor as usize);
+
+    let dir = std::env::temp_dir()
+        .join(format!("rmi_{}_test_{}", namespace, std::process::id()));
+    fs::create_dir_all(dir.join("rmi_data")).unwrap();
+
+    CodeGen::new(namespace, KeyType::U64)
+        .output_dir(&dir)
+        .data_dir(dir.join("rmi_data"))
+        .write(rmi)
+        .unwrap();
+
+    // the bottom layer is read from a data file and indexed by the top
+    // model's prediction
+    let generated = fs::read_to_string(dir.join(format!("{}.cpp", namespace))).unwrap();
+    assert!(generated.contains("modelIndex = "));
+    assert!(dir.join("rmi_data").join(format!("{}_L1_PARAMETERS", namespace)).exists());
+
+    fs::write(dir.join("main.cpp"), format!("
+#include <algorithm>
+#include <vector>
+#include \"{ns}.h\"
+
+bool check(const std::vector<uint64_t>& keys, uint64_t key) {{
+  size_t err;
+  uint64_t guess = {ns}::lookup(key, &err);
+  uint64_t correct = std::lower_bound(keys.begin(), keys.end(), key) - keys.begin();
+  uint64_t diff = guess > correct ? guess - correct : correct - guess;
+  return diff <= err;
+}}
+
+int main() {{
+  std::vector<uint64_t> keys;
+  for (uint64_t i = 1; i < 50000; i++) keys.push_back((i * i / 3 + 17 * i) << {shift});
+  if (!{ns}::load(\"rmi_data\")) return 2;
+
+  for (uint64_t key : keys) {{
+    if (!check(keys, key)) return 1;
+    if (!check(keys, key + 1)) return 3;
+  }}
+
+  {ns}::cleanup();
+  return 0;
+}}
+", ns = namespace, shift = shift)).unwrap();
+
+    let sources = [dir.join("main.cpp"), dir.join(format!("{}.cpp", namespace))];
+    let ran = match cpp_test::compile_and_run(&sources, &["-std=c++17", "-O1"]) {
+        Some(status) => status,
+        None => {
+            fs::remove_dir_all(&dir).unwrap();
+            return;
+        }
+    };
+    assert!(ran.success(), "two-layer {} RMI gave a wrong lookup ({:?})", models, ran.code());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_radix_top_layer() {
+    compile_and_check("radixtest", "radix,linear", 1024, 0);
+}
+
+#[test]
+fn test_two_level_radix() {
+    compile_and_check("radix2test", "radix18,radix_leaf8", 1024, 0);
+}
+
+#[test]
+fn test_relative_radix_top_layer() {
+    compile_and_check("radixreltest", "radix_rel18,linear", 1024, 0);
+}
+
+#[test]
+fn test_bounds_checked_top_layer() {
+    compile_and_check("cubictest", "linear,cubic", 256, 0);
+}
+
+// LearnedFIB leaves whose networks differ in size from leaf to leaf, over
+// keys spread across its buckets
+fn learned_fib_leaf(data: &RMITrainingData<u64>) -> Box<dyn Model> {
+    return Box::new(LearnedFIB::new(data, 16, 4));
+}
+
+fn learned_fib_fixed_leaf(data: &RMITrainingData<u64>) -> Box<dyn Model> {
+    let mut fib = LearnedFIB::new_with_offset_table(data, 16, 4);
+    fib.use_fixed_point(data);
+    return Box::new(fib);
+}
+
+#[test]
+fn test_learned_fib_bottom_layer() {
+    register_model("fib_leaf", learned_fib_leaf);
+    compile_and_check("fibtest", "radix,fib_leaf", 256, 34);
+}
+
+#[test]
+fn test_fixed_point_learned_fib_bottom_layer() {
+    register_model("fib_fixed_leaf", learned_fib_fixed_leaf);
+    compile_and_check("fibfixedtest", "radix,fib_fixed_leaf", 256, 34);
+}
@@ -14,6 +14,7 @@ mod load;
 
 use load::{load_data, DataType};
 use rmi_lib::{train, train_bounded};
+use rmi_lib::SearchStrategy;
 use rmi_lib::KeyType;
 use rmi_lib::optimizer;
 
@@ -95,10 +96,18 @@ fn main() {
         .arg(Arg::with_name("zero-build-time")
              .long("zero-build-time")
              .help("zero out the model build time field"))
+        .arg(Arg::with_name("search-stats")
+             .long("search-stats")
+             .help("count the comparisons a search of the error window takes per lookup, for each search strategy (integer keys only)"))
         .arg(Arg::with_name("optimize")
              .long("optimize")
              .value_name("file")
              .help("Search for Pareto efficient RMI configurations. Specify the name of the output file."))
+        .arg(Arg::with_name("objective")
+             .long("objective")
+             .value_name("cost")
+             .possible_values(&["size", "flops"])
+             .help("what the optimizer trades accuracy off against: size (default) or flops per lookup"))
         .get_matches();
 
     // set the max number of threads to 4 by default, otherwise Rayon goes
@@ -132,8 +141,12 @@ fn main() {
     };
 
     if matches.is_present("optimize") {
-        let results = dynamic!(optimizer::find_pareto_efficient_configs,
-                               data, 10);
+        let objective = match matches.value_of("objective") {
+            Some("flops") => optimizer::Objective::Flops,
+            _ => optimizer::Objective::Size,
+        };
+        let results = dynamic!(optimizer::find_pareto_efficient_configs_with,
+                               data, 10, objective);
 
         optimizer::RMIStatistics::display_table(&results);
 
@@ -262,6 +275,18 @@ fn main() {
 
     } else if matches.value_of("namespace").is_some() {
         let namespace = matches.value_of("namespace").unwrap().to_string();
+        let search_keys: Option<Vec<u64>> = if !matches.is_present("search-stats") {
+            None
+        } else {
+            match &data {
+                load::RMIMMap::UINT64(d) => Some(d.iter().map(|(k, _)| k).collect()),
+                load::RMIMMap::UINT32(d) => Some(d.iter().map(|(k, _)| k as u64).collect()),
+                load::RMIMMap::FLOAT64(_) => {
+                    warn!("Search statistics are only counted for integer keys");
+                    None
+                }
+            }
+        };
         let mut trained_model = match matches.value_of("max-size") {
             None => {
                 // assume they gave a model spec 
@@ -319,6 +344,16 @@ fn main() {
             trained_model.model_max_error,
             trained_model.model_max_error as f64 / num_rows as f64 * 100.0
         );
+
+        if let Some(keys) = search_keys {
+            for strategy in SearchStrategy::ALL.iter() {
+                let stats = trained_model.search_stats(&keys, *strategy);
+                info!(
+                    "{:?} search: {:.2} comparisons per lookup on average, {} at most",
+                    strategy, stats.mean_comparisons, stats.max_comparisons
+                );
+            }
+        }
         
         if !matches.is_present("no-code") {
             if matches.is_present("zero-build-time") {